@@ -0,0 +1,57 @@
+// Compiles the built-in WGSL shaders in `shaders/` to SPIR-V, so the engine renders without an asset directory.
+// The modules land in OUT_DIR and `builtin_shaders.rs` lists them for `include_bytes!`.
+
+use std::{env, fmt::Write as _, fs, path::PathBuf};
+
+fn main() {
+    let source_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("shaders");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed={}", source_dir.display());
+
+    let mut sources: Vec<PathBuf> = fs::read_dir(&source_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "wgsl"))
+                .collect()
+        })
+        .unwrap_or_default();
+    sources.sort();
+
+    let mut listing = String::from("&[\n");
+    for source in sources {
+        println!("cargo:rerun-if-changed={}", source.display());
+        let name = source.file_stem().unwrap().to_string_lossy().into_owned();
+        let words = compile(&source);
+        let output = out_dir.join(format!("{name}.spv"));
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        fs::write(&output, bytes).unwrap();
+        writeln!(
+            listing,
+            "    (\"{name}.spv\", include_bytes!(concat!(env!(\"OUT_DIR\"), \"/{name}.spv\"))),"
+        )
+        .unwrap();
+    }
+    listing.push(']');
+    fs::write(out_dir.join("builtin_shaders.rs"), listing).unwrap();
+}
+
+fn compile(source: &std::path::Path) -> Vec<u32> {
+    let text = fs::read_to_string(source).unwrap();
+    let path = source.to_string_lossy();
+    let module = naga::front::wgsl::parse_str(&text)
+        .unwrap_or_else(|error| panic!("{}", error.emit_to_string_with_path(&text, source)));
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .unwrap_or_else(|error| panic!("{}", error.emit_to_string_with_path(&text, &path)));
+    // Vulkan 1.3 takes SPIR-V 1.6, 1.5 keeps older tools able to read the output
+    let options = naga::back::spv::Options {
+        lang_version: (1, 5),
+        ..Default::default()
+    };
+    naga::back::spv::write_vec(&module, &info, &options, None)
+        .unwrap_or_else(|error| panic!("{path}: {error}"))
+}
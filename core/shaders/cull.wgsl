@@ -0,0 +1,212 @@
+// GPU-driven instance culling, see `src/vk/culling.rs`. Each invocation tests one instance against
+// the view frustum, and in the second phase against the depth pyramid of what the first phase drew,
+// then appends its draw to the list of its bucket. `hiz` builds the pyramid one level at a time.
+
+struct PushConstants {
+    // Storage buffer slots of the scene constants, the instance and primitive tables, the draw
+    // lists and the visibility words
+    scene: u32,
+    instances: u32,
+    primitives: u32,
+    draws: u32,
+    visibility: u32,
+    instance_count: u32,
+    // Zero draws what was visible last frame, one tests against the pyramid
+    phase: u32,
+    hiz_levels: u32,
+    // First instance of every bucket
+    bucket_starts: vec4<u32>,
+    // Sampled image slots of the pyramid levels
+    hiz: array<vec4<u32>, 4>,
+}
+
+struct HizPushConstants {
+    // Sampled image slot of the depth buffer or the level above, storage image slot of the level written
+    source: u32,
+    destination: u32,
+    _pad: vec2<u32>,
+}
+
+// The draw counts are appended to atomically and naga takes one view per binding, so here every
+// storage buffer is atomic words
+struct Words {
+    data: array<atomic<u32>>,
+}
+
+@group(0) @binding(0) var textures: binding_array<texture_2d<f32>>;
+@group(0) @binding(1) var storage_images: binding_array<texture_storage_2d<r32float, write> >;
+@group(0) @binding(2) var<storage, read_write> buffers: binding_array<Words>;
+
+var<push_constant> pc: PushConstants;
+var<push_constant> hiz_pc: HizPushConstants;
+
+// Scene layout, in vec4s, see `shaders/mesh.wgsl`
+const SCENE_VIEW_PROJECTION: u32 = 0u;
+const SCENE_VIEWPORT: u32 = 12u;
+
+// Instance layout, in vec4s: the model matrix, then the primitive and material
+const INSTANCE_STRIDE: u32 = 5u;
+// Primitive layout, in vec4s: the object space bounding sphere, then the first index, index
+// count, vertex offset and bucket
+const PRIMITIVE_STRIDE: u32 = 2u;
+// Draw list layout, in words: the counts of both phases, then each phase's commands
+const BUCKETS: u32 = 4u;
+const BLENDED: u32 = 2u;
+const COUNTS: u32 = 8u;
+const COMMAND_SIZE: u32 = 5u;
+
+fn word(buffer: u32, index: u32) -> u32 {
+    return atomicLoad(&buffers[buffer].data[index]);
+}
+
+fn vec4_bits(buffer: u32, index: u32) -> vec4<u32> {
+    let base = index * 4u;
+    return vec4<u32>(word(buffer, base), word(buffer, base + 1u), word(buffer, base + 2u), word(buffer, base + 3u));
+}
+
+fn vec4_at(buffer: u32, index: u32) -> vec4<f32> {
+    return bitcast<vec4<f32>>(vec4_bits(buffer, index));
+}
+
+fn matrix_at(buffer: u32, index: u32) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        vec4_at(buffer, index),
+        vec4_at(buffer, index + 1u),
+        vec4_at(buffer, index + 2u),
+        vec4_at(buffer, index + 3u),
+    );
+}
+
+// Whether the sphere is on the inner side of all six planes of the clip space of `view_projection`
+fn in_frustum(view_projection: mat4x4<f32>, center: vec3<f32>, radius: f32) -> bool {
+    let m = transpose(view_projection);
+    // Left, right, bottom, top, near at zero depth and far
+    var planes = array<vec4<f32>, 6>(m[3] + m[0], m[3] - m[0], m[3] + m[1], m[3] - m[1], m[2], m[3] - m[2]);
+    for (var i = 0u; i < 6u; i++) {
+        let plane = planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius * length(plane.xyz) {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Whether the pyramid says the sphere is behind what was drawn, from the box around it on screen
+fn occluded(view_projection: mat4x4<f32>, center: vec3<f32>, radius: f32) -> bool {
+    // No depth pyramid this frame, everything in view counts as visible
+    if pc.hiz_levels == 0u {
+        return false;
+    }
+    var ndc_min = vec3<f32>(1.0);
+    var ndc_max = vec3<f32>(-1.0);
+    for (var corner = 0u; corner < 8u; corner++) {
+        let offset = vec3<f32>(
+            select(-radius, radius, (corner & 1u) != 0u),
+            select(-radius, radius, (corner & 2u) != 0u),
+            select(-radius, radius, (corner & 4u) != 0u),
+        );
+        let clip = view_projection * vec4<f32>(center + offset, 1.0);
+        // Reaching in front of the near plane, it could cover anything
+        if clip.w <= 0.0 || clip.z < 0.0 {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        ndc_min = min(ndc_min, ndc);
+        ndc_max = max(ndc_max, ndc);
+    }
+    let viewport = vec4_at(pc.scene, SCENE_VIEWPORT).xy;
+    // Framebuffer rows run opposite to NDC y
+    let low = vec2<f32>(ndc_min.x * 0.5 + 0.5, 0.5 - ndc_max.y * 0.5) * viewport;
+    let high = vec2<f32>(ndc_max.x * 0.5 + 0.5, 0.5 - ndc_min.y * 0.5) * viewport;
+    let pixel_min = vec2<u32>(clamp(low, vec2<f32>(0.0), viewport - 1.0));
+    let pixel_max = vec2<u32>(clamp(high, vec2<f32>(0.0), viewport - 1.0));
+    // The level where the box spans at most two texels either way, level zero is half resolution
+    let span = f32(max(pixel_max.x - pixel_min.x, pixel_max.y - pixel_min.y));
+    let level = u32(clamp(i32(ceil(log2(max(span, 1.0)))) - 1, 0, i32(pc.hiz_levels) - 1));
+    let hiz = pc.hiz[level / 4u][level % 4u];
+    let last = vec2<u32>(textureDimensions(textures[hiz])) - 1u;
+    let texel_min = min(pixel_min >> vec2<u32>(level + 1u), last);
+    let texel_max = min(pixel_max >> vec2<u32>(level + 1u), last);
+    let farthest = max(
+        max(
+            textureLoad(textures[hiz], texel_min, 0).x,
+            textureLoad(textures[hiz], vec2<u32>(texel_max.x, texel_min.y), 0).x,
+        ),
+        max(
+            textureLoad(textures[hiz], vec2<u32>(texel_min.x, texel_max.y), 0).x,
+            textureLoad(textures[hiz], texel_max, 0).x,
+        ),
+    );
+    return ndc_min.z > farthest;
+}
+
+fn emit(instance: u32, bucket: u32, primitive: vec4<u32>) {
+    let slot = atomicAdd(&buffers[pc.draws].data[pc.phase * BUCKETS + bucket], 1u);
+    let command = pc.phase * pc.instance_count + pc.bucket_starts[bucket] + slot;
+    let base = COUNTS + command * COMMAND_SIZE;
+    atomicStore(&buffers[pc.draws].data[base], primitive.y);
+    atomicStore(&buffers[pc.draws].data[base + 1u], 1u);
+    atomicStore(&buffers[pc.draws].data[base + 2u], primitive.x);
+    atomicStore(&buffers[pc.draws].data[base + 3u], primitive.z);
+    // The vertex shaders find the instance through its index
+    atomicStore(&buffers[pc.draws].data[base + 4u], instance);
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let instance = id.x;
+    if instance >= pc.instance_count {
+        return;
+    }
+    let base = instance * INSTANCE_STRIDE;
+    let model = matrix_at(pc.instances, base);
+    let index = vec4_bits(pc.instances, base + 4u).x;
+    let sphere = vec4_at(pc.primitives, index * PRIMITIVE_STRIDE);
+    // First index, index count, vertex offset and bucket
+    let primitive = vec4_bits(pc.primitives, index * PRIMITIVE_STRIDE + 1u);
+    let bucket = primitive.w;
+
+    let center = (model * vec4<f32>(sphere.xyz, 1.0)).xyz;
+    let scale = max(max(length(model[0].xyz), length(model[1].xyz)), length(model[2].xyz));
+    let radius = sphere.w * scale;
+    let view_projection = matrix_at(pc.scene, SCENE_VIEW_PROJECTION);
+    let visible_before = word(pc.visibility, instance) != 0u;
+    let in_view = in_frustum(view_projection, center, radius);
+
+    if pc.phase == 0u {
+        // Blended instances wait for everything opaque
+        if bucket < BLENDED && visible_before && in_view {
+            emit(instance, bucket, primitive);
+        }
+        return;
+    }
+    let visible = in_view && !occluded(view_projection, center, radius);
+    atomicStore(&buffers[pc.visibility].data[instance], u32(visible));
+    // Opaque instances visible last frame were drawn by the first phase already
+    if visible && (bucket >= BLENDED || !visible_before) {
+        emit(instance, bucket, primitive);
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn hiz(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(storage_images[hiz_pc.destination]);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+    // Odd sizes round up, the last texel clamps instead of reading past the edge
+    let last = textureDimensions(textures[hiz_pc.source]) - 1u;
+    let first = id.xy * 2u;
+    let second = min(first + 1u, last);
+    let farthest = max(
+        max(
+            textureLoad(textures[hiz_pc.source], first, 0).x,
+            textureLoad(textures[hiz_pc.source], vec2<u32>(second.x, first.y), 0).x,
+        ),
+        max(
+            textureLoad(textures[hiz_pc.source], vec2<u32>(first.x, second.y), 0).x,
+            textureLoad(textures[hiz_pc.source], second, 0).x,
+        ),
+    );
+    textureStore(storage_images[hiz_pc.destination], id.xy, vec4<f32>(farthest, 0.0, 0.0, 1.0));
+}
@@ -0,0 +1,85 @@
+// Auto exposure, see `src/vk/post.rs`. One pass bins the log luminance of every pixel into a
+// histogram, the next averages it and eases the adapted luminance towards the average. Both share
+// one storage buffer that lives across frames: the bins, then the adapted luminance.
+
+struct PushConstants {
+    // Sampled image slot of the HDR image, storage buffer slot of the histogram
+    hdr: u32,
+    histogram: u32,
+    width: u32,
+    height: u32,
+    // Seconds since the last frame, zero snaps straight to the average
+    delta_time: f32,
+    adaptation_speed: f32,
+    _pad: vec2<u32>,
+}
+
+struct Histogram {
+    data: array<atomic<u32>>,
+}
+
+@group(0) @binding(0) var textures: binding_array<texture_2d<f32>>;
+@group(0) @binding(2) var<storage, read_write> buffers: binding_array<Histogram>;
+
+var<push_constant> pc: PushConstants;
+
+const BINS: u32 = 256u;
+// Follows the bins
+const ADAPTED_LUMINANCE: u32 = 256u;
+// Luminance range the bins cover, in stops. Bin zero holds everything darker
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+const LOG_LUMINANCE_RANGE: f32 = 22.0;
+
+const LUMA: vec3<f32> = vec3<f32>(0.2126, 0.7152, 0.0722);
+
+var<workgroup> bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<f32, 256>;
+
+fn bin(luminance: f32) -> u32 {
+    if luminance < exp2(MIN_LOG_LUMINANCE) {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - MIN_LOG_LUMINANCE) / LOG_LUMINANCE_RANGE, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    atomicStore(&bins[local], 0u);
+    workgroupBarrier();
+    if id.x < pc.width && id.y < pc.height {
+        let color = textureLoad(textures[pc.hdr], vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&bins[bin(dot(color, LUMA))], 1u);
+    }
+    workgroupBarrier();
+    atomicAdd(&buffers[pc.histogram].data[local], atomicLoad(&bins[local]));
+}
+
+@compute @workgroup_size(256)
+fn average(@builtin(local_invocation_index) local: u32) {
+    // Cleared for the next frame while it's read
+    let count = atomicExchange(&buffers[pc.histogram].data[local], 0u);
+    weighted[local] = f32(count) * f32(local);
+    workgroupBarrier();
+    for (var stride = BINS / 2u; stride > 0u; stride >>= 1u) {
+        if local < stride {
+            weighted[local] += weighted[local + stride];
+        }
+        workgroupBarrier();
+    }
+    if local == 0u {
+        // Black pixels would drag the average down without ever getting brighter
+        let lit = max(f32(pc.width * pc.height) - f32(count), 1.0);
+        let mean_bin = weighted[0] / lit;
+        let target_luminance = exp2((mean_bin - 1.0) / 254.0 * LOG_LUMINANCE_RANGE + MIN_LOG_LUMINANCE);
+        let previous = bitcast<f32>(atomicLoad(&buffers[pc.histogram].data[ADAPTED_LUMINANCE]));
+        var adapted = target_luminance;
+        if previous > 0.0 && pc.delta_time > 0.0 {
+            adapted = previous + (target_luminance - previous) * (1.0 - exp(-pc.delta_time * pc.adaptation_speed));
+        }
+        atomicStore(&buffers[pc.histogram].data[ADAPTED_LUMINANCE], bitcast<u32>(adapted));
+    }
+}
@@ -0,0 +1,90 @@
+// Bins the point and spot lights into view space froxels, so shading only loops over the lights
+// that can reach a fragment. One invocation per cluster, the grid matches `CLUSTERS` in `src/vk/lights.rs`.
+
+struct PushConstants {
+    // Storage buffer slot of the scene constants, which point at the lights and clusters
+    scene: u32,
+}
+
+// Raw words, floats are bitcast from them
+struct Words {
+    data: array<u32>,
+}
+
+@group(0) @binding(2) var<storage, read_write> buffers: binding_array<Words>;
+
+var<push_constant> pc: PushConstants;
+
+const CLUSTERS_X: u32 = 16u;
+const CLUSTERS_Y: u32 = 9u;
+const CLUSTERS_Z: u32 = 24u;
+// In words, a count followed by the light indices
+const CLUSTER_STRIDE: u32 = 128u;
+const MAX_CLUSTER_LIGHTS: u32 = 127u;
+
+// Scene layout, in vec4s, see `shaders/mesh.wgsl`
+const SCENE_VIEW: u32 = 5u;
+const SCENE_PROJECTION: u32 = 9u;
+const SCENE_LIGHTS: u32 = 11u;
+
+// Light layout, in vec4s per light
+const LIGHT_STRIDE: u32 = 4u;
+
+fn vec4_at(buffer: u32, index: u32) -> vec4<f32> {
+    let base = index * 4u;
+    return bitcast<vec4<f32>>(vec4<u32>(
+        buffers[buffer].data[base],
+        buffers[buffer].data[base + 1u],
+        buffers[buffer].data[base + 2u],
+        buffers[buffer].data[base + 3u],
+    ));
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let cluster = id.x;
+    if cluster >= CLUSTERS_X * CLUSTERS_Y * CLUSTERS_Z {
+        return;
+    }
+    let tile = vec2<u32>(cluster % CLUSTERS_X, (cluster / CLUSTERS_X) % CLUSTERS_Y);
+    let slice = cluster / (CLUSTERS_X * CLUSTERS_Y);
+
+    // x and y scale of the projection, then the near and far planes
+    let projection = vec4_at(pc.scene, SCENE_PROJECTION);
+    let depth_ratio = projection.w / projection.z;
+    let near = projection.z * pow(depth_ratio, f32(slice) / f32(CLUSTERS_Z));
+    let far = projection.z * pow(depth_ratio, f32(slice + 1u) / f32(CLUSTERS_Z));
+    let tiles = vec2<f32>(f32(CLUSTERS_X), f32(CLUSTERS_Y));
+    let ndc_min = vec2<f32>(tile) / tiles * 2.0 - 1.0;
+    let ndc_max = vec2<f32>(tile + 1u) / tiles * 2.0 - 1.0;
+    // The tile's corners on both planes of the slice, in view space looking down -Z
+    let scale = 1.0 / projection.xy;
+    let a = ndc_min * scale * near;
+    let b = ndc_max * scale * near;
+    let c = ndc_min * scale * far;
+    let d = ndc_max * scale * far;
+    let box_min = vec3<f32>(min(min(a, b), min(c, d)), -far);
+    let box_max = vec3<f32>(max(max(a, b), max(c, d)), -near);
+
+    let view = mat4x4<f32>(
+        vec4_at(pc.scene, SCENE_VIEW),
+        vec4_at(pc.scene, SCENE_VIEW + 1u),
+        vec4_at(pc.scene, SCENE_VIEW + 2u),
+        vec4_at(pc.scene, SCENE_VIEW + 3u),
+    );
+    // Light and cluster buffer slots, directional and total light counts
+    let header = bitcast<vec4<u32>>(vec4_at(pc.scene, SCENE_LIGHTS));
+    let base = cluster * CLUSTER_STRIDE;
+    var found = 0u;
+    // Directional lights come first and reach every cluster, they aren't binned
+    for (var light = header.z; light < header.w && found < MAX_CLUSTER_LIGHTS; light++) {
+        let position_range = vec4_at(header.x, light * LIGHT_STRIDE);
+        let center = (view * vec4<f32>(position_range.xyz, 1.0)).xyz;
+        let offset = center - clamp(center, box_min, box_max);
+        if dot(offset, offset) <= position_range.w * position_range.w {
+            buffers[header.y].data[base + 1u + found] = light;
+            found++;
+        }
+    }
+    buffers[header.y].data[base] = found;
+}
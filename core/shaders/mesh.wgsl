@@ -0,0 +1,421 @@
+// Forward+ shaded meshes with the glTF metallic-roughness material. Directional lights reach every
+// fragment, point and spot lights come from the cluster the fragment falls in, see `shaders/lights.wgsl`.
+// Lights picked to cast shadows are looked up in the shadow atlas, see `src/vk/shadows.rs`.
+// Everything is read through the bindless set, the push constants only carry indices. Draws are
+// one instance each, and its index picks the model matrix and material from the instance table.
+
+struct PushConstants {
+    // Storage buffer slots of the scene constants, the material table and the instance table
+    scene: u32,
+    materials: u32,
+    instances: u32,
+    // Sampler slot every material texture is read with
+    material_sampler: u32,
+}
+
+// Every storage buffer is viewed as a flat array of vec4s
+struct Vec4s {
+    data: array<vec4<f32>>,
+}
+
+@group(0) @binding(0) var textures: binding_array<texture_2d<f32>>;
+@group(0) @binding(2) var<storage, read> buffers: binding_array<Vec4s>;
+@group(0) @binding(3) var samplers: binding_array<sampler>;
+
+var<push_constant> pc: PushConstants;
+
+// Scene layout, in vec4s
+const SCENE_VIEW_PROJECTION: u32 = 0u;
+const SCENE_CAMERA: u32 = 4u;
+const SCENE_VIEW: u32 = 5u;
+// x and y scale of the projection, near and far planes
+const SCENE_PROJECTION: u32 = 9u;
+const SCENE_AMBIENT: u32 = 10u;
+// Light and cluster buffer slots, directional and total light counts
+const SCENE_LIGHTS: u32 = 11u;
+// Render target size in pixels, then the shadow buffer slot
+const SCENE_VIEWPORT: u32 = 12u;
+
+// Light layout, in vec4s per light: position and range, direction and kind, color times
+// intensity, then the spot cone as a scale and offset on the cosine and the first shadow view
+const LIGHT_STRIDE: u32 = 4u;
+
+// Shadow layout, in vec4s: atlas slot, filter and cascade count, the filter parameters and the
+// atlas texel size, the cascade splits, then per view its matrix, tile and projection parameters
+const SHADOW_HEADER: u32 = 3u;
+const SHADOW_VIEW_STRIDE: u32 = 6u;
+const FILTER_PCF: u32 = 1u;
+const FILTER_PCSS: u32 = 2u;
+// In texels of the shadow map, how far receivers are pushed along their normal
+const NORMAL_OFFSET: f32 = 1.5;
+const MAX_FILTER_RADIUS: f32 = 16.0;
+
+var<private> POISSON: array<vec2<f32>, 16> = array<vec2<f32>, 16>(
+    vec2<f32>(-0.94201624, -0.39906216),
+    vec2<f32>(0.94558609, -0.76890725),
+    vec2<f32>(-0.09418410, -0.92938870),
+    vec2<f32>(0.34495938, 0.29387760),
+    vec2<f32>(-0.91588581, 0.45771432),
+    vec2<f32>(-0.81544232, -0.87912464),
+    vec2<f32>(-0.38277543, 0.27676845),
+    vec2<f32>(0.97484398, 0.75648379),
+    vec2<f32>(0.44323325, -0.97511554),
+    vec2<f32>(0.53742981, -0.47373420),
+    vec2<f32>(-0.26496911, -0.41893023),
+    vec2<f32>(0.79197514, 0.19090188),
+    vec2<f32>(-0.24188840, 0.99706507),
+    vec2<f32>(-0.81409955, 0.91437590),
+    vec2<f32>(0.19984126, 0.78641367),
+    vec2<f32>(0.14383161, -0.14100790),
+);
+
+const CLUSTERS_X: u32 = 16u;
+const CLUSTERS_Y: u32 = 9u;
+const CLUSTERS_Z: u32 = 24u;
+// In words, a count followed by the light indices
+const CLUSTER_STRIDE: u32 = 128u;
+
+// Material layout, in vec4s per material. The last one holds the sampled image slots of the
+// base color, metallic-roughness, normal and emissive textures, white or a flat normal when unset
+const MATERIAL_STRIDE: u32 = 4u;
+const MATERIAL_TEXTURES: u32 = 3u;
+
+// Instance layout, in vec4s: the model matrix, then the primitive and material
+const INSTANCE_STRIDE: u32 = 5u;
+
+const PI: f32 = 3.14159265;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) tangent: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip: vec4<f32>,
+    @location(0) world: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    // World space, w is the bitangent sign and zero without tangents
+    @location(3) tangent: vec4<f32>,
+    @location(4) @interpolate(flat) material: u32,
+}
+
+// Set from the vertex output before anything reads the material
+var<private> material_index: u32;
+
+fn scene(index: u32) -> vec4<f32> {
+    return buffers[pc.scene].data[index];
+}
+
+fn material(index: u32) -> vec4<f32> {
+    return buffers[pc.materials].data[material_index * MATERIAL_STRIDE + index];
+}
+
+fn light(index: u32, row: u32) -> vec4<f32> {
+    let lights = bitcast<u32>(scene(SCENE_LIGHTS).x);
+    return buffers[lights].data[index * LIGHT_STRIDE + row];
+}
+
+// Cluster lists are words, read four at a time
+fn cluster_word(index: u32) -> u32 {
+    let clusters = bitcast<u32>(scene(SCENE_LIGHTS).y);
+    return bitcast<u32>(buffers[clusters].data[index / 4u][index % 4u]);
+}
+
+fn shadow(index: u32) -> vec4<f32> {
+    let shadows = bitcast<u32>(scene(SCENE_VIEWPORT).z);
+    return buffers[shadows].data[index];
+}
+
+// Depth stored in the atlas texel under `uv`, never leaving the tile `rect`
+fn shadow_depth(uv: vec2<f32>, rect: vec4<f32>) -> f32 {
+    let atlas = bitcast<u32>(shadow(0u).x);
+    let size = vec2<f32>(textureDimensions(textures[atlas]));
+    let texel = clamp(floor(uv * size), rect.xy * size, (rect.xy + rect.zw) * size - 1.0);
+    return textureLoad(textures[atlas], vec2<i32>(texel), 0).x;
+}
+
+// Distance from the light of a depth in a view with `parameters`: texel size, near, far and
+// whether the projection is perspective
+fn linear_shadow_depth(depth: f32, parameters: vec4<f32>) -> f32 {
+    let near = parameters.y;
+    let far = parameters.z;
+    if parameters.w == 0.0 {
+        return near + depth * (far - near);
+    }
+    return near * far / (far - depth * (far - near));
+}
+
+// How much of the light reaches `world` through shadow view `index`, one when outside of it
+fn shadow_visibility(index: u32, world: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let base = SHADOW_HEADER + index * SHADOW_VIEW_STRIDE;
+    let matrix = mat4x4<f32>(shadow(base), shadow(base + 1u), shadow(base + 2u), shadow(base + 3u));
+    let rect = shadow(base + 4u);
+    let parameters = shadow(base + 5u);
+
+    // Pushed out by about a texel, which grows with the distance in perspective views
+    let distance = (matrix * vec4<f32>(world, 1.0)).w;
+    let texel = select(parameters.x, parameters.x * distance, parameters.w != 0.0);
+    let clip = matrix * vec4<f32>(world + normal * texel * NORMAL_OFFSET, 1.0);
+    let ndc = clip.xyz / clip.w;
+    if any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    // Tile rows run down from the top, NDC runs up
+    let uv = rect.xy + vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * rect.zw;
+    let receiver = ndc.z;
+
+    let header = bitcast<vec4<u32>>(shadow(0u));
+    let filtering = shadow(1u);
+    var radius = filtering.x;
+    if header.y == FILTER_PCSS {
+        // Blockers are searched for as far as the widest penumbra the receiver can get
+        let receiver_distance = linear_shadow_depth(receiver, parameters);
+        var search: f32;
+        if parameters.w == 0.0 {
+            search = (receiver_distance - parameters.y) * filtering.x / parameters.x;
+        } else {
+            search = filtering.y / (parameters.x * receiver_distance);
+        }
+        search = clamp(search, 1.0, MAX_FILTER_RADIUS);
+        var blockers = 0.0;
+        var blocker_distance = 0.0;
+        for (var i = 0u; i < 16u; i++) {
+            let depth = shadow_depth(uv + POISSON[i] * search * filtering.zw, rect);
+            if depth < receiver {
+                blockers += 1.0;
+                blocker_distance += linear_shadow_depth(depth, parameters);
+            }
+        }
+        if blockers == 0.0 {
+            return 1.0;
+        }
+        blocker_distance /= blockers;
+        // The penumbra widens with the gap between blocker and receiver
+        let gap = receiver_distance - blocker_distance;
+        if parameters.w == 0.0 {
+            radius = gap * filtering.x / parameters.x;
+        } else {
+            radius = filtering.y * gap / max(blocker_distance, 1e-4) / (parameters.x * receiver_distance);
+        }
+        radius = clamp(radius, 0.5, MAX_FILTER_RADIUS);
+    }
+
+    var lit = 0.0;
+    for (var i = 0u; i < 16u; i++) {
+        let depth = shadow_depth(uv + POISSON[i] * radius * filtering.zw, rect);
+        lit += select(0.0, 1.0, receiver <= depth);
+    }
+    return lit / 16.0;
+}
+
+// Shadowing of the directional light `index`, through the cascade covering view `depth`
+fn directional_shadow(index: u32, world: vec3<f32>, normal: vec3<f32>, depth: f32) -> f32 {
+    let first = light(index, 3u).z;
+    if first < 0.0 {
+        return 1.0;
+    }
+    let cascades = bitcast<u32>(shadow(0u).z);
+    let splits = shadow(2u);
+    var cascade = 0u;
+    while cascade < cascades && depth > splits[cascade] {
+        cascade++;
+    }
+    if cascade == cascades {
+        return 1.0;
+    }
+    return shadow_visibility(u32(first) + cascade, world, normal);
+}
+
+// Shadowing of the point or spot light `index`, point lights pick the cube face `world` is on
+fn local_shadow(index: u32, world: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let first = light(index, 3u).z;
+    if first < 0.0 {
+        return 1.0;
+    }
+    var face = 0u;
+    if light(index, 1u).w == 1.0 {
+        // +X, -X, +Y, -Y, +Z, -Z, like the views are laid out
+        let d = world - light(index, 0u).xyz;
+        let a = abs(d);
+        if a.x >= a.y && a.x >= a.z {
+            face = select(1u, 0u, d.x > 0.0);
+        } else if a.y >= a.z {
+            face = select(3u, 2u, d.y > 0.0);
+        } else {
+            face = select(5u, 4u, d.z > 0.0);
+        }
+    }
+    return shadow_visibility(u32(first) + face, world, normal);
+}
+
+fn sample_material(index: u32, uv: vec2<f32>) -> vec4<f32> {
+    let slot = bitcast<u32>(material(MATERIAL_TEXTURES)[index]);
+    return textureSample(textures[slot], samplers[pc.material_sampler], uv);
+}
+
+@vertex
+fn vs_main(input: VertexInput, @builtin(instance_index) instance: u32) -> VertexOutput {
+    let base = instance * INSTANCE_STRIDE;
+    let model = mat4x4<f32>(
+        buffers[pc.instances].data[base],
+        buffers[pc.instances].data[base + 1u],
+        buffers[pc.instances].data[base + 2u],
+        buffers[pc.instances].data[base + 3u],
+    );
+    let view_projection = mat4x4<f32>(
+        scene(SCENE_VIEW_PROJECTION),
+        scene(SCENE_VIEW_PROJECTION + 1u),
+        scene(SCENE_VIEW_PROJECTION + 2u),
+        scene(SCENE_VIEW_PROJECTION + 3u),
+    );
+    let world = model * vec4<f32>(input.position, 1.0);
+    // Fine for uniform scales, skewed normals need the inverse transpose
+    let normal = (model * vec4<f32>(input.normal, 0.0)).xyz;
+    var output: VertexOutput;
+    output.clip = view_projection * world;
+    output.world = world.xyz;
+    output.normal = normal;
+    output.uv = input.uv;
+    output.tangent = vec4<f32>((model * vec4<f32>(input.tangent.xyz, 0.0)).xyz, input.tangent.w);
+    output.material = bitcast<u32>(buffers[pc.instances].data[base + 4u].y);
+    return output;
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let gv = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let gl = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return gv * gl;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+struct Surface {
+    n: vec3<f32>,
+    v: vec3<f32>,
+    base_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+}
+
+// Outgoing radiance towards the eye for light arriving from `l` with `radiance`
+fn shade(surface: Surface, l: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let n = surface.n;
+    let h = normalize(surface.v + l);
+    let n_dot_v = max(dot(n, surface.v), 1e-4);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_h = max(dot(n, h), 0.0);
+
+    let f0 = mix(vec3<f32>(0.04), surface.base_color, surface.metallic);
+    let f = fresnel_schlick(max(dot(h, surface.v), 0.0), f0);
+    let specular = distribution_ggx(n_dot_h, surface.roughness)
+        * geometry_smith(n_dot_v, n_dot_l, surface.roughness) * f
+        / (4.0 * n_dot_v * max(n_dot_l, 1e-4));
+    let diffuse = (1.0 - f) * (1.0 - surface.metallic) * surface.base_color / PI;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+// Radiance of the point or spot light `index` at `world`, falling off with the square of the
+// distance and windowed to zero at its range like KHR_lights_punctual suggests
+fn local_light(surface: Surface, index: u32, world: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let position_range = light(index, 0u);
+    let to_light = position_range.xyz - world;
+    let distance_squared = max(dot(to_light, to_light), 1e-4);
+    let l = to_light * inverseSqrt(distance_squared);
+    let window = clamp(1.0 - pow(distance_squared / (position_range.w * position_range.w), 2.0), 0.0, 1.0);
+    // Point lights have a scale of zero and an offset of one, so the cone never cuts them
+    let cone = light(index, 3u);
+    let spot = clamp(dot(light(index, 1u).xyz, -l) * cone.x + cone.y, 0.0, 1.0);
+    let attenuation = window * window * spot * spot / distance_squared;
+    if attenuation <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let visibility = local_shadow(index, world, normal);
+    return shade(surface, l, light(index, 2u).rgb * attenuation * visibility);
+}
+
+@fragment
+fn fs_main(input: VertexOutput, @builtin(front_facing) front: bool) -> @location(0) vec4<f32> {
+    material_index = input.material;
+    // Sampled before the discard, derivatives are only defined in uniform control flow
+    let base_color = material(0u) * sample_material(0u, input.uv);
+    // glTF packs roughness in green and metalness in blue
+    let metallic_roughness = sample_material(1u, input.uv);
+    let normal_sample = sample_material(2u, input.uv).xyz * 2.0 - 1.0;
+    let metallic = clamp(material(1u).x * metallic_roughness.b, 0.0, 1.0);
+    let roughness = clamp(material(1u).y * metallic_roughness.g, 0.04, 1.0);
+    let emissive = material(2u).xyz * sample_material(3u, input.uv).rgb;
+    // Zero unless the material is alpha masked
+    if base_color.a < material(1u).z {
+        discard;
+    }
+
+    var n = normalize(input.normal);
+    // Unmapped, shadow lookups are pushed out along it
+    let geometric = select(-n, n, front);
+    // Normal maps need tangents, which the loaders leave zeroed when the mesh has none
+    if input.tangent.w != 0.0 {
+        let t = normalize(input.tangent.xyz - n * dot(n, input.tangent.xyz));
+        let b = cross(n, t) * input.tangent.w;
+        n = normalize(mat3x3<f32>(t, b, n) * normal_sample);
+    }
+    // Double sided materials are drawn without culling, light their back faces too
+    if !front {
+        n = -n;
+    }
+    let surface = Surface(
+        n,
+        normalize(scene(SCENE_CAMERA).xyz - input.world),
+        base_color.rgb,
+        metallic,
+        roughness,
+    );
+
+    let view = mat4x4<f32>(
+        scene(SCENE_VIEW),
+        scene(SCENE_VIEW + 1u),
+        scene(SCENE_VIEW + 2u),
+        scene(SCENE_VIEW + 3u),
+    );
+    let depth = -(view * vec4<f32>(input.world, 1.0)).z;
+
+    var color = vec3<f32>(0.0);
+    let header = bitcast<vec4<u32>>(scene(SCENE_LIGHTS));
+    for (var index = 0u; index < header.z; index++) {
+        let visibility = directional_shadow(index, input.world, geometric, depth);
+        color += shade(surface, -light(index, 1u).xyz, light(index, 2u).rgb * visibility);
+    }
+
+    // The cluster is picked like `shaders/lights.wgsl` builds them: tiles of the viewport,
+    // exponential slices of the view depth. Tiles count up from the bottom like NDC does,
+    // framebuffer rows count down from the top
+    let projection = scene(SCENE_PROJECTION);
+    let slice = log(max(depth, projection.z) / projection.z) / log(projection.w / projection.z);
+    let clusters = vec3<u32>(CLUSTERS_X, CLUSTERS_Y, CLUSTERS_Z);
+    let screen = input.clip.xy / scene(SCENE_VIEWPORT).xy;
+    let coordinate = vec3<f32>(screen.x, 1.0 - screen.y, slice) * vec3<f32>(clusters);
+    let cluster = min(vec3<u32>(max(coordinate, vec3<f32>(0.0))), clusters - 1u);
+    let base = ((cluster.z * CLUSTERS_Y + cluster.y) * CLUSTERS_X + cluster.x) * CLUSTER_STRIDE;
+    let count = cluster_word(base);
+    for (var i = 0u; i < count; i++) {
+        color += local_light(surface, cluster_word(base + 1u + i), input.world, geometric);
+    }
+
+    // Hemisphere ambient, so unlit sides aren't pitch black
+    let up = n.y * 0.5 + 0.5;
+    let ambient = mix(scene(SCENE_AMBIENT).rgb * 0.5, scene(SCENE_AMBIENT).rgb, up) * base_color.rgb;
+
+    return vec4<f32>(color + ambient + emissive, base_color.a);
+}
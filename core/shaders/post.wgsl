@@ -0,0 +1,496 @@
+// Full screen passes of the post-processing chain, see `src/vk/post.rs`: the bloom down and up
+// samples, tonemapping with exposure, bloom and color grading, then FXAA or the three passes of
+// SMAA. Every pass reads its inputs through the bindless set with one linear clamped sampler.
+
+struct PushConstants {
+    // Sampled image slots of the pass input, the bloom chain and the grading LUT
+    source: u32,
+    bloom: u32,
+    lut: u32,
+    post_sampler: u32,
+    // Storage buffer slot of the histogram and adapted luminance
+    exposure: u32,
+    flags: u32,
+    tonemapper: u32,
+    // Texels along each side of one LUT slice
+    lut_size: u32,
+    // Texel size of `source`
+    texel: vec2<f32>,
+    // Multiplier of the scene colors, on top of auto exposure
+    exposure_scale: f32,
+    bloom_intensity: f32,
+    // In nits, where SDR white and the brightest highlights end up on an HDR display
+    paper_white: f32,
+    peak_brightness: f32,
+    // Sampled image slot of the SMAA blend weights
+    smaa: u32,
+}
+
+// Raw words, floats are bitcast from them
+struct Words {
+    data: array<u32>,
+}
+
+@group(0) @binding(0) var textures: binding_array<texture_2d<f32>>;
+@group(0) @binding(2) var<storage, read> buffers: binding_array<Words>;
+@group(0) @binding(3) var samplers: binding_array<sampler>;
+
+var<push_constant> pc: PushConstants;
+
+// Flags, matching `src/vk/post.rs`
+const AUTO_EXPOSURE: u32 = 1u;
+const BLOOM: u32 = 2u;
+const GRADING: u32 = 4u;
+// The target isn't an sRGB format, so the shader encodes what it writes
+const ENCODE_SRGB: u32 = 8u;
+// First bloom downsample, bright single pixels are tamed so they don't flicker
+const KARIS_AVERAGE: u32 = 16u;
+// The display shows HDR, highlights roll off towards its peak instead of white
+const HDR_OUTPUT: u32 = 32u;
+// HDR10 and scRGB targets, encoded from linear Rec. 709 where 1.0 is paper white
+const ENCODE_PQ: u32 = 64u;
+const ENCODE_SCRGB: u32 = 128u;
+
+const TONEMAP_NONE: u32 = 0u;
+const TONEMAP_ACES: u32 = 1u;
+const TONEMAP_AGX: u32 = 2u;
+
+// Where the adapted luminance follows the bins, see `shaders/exposure.wgsl`
+const ADAPTED_LUMINANCE: u32 = 256u;
+// Middle gray the average luminance is exposed to
+const KEY: f32 = 0.18;
+// In nits, what 1.0 means in scRGB and the most the ST 2084 curve encodes
+const SCRGB_WHITE: f32 = 80.0;
+const PQ_MAX: f32 = 10000.0;
+
+const LUMA: vec3<f32> = vec3<f32>(0.2126, 0.7152, 0.0722);
+
+struct VertexOutput {
+    @builtin(position) clip: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the screen, no vertex buffer needed. Texture rows run down from the top
+// while NDC runs up, so v is flipped or every pass would mirror the image
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var output: VertexOutput;
+    output.clip = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    output.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return output;
+}
+
+fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(textures[pc.source], samplers[pc.post_sampler], uv, 0.0).rgb;
+}
+
+fn karis_weight(color: vec3<f32>) -> f32 {
+    return 1.0 / (1.0 + dot(color, LUMA));
+}
+
+// The 13 tap downsample of Call of Duty: Advanced Warfare, as five overlapping boxes
+@fragment
+fn fs_bloom_down(input: VertexOutput) -> @location(0) vec4<f32> {
+    let t = pc.texel;
+    let a = sample_source(input.uv + t * vec2<f32>(-2.0, -2.0));
+    let b = sample_source(input.uv + t * vec2<f32>(0.0, -2.0));
+    let c = sample_source(input.uv + t * vec2<f32>(2.0, -2.0));
+    let d = sample_source(input.uv + t * vec2<f32>(-2.0, 0.0));
+    let e = sample_source(input.uv);
+    let f = sample_source(input.uv + t * vec2<f32>(2.0, 0.0));
+    let g = sample_source(input.uv + t * vec2<f32>(-2.0, 2.0));
+    let h = sample_source(input.uv + t * vec2<f32>(0.0, 2.0));
+    let i = sample_source(input.uv + t * vec2<f32>(2.0, 2.0));
+    let j = sample_source(input.uv + t * vec2<f32>(-1.0, -1.0));
+    let k = sample_source(input.uv + t * vec2<f32>(1.0, -1.0));
+    let l = sample_source(input.uv + t * vec2<f32>(-1.0, 1.0));
+    let m = sample_source(input.uv + t * vec2<f32>(1.0, 1.0));
+
+    let boxes = array<vec3<f32>, 5>(
+        (j + k + l + m) * 0.25,
+        (a + b + d + e) * 0.25,
+        (b + c + e + f) * 0.25,
+        (d + e + g + h) * 0.25,
+        (e + f + h + i) * 0.25,
+    );
+    let weights = array<f32, 5>(0.5, 0.125, 0.125, 0.125, 0.125);
+    var color = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var index = 0u; index < 5u; index++) {
+        var weight = weights[index];
+        if (pc.flags & KARIS_AVERAGE) != 0u {
+            weight *= karis_weight(boxes[index]);
+        }
+        color += boxes[index] * weight;
+        total += weight;
+    }
+    return vec4<f32>(color / total, 1.0);
+}
+
+// 3x3 tent, blended onto the level below so every level adds up on the way back
+@fragment
+fn fs_bloom_up(input: VertexOutput) -> @location(0) vec4<f32> {
+    let t = pc.texel;
+    var color = sample_source(input.uv) * 4.0;
+    color += (sample_source(input.uv + t * vec2<f32>(-1.0, 0.0))
+        + sample_source(input.uv + t * vec2<f32>(1.0, 0.0))
+        + sample_source(input.uv + t * vec2<f32>(0.0, -1.0))
+        + sample_source(input.uv + t * vec2<f32>(0.0, 1.0))) * 2.0;
+    color += sample_source(input.uv + t * vec2<f32>(-1.0, -1.0))
+        + sample_source(input.uv + t * vec2<f32>(1.0, -1.0))
+        + sample_source(input.uv + t * vec2<f32>(-1.0, 1.0))
+        + sample_source(input.uv + t * vec2<f32>(1.0, 1.0));
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+// Stephen Hill's fit of the ACES RRT and ODT, with the sRGB to ACEScg round trip around it
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input_matrix = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output_matrix = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+    let v = input_matrix * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output_matrix * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// AgX with the default look, after Benjamin Wrensch's polynomial fit of the contrast curve
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var v = inset * color;
+    v = clamp(log2(max(v, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+    let v2 = v * v;
+    let v4 = v2 * v2;
+    v = 15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.00232;
+    v = outset * v;
+    return pow(max(v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn srgb_encode(color: vec3<f32>) -> vec3<f32> {
+    let c = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+fn srgb_decode(color: vec3<f32>) -> vec3<f32> {
+    return select(pow((color + 0.055) / 1.055, vec3<f32>(2.4)), color / 12.92, color <= vec3<f32>(0.04045));
+}
+
+// Looks up an encoded color in the LUT strip, blending between the two nearest blue slices
+fn grade(color: vec3<f32>) -> vec3<f32> {
+    let size = f32(pc.lut_size);
+    let blue = clamp(color.b, 0.0, 1.0) * (size - 1.0);
+    let slice = floor(blue);
+    let next = min(slice + 1.0, size - 1.0);
+    let uv = (clamp(color.rg, vec2<f32>(0.0), vec2<f32>(1.0)) * (size - 1.0) + 0.5) / vec2<f32>(size * size, size);
+    let low = textureSampleLevel(textures[pc.lut], samplers[pc.post_sampler], uv + vec2<f32>(slice / size, 0.0), 0.0).rgb;
+    let high = textureSampleLevel(textures[pc.lut], samplers[pc.post_sampler], uv + vec2<f32>(next / size, 0.0), 0.0).rgb;
+    return mix(low, high, blue - slice);
+}
+
+// Leaves everything up to paper white alone and rolls what's brighter off towards `peak`,
+// scaling the channels together so hues don't shift
+fn hdr_rolloff(color: vec3<f32>, peak: f32) -> vec3<f32> {
+    let brightest = max(color.r, max(color.g, color.b));
+    let knee = min(1.0, peak * 0.5);
+    if brightest <= knee {
+        return max(color, vec3<f32>(0.0));
+    }
+    let over = brightest - knee;
+    let rolled = knee + over / (1.0 + over / (peak - knee));
+    return max(color * (rolled / brightest), vec3<f32>(0.0));
+}
+
+// SMPTE ST 2084, from nits over its 10000 nit range
+fn pq_encode(color: vec3<f32>) -> vec3<f32> {
+    let m1 = 0.1593017578125;
+    let m2 = 78.84375;
+    let c1 = 0.8359375;
+    let c2 = 18.8515625;
+    let c3 = 18.6875;
+    let y = pow(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3<f32>(m2));
+}
+
+fn output(color: vec3<f32>) -> vec4<f32> {
+    if (pc.flags & ENCODE_SRGB) != 0u {
+        return vec4<f32>(srgb_encode(color), 1.0);
+    }
+    if (pc.flags & ENCODE_PQ) != 0u {
+        let rec709_to_rec2020 = mat3x3<f32>(
+            vec3<f32>(0.627404, 0.069097, 0.016391),
+            vec3<f32>(0.329283, 0.919541, 0.088013),
+            vec3<f32>(0.043313, 0.011362, 0.895595),
+        );
+        let nits = rec709_to_rec2020 * color * pc.paper_white;
+        return vec4<f32>(pq_encode(nits / PQ_MAX), 1.0);
+    }
+    if (pc.flags & ENCODE_SCRGB) != 0u {
+        return vec4<f32>(color * (pc.paper_white / SCRGB_WHITE), 1.0);
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_tonemap(input: VertexOutput) -> @location(0) vec4<f32> {
+    var color = sample_source(input.uv);
+    if (pc.flags & BLOOM) != 0u {
+        let bloom = textureSampleLevel(textures[pc.bloom], samplers[pc.post_sampler], input.uv, 0.0).rgb;
+        color = mix(color, bloom, pc.bloom_intensity);
+    }
+    var exposure = pc.exposure_scale;
+    if (pc.flags & AUTO_EXPOSURE) != 0u {
+        let adapted = bitcast<f32>(buffers[pc.exposure].data[ADAPTED_LUMINANCE]);
+        exposure *= KEY / max(adapted, 1e-4);
+    }
+    color *= exposure;
+
+    if (pc.flags & HDR_OUTPUT) != 0u {
+        let peak = pc.peak_brightness / pc.paper_white;
+        if pc.tonemapper == TONEMAP_NONE {
+            return output(clamp(color, vec3<f32>(0.0), vec3<f32>(peak)));
+        }
+        return output(hdr_rolloff(color, peak));
+    }
+    switch pc.tonemapper {
+        case TONEMAP_ACES: {
+            color = aces(color);
+        }
+        case TONEMAP_AGX: {
+            color = agx(color);
+        }
+        default: {
+            color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+    if (pc.flags & GRADING) != 0u {
+        color = srgb_decode(grade(srgb_encode(color)));
+    }
+    return output(color);
+}
+
+// Perceptual luma, the tonemapped input is read back linear from its sRGB image
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, LUMA));
+}
+
+// FXAA along the lines of Timothy Lottes' console version: blur along the edge direction,
+// falling back to the narrower blur when the wider one overshoots the local contrast
+@fragment
+fn fs_fxaa(input: VertexOutput) -> @location(0) vec4<f32> {
+    let t = pc.texel;
+    let nw = luma(sample_source(input.uv + t * vec2<f32>(-1.0, -1.0)));
+    let ne = luma(sample_source(input.uv + t * vec2<f32>(1.0, -1.0)));
+    let sw = luma(sample_source(input.uv + t * vec2<f32>(-1.0, 1.0)));
+    let se = luma(sample_source(input.uv + t * vec2<f32>(1.0, 1.0)));
+    let center = sample_source(input.uv);
+    let m = luma(center);
+    let luma_min = min(m, min(min(nw, ne), min(sw, se)));
+    let luma_max = max(m, max(max(nw, ne), max(sw, se)));
+
+    var direction = vec2<f32>(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+    let reduce = max((nw + ne + sw + se) * 0.25 * (1.0 / 8.0), 1.0 / 128.0);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-8.0), vec2<f32>(8.0)) * t;
+
+    let narrow = 0.5 * (sample_source(input.uv + direction * (1.0 / 3.0 - 0.5))
+        + sample_source(input.uv + direction * (2.0 / 3.0 - 0.5)));
+    let wide = narrow * 0.5 + 0.25 * (sample_source(input.uv - direction * 0.5)
+        + sample_source(input.uv + direction * 0.5));
+    let wide_luma = luma(wide);
+    if wide_luma < luma_min || wide_luma > luma_max {
+        return output(narrow);
+    }
+    return output(wide);
+}
+
+// SMAA 1x after Jimenez et al. The area and search textures of the reference implementation are
+// replaced by a texel by texel search and the coverage computed from the line the edge shape
+// stands for, which handles horizontal and vertical shapes but not diagonal ones
+const SMAA_THRESHOLD: f32 = 0.1;
+// Edges this many times weaker than the strongest one next to them are dropped
+const SMAA_CONTRAST_ADAPTATION: f32 = 2.0;
+// In pixels, along each side of the pixel an edge shape is searched for
+const SMAA_MAX_SEARCH: i32 = 16;
+
+fn luma_at(pixel: vec2<i32>) -> f32 {
+    let last = vec2<i32>(textureDimensions(textures[pc.source])) - 1;
+    return luma(textureLoad(textures[pc.source], clamp(pixel, vec2<i32>(0), last), 0).rgb);
+}
+
+// Edges on the left and on the top of every pixel, in red and green
+@fragment
+fn fs_smaa_edges(input: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(input.clip.xy);
+    let center = luma_at(pixel);
+    let left = luma_at(pixel + vec2<i32>(-1, 0));
+    let top = luma_at(pixel + vec2<i32>(0, -1));
+    let delta = abs(center - vec2<f32>(left, top));
+    var edges = step(vec2<f32>(SMAA_THRESHOLD), delta);
+    if edges.x + edges.y == 0.0 {
+        return vec4<f32>(0.0);
+    }
+    // Local contrast adaptation, a strong edge nearby hides a weak one
+    let right = abs(center - luma_at(pixel + vec2<i32>(1, 0)));
+    let bottom = abs(center - luma_at(pixel + vec2<i32>(0, 1)));
+    let left_left = abs(left - luma_at(pixel + vec2<i32>(-2, 0)));
+    let top_top = abs(top - luma_at(pixel + vec2<i32>(0, -2)));
+    let strongest = max(max(max(delta.x, delta.y), max(right, bottom)), max(left_left, top_top));
+    edges *= step(vec2<f32>(strongest), SMAA_CONTRAST_ADAPTATION * delta);
+    return vec4<f32>(edges, 0.0, 1.0);
+}
+
+// Nothing lies past the borders of the image
+fn texel_or_zero(slot: u32, pixel: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(textures[slot]));
+    if any(pixel < vec2<i32>(0)) || any(pixel >= size) {
+        return vec4<f32>(0.0);
+    }
+    return textureLoad(textures[slot], pixel, 0);
+}
+
+fn edge(pixel: vec2<i32>) -> vec2<f32> {
+    return texel_or_zero(pc.source, pixel).rg;
+}
+
+// Edges crossing the one being followed where `pixel` starts along it, on the side of the pixel
+// and then on the side of its neighbour at `across`
+fn smaa_crossings(pixel: vec2<i32>, across: vec2<i32>, crossing: u32) -> vec2<f32> {
+    return vec2<f32>(edge(pixel)[crossing], edge(pixel + across)[crossing]);
+}
+
+// How the line from `start` to `end` covers the pixel spanning [x, x + 1] along the edge, where
+// the edge is at zero and negative is on the side of the pixel. What's on its side is how much
+// it blends towards the neighbour, what's on the neighbour's side how much that blends towards it
+fn smaa_area(start: vec2<f32>, end: vec2<f32>, x: f32) -> vec2<f32> {
+    if x + 1.0 <= start.x || x >= end.x {
+        return vec2<f32>(0.0);
+    }
+    let slope = (end.y - start.y) / (end.x - start.x);
+    let y1 = start.y + slope * (x - start.x);
+    let y2 = start.y + slope * (x + 1.0 - start.x);
+    if sign(y1) == sign(y2) || abs(y1) < 1e-4 || abs(y2) < 1e-4 {
+        let area = (y1 + y2) * 0.5;
+        return vec2<f32>(max(-area, 0.0), max(area, 0.0));
+    }
+    // The line crosses the edge inside the pixel, a triangle on either side, as far as the line goes
+    let crossing = start.x - start.y / slope;
+    let a1 = select(0.0, y1 * (crossing - x) * 0.5, crossing > start.x);
+    let a2 = select(0.0, y2 * (x + 1.0 - crossing) * 0.5, crossing < end.x);
+    return vec2<f32>(max(-a1, 0.0) + max(-a2, 0.0), max(a1, 0.0) + max(a2, 0.0));
+}
+
+// The weights of the pixel `before` pixels from the start of an edge `before + after + 1` long.
+// The steps at either end are half a pixel towards the side a crossing edge is on, or zero
+fn smaa_coverage(before: f32, after: f32, start_step: f32, end_step: f32) -> vec2<f32> {
+    let length = before + after + 1.0;
+    let start = vec2<f32>(0.0, start_step);
+    let middle = vec2<f32>(length * 0.5, 0.0);
+    let end = vec2<f32>(length, end_step);
+    if start_step == 0.0 && end_step == 0.0 {
+        return vec2<f32>(0.0);
+    }
+    // L shapes, the line runs from the step to the middle and the rest stays as is
+    if end_step == 0.0 {
+        return select(vec2<f32>(0.0), smaa_area(start, middle, before), before <= after);
+    }
+    if start_step == 0.0 {
+        return select(vec2<f32>(0.0), smaa_area(middle, end, before), before >= after);
+    }
+    // U shapes bend back to the middle, Z shapes go straight from step to step
+    if start_step == end_step {
+        return smaa_area(start, middle, before) + smaa_area(middle, end, before);
+    }
+    return smaa_area(start, end, before);
+}
+
+// Follows the edge between `pixel` and its neighbour at `across` along `along` both ways, until
+// it ends or another edge crosses it, and weighs the pixel by the shape that makes
+fn smaa_edge_weights(pixel: vec2<i32>, along: vec2<i32>, across: vec2<i32>, component: u32) -> vec2<f32> {
+    let crossing = 1u - component;
+    var before = 0;
+    for (; before < SMAA_MAX_SEARCH; before++) {
+        let end = pixel - along * before;
+        let crossings = smaa_crossings(end, across, crossing);
+        if crossings.x + crossings.y > 0.0 || edge(end - along)[component] == 0.0 {
+            break;
+        }
+    }
+    var after = 0;
+    for (; after < SMAA_MAX_SEARCH; after++) {
+        let end = pixel + along * after;
+        let crossings = smaa_crossings(end + along, across, crossing);
+        if crossings.x + crossings.y > 0.0 || edge(end + along)[component] == 0.0 {
+            break;
+        }
+    }
+    let start = smaa_crossings(pixel - along * before, across, crossing);
+    let end = smaa_crossings(pixel + along * (after + 1), across, crossing);
+    // Crossings on both sides make no step
+    let start_step = (start.y - start.x) * 0.5;
+    let end_step = (end.y - end.x) * 0.5;
+    return smaa_coverage(f32(before), f32(after), start_step, end_step);
+}
+
+// Towards and from the neighbour above in red and green, towards and from the one on the left
+// in blue and alpha
+@fragment
+fn fs_smaa_weights(input: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(input.clip.xy);
+    let edges = edge(pixel);
+    var weights = vec4<f32>(0.0);
+    if edges.y > 0.0 {
+        weights = vec4<f32>(smaa_edge_weights(pixel, vec2<i32>(1, 0), vec2<i32>(0, -1), 1u), weights.zw);
+    }
+    if edges.x > 0.0 {
+        weights = vec4<f32>(weights.xy, smaa_edge_weights(pixel, vec2<i32>(0, 1), vec2<i32>(-1, 0), 0u));
+    }
+    return weights;
+}
+
+// Blends towards the neighbours the weights ask for, the ones below and to the right keep what
+// this pixel blends by in their own weights
+@fragment
+fn fs_smaa_blend(input: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(input.clip.xy);
+    let own = texel_or_zero(pc.smaa, pixel);
+    let top = own.r;
+    let left = own.b;
+    let right = texel_or_zero(pc.smaa, pixel + vec2<i32>(1, 0)).a;
+    let bottom = texel_or_zero(pc.smaa, pixel + vec2<i32>(0, 1)).g;
+    if top + left + right + bottom < 1e-5 {
+        return output(sample_source(input.uv));
+    }
+    // Only along the stronger direction, the linear sampler mixes in as much of the neighbour
+    let t = pc.texel;
+    var weights = vec2<f32>(top, bottom);
+    var first = vec2<f32>(0.0, -t.y * top);
+    var second = vec2<f32>(0.0, t.y * bottom);
+    if max(left, right) > max(top, bottom) {
+        weights = vec2<f32>(left, right);
+        first = vec2<f32>(-t.x * left, 0.0);
+        second = vec2<f32>(t.x * right, 0.0);
+    }
+    weights /= weights.x + weights.y;
+    let color = weights.x * sample_source(input.uv + first) + weights.y * sample_source(input.uv + second);
+    return output(color);
+}
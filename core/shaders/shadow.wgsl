@@ -0,0 +1,46 @@
+// Depth-only rendering into one tile of the shadow atlas. The view matrices live in the shadow
+// buffer, see `src/vk/shadows.rs`, the push constants pick which one. Model matrices come from the
+// instance table, see `shaders/mesh.wgsl`.
+
+struct PushConstants {
+    // Storage buffer slot of the shadow constants, and the view drawn into
+    shadows: u32,
+    view: u32,
+    instances: u32,
+    _pad: u32,
+}
+
+// Every storage buffer is viewed as a flat array of vec4s
+struct Vec4s {
+    data: array<vec4<f32>>,
+}
+
+@group(0) @binding(2) var<storage, read> buffers: binding_array<Vec4s>;
+
+var<push_constant> pc: PushConstants;
+
+// In vec4s, the header rows come before the views
+const SHADOW_HEADER: u32 = 3u;
+const SHADOW_VIEW_STRIDE: u32 = 6u;
+const INSTANCE_STRIDE: u32 = 5u;
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @builtin(instance_index) instance: u32,
+) -> @builtin(position) vec4<f32> {
+    let base = SHADOW_HEADER + pc.view * SHADOW_VIEW_STRIDE;
+    let view_projection = mat4x4<f32>(
+        buffers[pc.shadows].data[base],
+        buffers[pc.shadows].data[base + 1u],
+        buffers[pc.shadows].data[base + 2u],
+        buffers[pc.shadows].data[base + 3u],
+    );
+    let model = mat4x4<f32>(
+        buffers[pc.instances].data[instance * INSTANCE_STRIDE],
+        buffers[pc.instances].data[instance * INSTANCE_STRIDE + 1u],
+        buffers[pc.instances].data[instance * INSTANCE_STRIDE + 2u],
+        buffers[pc.instances].data[instance * INSTANCE_STRIDE + 3u],
+    );
+    return view_projection * model * vec4<f32>(position, 1.0);
+}
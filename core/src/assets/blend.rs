@@ -0,0 +1,682 @@
+#![cfg(feature = "blend_usage")]
+
+use {
+    crate::assets::{
+        AlphaMode, Camera, Light, LightKind, Material, Mesh, Node, Primitive, Projection, Scene,
+        SceneError, Vertex,
+    },
+    blend::{Blend, Instance},
+    error_stack::{Report, ResultExt},
+    glam::{Mat4, Quat, Vec3, Vec4},
+    std::{
+        collections::HashMap,
+        f32::consts::{FRAC_PI_2, PI},
+        panic::{self, AssertUnwindSafe},
+        path::Path,
+    },
+};
+
+// Object types, from DNA_object_types.h
+const OB_EMPTY: i16 = 0;
+const OB_MESH: i16 = 1;
+const OB_LAMP: i16 = 10;
+const OB_CAMERA: i16 = 11;
+// Parented to the whole object rather than a bone or vertices
+const PAROBJECT: i16 = 0;
+// CustomData layer types, from DNA_customdata_types.h
+const CD_PROP_INT32: i32 = 11;
+const CD_MLOOPUV: i32 = 16;
+const CD_CUSTOMLOOPNORMAL: i32 = 41;
+const CD_PROP_FLOAT3: i32 = 48;
+const CD_PROP_FLOAT2: i32 = 49;
+const CD_PROP_BOOL: i32 = 50;
+// MPoly::flag
+const ME_SMOOTH: i8 = 1;
+// Material::blend_method and Material::blend_flag
+const MA_BM_CLIP: i8 = 3;
+const MA_BM_HASHED: i8 = 4;
+const MA_BM_BLEND: i8 = 5;
+const MA_BL_CULL_BACKFACE: i8 = 1 << 2;
+// Light::type
+const LA_LOCAL: i16 = 0;
+const LA_SUN: i16 = 1;
+const LA_SPOT: i16 = 2;
+// Camera::type and Camera::sensor_fit
+const CAM_ORTHO: i8 = 1;
+const CAM_PANO: i8 = 2;
+const CAMERA_SENSOR_FIT_HOR: i8 = 1;
+const CAMERA_SENSOR_FIT_VERT: i8 = 2;
+// The luminous efficacy glTF exporters assume to turn watts into lumens
+const LUMENS_PER_WATT: f32 = 683.0;
+
+/// Loads the objects of an uncompressed `.blend` file, converted from Blender's Z up to glTF's Y up.
+pub(crate) fn load(path: &Path) -> Result<Scene, Report<SceneError>> {
+    let attach_path = || format!("path: {}", path.display());
+    let bytes = std::fs::read(path)
+        .map_err(|x| Report::new(SceneError::Open).attach(format!("{x}")))
+        .attach_with(attach_path)?;
+    if !bytes.starts_with(b"BLENDER") {
+        let report =
+            if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) || bytes.starts_with(&[0x1f, 0x8b]) {
+                Report::new(SceneError::UnsupportedFormat)
+                    .attach("compressed .blend files are not supported, save with compression off")
+            } else {
+                Report::new(SceneError::InvalidFile).attach("missing the BLENDER header")
+            };
+        return Err(report.attach(attach_path()));
+    }
+    // The blend crate panics on data it doesn't expect instead of returning errors
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let blend = Blend::new(&bytes[..])
+            .map_err(|x| Report::new(SceneError::InvalidFile).attach(format!("{x:?}")))?;
+        Ok(Importer::default().import(&blend))
+    }))
+    .unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| payload.downcast_ref::<&str>().copied())
+            .unwrap_or("unknown panic");
+        Err(Report::new(SceneError::InvalidFile).attach(format!("blend: {message}")))
+    })
+    .attach_with(attach_path)
+}
+
+#[derive(Default)]
+struct Importer {
+    scene: Scene,
+    // Material index by name
+    materials: HashMap<String, usize>,
+    // Primitives of every slot by mesh name, before materials are assigned
+    mesh_data: HashMap<String, Vec<Primitive>>,
+    // Mesh index by mesh name and the materials of its slots
+    meshes: HashMap<(String, Vec<Option<usize>>), usize>,
+}
+
+impl Importer {
+    fn import(mut self, blend: &Blend) -> Scene {
+        let aspect_ratio = blend
+            .instances_with_code(*b"SC")
+            .next()
+            .map(|scene| {
+                let render = scene.get("r");
+                let width = render.get_i32("xsch") as f32 * render.get_f32("xasp");
+                let height = render.get_i32("ysch") as f32 * render.get_f32("yasp");
+                width / height
+            })
+            .filter(|x| x.is_finite() && *x > 0.0)
+            .unwrap_or(16.0 / 9.0);
+
+        let objects: Vec<Instance> = blend.instances_with_code(*b"OB").collect();
+        let names: Vec<String> = objects.iter().map(id_name).collect();
+        let index: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(x, name)| (name.as_str(), x))
+            .collect();
+        let mut parents = vec![None; objects.len()];
+        for (x, object) in objects.iter().enumerate() {
+            let name = &names[x];
+            let mut node = Node {
+                name: Some(name.clone()),
+                transform: self.transform(object, name),
+                ..Default::default()
+            };
+            match object.get_i16("type") {
+                OB_EMPTY => {}
+                OB_MESH if object.is_valid("data") => {
+                    node.mesh = Some(self.mesh(object, name));
+                }
+                OB_LAMP if object.is_valid("data") => {
+                    node.light = self.light(&object.get("data"));
+                }
+                OB_CAMERA if object.is_valid("data") => {
+                    node.camera = Some(self.camera(&object.get("data"), aspect_ratio));
+                }
+                OB_MESH | OB_LAMP | OB_CAMERA => {}
+                kind => self.report(format!(
+                    "object {name} is {}, which is not imported",
+                    object_kind(kind)
+                )),
+            }
+            if object.is_valid("modifiers") {
+                let count = object.get_iter("modifiers").count();
+                self.report(format!("object {name}: {count} modifiers are not applied"));
+            }
+            if object.is_valid("instance_collection") {
+                self.report(format!(
+                    "object {name}: collection instances are not expanded"
+                ));
+            }
+            if object.is_valid("adt") {
+                self.report(format!("object {name}: animation is ignored"));
+            }
+            if object.is_valid("parent") {
+                if object.get_i16("partype") != PAROBJECT {
+                    self.report(format!(
+                        "object {name}: parented to a bone or vertices, treated as parented to the object"
+                    ));
+                }
+                parents[x] = index.get(id_name(&object.get("parent")).as_str()).copied();
+            }
+            self.scene.nodes.push(node);
+        }
+        for (child, parent) in parents.iter().enumerate() {
+            if let Some(parent) = parent {
+                self.scene.nodes[*parent].children.push(child);
+            }
+        }
+
+        // Everything hangs off one node turning Z up into Y up
+        self.scene.roots.push(self.scene.nodes.len());
+        self.scene.nodes.push(Node {
+            name: Some("Blender Z up".to_string()),
+            transform: Mat4::from_rotation_x(-FRAC_PI_2),
+            children: (0..objects.len())
+                .filter(|&x| parents[x].is_none())
+                .collect(),
+            ..Default::default()
+        });
+        self.scene
+    }
+
+    fn report(&mut self, note: String) {
+        self.scene.unsupported.push(note);
+    }
+
+    /// The object's transform relative to its parent.
+    fn transform(&mut self, object: &Instance, name: &str) -> Mat4 {
+        let vec3 = |field: &str| Vec3::from_slice(&object.get_f32_vec(field));
+        let mode = object.get_i16("rotmode");
+        let rotation = |euler: &str, quat: &str, axis: &str, angle: &str| {
+            let quat = object.get_f32_vec(quat);
+            rotation(
+                mode,
+                vec3(euler),
+                Quat::from_xyzw(quat[1], quat[2], quat[3], quat[0]),
+                vec3(axis),
+                object.get_f32(angle),
+            )
+        };
+        let (Some(delta), Some(rotation)) = (
+            rotation("drot", "dquat", "drotAxis", "drotAngle"),
+            rotation("rot", "quat", "rotAxis", "rotAngle"),
+        ) else {
+            self.report(format!(
+                "object {name}: rotation mode {mode} is unknown, the rotation is ignored"
+            ));
+            return Mat4::from_translation(vec3("loc") + vec3("dloc"))
+                * Mat4::from_scale(vec3("size") * vec3("dscale"));
+        };
+        let local = Mat4::from_scale_rotation_translation(
+            vec3("size") * vec3("dscale"),
+            delta * rotation,
+            vec3("loc") + vec3("dloc"),
+        );
+        // Blender keeps the parent's inverse transform at the time of parenting
+        Mat4::from_cols_array_2d(&matrix(&object.get_f32_vec("parentinv"))) * local
+    }
+
+    fn mesh(&mut self, object: &Instance, name: &str) -> usize {
+        let mesh = object.get("data");
+        let mesh_name = id_name(&mesh);
+        if !self.mesh_data.contains_key(&mesh_name) {
+            let primitives = self.mesh_primitives(&mesh, &mesh_name);
+            self.mesh_data.insert(mesh_name.clone(), primitives);
+        }
+
+        // Each slot takes its material from the mesh, or from the object when its bit is set
+        let slots = self.mesh_data[&mesh_name].len();
+        let mesh_materials = self.slot_materials(&mesh, "mat", slots);
+        let object_materials = self.slot_materials(object, "mat", slots);
+        let bits = match object.is_valid("matbits") {
+            true => object.get_i8_vec("matbits"),
+            false => Vec::new(),
+        };
+        let materials: Vec<Option<usize>> = (0..slots)
+            .map(|x| match bits.get(x) {
+                Some(&bit) if bit != 0 => object_materials[x],
+                _ => mesh_materials[x],
+            })
+            .collect();
+        if mesh.get_i16("totcol") > 0 && materials.contains(&None) {
+            self.report(format!(
+                "object {name}: empty material slots use the default material"
+            ));
+        }
+
+        let key = (mesh_name, materials);
+        if let Some(&index) = self.meshes.get(&key) {
+            return index;
+        }
+        let index = self.scene.meshes.len();
+        let primitives = self.mesh_data[&key.0]
+            .iter()
+            .zip(&key.1)
+            .filter(|(primitive, _)| !primitive.indices.is_empty())
+            .map(|(primitive, &material)| Primitive {
+                material,
+                ..primitive.clone()
+            })
+            .collect();
+        self.scene.meshes.push(Mesh {
+            name: Some(key.0.clone()),
+            primitives,
+        });
+        self.meshes.insert(key, index);
+        index
+    }
+
+    /// The materials in the `field` slots of `instance`, None where a slot is empty.
+    fn slot_materials(
+        &mut self,
+        instance: &Instance,
+        field: &str,
+        slots: usize,
+    ) -> Vec<Option<usize>> {
+        // A single empty slot makes the whole list unreadable
+        if !instance.is_valid(field) {
+            return vec![None; slots];
+        }
+        let mut materials: Vec<Option<usize>> = instance
+            .get_iter(field)
+            .map(|material| Some(self.material(&material)))
+            .collect();
+        materials.resize(slots, None);
+        materials
+    }
+
+    fn material(&mut self, material: &Instance) -> usize {
+        let name = id_name(material);
+        if let Some(&index) = self.materials.get(&name) {
+            return index;
+        }
+        let f32_or = |field: &str, default: f32| match material.is_valid(field) {
+            true => material.get_f32(field),
+            false => default,
+        };
+        let i8_or = |field: &str| match material.is_valid(field) {
+            true => material.get_i8(field),
+            false => 0,
+        };
+        if i8_or("use_nodes") != 0 {
+            self.report(format!(
+                "material {name}: shader nodes are ignored, only the viewport display color, metallic and roughness are imported"
+            ));
+        }
+        let alpha_mode = match i8_or("blend_method") {
+            MA_BM_CLIP => AlphaMode::Mask,
+            MA_BM_HASHED | MA_BM_BLEND => AlphaMode::Blend,
+            _ => AlphaMode::Opaque,
+        };
+        let index = self.scene.materials.len();
+        self.scene.materials.push(Material {
+            name: Some(name.clone()),
+            base_color: Vec4::new(
+                f32_or("r", 0.8),
+                f32_or("g", 0.8),
+                f32_or("b", 0.8),
+                f32_or("a", 1.0),
+            ),
+            metallic: f32_or("metallic", 0.0),
+            roughness: f32_or("roughness", 0.4),
+            alpha_mode,
+            alpha_cutoff: f32_or("alpha_threshold", 0.5),
+            double_sided: i8_or("blend_flag") & MA_BL_CULL_BACKFACE == 0,
+            ..Default::default()
+        });
+        self.materials.insert(name, index);
+        index
+    }
+
+    /// One primitive per material slot, unwelded per face corner and without materials yet.
+    fn mesh_primitives(&mut self, mesh: &Instance, name: &str) -> Vec<Primitive> {
+        let positions: Vec<Vec3> = if mesh.is_valid("mvert") {
+            mesh.get_iter("mvert")
+                .map(|vertex| Vec3::from_slice(&vertex.get_f32_vec("co")))
+                .collect()
+        } else {
+            layer(mesh, "vdata", CD_PROP_FLOAT3, Some("position"))
+                .map(|layer| {
+                    let data = layer.get_f32_vec("data");
+                    data.as_chunks::<3>()
+                        .0
+                        .iter()
+                        .map(|&x| Vec3::from(x))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let corners: Vec<usize> = if mesh.is_valid("mloop") {
+            mesh.get_iter("mloop")
+                .map(|corner| corner.get_u32("v") as usize)
+                .collect()
+        } else {
+            layer(mesh, "ldata", CD_PROP_INT32, Some(".corner_vert"))
+                .map(|layer| {
+                    layer
+                        .get_i32_vec("data")
+                        .into_iter()
+                        .map(|x| x as usize)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        // (first corner, corner count, material slot, smooth)
+        let faces: Vec<(usize, usize, usize, bool)> = if mesh.is_valid("mpoly") {
+            mesh.get_iter("mpoly")
+                .map(|face| {
+                    (
+                        face.get_i32("loopstart") as usize,
+                        face.get_i32("totloop") as usize,
+                        face.get_i16("mat_nr").max(0) as usize,
+                        face.get_i8("flag") & ME_SMOOTH != 0,
+                    )
+                })
+                .collect()
+        } else {
+            let offsets = ["face_offset_indices", "poly_offset_indices"]
+                .into_iter()
+                .find(|field| mesh.is_valid(field))
+                .map(|field| mesh.get_i32_vec(field))
+                .unwrap_or_default();
+            let count = offsets.len().saturating_sub(1);
+            let materials = layer(mesh, "pdata", CD_PROP_INT32, Some("material_index"))
+                .map(|layer| layer.get_i32_vec("data"))
+                .unwrap_or_default();
+            let sharp = layer(mesh, "pdata", CD_PROP_BOOL, Some("sharp_face"))
+                .map(|layer| layer.get_i8_vec("data"))
+                .unwrap_or_default();
+            (0..count)
+                .map(|x| {
+                    (
+                        offsets[x] as usize,
+                        (offsets[x + 1] - offsets[x]) as usize,
+                        materials.get(x).map_or(0, |&x| x.max(0) as usize),
+                        sharp.get(x).is_none_or(|&x| x == 0),
+                    )
+                })
+                .collect()
+        };
+
+        let uv_layers: Vec<Instance> = uv_layers(mesh);
+        if uv_layers.len() > 1 {
+            self.report(format!(
+                "mesh {name}: only the first of {} UV maps is imported",
+                uv_layers.len()
+            ));
+        }
+        let uvs: Vec<[f32; 2]> = uv_layers
+            .first()
+            .map(|layer| {
+                let data = layer.get_f32_vec("data");
+                // MLoopUV holds a flag after the coordinates
+                let stride = if layer.get_i32("type") == CD_MLOOPUV {
+                    3
+                } else {
+                    2
+                };
+                data.chunks_exact(stride)
+                    .map(|x| [x[0], 1.0 - x[1]])
+                    .collect()
+            })
+            .unwrap_or_default();
+        if layer(mesh, "ldata", CD_CUSTOMLOOPNORMAL, None).is_some() {
+            self.report(format!("mesh {name}: custom normals are ignored"));
+        }
+        if mesh.is_valid("key") {
+            self.report(format!("mesh {name}: shape keys are ignored"));
+        }
+
+        let valid = |&&(start, count, _, _): &&(usize, usize, usize, bool)| {
+            count >= 3
+                && start + count <= corners.len()
+                && corners[start..start + count]
+                    .iter()
+                    .all(|&x| x < positions.len())
+        };
+        let skipped = faces.iter().filter(|face| !valid(face)).count();
+        if skipped > 0 {
+            self.report(format!(
+                "mesh {name}: {skipped} degenerate or broken faces are skipped"
+            ));
+        }
+        let faces: Vec<_> = faces.iter().filter(valid).copied().collect();
+
+        // Area weighted face normals, from Newell's method to cope with ngons
+        let face_normals: Vec<Vec3> = faces
+            .iter()
+            .map(|&(start, count, _, _)| {
+                (0..count)
+                    .map(|x| {
+                        let a = positions[corners[start + x]];
+                        let b = positions[corners[start + (x + 1) % count]];
+                        a.cross(b)
+                    })
+                    .sum()
+            })
+            .collect();
+        let mut vertex_normals = vec![Vec3::ZERO; positions.len()];
+        for (&(start, count, _, _), normal) in faces.iter().zip(&face_normals) {
+            for &corner in &corners[start..start + count] {
+                vertex_normals[corner] += *normal;
+            }
+        }
+
+        let slots = faces.iter().map(|face| face.2 + 1).max().unwrap_or(1);
+        let slots = slots.max(mesh.get_i16("totcol").max(0) as usize).max(1);
+        let mut primitives = vec![Primitive::default(); slots];
+        for (&(start, count, slot, smooth), face_normal) in faces.iter().zip(&face_normals) {
+            let primitive = &mut primitives[slot];
+            let first = primitive.vertices.len() as u32;
+            for (corner, &vertex) in corners.iter().enumerate().skip(start).take(count) {
+                let normal = if smooth {
+                    vertex_normals[vertex]
+                } else {
+                    *face_normal
+                };
+                primitive.vertices.push(Vertex {
+                    position: positions[vertex].to_array(),
+                    normal: normal.normalize_or_zero().to_array(),
+                    uv: uvs.get(corner).copied().unwrap_or_default(),
+                    tangent: [0.0; 4],
+                });
+            }
+            // Fans work for the convex faces Blender mostly has
+            for x in 1..count as u32 - 1 {
+                primitive.indices.extend([first, first + x, first + x + 1]);
+            }
+        }
+        for primitive in &mut primitives {
+            (primitive.min, primitive.max) = primitive.vertices.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), vertex| {
+                    let position = Vec3::from(vertex.position);
+                    (min.min(position), max.max(position))
+                },
+            );
+        }
+        primitives
+    }
+
+    fn light(&mut self, light: &Instance) -> Option<usize> {
+        let name = id_name(light);
+        let energy = light.get_f32("energy");
+        // Blender gives point and spot lights in watts radiated over the whole sphere
+        let candela = energy * LUMENS_PER_WATT / (4.0 * PI);
+        let (kind, intensity) = match light.get_i16("type") {
+            LA_LOCAL => (LightKind::Point, candela),
+            // Already in irradiance, which glTF exporters pass on as lux
+            LA_SUN => (LightKind::Directional, energy),
+            LA_SPOT => {
+                let outer_cone_angle = light.get_f32("spotsize") * 0.5;
+                let inner_cone_angle = outer_cone_angle * (1.0 - light.get_f32("spotblend"));
+                (
+                    LightKind::Spot {
+                        inner_cone_angle,
+                        outer_cone_angle,
+                    },
+                    candela,
+                )
+            }
+            _ => {
+                self.report(format!("light {name}: area lights are not imported"));
+                return None;
+            }
+        };
+        self.scene.lights.push(Light {
+            name: Some(name),
+            kind,
+            color: Vec3::new(light.get_f32("r"), light.get_f32("g"), light.get_f32("b")),
+            intensity,
+            range: None,
+        });
+        Some(self.scene.lights.len() - 1)
+    }
+
+    fn camera(&mut self, camera: &Instance, aspect_ratio: f32) -> usize {
+        let name = id_name(camera);
+        let kind = camera.get_i8("type");
+        if kind == CAM_PANO {
+            self.report(format!("camera {name}: panoramic, imported as perspective"));
+        }
+        // Which way the sensor size applies, the longer side unless the camera says otherwise
+        let horizontal = match camera.get_i8("sensor_fit") {
+            CAMERA_SENSOR_FIT_HOR => true,
+            CAMERA_SENSOR_FIT_VERT => false,
+            _ => aspect_ratio >= 1.0,
+        };
+        let projection = if kind == CAM_ORTHO {
+            let half = camera.get_f32("ortho_scale") * 0.5;
+            match horizontal {
+                true => Projection::Orthographic {
+                    xmag: half,
+                    ymag: half / aspect_ratio,
+                },
+                false => Projection::Orthographic {
+                    xmag: half * aspect_ratio,
+                    ymag: half,
+                },
+            }
+        } else {
+            let lens = camera.get_f32("lens");
+            let yfov = match (horizontal, camera.get_i8("sensor_fit")) {
+                (true, _) => {
+                    let xfov = 2.0 * (camera.get_f32("sensor_x") * 0.5 / lens).atan();
+                    2.0 * ((xfov * 0.5).tan() / aspect_ratio).atan()
+                }
+                // Auto fit always uses the horizontal sensor size
+                (false, CAMERA_SENSOR_FIT_VERT) => {
+                    2.0 * (camera.get_f32("sensor_y") * 0.5 / lens).atan()
+                }
+                (false, _) => 2.0 * (camera.get_f32("sensor_x") * 0.5 / lens).atan(),
+            };
+            Projection::Perspective {
+                yfov,
+                aspect_ratio: Some(aspect_ratio),
+            }
+        };
+        self.scene.cameras.push(Camera {
+            name: Some(name),
+            projection,
+            znear: camera.get_f32("clipsta"),
+            zfar: Some(camera.get_f32("clipend")),
+        });
+        self.scene.cameras.len() - 1
+    }
+}
+
+/// The ID name without the two letter type code in front.
+fn id_name(instance: &Instance) -> String {
+    let name = instance.get("id").get_string("name");
+    name.get(2..).unwrap_or_default().to_string()
+}
+
+fn object_kind(kind: i16) -> String {
+    match kind {
+        2 => "a curve".to_string(),
+        3 => "a surface".to_string(),
+        4 => "a text".to_string(),
+        5 => "a metaball".to_string(),
+        8 | 9 => "a grease pencil".to_string(),
+        25 => "a lattice".to_string(),
+        26 => "an armature".to_string(),
+        27 => "a hair curves".to_string(),
+        28 => "a point cloud".to_string(),
+        29 => "a volume".to_string(),
+        _ => format!("of type {kind}"),
+    }
+}
+
+/// A Blender rotation, None for an unknown `mode`.
+fn rotation(mode: i16, euler: Vec3, quat: Quat, axis: Vec3, angle: f32) -> Option<Quat> {
+    // The axes in the order they are applied, from DNA_action_types.h
+    let order = match mode {
+        0 if quat.length_squared() > 0.0 => return Some(quat.normalize()),
+        0 => return Some(Quat::IDENTITY),
+        -1 => return Some(Quat::from_axis_angle(axis.normalize_or(Vec3::Y), angle)),
+        1 => [0, 1, 2],
+        2 => [0, 2, 1],
+        3 => [1, 0, 2],
+        4 => [1, 2, 0],
+        5 => [2, 0, 1],
+        6 => [2, 1, 0],
+        _ => return None,
+    };
+    Some(order.into_iter().fold(Quat::IDENTITY, |rotation, axis| {
+        Quat::from_axis_angle(Vec3::AXES[axis], euler[axis]) * rotation
+    }))
+}
+
+fn matrix(values: &[f32]) -> [[f32; 4]; 4] {
+    let mut matrix = [[0.0; 4]; 4];
+    for (x, column) in matrix.iter_mut().enumerate() {
+        column.copy_from_slice(&values[x * 4..x * 4 + 4]);
+    }
+    matrix
+}
+
+/// The first layer of `kind` in the CustomData `field` of `mesh`, also matching `name` if given.
+fn layer<'a>(
+    mesh: &Instance<'a>,
+    field: &str,
+    kind: i32,
+    name: Option<&str>,
+) -> Option<Instance<'a>> {
+    let data = mesh.get(field);
+    if !data.is_valid("layers") {
+        return None;
+    }
+    data.get_iter("layers").find(|layer| {
+        layer.get_i32("type") == kind
+            && layer.is_valid("data")
+            && name.is_none_or(|name| layer.get_string("name") == name)
+    })
+}
+
+/// The UV map layers of `mesh`, the legacy and the generic attribute kind.
+fn uv_layers<'a>(mesh: &Instance<'a>) -> Vec<Instance<'a>> {
+    let data = mesh.get("ldata");
+    if !data.is_valid("layers") {
+        return Vec::new();
+    }
+    let layers: Vec<Instance> = data
+        .get_iter("layers")
+        .filter(|layer| layer.is_valid("data"))
+        .collect();
+    // Files with both kinds keep the legacy ones as a copy for older versions
+    let legacy: Vec<Instance> = layers
+        .iter()
+        .filter(|layer| layer.get_i32("type") == CD_MLOOPUV)
+        .cloned()
+        .collect();
+    if !legacy.is_empty() {
+        return legacy;
+    }
+    layers
+        .into_iter()
+        .filter(|layer| {
+            layer.get_i32("type") == CD_PROP_FLOAT2 && !layer.get_string("name").starts_with('.')
+        })
+        .collect()
+}
@@ -0,0 +1,657 @@
+use {
+    crate::assets::{
+        AlphaMode, Camera, ColorSpace, Light, LightKind, Material, Mesh, Node, Primitive,
+        Projection, Scene, SceneError, Texture, Vertex,
+    },
+    ::gltf::{
+        Document, Gltf, camera,
+        image::Format,
+        khr_lights_punctual::Kind,
+        mesh::Mode,
+        texture::{MagFilter, WrappingMode},
+    },
+    error_stack::{Report, ResultExt},
+    glam::{Mat4, Vec3, Vec4},
+    image::{DynamicImage, ImageBuffer},
+    std::{collections::HashMap, fs::File, io::BufReader, path::Path},
+};
+
+// Extensions the loader understands, files requiring anything else are refused
+const SUPPORTED_EXTENSIONS: &[&str] = &["KHR_lights_punctual"];
+
+pub(crate) fn load(path: &Path) -> Result<Scene, Report<SceneError>> {
+    let attach_path = || format!("path: {}", path.display());
+    let file = File::open(path)
+        .map_err(|x| Report::new(SceneError::Open).attach(format!("{x}")))
+        .attach_with(attach_path)?;
+    // Unvalidated first, so unknown required extensions are reported by name instead of as a validation error
+    let Gltf { document, blob } = Gltf::from_reader_without_validation(BufReader::new(file))
+        .map_err(|x| Report::new(SceneError::InvalidFile).attach(format!("{x}")))
+        .attach_with(attach_path)?;
+    let unsupported: Vec<&str> = document
+        .extensions_required()
+        .filter(|extension| !SUPPORTED_EXTENSIONS.contains(extension))
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(Report::new(SceneError::UnsupportedExtension)
+            .attach(format!("extensions: {}", unsupported.join(", ")))
+            .attach(attach_path()));
+    }
+    let document = Document::from_json(document.into_json())
+        .map_err(|x| Report::new(SceneError::InvalidFile).attach(format!("{x}")))
+        .attach_with(attach_path)?;
+    // External buffers are relative to the file, embedded ones are base64 URIs or the GLB blob
+    let buffers = ::gltf::import_buffers(&document, path.parent(), blob)
+        .map_err(|x| Report::new(SceneError::Open).attach(format!("{x}")))
+        .attach_with(attach_path)?;
+
+    let mut meshes = Vec::new();
+    for mesh in document.meshes() {
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            let describe = || {
+                format!(
+                    "mesh {} ({}), primitive {}",
+                    mesh.index(),
+                    mesh.name().unwrap_or("unnamed"),
+                    primitive.index()
+                )
+            };
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|x| &x.0[..]));
+            let positions: Vec<[f32; 3]> = reader
+                .read_positions()
+                .ok_or_else(|| {
+                    Report::new(SceneError::MissingAttribute)
+                        .attach("POSITION")
+                        .attach(describe())
+                })
+                .attach_with(attach_path)?
+                .collect();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let indices = triangulate(primitive.mode(), indices)
+                .attach(describe())
+                .attach_with(attach_path)?;
+            // Both the flat normals below and the GPU would read past the vertices
+            if let Some(index) = indices.iter().find(|&&x| x as usize >= positions.len()) {
+                return Err(Report::new(SceneError::InvalidFile)
+                    .attach(format!(
+                        "index {index} is out of range for {} vertices",
+                        positions.len()
+                    ))
+                    .attach(describe())
+                    .attach(attach_path()));
+            }
+
+            let mut vertices: Vec<Vertex> = positions
+                .iter()
+                .map(|&position| Vertex {
+                    position,
+                    ..Default::default()
+                })
+                .collect();
+            if let Some(uvs) = reader.read_tex_coords(0) {
+                for (vertex, uv) in vertices.iter_mut().zip(uvs.into_f32()) {
+                    vertex.uv = uv;
+                }
+            }
+            if let Some(tangents) = reader.read_tangents() {
+                for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+                    vertex.tangent = tangent;
+                }
+            }
+            let (vertices, indices) = match reader.read_normals() {
+                Some(normals) => {
+                    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+                        vertex.normal = normal;
+                    }
+                    (vertices, indices)
+                }
+                None => flat_normals(&vertices, &indices),
+            };
+
+            let (min, max) = positions.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), &position| (min.min(position.into()), max.max(position.into())),
+            );
+            primitives.push(Primitive {
+                vertices,
+                indices,
+                material: primitive.material().index(),
+                min,
+                max,
+            });
+        }
+        meshes.push(Mesh {
+            name: mesh.name().map(str::to_string),
+            primitives,
+        });
+    }
+
+    // Optional extensions and content the engine has no use for yet are ignored, but not silently
+    let mut unsupported: Vec<String> = document
+        .extensions_used()
+        .filter(|extension| !SUPPORTED_EXTENSIONS.contains(extension))
+        .map(|extension| format!("extension {extension} is ignored"))
+        .collect();
+    let counts = [
+        (document.animations().len(), "animations"),
+        (document.skins().len(), "skins"),
+    ];
+    for (count, what) in counts.into_iter().filter(|&(count, _)| count > 0) {
+        unsupported.push(format!("{count} {what} are ignored"));
+    }
+
+    // Images are decoded once for every color space they are used with
+    let mut textures = Vec::new();
+    let mut decoded: HashMap<(usize, ColorSpace), Option<usize>> = HashMap::new();
+    let mut texture = |texture: ::gltf::Texture, tex_coord: u32, color_space: ColorSpace| {
+        let image = texture.source();
+        if tex_coord != 0 {
+            unsupported.push(format!(
+                "texture {}: reads UV set {tex_coord}, the first one is used instead",
+                texture.index()
+            ));
+        }
+        *decoded
+            .entry((image.index(), color_space))
+            .or_insert_with(|| {
+                let sampler = texture.sampler();
+                let default_sampler = sampler.wrap_s() == WrappingMode::Repeat
+                    && sampler.wrap_t() == WrappingMode::Repeat
+                    && sampler.mag_filter() != Some(MagFilter::Nearest);
+                if !default_sampler {
+                    unsupported.push(format!(
+                        "texture {}: its sampler is ignored, every texture repeats and filters linearly",
+                        texture.index()
+                    ));
+                }
+                match ::gltf::image::Data::from_source(image.source(), path.parent(), &buffers)
+                    .ok()
+                    .and_then(|data| decode(data, color_space))
+                {
+                    Some(mut decoded) => {
+                        decoded.name = image.name().map(str::to_string);
+                        textures.push(decoded);
+                        Some(textures.len() - 1)
+                    }
+                    None => {
+                        unsupported.push(format!(
+                            "image {}: could not be loaded",
+                            image.index()
+                        ));
+                        None
+                    }
+                }
+            })
+    };
+
+    let materials: Vec<Material> = document
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            Material {
+                name: material.name().map(str::to_string),
+                base_color: Vec4::from(pbr.base_color_factor()),
+                base_color_texture: pbr
+                    .base_color_texture()
+                    .and_then(|x| texture(x.texture(), x.tex_coord(), ColorSpace::Srgb)),
+                metallic: pbr.metallic_factor(),
+                roughness: pbr.roughness_factor(),
+                metallic_roughness_texture: pbr
+                    .metallic_roughness_texture()
+                    .and_then(|x| texture(x.texture(), x.tex_coord(), ColorSpace::Linear)),
+                normal_texture: material
+                    .normal_texture()
+                    .and_then(|x| texture(x.texture(), x.tex_coord(), ColorSpace::Linear)),
+                emissive: Vec3::from(material.emissive_factor()),
+                emissive_texture: material
+                    .emissive_texture()
+                    .and_then(|x| texture(x.texture(), x.tex_coord(), ColorSpace::Srgb)),
+                alpha_mode: match material.alpha_mode() {
+                    ::gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                    ::gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                    ::gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                },
+                alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+                double_sided: material.double_sided(),
+            }
+        })
+        .collect();
+
+    let cameras = document
+        .cameras()
+        .map(|camera| {
+            let (projection, znear, zfar) = match camera.projection() {
+                camera::Projection::Perspective(x) => (
+                    Projection::Perspective {
+                        yfov: x.yfov(),
+                        aspect_ratio: x.aspect_ratio(),
+                    },
+                    x.znear(),
+                    x.zfar(),
+                ),
+                camera::Projection::Orthographic(x) => (
+                    Projection::Orthographic {
+                        xmag: x.xmag(),
+                        ymag: x.ymag(),
+                    },
+                    x.znear(),
+                    Some(x.zfar()),
+                ),
+            };
+            Camera {
+                name: camera.name().map(str::to_string),
+                projection,
+                znear,
+                zfar,
+            }
+        })
+        .collect();
+
+    let lights = document
+        .lights()
+        .into_iter()
+        .flatten()
+        .map(|light| Light {
+            name: light.name().map(str::to_string),
+            kind: match light.kind() {
+                Kind::Directional => LightKind::Directional,
+                Kind::Point => LightKind::Point,
+                Kind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                } => LightKind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                },
+            },
+            color: Vec3::from(light.color()),
+            intensity: light.intensity(),
+            range: light.range(),
+        })
+        .collect();
+
+    let nodes = document
+        .nodes()
+        .map(|node| Node {
+            name: node.name().map(str::to_string),
+            transform: Mat4::from_cols_array_2d(&node.transform().matrix()),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+            camera: node.camera().map(|camera| camera.index()),
+            light: node.light().map(|light| light.index()),
+            children: node.children().map(|child| child.index()).collect(),
+        })
+        .collect();
+
+    // Files without scenes still get drawn, starting at every node nothing points to
+    let roots = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+        None => {
+            let mut has_parent = vec![false; document.nodes().len()];
+            for node in document.nodes() {
+                for child in node.children() {
+                    has_parent[child.index()] = true;
+                }
+            }
+            (0..has_parent.len()).filter(|&x| !has_parent[x]).collect()
+        }
+    };
+
+    for material in document.materials() {
+        if material.occlusion_texture().is_some() {
+            unsupported.push(format!(
+                "material {}: its occlusion texture is ignored",
+                material.name().unwrap_or("unnamed")
+            ));
+        }
+    }
+    for (mesh, primitive) in meshes
+        .iter()
+        .flat_map(|mesh: &Mesh| mesh.primitives.iter().map(move |x| (mesh, x)))
+    {
+        let normal_texture = primitive
+            .material
+            .is_some_and(|x: usize| materials[x].normal_texture.is_some());
+        if normal_texture
+            && primitive
+                .vertices
+                .first()
+                .is_some_and(|x| x.tangent[3] == 0.0)
+        {
+            unsupported.push(format!(
+                "mesh {}: has no tangents, so its normal texture is ignored",
+                mesh.name.as_deref().unwrap_or("unnamed")
+            ));
+        }
+    }
+
+    Ok(Scene {
+        meshes,
+        materials,
+        textures,
+        cameras,
+        lights,
+        nodes,
+        roots,
+        unsupported,
+    })
+}
+
+/// Turns what the gltf crate decoded back into an image, None if the pixels don't match the size.
+fn decode(data: ::gltf::image::Data, color_space: ColorSpace) -> Option<Texture> {
+    let ::gltf::image::Data {
+        pixels,
+        format,
+        width,
+        height,
+    } = data;
+    let u16s = || -> Vec<u16> {
+        pixels
+            .as_chunks::<2>()
+            .0
+            .iter()
+            .map(|&x| u16::from_ne_bytes(x))
+            .collect()
+    };
+    let f32s = || -> Vec<f32> {
+        pixels
+            .as_chunks::<4>()
+            .0
+            .iter()
+            .map(|&x| f32::from_ne_bytes(x))
+            .collect()
+    };
+    // One and two channels are what the gltf crate makes of gray and gray-alpha images
+    let image = match format {
+        Format::R8 => {
+            ImageBuffer::from_raw(width, height, pixels.clone()).map(DynamicImage::ImageLuma8)
+        }
+        Format::R8G8 => {
+            ImageBuffer::from_raw(width, height, pixels.clone()).map(DynamicImage::ImageLumaA8)
+        }
+        Format::R8G8B8 => {
+            ImageBuffer::from_raw(width, height, pixels.clone()).map(DynamicImage::ImageRgb8)
+        }
+        Format::R8G8B8A8 => {
+            ImageBuffer::from_raw(width, height, pixels.clone()).map(DynamicImage::ImageRgba8)
+        }
+        Format::R16 => ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageLuma16),
+        Format::R16G16 => {
+            ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageLumaA16)
+        }
+        Format::R16G16B16 => {
+            ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageRgb16)
+        }
+        Format::R16G16B16A16 => {
+            ImageBuffer::from_raw(width, height, u16s()).map(DynamicImage::ImageRgba16)
+        }
+        Format::R32G32B32FLOAT => {
+            ImageBuffer::from_raw(width, height, f32s()).map(DynamicImage::ImageRgb32F)
+        }
+        Format::R32G32B32A32FLOAT => {
+            ImageBuffer::from_raw(width, height, f32s()).map(DynamicImage::ImageRgba32F)
+        }
+    }?;
+    Some(Texture::from_image(image, color_space))
+}
+
+/// Turns the indices of `mode` into a triangle list.
+fn triangulate(mode: Mode, indices: Vec<u32>) -> Result<Vec<u32>, Report<SceneError>> {
+    let triangle_count = indices.len().saturating_sub(2);
+    match mode {
+        Mode::Triangles => Ok(indices),
+        // Every other triangle is flipped to keep the winding
+        Mode::TriangleStrip => Ok((0..triangle_count)
+            .flat_map(|x| {
+                if x % 2 == 0 {
+                    [indices[x], indices[x + 1], indices[x + 2]]
+                } else {
+                    [indices[x + 1], indices[x], indices[x + 2]]
+                }
+            })
+            .collect()),
+        Mode::TriangleFan => Ok((0..triangle_count)
+            .flat_map(|x| [indices[0], indices[x + 1], indices[x + 2]])
+            .collect()),
+        Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => {
+            Err(Report::new(SceneError::UnsupportedPrimitive)
+                .attach(format!("mode: {mode:?}, only triangles are drawn")))
+        }
+    }
+}
+
+/// Unwelds the triangles and gives each one its face normal, what glTF asks for when normals are missing.
+fn flat_normals(vertices: &[Vertex], indices: &[u32]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut flat = Vec::with_capacity(indices.len());
+    for triangle in indices.as_chunks::<3>().0 {
+        let corners = triangle.map(|x| vertices[x as usize]);
+        let [a, b, c] = corners.map(|x| Vec3::from(x.position));
+        let normal = (b - a).cross(c - a).normalize_or_zero().to_array();
+        flat.extend(corners.map(|x| Vertex { normal, ..x }));
+    }
+    let indices = (0..flat.len() as u32).collect();
+    (flat, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::path::PathBuf};
+
+    /// Standard base64 with padding, what glTF data URIs use.
+    fn base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut encoded = String::new();
+        for chunk in bytes.chunks(3) {
+            let word = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |word, (i, &x)| word | (x as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                encoded.push(if i <= chunk.len() {
+                    ALPHABET[(word >> (18 - 6 * i) & 63) as usize] as char
+                } else {
+                    '='
+                });
+            }
+        }
+        encoded
+    }
+
+    /// A file with a single primitive of `mode` over `positions` and 16 bit `indices`,
+    /// all in one embedded buffer.
+    fn gltf_file(name: &str, mode: u32, positions: &[[f32; 3]], indices: &[u16]) -> PathBuf {
+        gltf_file_requiring(name, &[], mode, positions, indices)
+    }
+
+    fn gltf_file_requiring(
+        name: &str,
+        extensions: &[&str],
+        mode: u32,
+        positions: &[[f32; 3]],
+        indices: &[u16],
+    ) -> PathBuf {
+        let mut bytes: Vec<u8> = positions
+            .iter()
+            .flatten()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let positions_length = bytes.len();
+        bytes.extend(indices.iter().flat_map(|x| x.to_le_bytes()));
+        let (min, max) = positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), &position| (min.min(position.into()), max.max(position.into())),
+        );
+        let extensions = format!("{extensions:?}");
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "extensionsUsed": {extensions},
+                "extensionsRequired": {extensions},
+                "meshes": [{{
+                    "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1, "mode": {mode} }}]
+                }}],
+                "accessors": [
+                    {{
+                        "bufferView": 0,
+                        "componentType": 5126,
+                        "count": {},
+                        "type": "VEC3",
+                        "min": {:?},
+                        "max": {:?}
+                    }},
+                    {{ "bufferView": 1, "componentType": 5123, "count": {}, "type": "SCALAR" }}
+                ],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteLength": {positions_length} }},
+                    {{ "buffer": 0, "byteOffset": {positions_length}, "byteLength": {} }}
+                ],
+                "buffers": [{{
+                    "uri": "data:application/octet-stream;base64,{}",
+                    "byteLength": {}
+                }}]
+            }}"#,
+            positions.len(),
+            min.to_array(),
+            max.to_array(),
+            indices.len(),
+            bytes.len() - positions_length,
+            base64(&bytes),
+            bytes.len(),
+        );
+        let dir = std::env::temp_dir().join(format!("redefyning-gltf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{name}.gltf"));
+        std::fs::write(&path, json).unwrap();
+        path
+    }
+
+    /// The context of the rejection and everything attached to it as text.
+    fn error(path: &Path) -> (SceneError, Vec<String>) {
+        let report = load(path).err().expect("The file should be rejected");
+        let attachments = report
+            .frames()
+            .filter_map(|frame| frame.downcast_ref::<String>().cloned())
+            .collect();
+        (report.current_context().clone(), attachments)
+    }
+
+    // A unit square in the XY plane, facing +Z when wound counter-clockwise
+    const SQUARE: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+    ];
+
+    // glTF primitive modes
+    const POINTS: u32 = 0;
+    const LINES: u32 = 1;
+    const TRIANGLES: u32 = 4;
+    const TRIANGLE_STRIP: u32 = 5;
+    const TRIANGLE_FAN: u32 = 6;
+
+    #[test]
+    fn strips_keep_their_winding() {
+        assert_eq!(
+            triangulate(Mode::TriangleStrip, vec![0, 1, 2, 3, 4]).unwrap(),
+            [0, 1, 2, 2, 1, 3, 2, 3, 4]
+        );
+        let path = gltf_file("strip", TRIANGLE_STRIP, &SQUARE, &[0, 1, 2, 3]);
+        let primitive = &load(&path).unwrap().meshes[0].primitives[0];
+        // Flat normals unweld the two triangles, the second one is flipped back to face +Z
+        assert_eq!(primitive.indices, [0, 1, 2, 3, 4, 5]);
+        let positions: Vec<[f32; 3]> = primitive.vertices.iter().map(|x| x.position).collect();
+        assert_eq!(
+            positions,
+            [
+                SQUARE[0], SQUARE[1], SQUARE[2], SQUARE[2], SQUARE[1], SQUARE[3]
+            ]
+        );
+        assert!(
+            primitive
+                .vertices
+                .iter()
+                .all(|x| x.normal == [0.0, 0.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn fans_share_their_first_vertex() {
+        assert_eq!(
+            triangulate(Mode::TriangleFan, vec![0, 1, 2, 3]).unwrap(),
+            [0, 1, 2, 0, 2, 3]
+        );
+        let path = gltf_file("fan", TRIANGLE_FAN, &SQUARE, &[0, 1, 3, 2]);
+        let primitive = &load(&path).unwrap().meshes[0].primitives[0];
+        let positions: Vec<[f32; 3]> = primitive.vertices.iter().map(|x| x.position).collect();
+        assert_eq!(
+            positions,
+            [
+                SQUARE[0], SQUARE[1], SQUARE[3], SQUARE[0], SQUARE[3], SQUARE[2]
+            ]
+        );
+        assert!(
+            primitive
+                .vertices
+                .iter()
+                .all(|x| x.normal == [0.0, 0.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn too_few_indices_make_no_triangles() {
+        assert!(
+            triangulate(Mode::TriangleStrip, vec![0, 1])
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            triangulate(Mode::TriangleFan, Vec::new())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rejects_lines_and_points() {
+        for (name, mode, expected) in [("lines", LINES, "Lines"), ("points", POINTS, "Points")] {
+            let path = gltf_file(name, mode, &SQUARE, &[0, 1, 2, 3]);
+            let (context, attachments) = error(&path);
+            assert_eq!(context, SceneError::UnsupportedPrimitive);
+            assert!(attachments.contains(&format!("mode: {expected}, only triangles are drawn")));
+            assert!(attachments.contains(&String::from("mesh 0 (unnamed), primitive 0")));
+            assert!(attachments.contains(&format!("path: {}", path.display())));
+        }
+    }
+
+    #[test]
+    fn rejects_indices_past_the_vertices() {
+        let path = gltf_file("out-of-range", TRIANGLES, &SQUARE, &[0, 1, 4]);
+        let (context, attachments) = error(&path);
+        assert_eq!(context, SceneError::InvalidFile);
+        assert!(attachments.contains(&String::from("index 4 is out of range for 4 vertices")));
+        assert!(attachments.contains(&String::from("mesh 0 (unnamed), primitive 0")));
+    }
+
+    #[test]
+    fn names_the_required_extensions_it_lacks() {
+        let extensions = ["KHR_lights_punctual", "KHR_draco_mesh_compression"];
+        let path = gltf_file_requiring("draco", &extensions, TRIANGLES, &SQUARE, &[0, 1, 2]);
+        let (context, attachments) = error(&path);
+        assert_eq!(context, SceneError::UnsupportedExtension);
+        assert!(attachments.contains(&String::from("extensions: KHR_draco_mesh_compression")));
+        assert!(attachments.contains(&format!("path: {}", path.display())));
+        // The ones it implements are fine to require
+        let path = gltf_file_requiring("lights", &extensions[..1], TRIANGLES, &SQUARE, &[0, 1, 2]);
+        assert_eq!(
+            load(&path).unwrap().meshes[0].primitives[0].indices.len(),
+            3
+        );
+    }
+}
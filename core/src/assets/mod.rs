@@ -0,0 +1,294 @@
+use {
+    error_stack::Report,
+    glam::{Mat4, Vec3, Vec4},
+    std::{fmt, path::Path},
+};
+
+#[path = "blend.rs"]
+mod blend;
+#[path = "gltf.rs"]
+mod gltf;
+#[path = "texture.rs"]
+mod texture;
+
+pub use texture::{ColorSpace, Texture, TextureError, TextureFormat};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SceneError {
+    /// The file couldn't be read.
+    Open,
+    /// The file isn't a valid scene.
+    InvalidFile,
+    /// The file extension isn't one of the supported formats.
+    UnsupportedFormat,
+    /// The file requires an extension the engine doesn't implement.
+    UnsupportedExtension,
+    /// A primitive uses a mode that can't be turned into triangles.
+    UnsupportedPrimitive,
+    /// A primitive lacks an attribute every mesh needs.
+    MissingAttribute,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Open => "Failed to open the scene",
+            Self::InvalidFile => "The scene file is invalid",
+            Self::UnsupportedFormat => "The scene format is not supported",
+            Self::UnsupportedExtension => "The scene requires an unsupported extension",
+            Self::UnsupportedPrimitive => "The scene contains an unsupported primitive",
+            Self::MissingAttribute => "A primitive is missing a required attribute",
+        })
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// The vertex format every mesh is converted to, matching the built-in `mesh.spv` inputs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    /// xyz is the tangent, w the handedness of the bitangent. Zero when the file has none.
+    pub tangent: [f32; 4],
+}
+
+// Only f32 fields and no padding
+unsafe impl bytemuck::Zeroable for Vertex {}
+unsafe impl bytemuck::Pod for Vertex {}
+
+/// A triangle list with one material.
+#[derive(Debug, Clone, Default)]
+pub struct Primitive {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// Index into [`Scene::materials`], the default material if None.
+    pub material: Option<usize>,
+    /// Object space bounds.
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub name: Option<String>,
+    pub primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    /// Fragments below [`Material::alpha_cutoff`] are discarded.
+    Mask,
+    /// Blended over what's behind, drawn after everything opaque.
+    Blend,
+}
+
+/// Metallic-roughness material factors, and the textures they are multiplied with.
+/// Textures are indices into [`Scene::textures`] and read with the first UV set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: Option<String>,
+    pub base_color: Vec4,
+    /// sRGB.
+    pub base_color_texture: Option<usize>,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Linear, roughness in green and metalness in blue.
+    pub metallic_roughness_texture: Option<usize>,
+    /// Linear tangent space normals, only applied to vertices with tangents.
+    pub normal_texture: Option<usize>,
+    pub emissive: Vec3,
+    /// sRGB.
+    pub emissive_texture: Option<usize>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    /// Back faces are drawn too.
+    pub double_sided: bool,
+}
+
+impl Default for Material {
+    // The glTF default material
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color: Vec4::ONE,
+            base_color_texture: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            emissive: Vec3::ZERO,
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// `aspect_ratio` is width / height, the viewport's when None.
+    Perspective {
+        yfov: f32,
+        aspect_ratio: Option<f32>,
+    },
+    /// Half the width and height of the view volume.
+    Orthographic { xmag: f32, ymag: f32 },
+}
+
+/// Looks down its node's -Z axis with +Y up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera {
+    pub name: Option<String>,
+    pub projection: Projection,
+    pub znear: f32,
+    /// Infinite when None.
+    pub zfar: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    /// Shines down its node's -Z axis, `intensity` is in lux.
+    Directional,
+    /// `intensity` is in candela.
+    Point,
+    /// Shines down its node's -Z axis, `intensity` is in candela. The angles are from the axis, in radians.
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub name: Option<String>,
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Distance where the light reaches zero, infinite when None.
+    pub range: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Node {
+    pub name: Option<String>,
+    /// Relative to the parent.
+    pub transform: Mat4,
+    /// Index into [`Scene::meshes`].
+    pub mesh: Option<usize>,
+    /// Index into [`Scene::cameras`].
+    pub camera: Option<usize>,
+    /// Index into [`Scene::lights`].
+    pub light: Option<usize>,
+    /// Indices into [`Scene::nodes`].
+    pub children: Vec<usize>,
+}
+
+/// Meshes, materials, cameras and lights, and the node hierarchy placing them, as loaded from a file.
+/// Every format is converted to glTF conventions: Y up, meters, linear colors.
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub textures: Vec<Texture>,
+    pub cameras: Vec<Camera>,
+    pub lights: Vec<Light>,
+    pub nodes: Vec<Node>,
+    /// The nodes the hierarchy starts at.
+    pub roots: Vec<usize>,
+    /// Whatever the file contains that couldn't be imported, one line each.
+    pub unsupported: Vec<String>,
+}
+
+impl Scene {
+    /// Loads a `.gltf` (with embedded or external buffers) or `.glb` file,
+    /// or an uncompressed `.blend` file with the `blend_usage` feature.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Report<SceneError>> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("gltf" | "glb") => gltf::load(path),
+            #[cfg(feature = "blend_usage")]
+            Some("blend") => blend::load(path),
+            _ => Err(Report::new(SceneError::UnsupportedFormat)
+                .attach(format!("path: {}", path.display()))),
+        }
+    }
+
+    /// Every node's transform with its parents applied, indexed like [`Scene::nodes`].
+    pub fn world_transforms(&self) -> Vec<Mat4> {
+        let mut world = vec![Mat4::IDENTITY; self.nodes.len()];
+        let mut stack: Vec<(usize, Mat4)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY))
+            .collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            world[index] = parent * node.transform;
+            stack.extend(node.children.iter().map(|&child| (child, world[index])));
+        }
+        world
+    }
+
+    /// Every mesh placed by the hierarchy, with its world transform.
+    pub fn instances(&self) -> Vec<(usize, Mat4)> {
+        self.placed(|node| node.mesh)
+    }
+
+    /// Every camera placed by the hierarchy, with its world transform.
+    pub fn camera_instances(&self) -> Vec<(usize, Mat4)> {
+        self.placed(|node| node.camera)
+    }
+
+    /// Every light placed by the hierarchy, with its world transform.
+    pub fn light_instances(&self) -> Vec<(usize, Mat4)> {
+        self.placed(|node| node.light)
+    }
+
+    fn placed(&self, content: impl Fn(&Node) -> Option<usize>) -> Vec<(usize, Mat4)> {
+        let world = self.world_transforms();
+        let mut placed = Vec::new();
+        let mut stack = self.roots.clone();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if let Some(content) = content(node) {
+                placed.push((content, world[index]));
+            }
+            stack.extend_from_slice(&node.children);
+        }
+        placed
+    }
+
+    /// World space bounds of every instance, None for an empty scene.
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        for (mesh, transform) in self.instances() {
+            for primitive in &self.meshes[mesh].primitives {
+                for corner in 0..8 {
+                    let pick =
+                        |bit: usize, min: f32, max: f32| if corner & bit == 0 { min } else { max };
+                    let point = transform.transform_point3(Vec3::new(
+                        pick(1, primitive.min.x, primitive.max.x),
+                        pick(2, primitive.min.y, primitive.max.y),
+                        pick(4, primitive.min.z, primitive.max.z),
+                    ));
+                    bounds = Some(match bounds {
+                        Some((min, max)) => (min.min(point), max.max(point)),
+                        None => (point, point),
+                    });
+                }
+            }
+        }
+        bounds
+    }
+}
@@ -0,0 +1,336 @@
+use {
+    error_stack::{Report, ResultExt},
+    image::DynamicImage,
+    std::{fmt, path::Path},
+};
+
+const KTX2_IDENTIFIER: [u8; 12] = *b"\xabKTX 20\xbb\r\n\x1a\n";
+// Header, index and one level index entry, in bytes
+const KTX2_HEADER_SIZE: usize = 80;
+const KTX2_LEVEL_SIZE: usize = 24;
+// Far above what devices sample, and small enough for level sizes to never overflow
+const MAX_DIMENSION: u32 = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextureError {
+    /// The file couldn't be read.
+    Open,
+    /// The file isn't a valid image.
+    InvalidFile,
+    /// The image uses a pixel format, layout or compression the engine doesn't load.
+    UnsupportedFormat,
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Open => "Failed to open the texture",
+            Self::InvalidFile => "The texture file is invalid",
+            Self::UnsupportedFormat => "The texture format is not supported",
+        })
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+/// How the color channels of a texture are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ColorSpace {
+    /// Colors meant to be seen, like base color and emissive. Decoded to linear when sampled.
+    #[default]
+    Srgb,
+    /// Data like normals, roughness and metalness, sampled as stored.
+    Linear,
+}
+
+/// The pixel formats textures are uploaded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureFormat {
+    /// 8 bit RGBA, what every LDR image is decoded to.
+    Rgba8,
+    /// 16 bit float RGBA.
+    Rgba16F,
+    /// 32 bit float RGBA, what HDR images are decoded to.
+    Rgba32F,
+    /// BC1 to BC7 blocks, 4x4 pixels each. BC4, BC5 and BC6H are always linear.
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc5,
+    Bc6H,
+    Bc7,
+}
+
+impl TextureFormat {
+    pub fn is_compressed(self) -> bool {
+        !matches!(self, Self::Rgba8 | Self::Rgba16F | Self::Rgba32F)
+    }
+
+    /// Whether [`ColorSpace::Srgb`] makes a difference, float and data formats are linear either way.
+    pub fn has_srgb(self) -> bool {
+        matches!(
+            self,
+            Self::Rgba8 | Self::Bc1 | Self::Bc2 | Self::Bc3 | Self::Bc7
+        )
+    }
+
+    /// The size in bytes of one `width` x `height` level.
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        let blocks = width.div_ceil(4) * height.div_ceil(4);
+        match self {
+            Self::Rgba8 => width * height * 4,
+            Self::Rgba16F => width * height * 8,
+            Self::Rgba32F => width * height * 16,
+            Self::Bc1 | Self::Bc4 => blocks * 8,
+            Self::Bc2 | Self::Bc3 | Self::Bc5 | Self::Bc6H | Self::Bc7 => blocks * 16,
+        }
+    }
+}
+
+/// A 2D image ready for upload, decoded or still block compressed.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub name: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    pub color_space: ColorSpace,
+    /// Mip levels, the full size one first. When the chain is incomplete and the format isn't
+    /// compressed, the renderer generates the missing levels from the last one given.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl Texture {
+    /// Loads a `.ktx2` file as stored, or decodes anything the `image` crate reads (PNG, JPEG, HDR, EXR, ...).
+    /// `color_space` wins over whether a KTX2 format is the sRGB variant.
+    pub fn load(
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+    ) -> Result<Self, Report<TextureError>> {
+        let path = path.as_ref();
+        let attach_path = || format!("path: {}", path.display());
+        let bytes = std::fs::read(path)
+            .map_err(|x| Report::new(TextureError::Open).attach(format!("{x}")))
+            .attach_with(attach_path)?;
+        let mut texture = Self::from_memory(&bytes, color_space).attach_with(attach_path)?;
+        texture.name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        Ok(texture)
+    }
+
+    /// Like [`Texture::load`] for a file that is already in memory, the format is guessed from the contents.
+    pub fn from_memory(
+        bytes: &[u8],
+        color_space: ColorSpace,
+    ) -> Result<Self, Report<TextureError>> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            return ktx2(bytes, color_space);
+        }
+        let image = image::load_from_memory(bytes)
+            .map_err(|x| Report::new(TextureError::InvalidFile).attach(format!("{x}")))?;
+        Ok(Self::from_image(image, color_space))
+    }
+
+    /// Float images stay float, everything else is converted to 8 bit RGBA.
+    pub fn from_image(image: DynamicImage, color_space: ColorSpace) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let (format, pixels) = match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => (
+                TextureFormat::Rgba32F,
+                bytemuck::cast_slice(&image.into_rgba32f().into_raw()).to_vec(),
+            ),
+            image => (TextureFormat::Rgba8, image.into_rgba8().into_raw()),
+        };
+        Self {
+            name: None,
+            width,
+            height,
+            format,
+            color_space,
+            levels: vec![pixels],
+        }
+    }
+
+    /// The levels of a full mip chain down to 1x1.
+    pub fn full_mip_count(&self) -> u32 {
+        32 - self.width.max(self.height).max(1).leading_zeros()
+    }
+}
+
+/// The levels of a KTX2 file as stored, without supercompression.
+fn ktx2(bytes: &[u8], color_space: ColorSpace) -> Result<Texture, Report<TextureError>> {
+    let invalid = |reason: &str| Report::new(TextureError::InvalidFile).attach(reason.to_string());
+    let unsupported = |reason: String| Report::new(TextureError::UnsupportedFormat).attach(reason);
+    if bytes.len() < KTX2_HEADER_SIZE {
+        return Err(invalid("the KTX2 header is cut off"));
+    }
+    // Only called within the header and level index, which are checked to be there
+    let u32_at =
+        |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"));
+    let u64_at =
+        |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"));
+    let vk_format = u32_at(12);
+    let (width, height, depth) = (u32_at(20), u32_at(24), u32_at(28));
+    let (layers, faces, level_count) = (u32_at(32), u32_at(36), u32_at(40));
+    let supercompression = u32_at(44);
+
+    // The Vulkan formats the renderer has an equivalent for, sRGB variants included
+    let format = match vk_format {
+        37 | 43 => TextureFormat::Rgba8,
+        97 => TextureFormat::Rgba16F,
+        109 => TextureFormat::Rgba32F,
+        // The RGB variants only differ for blocks using transparent black
+        131..=134 => TextureFormat::Bc1,
+        135 | 136 => TextureFormat::Bc2,
+        137 | 138 => TextureFormat::Bc3,
+        139 => TextureFormat::Bc4,
+        141 => TextureFormat::Bc5,
+        143 => TextureFormat::Bc6H,
+        145 | 146 => TextureFormat::Bc7,
+        0 => {
+            return Err(unsupported(
+                "Basis Universal KTX2 files need transcoding".into(),
+            ));
+        }
+        x => return Err(unsupported(format!("VkFormat {x}"))),
+    };
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(unsupported(format!(
+            "{width}x{height} is larger than {MAX_DIMENSION} pixels on a side"
+        )));
+    }
+    if width == 0 || height == 0 || depth > 1 || layers > 1 || faces != 1 {
+        return Err(unsupported(format!(
+            "only 2D textures are loaded, this one is {width}x{height}x{depth} with {layers} layers and {faces} faces"
+        )));
+    }
+    if supercompression != 0 {
+        return Err(unsupported(format!(
+            "supercompression scheme {supercompression}"
+        )));
+    }
+
+    // Zero levels asks for them to be generated
+    let full_mip_count = 32 - width.max(height).leading_zeros();
+    if level_count > full_mip_count {
+        return Err(invalid(&format!(
+            "{level_count} levels, a {width}x{height} chain only has {full_mip_count}"
+        )));
+    }
+    let level_count = level_count.max(1) as usize;
+    if bytes.len() < KTX2_HEADER_SIZE + level_count * KTX2_LEVEL_SIZE {
+        return Err(invalid("the KTX2 level index is cut off"));
+    }
+    let mut levels = Vec::with_capacity(level_count);
+    for level in 0..level_count {
+        let entry = KTX2_HEADER_SIZE + level * KTX2_LEVEL_SIZE;
+        let (offset, length) = (u64_at(entry) as usize, u64_at(entry + 8) as usize);
+        let expected = format.level_size((width >> level).max(1), (height >> level).max(1));
+        if length != expected {
+            return Err(invalid(&format!(
+                "level {level} holds {length} bytes instead of {expected}"
+            )));
+        }
+        let data = offset
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| invalid(&format!("level {level} is out of bounds")))?;
+        levels.push(data.to_vec());
+    }
+
+    Ok(Texture {
+        name: None,
+        width,
+        height,
+        format,
+        color_space,
+        levels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RGBA8 UNORM
+    const VK_FORMAT: u32 = 37;
+
+    /// A KTX2 file whose level index is `levels`, as offset and length pairs, followed by `data`.
+    fn ktx2_file(width: u32, height: u32, levels: &[(u64, u64)], data: &[u8]) -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for value in [VK_FORMAT, 1, width, height, 0, 0, 1, levels.len() as u32, 0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        // No data format descriptor, key/value data or supercompression global data
+        bytes.resize(KTX2_HEADER_SIZE, 0);
+        for &(offset, length) in levels {
+            bytes.extend(offset.to_le_bytes());
+            bytes.extend(length.to_le_bytes());
+            // Uncompressed length, the same without supercompression
+            bytes.extend(length.to_le_bytes());
+        }
+        bytes.extend(data);
+        bytes
+    }
+
+    fn error(bytes: &[u8]) -> TextureError {
+        Texture::from_memory(bytes, ColorSpace::Srgb)
+            .err()
+            .map(|report| report.current_context().clone())
+            .expect("The file should be rejected")
+    }
+
+    #[test]
+    fn loads_every_level() {
+        let data_start = (KTX2_HEADER_SIZE + 2 * KTX2_LEVEL_SIZE) as u64;
+        let data: Vec<u8> = (0..20).collect();
+        let bytes = ktx2_file(2, 2, &[(data_start, 16), (data_start + 16, 4)], &data);
+        let texture = Texture::from_memory(&bytes, ColorSpace::Srgb).unwrap();
+        assert_eq!((texture.width, texture.height), (2, 2));
+        assert_eq!(texture.format, TextureFormat::Rgba8);
+        assert_eq!(texture.levels, [data[..16].to_vec(), data[16..].to_vec()]);
+    }
+
+    #[test]
+    fn rejects_cut_off_files() {
+        let bytes = ktx2_file(2, 2, &[(0, 16)], &[]);
+        assert_eq!(
+            error(&bytes[..KTX2_HEADER_SIZE - 1]),
+            TextureError::InvalidFile
+        );
+        assert_eq!(error(&bytes[..KTX2_HEADER_SIZE]), TextureError::InvalidFile);
+        // The level runs past the end of the file
+        let data_start = (KTX2_HEADER_SIZE + KTX2_LEVEL_SIZE) as u64;
+        let bytes = ktx2_file(2, 2, &[(data_start, 16)], &[0; 15]);
+        assert_eq!(error(&bytes), TextureError::InvalidFile);
+    }
+
+    #[test]
+    fn rejects_level_ranges_that_overflow() {
+        let bytes = ktx2_file(2, 2, &[(u64::MAX - 8, 16)], &[0; 16]);
+        assert_eq!(error(&bytes), TextureError::InvalidFile);
+    }
+
+    #[test]
+    fn rejects_more_levels_than_the_chain_has() {
+        // 40 levels would shift the size past 32 bits
+        let bytes = ktx2_file(2, 2, &[(0, 4); 40], &[]);
+        assert_eq!(error(&bytes), TextureError::InvalidFile);
+    }
+
+    #[test]
+    fn rejects_levels_of_the_wrong_size() {
+        let data_start = (KTX2_HEADER_SIZE + KTX2_LEVEL_SIZE) as u64;
+        let bytes = ktx2_file(2, 2, &[(data_start, 15)], &[0; 16]);
+        assert_eq!(error(&bytes), TextureError::InvalidFile);
+    }
+
+    #[test]
+    fn rejects_huge_dimensions() {
+        let bytes = ktx2_file(u32::MAX, u32::MAX, &[(0, 0)], &[]);
+        assert_eq!(error(&bytes), TextureError::UnsupportedFormat);
+    }
+}
@@ -6,8 +6,14 @@
 
 use {
     crate::utils::{AppState, RawWindowingHandles},
+    error_stack::{Report, ResultExt},
     once_cell::sync::Lazy,
-    std::sync::mpsc::channel,
+    std::{
+        any::Any,
+        path::PathBuf,
+        sync::{Arc, Mutex, mpsc::channel},
+        thread::{self, JoinHandle},
+    },
     tokio::sync::{mpsc, oneshot},
     winit::dpi::PhysicalSize,
 };
@@ -22,11 +28,517 @@ mod vk;
 #[path = "utils.rs"]
 mod utils;
 
+#[path = "assets/mod.rs"]
+mod assets;
+
 #[cfg(feature = "mimalloc")]
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-pub use winit::window::WindowAttributes as WindowSettings;
+pub use {
+    assets::{
+        AlphaMode, Camera, ColorSpace, Light, LightKind, Material, Mesh, Node, Primitive,
+        Projection, Scene, SceneError, Texture, TextureError, TextureFormat, Vertex,
+    },
+    image::DynamicImage as FrameImage,
+    utils::shutdown_requested,
+    winit::window::WindowAttributes as WindowSettings,
+};
+
+// Used by `logln!` and `debug_logln!`, which expand in the caller's crate
+#[doc(hidden)]
+pub use utils::{DEBUG_LOG, log_elapsed, log_line};
+
+#[cfg(feature = "vulkan")]
+pub use vk::error::RendererError;
+
+/// Called with the frame number and its pixels for every frame read back in headless mode.
+/// SDR frames are 8 bit RGBA, see [`App::color_output`] for HDR ones.
+pub type FrameCallback = Box<dyn FnMut(u64, &FrameImage) + Send + 'static>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Renders into an offscreen image instead of a window, for CI machines and render servers.
+/// No windowing system is touched at all, so this works without a display.
+pub struct HeadlessSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Stops the App after this many frames, runs until the process exits if None.
+    pub frame_limit: Option<u64>,
+    /// Writes every frame into this directory as `frame_00000.png`, `frame_00001.png`, ...
+    /// scRGB frames are written as `.exr` instead.
+    pub output_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Forces a specific GPU instead of letting the engine score them and pick the best one.
+/// The `REDEFYNING_DEVICE` environment variable takes precedence over [`App::prefer_device`],
+/// it's parsed by [`DeviceSelector::from_str`](std::str::FromStr).
+pub enum DeviceSelector {
+    /// Case-insensitive part of the device name, like "radeon" or "llvmpipe".
+    Name(String),
+    /// Position in the order Vulkan enumerates the devices.
+    Index(usize),
+    /// The driver reported device UUID.
+    Uuid([u8; 16]),
+}
+
+impl DeviceSelector {
+    /// The environment variable checked for a device override.
+    pub const ENV_VAR: &'static str = "REDEFYNING_DEVICE";
+
+    /// Reads the selector from `REDEFYNING_DEVICE`, `None` if it's unset or empty.
+    /// A value that doesn't parse is an error, rather than quietly picking some other device.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        match std::env::var(Self::ENV_VAR) {
+            Ok(value) if value.trim().is_empty() => Ok(None),
+            Ok(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|x| format!("{}: {x}", Self::ENV_VAR)),
+            Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(x) => Err(format!("{}: {x}", Self::ENV_VAR)),
+        }
+    }
+
+    fn parse_uuid(text: &str) -> Option<[u8; 16]> {
+        let hex: String = text.chars().filter(|c| *c != '-').collect();
+        if hex.len() != 32 {
+            return None;
+        }
+        let mut uuid = [0u8; 16];
+        for (i, byte) in uuid.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        Some(uuid)
+    }
+}
+
+impl std::str::FromStr for DeviceSelector {
+    type Err = String;
+
+    /// Accepts `index:1`, `uuid:<32 hex digits>` and `name:<text>`.
+    /// Without a prefix, numbers are indices, 32 hex digits are UUIDs and anything else is a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(index) = s.strip_prefix("index:") {
+            return index
+                .trim()
+                .parse()
+                .map(Self::Index)
+                .map_err(|x| format!("Invalid device index {index:?}: {x}"));
+        }
+        if let Some(uuid) = s.strip_prefix("uuid:") {
+            return Self::parse_uuid(uuid.trim())
+                .map(Self::Uuid)
+                .ok_or_else(|| format!("Invalid device UUID {uuid:?}"));
+        }
+        if let Some(name) = s.strip_prefix("name:") {
+            return Ok(Self::Name(name.trim().to_string()));
+        }
+        if let Ok(index) = s.parse() {
+            return Ok(Self::Index(index));
+        }
+        Ok(Self::parse_uuid(s).map_or_else(|| Self::Name(s.to_string()), Self::Uuid))
+    }
+}
+
+impl std::fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "name:{name}"),
+            Self::Index(index) => write!(f, "index:{index}"),
+            Self::Uuid(uuid) => {
+                write!(f, "uuid:")?;
+                uuid.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// How serious a validation message is, from least to most.
+pub enum ValidationSeverity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Which Vulkan validation messages get logged, with the objects and command labels they mention.
+/// Validation only runs with the `debug` feature, these are ignored without it.
+pub struct ValidationSettings {
+    /// Messages less severe than this are dropped.
+    pub min_severity: ValidationSeverity,
+    /// Messages about the layers and the loader rather than how the API is used.
+    pub general: bool,
+    /// Uses of the API the specification forbids.
+    pub validation: bool,
+    /// Uses of the API that are allowed but likely slow.
+    pub performance: bool,
+    /// Panics on the renderer thread after the first validation error, so test runs fail loudly.
+    pub panic_on_error: bool,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            min_severity: ValidationSeverity::Warning,
+            general: false,
+            validation: true,
+            performance: true,
+            panic_on_error: false,
+        }
+    }
+}
+
+impl ValidationSettings {
+    pub fn min_severity(mut self, severity: ValidationSeverity) -> Self {
+        self.min_severity = severity;
+        self
+    }
+
+    pub fn general(mut self, enabled: bool) -> Self {
+        self.general = enabled;
+        self
+    }
+
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.validation = enabled;
+        self
+    }
+
+    pub fn performance(mut self, enabled: bool) -> Self {
+        self.performance = enabled;
+        self
+    }
+
+    pub fn panic_on_error(mut self, enabled: bool) -> Self {
+        self.panic_on_error = enabled;
+        self
+    }
+}
+
+impl HeadlessSettings {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            frame_limit: None,
+            output_dir: None,
+        }
+    }
+
+    pub fn frame_limit(mut self, frames: u64) -> Self {
+        self.frame_limit = Some(frames);
+        self
+    }
+
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(dir.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// How shadow edges are softened.
+pub enum ShadowFilter {
+    /// Percentage-closer filtering, a fixed blur `radius` texels wide.
+    Pcf { radius: f32 },
+    /// Percentage-closer soft shadows, sharp near the caster and softer away from it.
+    /// The sun is `sun_angle` radians wide, point and spot lights are spheres of `light_radius`.
+    Pcss { sun_angle: f32, light_radius: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Shadows of the brightest directional light, through cascaded shadow maps, and of the brightest
+/// point and spot lights, through cube and perspective shadow maps.
+pub struct ShadowSettings {
+    pub enabled: bool,
+    pub filter: ShadowFilter,
+    /// Cascades splitting the view for the directional light, 1 to 4.
+    pub cascades: u32,
+    /// How far from the camera directional shadows reach, the far plane if None.
+    pub distance: Option<f32>,
+    /// Texels across every shadow map, cascades and cube faces alike.
+    pub resolution: u32,
+    /// How many point and spot lights cast shadows, each point light takes six shadow maps.
+    pub max_local_lights: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            filter: ShadowFilter::Pcf { radius: 1.5 },
+            cascades: 4,
+            distance: None,
+            resolution: 1024,
+            max_local_lights: 4,
+        }
+    }
+}
+
+impl ShadowSettings {
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+
+    pub fn filter(mut self, filter: ShadowFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn cascades(mut self, cascades: u32) -> Self {
+        self.cascades = cascades.clamp(1, 4);
+        self
+    }
+
+    pub fn distance(mut self, distance: f32) -> Self {
+        self.distance = Some(distance);
+        self
+    }
+
+    pub fn resolution(mut self, resolution: u32) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    pub fn max_local_lights(mut self, lights: u32) -> Self {
+        self.max_local_lights = lights;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// How the HDR image is squeezed into the range the display shows. An HDR display shows
+/// everything up to paper white as is and rolls brighter highlights off towards its peak,
+/// with any tonemapper but None, which clips at the peak.
+pub enum Tonemapper {
+    /// Clips everything brighter than white.
+    None,
+    /// The ACES reference rendering and output transforms, through Stephen Hill's fit.
+    Aces,
+    /// Troy Sobotka's AgX, desaturates highlights instead of skewing their hue.
+    Agx,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// How edges are smoothed after tonemapping. SMAA computes the coverage of horizontal and
+/// vertical edge shapes directly instead of reading the reference implementation's lookup
+/// textures, and leaves diagonal shapes to the horizontal and vertical ones they're made of.
+pub enum AntiAliasing {
+    None,
+    /// Fast approximate anti-aliasing, one pass over the tonemapped image.
+    Fxaa,
+    /// Subpixel morphological anti-aliasing: finds edges, measures the shapes they form and
+    /// blends across them by how much each pixel is covered. Sharper than FXAA, in three passes.
+    Smaa,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The chain that turns the HDR image the scene is shaded into into what's shown: exposure, bloom,
+/// tonemapping, color grading and anti-aliasing, in that order. Every effect can be switched at
+/// runtime through [`App::post_controls`].
+pub struct PostSettings {
+    /// Adapts exposure to the average brightness of the frame, measured with a luminance histogram.
+    pub auto_exposure: bool,
+    /// In stops, on top of auto exposure or as the whole exposure without it.
+    pub exposure_compensation: f32,
+    /// How fast auto exposure catches up with a change in brightness, per second.
+    pub adaptation_speed: f32,
+    pub bloom: bool,
+    /// How much of the blurred image is mixed in, 0 to 1.
+    pub bloom_intensity: f32,
+    pub tonemapper: Tonemapper,
+    /// Grades through the LUT given to [`App::color_grading_lut`], ignored without one.
+    pub color_grading: bool,
+    pub anti_aliasing: AntiAliasing,
+    /// In nits, how bright SDR white is shown on an HDR display. Ignored in SDR.
+    pub paper_white: f32,
+    /// In nits, the brightest an HDR display is asked to go. Ignored in SDR.
+    pub peak_brightness: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            auto_exposure: true,
+            exposure_compensation: 0.0,
+            adaptation_speed: 2.0,
+            bloom: true,
+            bloom_intensity: 0.04,
+            tonemapper: Tonemapper::Agx,
+            color_grading: true,
+            anti_aliasing: AntiAliasing::Fxaa,
+            // The reference white of ITU-R BT.2408 and a common HDR10 mastering peak
+            paper_white: 203.0,
+            peak_brightness: 1000.0,
+        }
+    }
+}
+
+impl PostSettings {
+    /// Only tonemapping, with a fixed exposure of one.
+    pub fn minimal() -> Self {
+        Self {
+            auto_exposure: false,
+            bloom: false,
+            color_grading: false,
+            anti_aliasing: AntiAliasing::None,
+            ..Default::default()
+        }
+    }
+
+    pub fn auto_exposure(mut self, enabled: bool) -> Self {
+        self.auto_exposure = enabled;
+        self
+    }
+
+    pub fn exposure_compensation(mut self, stops: f32) -> Self {
+        self.exposure_compensation = stops;
+        self
+    }
+
+    pub fn bloom(mut self, enabled: bool) -> Self {
+        self.bloom = enabled;
+        self
+    }
+
+    pub fn bloom_intensity(mut self, intensity: f32) -> Self {
+        self.bloom_intensity = intensity.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn tonemapper(mut self, tonemapper: Tonemapper) -> Self {
+        self.tonemapper = tonemapper;
+        self
+    }
+
+    pub fn color_grading(mut self, enabled: bool) -> Self {
+        self.color_grading = enabled;
+        self
+    }
+
+    pub fn anti_aliasing(mut self, anti_aliasing: AntiAliasing) -> Self {
+        self.anti_aliasing = anti_aliasing;
+        self
+    }
+
+    /// Both in nits, the peak is kept at or above paper white.
+    pub fn hdr_brightness(mut self, paper_white: f32, peak: f32) -> Self {
+        self.paper_white = paper_white.max(1.0);
+        self.peak_brightness = peak.max(self.paper_white);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// The range and encoding frames are presented in.
+pub enum ColorOutput {
+    /// sRGB, in 10 bits per channel when the display takes them and 8 otherwise.
+    #[default]
+    Sdr,
+    /// Rec. 2020 primaries through the ST 2084 (PQ) curve, in 10 bits per channel.
+    Hdr10,
+    /// Linear Rec. 709 in half floats, where 1.0 is 80 nits and brighter goes above it.
+    ScRgb,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Shared [`PostSettings`], the renderer picks up changes at the start of the next frame.
+/// Clones change the same settings, so one can be moved into a script.
+pub struct PostControls(Arc<Mutex<PostSettings>>);
+
+impl PostControls {
+    pub fn get(&self) -> PostSettings {
+        *self.0.lock().unwrap_or_else(|x| x.into_inner())
+    }
+
+    pub fn set(&self, settings: PostSettings) {
+        *self.0.lock().unwrap_or_else(|x| x.into_inner()) = settings;
+    }
+
+    /// Changes the settings in place, like `controls.update(|x| x.bloom = !x.bloom)`.
+    pub fn update(&self, change: impl FnOnce(&mut PostSettings)) {
+        change(&mut self.0.lock().unwrap_or_else(|x| x.into_inner()));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Whether presenting waits for the display to refresh.
+pub enum VSync {
+    /// Every frame waits for a refresh, never tears.
+    On,
+    /// Frames are shown as soon as they're done. Mailbox replaces frames still waiting for a
+    /// refresh so nothing tears, displays without it fall back on immediate presentation, which may.
+    #[default]
+    Off,
+    /// Waits for a refresh unless the frame missed the last one, then it's shown right away
+    /// and may tear.
+    Adaptive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// How frames are presented and paced. Every setting can be switched at runtime through
+/// [`App::display_controls`], changing vsync rebuilds the swapchain.
+pub struct DisplaySettings {
+    /// Ignored by headless Apps, which never present.
+    pub vsync: VSync,
+    /// The most frames per second the renderer starts, on top of vsync. Uncapped if None.
+    pub fps_limit: Option<f32>,
+}
+
+impl DisplaySettings {
+    pub fn vsync(mut self, vsync: VSync) -> Self {
+        self.vsync = vsync;
+        self
+    }
+
+    /// Caps the frame rate, anything but a positive rate uncaps it.
+    pub fn fps_limit(mut self, fps: f32) -> Self {
+        self.fps_limit = (fps > 0.0).then_some(fps);
+        self
+    }
+
+    pub fn uncapped(mut self) -> Self {
+        self.fps_limit = None;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Shared [`DisplaySettings`], the renderer picks up changes before the next frame.
+/// Clones change the same settings, so one can be handed to a settings menu.
+pub struct DisplayControls(Arc<Mutex<DisplaySettings>>);
+
+impl DisplayControls {
+    pub fn get(&self) -> DisplaySettings {
+        *self.0.lock().unwrap_or_else(|x| x.into_inner())
+    }
+
+    pub fn set(&self, settings: DisplaySettings) {
+        *self.0.lock().unwrap_or_else(|x| x.into_inner()) = settings;
+    }
+
+    /// Changes the settings in place, like `controls.update(|x| x.vsync = VSync::On)`.
+    pub fn update(&self, change: impl FnOnce(&mut DisplaySettings)) {
+        change(&mut self.0.lock().unwrap_or_else(|x| x.into_inner()));
+    }
+}
+
+/// What the renderer draws and how, handed over from the [`App`] when it starts.
+pub(crate) struct RendererConfig {
+    pub(crate) asset_dir: PathBuf,
+    pub(crate) scenes: Vec<PathBuf>,
+    pub(crate) shadows: ShadowSettings,
+    pub(crate) post: PostControls,
+    pub(crate) lut: Option<PathBuf>,
+    pub(crate) display: DisplayControls,
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
 /// This struct represents the application's version.
@@ -76,11 +588,46 @@ impl AppVersion {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Why [`App::run`] failed. Renderer failures have a `RendererError` beneath them, saying what
+/// went wrong and on which device, so a game can show a friendly message instead of crashing.
+pub enum AppError {
+    /// The renderer couldn't start, or stopped on an error.
+    Renderer,
+    /// The renderer thread panicked.
+    RendererPanicked,
+    /// A script panicked.
+    ScriptPanicked,
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Renderer => "The renderer failed",
+            Self::RendererPanicked => "The renderer panicked",
+            Self::ScriptPanicked => "A script panicked",
+        })
+    }
+}
+
+impl std::error::Error for AppError {}
+
 pub struct App {
     scripts: Vec<Box<dyn Fn() + Send + 'static>>,
     name: &'static str,
     version: AppVersion,
     window_settings: WindowSettings,
+    headless: Option<HeadlessSettings>,
+    frame_callback: Option<FrameCallback>,
+    device_selector: Option<DeviceSelector>,
+    asset_dir: PathBuf,
+    scenes: Vec<PathBuf>,
+    shadows: ShadowSettings,
+    post: PostControls,
+    lut: Option<PathBuf>,
+    color_output: ColorOutput,
+    display: DisplayControls,
+    validation: ValidationSettings,
 }
 
 impl App {
@@ -94,15 +641,117 @@ impl App {
             name,
             version,
             window_settings: window_settings.unwrap_or_default(),
+            headless: None,
+            frame_callback: None,
+            device_selector: None,
+            asset_dir: PathBuf::from("assets"),
+            scenes: Vec::new(),
+            shadows: ShadowSettings::default(),
+            post: PostControls::default(),
+            lut: None,
+            color_output: ColorOutput::default(),
+            display: DisplayControls::default(),
+            validation: ValidationSettings::default(),
         }
     }
 
+    /// Where shaders and other assets are loaded from, `assets` by default.
+    /// Shaders live in its `shaders` directory and are reloaded when they change, files there
+    /// override the built-in shaders of the same name.
+    pub fn asset_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.asset_dir = dir.into();
+        self
+    }
+
+    /// Loads a `.gltf`, `.glb` or `.blend` [`Scene`] when the renderer starts and draws it.
+    /// Relative paths are resolved against the asset directory. A scene that fails to load is
+    /// reported and skipped, as is anything it contains the engine can't import.
+    pub fn add_scene(mut self, path: impl Into<PathBuf>) -> Self {
+        self.scenes.push(path.into());
+        self
+    }
+
+    /// How shadows are rendered, see [`ShadowSettings`].
+    pub fn shadows(mut self, settings: ShadowSettings) -> Self {
+        self.shadows = settings;
+        self
+    }
+
+    /// The post-processing the App starts with, see [`PostSettings`].
+    pub fn post_processing(self, settings: PostSettings) -> Self {
+        self.post.set(settings);
+        self
+    }
+
+    /// A handle to change the post-processing while the App runs.
+    pub fn post_controls(&self) -> PostControls {
+        self.post.clone()
+    }
+
+    /// Grades colors through a LUT image, relative to the asset directory. It's laid out the
+    /// common way, as a horizontal strip of N slices of N by N texels with blue growing from
+    /// slice to slice, and is applied to the tonemapped sRGB values.
+    pub fn color_grading_lut(mut self, path: impl Into<PathBuf>) -> Self {
+        self.lut = Some(path.into());
+        self
+    }
+
+    /// Prefers presenting in HDR or SDR, see [`ColorOutput`]. A display that can't show the preferred
+    /// output gets the other HDR one, then SDR. Headless Apps render into an image of exactly this
+    /// format: HDR10 frames are read back as the 16 bit PQ signal and scRGB ones as linear floats,
+    /// written as PNG and OpenEXR.
+    pub fn color_output(mut self, output: ColorOutput) -> Self {
+        self.color_output = output;
+        self
+    }
+
+    /// How frames are presented and paced when the App starts, see [`DisplaySettings`].
+    pub fn display(self, settings: DisplaySettings) -> Self {
+        self.display.set(settings);
+        self
+    }
+
+    /// A handle to change vsync and the frame rate cap while the App runs.
+    pub fn display_controls(&self) -> DisplayControls {
+        self.display.clone()
+    }
+
+    /// Which validation messages are logged and whether errors panic, see [`ValidationSettings`].
+    pub fn validation(mut self, settings: ValidationSettings) -> Self {
+        self.validation = settings;
+        self
+    }
+
+    /// Forces a specific GPU, see [`DeviceSelector`].
+    pub fn prefer_device(mut self, selector: DeviceSelector) -> Self {
+        self.device_selector = Some(selector);
+        self
+    }
+
+    /// Runs without a window, see [`HeadlessSettings`].
+    pub fn headless(mut self, settings: HeadlessSettings) -> Self {
+        self.headless = Some(settings);
+        self
+    }
+
+    /// Reads every frame back to CPU memory and hands it to `callback`, in frame order.
+    /// Only headless Apps read their frames back.
+    pub fn on_frame(mut self, callback: FrameCallback) -> Self {
+        self.frame_callback = Some(callback);
+        self
+    }
+
+    /// Runs `script` on the scripting thread once the App starts, after the ones added before it.
+    /// Scripts that loop should return once [`shutdown_requested`] says so.
     pub fn add_script(mut self, script: Box<dyn Fn() + Send + 'static>) -> Self {
         self.scripts.push(script);
         self
     }
 
-    pub fn run(self) {
+    /// Runs until the window is closed, or headless Apps reach their frame limit, then waits for
+    /// the GPU, the renderer and every script to finish. Fails when the renderer couldn't start or
+    /// stopped on an error, or when any thread panicked.
+    pub fn run(mut self) -> Result<(), Report<AppError>> {
         Lazy::force(&utils::TIMER);
         // Fix this later
         #[cfg(any(
@@ -113,50 +762,238 @@ impl App {
             panic!("Debug feature enabled but debug assertions isn't (or vice versa)")
         }
 
+        if let Some(settings) = self.headless.take() {
+            return self.run_headless(settings);
+        }
+
         let (tx, rx) = channel::<AppState>();
         let (oneshot_tx, oneshot_rx) = oneshot::channel::<RawWindowingHandles>();
-        let (tokio_tx, tokio_rx) = mpsc::channel::<PhysicalSize<u32>>(16);
+        let (tokio_tx, mut tokio_rx) = mpsc::channel::<PhysicalSize<u32>>(16);
+        // Never sent on, dropping it tells the window the renderer is done with the surface
+        let (done_tx, done_rx) = channel::<()>();
 
         let mut app_window = Box::new(window::AppWindow::default());
         app_window.modify_window_attrs(&self.window_settings);
-        app_window.init_render_communicator(tx.clone());
+        app_window.init_render_communicator(tx.clone(), done_rx);
 
         let name = self.name;
         let version = self.version;
-        let scripts = self.scripts;
+        let device_selector = self.device_selector;
+        let validation = self.validation;
+        let color_output = self.color_output;
+        let vsync = self.display.get().vsync;
+        let config = RendererConfig {
+            asset_dir: self.asset_dir,
+            scenes: self.scenes,
+            shadows: self.shadows,
+            post: self.post,
+            lut: self.lut,
+            display: self.display,
+        };
 
         // Renderer thread
-        let _renderer_thread = std::thread::spawn(move || {
+        let renderer_thread = thread::spawn(move || -> Result<(), Report<AppError>> {
+            let _done = done_tx;
             let rt = tokio::runtime::Runtime::new().unwrap();
             rt.block_on(async move {
-                let surface_handles = oneshot_rx.await.unwrap().unpack();
+                // The window closed before it was ever shown
+                let Ok(surface_handles) = oneshot_rx.await else {
+                    return Ok(());
+                };
+                let surface_handles = surface_handles.unpack();
+                // The window sends its size right after its handles, later sizes are resizes
+                let Some(window_size) = tokio_rx.recv().await else {
+                    return Ok(());
+                };
 
                 #[cfg(feature = "vulkan")]
                 {
-                    let mut vulkan_setup = vk::setup::VulkanSetup::new(
-                        surface_handles,
+                    let mut vk_core = vk::data::VkCore::new(
+                        vk::data::TargetRequest::Window {
+                            handles: surface_handles,
+                            size: window_size,
+                            inner_size_reciever: tokio_rx,
+                            color_output,
+                            vsync,
+                        },
                         name,
                         version.unpack_raw(),
-                        tokio_rx,
+                        device_selector,
+                        validation,
                     )
-                    .expect("Unable to create Vulkan Setup");
-                    vulkan_setup.init_window_communicator(rx);
-                    let core = vk::Core::new(vulkan_setup);
-                    core.main_loop();
+                    .change_context(AppError::Renderer)?;
+                    vk_core.init_window_communicator(rx);
+                    vk::Core::new(vk_core, None, None, config)
+                        .and_then(vk::Core::main_loop)
+                        .change_context(AppError::Renderer)?;
                 }
-            });
+                Ok(())
+            })
         });
 
-        // Scripting thread
-        let _scripting_thread = std::thread::spawn(move || {
+        let scripting_thread = Self::spawn_scripting_thread(self.scripts);
+
+        app_window.start(oneshot_tx, tokio_tx);
+        // The event loop can also end without the window asking, a closed channel stops the renderer too
+        utils::request_shutdown();
+        drop(app_window);
+        drop(tx);
+        let renderer = renderer_thread.join();
+        Self::joined(renderer, scripting_thread.join())
+    }
+
+    fn run_headless(self, settings: HeadlessSettings) -> Result<(), Report<AppError>> {
+        let Self {
+            scripts,
+            name,
+            version,
+            frame_callback,
+            device_selector,
+            validation,
+            asset_dir,
+            scenes,
+            shadows,
+            post,
+            lut,
+            color_output,
+            display,
+            ..
+        } = self;
+        let config = RendererConfig {
+            asset_dir,
+            scenes,
+            shadows,
+            post,
+            lut,
+            display,
+        };
+        // Nothing ever sends on this, but the renderer treats a closed channel as a close request
+        let (tx, rx) = channel::<AppState>();
+
+        // Renderer thread
+        let renderer_thread = thread::spawn(move || -> Result<(), Report<AppError>> {
+            #[cfg(feature = "vulkan")]
+            {
+                let extent = ash::vk::Extent2D {
+                    width: settings.width,
+                    height: settings.height,
+                };
+                let mut vk_core = vk::data::VkCore::new(
+                    vk::data::TargetRequest::Offscreen(extent, color_output),
+                    name,
+                    version.unpack_raw(),
+                    device_selector,
+                    validation,
+                )
+                .change_context(AppError::Renderer)?;
+                vk_core.init_window_communicator(rx);
+                vk::offscreen::FrameReadback::new(frame_callback, settings.output_dir)
+                    .and_then(|readback| {
+                        vk::Core::new(vk_core, Some(readback), settings.frame_limit, config)
+                    })
+                    .and_then(vk::Core::main_loop)
+                    .change_context(AppError::Renderer)?;
+            }
+            Ok(())
+        });
+
+        let scripting_thread = Self::spawn_scripting_thread(scripts);
+
+        // Without an event loop to block on, the renderer decides when the App is done
+        let renderer = renderer_thread.join();
+        utils::request_shutdown();
+        drop(tx);
+        Self::joined(renderer, scripting_thread.join())
+    }
+
+    /// The renderer's failure wins over a script's, panics carry their message.
+    fn joined(
+        renderer: thread::Result<Result<(), Report<AppError>>>,
+        scripting: thread::Result<()>,
+    ) -> Result<(), Report<AppError>> {
+        let panicked = |context: AppError, payload: Box<dyn Any + Send>| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|x| x.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("no panic message"));
+            Report::new(context).attach(message)
+        };
+        renderer
+            .unwrap_or_else(|payload| Err(panicked(AppError::RendererPanicked, payload)))
+            .and(scripting.map_err(|payload| panicked(AppError::ScriptPanicked, payload)))
+    }
+
+    fn spawn_scripting_thread(scripts: Vec<Box<dyn Fn() + Send + 'static>>) -> JoinHandle<()> {
+        thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
             rt.block_on(async move {
                 for script in scripts {
                     script();
                 }
             });
-        });
+        })
+    }
+}
 
-        app_window.start(oneshot_tx, tokio_tx);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_selector_prefixes() {
+        assert_eq!("index:2".parse(), Ok(DeviceSelector::Index(2)));
+        assert_eq!(
+            "name: GeForce ".parse(),
+            Ok(DeviceSelector::Name(String::from("GeForce")))
+        );
+        assert_eq!(
+            "uuid:00112233-4455-6677-8899-aabbccddeeff".parse(),
+            Ok(DeviceSelector::Uuid([
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+                0xee, 0xff
+            ]))
+        );
+        // A prefix makes the rest mean exactly that, even if it looks like something else
+        assert_eq!(
+            "name:1".parse(),
+            Ok(DeviceSelector::Name(String::from("1")))
+        );
+    }
+
+    #[test]
+    fn device_selector_without_prefix() {
+        assert_eq!("1".parse(), Ok(DeviceSelector::Index(1)));
+        assert_eq!(
+            "00112233445566778899AABBCCDDEEFF".parse::<DeviceSelector>(),
+            "uuid:00112233445566778899aabbccddeeff".parse()
+        );
+        assert_eq!(
+            "llvmpipe".parse(),
+            Ok(DeviceSelector::Name(String::from("llvmpipe")))
+        );
+    }
+
+    #[test]
+    fn device_selector_rejects_bad_prefixed_values() {
+        assert!("index:first".parse::<DeviceSelector>().is_err());
+        assert!("index:-1".parse::<DeviceSelector>().is_err());
+        assert!("uuid:0011".parse::<DeviceSelector>().is_err());
+        assert!(
+            "uuid:zz112233445566778899aabbccddeeff"
+                .parse::<DeviceSelector>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn device_selector_display_parses_back() {
+        for selector in [
+            DeviceSelector::Index(3),
+            DeviceSelector::Name(String::from("Radeon")),
+            DeviceSelector::Uuid([0xab; 16]),
+        ] {
+            assert_eq!(selector.to_string().parse(), Ok(selector));
+        }
     }
 }
@@ -3,11 +3,67 @@
 use {
     once_cell::sync::Lazy,
     raw_window_handle::{RawDisplayHandle, RawWindowHandle},
-    std::time::{Duration, Instant},
+    std::{
+        fmt,
+        io::{self, Write},
+        sync::atomic::{AtomicBool, Ordering},
+        time::{Duration, Instant},
+    },
 };
 
 pub(crate) static TIMER: Lazy<Instant> = Lazy::new(Instant::now);
 
+// Set once the App starts shutting down, never cleared
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Whether the App is shutting down. [`App::run`](crate::App::run) waits for every script to
+/// return, so scripts that loop should check this and return once it's set.
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::Acquire)
+}
+
+pub(crate) fn request_shutdown() {
+    SHUTDOWN.store(true, Ordering::Release);
+}
+
+// Sleeping overshoots by up to this much on most schedulers, the rest of the wait spins
+const SPIN_WINDOW: Duration = Duration::from_millis(1);
+
+/// Spaces frames evenly on the [`TIMER`] clock when the frame rate is capped.
+#[derive(Debug, Default)]
+pub(crate) struct FramePacer {
+    // When the next frame may start, since startup
+    next_frame: Option<Duration>,
+}
+
+impl FramePacer {
+    /// Blocks until the next frame may start at `fps_limit` frames per second, uncapped if None
+    /// or if the rate is too low or not a positive number.
+    /// A frame that ran late pushes the ones after it back instead of letting them catch up in a burst.
+    pub(crate) fn wait(&mut self, fps_limit: Option<f32>) {
+        // Zero, negative and NaN rates give an interval that is infinite, negative or NaN
+        let Some(interval) = fps_limit.and_then(|fps| Duration::try_from_secs_f32(1.0 / fps).ok())
+        else {
+            self.next_frame = None;
+            return;
+        };
+        let now = TIMER.elapsed();
+        let target = match self.next_frame {
+            Some(next) if next.saturating_add(interval) > now => next,
+            _ => now,
+        };
+        if let Some(remaining) = target.checked_sub(now) {
+            if let Some(sleep) = remaining.checked_sub(SPIN_WINDOW) {
+                std::thread::sleep(sleep);
+            }
+            while TIMER.elapsed() < target {
+                std::thread::yield_now();
+            }
+        }
+        self.next_frame = Some(target.saturating_add(interval));
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AppState {
     Closed = 0,
@@ -58,62 +114,81 @@ fn format_duration(d: Duration) -> String {
     format!("{}:{:02}:{:02}.{:03}", hours, mins, secs_rem, millis)
 }
 
+/// Whether [`debug_logln!`] logs anything, decided by the engine's build rather than the caller's.
+#[doc(hidden)]
+pub const DEBUG_LOG: bool = cfg!(all(debug_assertions, feature = "debug"));
+
+/// What [`logln!`] without arguments writes, the time since startup.
+#[doc(hidden)]
+pub fn log_elapsed() {
+    let mut lock = io::stdout().lock();
+    // Logging has nowhere to report its own failures to
+    let _ = writeln!(
+        lock,
+        "Time Elapsed since Startup: {}",
+        format_duration(TIMER.elapsed())
+    );
+    let _ = lock.flush();
+}
+
+/// What [`logln!`] writes, the time since startup, then `message` with a tag for every word of `location`.
+#[doc(hidden)]
+pub fn log_line(location: &str, message: fmt::Arguments) {
+    let tags: String = location
+        .to_uppercase()
+        .split_whitespace()
+        .map(|word| format!("[{word}]"))
+        .collect();
+    // Both lines go out under one lock, so lines from other threads can't end up in between
+    let mut lock = io::stdout().lock();
+    let _ = writeln!(
+        lock,
+        "Time Elapsed since Startup: {}",
+        format_duration(TIMER.elapsed())
+    );
+    let _ = writeln!(lock, "[ENGINE]{tags}; {message}");
+    let _ = lock.flush();
+}
+
+/// Logs the time since startup and a formatted message, tagged with every word of the location:
+/// `logln!("vulkan validation", "{count} messages")` writes `[ENGINE][VULKAN][VALIDATION]; ...`.
+/// Without arguments, only the time is logged.
 #[macro_export]
 macro_rules! logln {
-    () => {{
-        use std::io::Write;
-        let elapsed = START.elapsed();
-        let ts = format_duration(elapsed);
-        let mut lock = io::stdout().lock();
-        writeln!(lock, "Time Elapsed since Startup: {}", ts).expect("Write Failed");
-        lock.flush().expect("Flush Failed");
-    }};
-    ($msg:expr, $location:expr) => {{
-        use std::io::Write;
-        let elapsed = START.elapsed();
-        let ts = format_duration(elapsed);
-        let mut lock = io::stdout().lock();
-        let location_upper = format!("{}", $location).to_uppercase();
-        let words = location_upper.split_whitespace()
-            .map(|w| format!("[{}]", w.to_string()))
-            .collect();
-        let message = format!($fmt, $(, $args)*);
-        let tags = words.join("");
-        writeln!(lock, "Time Elapsed since Startup: {}", ts).expect("Write Failed");
-        writeln!(lock, "[ENGINE]{}; {}", words, message);
-        lock.flush().expect("Flush Failed");
-    }};
-    ($fmt:expr, $($args:expr)*, $location:expr) => {{
-        use std::io::Write;
-        let elapsed = START.elapsed();
-        let ts = format_duration(elapsed);
-        let mut lock = io::stdout().lock();
-        let location_upper = format!("{}", $location).to_uppercase();
-        let words = location_upper.split_whitespace()
-            .map(|w| format!("[{}]", w.to_string()))
-            .collect();
-        let tags = words.join("");
-        let message = format!($fmt, $(, $args)*);
-        writeln!(lock, "Time Elapsed since Startup: {}", ts).expect("Write Failed");
-        writeln!(lock, "[ENGINE]{}; {}", words, message);
-        lock.flush().expect("Flush Failed");
-    }};
+    () => {
+        $crate::log_elapsed()
+    };
+    ($location:expr, $($arg:tt)+) => {
+        $crate::log_line(&$location, format_args!($($arg)+))
+    };
 }
 
-/// Use this for debug mode ONLY, this will evaluate to nothing when debug_assertions is disabled & the debug feature.
+/// [`logln!`](crate::logln) for debug builds of the engine with the `debug` feature, checked at runtime
+/// against [`DEBUG_LOG`]. The arguments are still type checked in every build, but
+/// never formatted when it's off.
 #[macro_export]
 macro_rules! debug_logln {
-    () => {{
-        #[cfg(all(debug_assertions, feature = "debug"))]
-        {logln!()}
-    }};
-    ($msg:expr, $location:expr) => {{
-        #[cfg(all(debug_assertions, feature = "debug"))]
-        {logln!($msg, $location)}
-    }};
-    ($fmt:expr, $($args:expr)*, $location:expr) => {{
-        #[cfg(all(debug_assertions, feature = "debug"))] {
-            logln!($fmt, $($args)*, $location);
+    ($($arg:tt)*) => {
+        if $crate::DEBUG_LOG {
+            $crate::logln!($($arg)*)
         }
-    }};
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_without_an_interval_are_uncapped() {
+        let mut pacer = FramePacer::default();
+        for fps in [0.0, -60.0, f32::NAN, f32::MIN_POSITIVE] {
+            pacer.wait(Some(fps));
+            assert!(pacer.next_frame.is_none(), "{fps} fps should be uncapped");
+        }
+        // An infinite rate has no interval to wait for
+        pacer.wait(Some(f32::INFINITY));
+        pacer.wait(Some(f32::INFINITY));
+        assert!(pacer.next_frame.is_some());
+    }
 }
@@ -0,0 +1,294 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::capabilities::DescriptorLimits,
+    ash::{Device, prelude::VkResult, vk},
+    std::sync::Arc,
+};
+
+// Upper bounds, the device limits can only make these smaller
+const MAX_SAMPLED_IMAGES: u32 = 16 * 1024;
+const MAX_STORAGE_IMAGES: u32 = 1024;
+const MAX_STORAGE_BUFFERS: u32 = 4 * 1024;
+const MAX_SAMPLERS: u32 = 128;
+/// Push constants every bindless pipeline gets, 128 bytes is the guaranteed minimum.
+pub const PUSH_CONSTANT_SIZE: u32 = 128;
+
+/// The arrays of the bindless set, each one is its own binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DescriptorKind {
+    SampledImage,
+    StorageImage,
+    StorageBuffer,
+    Sampler,
+}
+
+impl DescriptorKind {
+    const ALL: [Self; 4] = [
+        Self::SampledImage,
+        Self::StorageImage,
+        Self::StorageBuffer,
+        Self::Sampler,
+    ];
+
+    /// The binding shaders declare the array at, in set 0.
+    pub fn binding(self) -> u32 {
+        self as u32
+    }
+
+    /// The kind living at `binding` of set 0, if any.
+    pub fn from_binding(binding: u32) -> Option<Self> {
+        Self::ALL.get(binding as usize).copied()
+    }
+
+    /// The most descriptors the array can have, the device limits may allow fewer.
+    pub(crate) fn max_count(self) -> u32 {
+        match self {
+            Self::SampledImage => MAX_SAMPLED_IMAGES,
+            Self::StorageImage => MAX_STORAGE_IMAGES,
+            Self::StorageBuffer => MAX_STORAGE_BUFFERS,
+            Self::Sampler => MAX_SAMPLERS,
+        }
+    }
+
+    pub(crate) fn descriptor_type(self) -> vk::DescriptorType {
+        match self {
+            Self::SampledImage => vk::DescriptorType::SAMPLED_IMAGE,
+            Self::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+            Self::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
+            Self::Sampler => vk::DescriptorType::SAMPLER,
+        }
+    }
+}
+
+/// A slot in the bindless set, `index` is what shaders use to look the resource up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DescriptorHandle {
+    pub kind: DescriptorKind,
+    pub index: u32,
+}
+
+/// Hands out the indices of one array, reusing freed ones first.
+struct Slots {
+    capacity: u32,
+    next: u32,
+    free: Vec<u32>,
+}
+
+impl Slots {
+    fn allocate(&mut self) -> Option<u32> {
+        self.free.pop().or_else(|| {
+            (self.next < self.capacity).then(|| {
+                self.next += 1;
+                self.next - 1
+            })
+        })
+    }
+}
+
+/// One global descriptor set every shader sees, holding all sampled images, storage images,
+/// storage buffers and samplers. Slots are written with update-after-bind, so they can change
+/// while the set is bound, and freed slots are only reused once the GPU is done with them.
+pub struct BindlessHeap {
+    device: Arc<Device>,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    slots: [Slots; 4],
+    // Released handles and the timeline value after which nothing uses them anymore
+    pending: Vec<(u64, DescriptorHandle)>,
+}
+
+impl BindlessHeap {
+    pub(crate) fn new(device: Arc<Device>, limits: &DescriptorLimits) -> VkResult<Self> {
+        let mut capacities = [
+            MAX_SAMPLED_IMAGES.min(limits.sampled_images),
+            MAX_STORAGE_IMAGES.min(limits.storage_images),
+            MAX_STORAGE_BUFFERS.min(limits.storage_buffers),
+            MAX_SAMPLERS.min(limits.samplers),
+        ];
+        // Sampled images give way when the stage can't hold everything at once
+        let others: u32 = capacities[1..].iter().sum();
+        capacities[0] = capacities[0].min(limits.resources.saturating_sub(others));
+
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = DescriptorKind::ALL
+            .iter()
+            .zip(capacities)
+            .map(|(kind, capacity)| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(kind.binding())
+                    .descriptor_type(kind.descriptor_type())
+                    .descriptor_count(capacity)
+                    .stage_flags(vk::ShaderStageFlags::ALL)
+            })
+            .collect();
+        // Unwritten slots are fine as long as shaders don't read them
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+            4];
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::default()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(&bindings)
+            .push_next(&mut binding_flags_info);
+
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = DescriptorKind::ALL
+            .iter()
+            .zip(capacities)
+            .map(|(kind, capacity)| vk::DescriptorPoolSize {
+                ty: kind.descriptor_type(),
+                descriptor_count: capacity,
+            })
+            .collect();
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+
+        unsafe {
+            let layout = device.create_descriptor_set_layout(&layout_info, None)?;
+            let pool = device.create_descriptor_pool(&pool_info, None)?;
+            let layouts = [layout];
+            let set = device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(pool)
+                    .set_layouts(&layouts),
+            )?[0];
+            let push_constants = [vk::PushConstantRange::default()
+                .stage_flags(vk::ShaderStageFlags::ALL)
+                .size(PUSH_CONSTANT_SIZE)];
+            let pipeline_layout = device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&layouts)
+                    .push_constant_ranges(&push_constants),
+                None,
+            )?;
+            Ok(Self {
+                device,
+                pool,
+                layout,
+                set,
+                pipeline_layout,
+                slots: capacities.map(|capacity| Slots {
+                    capacity,
+                    next: 0,
+                    free: Vec::new(),
+                }),
+                pending: Vec::new(),
+            })
+        }
+    }
+
+    /// The layout every bindless pipeline uses: this set plus [`PUSH_CONSTANT_SIZE`] bytes of push constants.
+    pub fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    /// Binds the set at index 0 for `bind_point`.
+    pub fn bind(&self, command_buffer: vk::CommandBuffer, bind_point: vk::PipelineBindPoint) {
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                bind_point,
+                self.pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            );
+        }
+    }
+
+    /// `None` once every sampled image slot is taken, callers that return a `VkResult` report it
+    /// as `ERROR_OUT_OF_POOL_MEMORY`, like a full descriptor pool.
+    pub fn add_sampled_image(
+        &mut self,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> Option<DescriptorHandle> {
+        let info = [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(layout)];
+        self.write(DescriptorKind::SampledImage, |write| {
+            write.image_info(&info)
+        })
+    }
+
+    /// Storage images are always accessed in `GENERAL` layout.
+    pub fn add_storage_image(&mut self, view: vk::ImageView) -> Option<DescriptorHandle> {
+        let info = [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::GENERAL)];
+        self.write(DescriptorKind::StorageImage, |write| {
+            write.image_info(&info)
+        })
+    }
+
+    pub fn add_storage_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> Option<DescriptorHandle> {
+        let info = [vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(offset)
+            .range(range)];
+        self.write(DescriptorKind::StorageBuffer, |write| {
+            write.buffer_info(&info)
+        })
+    }
+
+    pub fn add_sampler(&mut self, sampler: vk::Sampler) -> Option<DescriptorHandle> {
+        let info = [vk::DescriptorImageInfo::default().sampler(sampler)];
+        self.write(DescriptorKind::Sampler, |write| write.image_info(&info))
+    }
+
+    fn write<'a>(
+        &mut self,
+        kind: DescriptorKind,
+        fill: impl FnOnce(vk::WriteDescriptorSet<'a>) -> vk::WriteDescriptorSet<'a>,
+    ) -> Option<DescriptorHandle> {
+        let index = self.slots[kind as usize].allocate()?;
+        let write = fill(
+            vk::WriteDescriptorSet::default()
+                .dst_set(self.set)
+                .dst_binding(kind.binding())
+                .dst_array_element(index)
+                .descriptor_type(kind.descriptor_type()),
+        );
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+        Some(DescriptorHandle { kind, index })
+    }
+
+    /// Frees `handle` once the timeline reaches `retire_value`, the value of the last frame that may use it.
+    pub fn release(&mut self, handle: DescriptorHandle, retire_value: u64) {
+        self.pending.push((retire_value, handle));
+    }
+
+    /// Makes the slots of every frame that finished (`completed_value` on the timeline) reusable.
+    pub(crate) fn reclaim(&mut self, completed_value: u64) {
+        let slots = &mut self.slots;
+        self.pending.retain(|&(retire_value, handle)| {
+            let done = retire_value <= completed_value;
+            if done {
+                slots[handle.kind as usize].free.push(handle.index);
+            }
+            !done
+        });
+    }
+}
+
+impl Drop for BindlessHeap {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            // Destroying the pool frees the set with it
+            self.device.destroy_descriptor_pool(self.pool, None);
+            self.device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
@@ -0,0 +1,415 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    ash::{Instance, vk},
+    std::ffi::{CStr, c_char},
+};
+
+/// Extensions the engine can't run without, only the swapchain when presenting.
+/// Everything else we rely on is core in Vulkan 1.3, its features are checked instead.
+pub(crate) const REQUIRED_PRESENT_EXTENSIONS: &[&CStr] = &[ash::khr::swapchain::NAME];
+
+/// Extensions that are enabled when supported, the renderer checks [`DeviceCapabilities`] before using them.
+pub(crate) const OPTIONAL_DEVICE_EXTENSIONS: &[&CStr] = &[
+    // Query VRAM Usage
+    ash::ext::memory_budget::NAME,
+    // Logic op and patch control points as dynamic state
+    ash::ext::extended_dynamic_state2::NAME,
+    // Change Polygon Mode, Blend State, Samples, etc. at draw time
+    ash::ext::extended_dynamic_state3::NAME,
+    // Dynamic Vertex Bindings/Attibutes
+    ash::ext::vertex_input_dynamic_state::NAME,
+    // Per-attachment color write control
+    ash::ext::color_write_enable::NAME,
+    // Pipeline creation feedback, cheaper pipeline binaries
+    ash::khr::maintenance5::NAME,
+];
+
+/// The `VK_EXT_extended_dynamic_state3` sub-features the engine knows how to use.
+/// The vendor specific ones (coverage modulation, viewport swizzle, ...) are never enabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DynamicState3 {
+    pub tessellation_domain_origin: bool,
+    pub depth_clamp_enable: bool,
+    pub polygon_mode: bool,
+    pub rasterization_samples: bool,
+    pub sample_mask: bool,
+    pub alpha_to_coverage_enable: bool,
+    pub alpha_to_one_enable: bool,
+    pub logic_op_enable: bool,
+    pub color_blend_enable: bool,
+    pub color_blend_equation: bool,
+    pub color_write_mask: bool,
+    pub depth_clip_enable: bool,
+    pub provoking_vertex_mode: bool,
+    pub line_rasterization_mode: bool,
+    pub depth_clip_negative_one_to_one: bool,
+}
+
+impl DynamicState3 {
+    fn from_features(features: &vk::PhysicalDeviceExtendedDynamicState3FeaturesEXT) -> Self {
+        Self {
+            tessellation_domain_origin: supported(
+                features.extended_dynamic_state3_tessellation_domain_origin,
+            ),
+            depth_clamp_enable: supported(features.extended_dynamic_state3_depth_clamp_enable),
+            polygon_mode: supported(features.extended_dynamic_state3_polygon_mode),
+            rasterization_samples: supported(
+                features.extended_dynamic_state3_rasterization_samples,
+            ),
+            sample_mask: supported(features.extended_dynamic_state3_sample_mask),
+            alpha_to_coverage_enable: supported(
+                features.extended_dynamic_state3_alpha_to_coverage_enable,
+            ),
+            alpha_to_one_enable: supported(features.extended_dynamic_state3_alpha_to_one_enable),
+            logic_op_enable: supported(features.extended_dynamic_state3_logic_op_enable),
+            color_blend_enable: supported(features.extended_dynamic_state3_color_blend_enable),
+            color_blend_equation: supported(features.extended_dynamic_state3_color_blend_equation),
+            color_write_mask: supported(features.extended_dynamic_state3_color_write_mask),
+            depth_clip_enable: supported(features.extended_dynamic_state3_depth_clip_enable),
+            provoking_vertex_mode: supported(
+                features.extended_dynamic_state3_provoking_vertex_mode,
+            ),
+            line_rasterization_mode: supported(
+                features.extended_dynamic_state3_line_rasterization_mode,
+            ),
+            depth_clip_negative_one_to_one: supported(
+                features.extended_dynamic_state3_depth_clip_negative_one_to_one,
+            ),
+        }
+    }
+
+    /// The feature struct to chain into `DeviceCreateInfo`, only what is supported is set.
+    pub(crate) fn features(&self) -> vk::PhysicalDeviceExtendedDynamicState3FeaturesEXT<'static> {
+        vk::PhysicalDeviceExtendedDynamicState3FeaturesEXT::default()
+            .extended_dynamic_state3_tessellation_domain_origin(self.tessellation_domain_origin)
+            .extended_dynamic_state3_depth_clamp_enable(self.depth_clamp_enable)
+            .extended_dynamic_state3_polygon_mode(self.polygon_mode)
+            .extended_dynamic_state3_rasterization_samples(self.rasterization_samples)
+            .extended_dynamic_state3_sample_mask(self.sample_mask)
+            .extended_dynamic_state3_alpha_to_coverage_enable(self.alpha_to_coverage_enable)
+            .extended_dynamic_state3_alpha_to_one_enable(self.alpha_to_one_enable)
+            .extended_dynamic_state3_logic_op_enable(self.logic_op_enable)
+            .extended_dynamic_state3_color_blend_enable(self.color_blend_enable)
+            .extended_dynamic_state3_color_blend_equation(self.color_blend_equation)
+            .extended_dynamic_state3_color_write_mask(self.color_write_mask)
+            .extended_dynamic_state3_depth_clip_enable(self.depth_clip_enable)
+            .extended_dynamic_state3_provoking_vertex_mode(self.provoking_vertex_mode)
+            .extended_dynamic_state3_line_rasterization_mode(self.line_rasterization_mode)
+            .extended_dynamic_state3_depth_clip_negative_one_to_one(
+                self.depth_clip_negative_one_to_one,
+            )
+    }
+
+    fn count(&self) -> u64 {
+        [
+            self.tessellation_domain_origin,
+            self.depth_clamp_enable,
+            self.polygon_mode,
+            self.rasterization_samples,
+            self.sample_mask,
+            self.alpha_to_coverage_enable,
+            self.alpha_to_one_enable,
+            self.logic_op_enable,
+            self.color_blend_enable,
+            self.color_blend_equation,
+            self.color_write_mask,
+            self.depth_clip_enable,
+            self.provoking_vertex_mode,
+            self.line_rasterization_mode,
+            self.depth_clip_negative_one_to_one,
+        ]
+        .into_iter()
+        .filter(|&x| x)
+        .count() as u64
+    }
+}
+
+/// Update-after-bind descriptor limits, the smaller of the per-stage and per-set limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DescriptorLimits {
+    pub sampled_images: u32,
+    pub storage_images: u32,
+    pub storage_buffers: u32,
+    pub samplers: u32,
+    /// All descriptor types of one stage together.
+    pub resources: u32,
+}
+
+impl DescriptorLimits {
+    fn from_properties(properties: &vk::PhysicalDeviceVulkan12Properties) -> Self {
+        Self {
+            sampled_images: properties
+                .max_per_stage_descriptor_update_after_bind_sampled_images
+                .min(properties.max_descriptor_set_update_after_bind_sampled_images),
+            storage_images: properties
+                .max_per_stage_descriptor_update_after_bind_storage_images
+                .min(properties.max_descriptor_set_update_after_bind_storage_images),
+            storage_buffers: properties
+                .max_per_stage_descriptor_update_after_bind_storage_buffers
+                .min(properties.max_descriptor_set_update_after_bind_storage_buffers),
+            samplers: properties
+                .max_per_stage_descriptor_update_after_bind_samplers
+                .min(properties.max_descriptor_set_update_after_bind_samplers),
+            resources: properties.max_per_stage_update_after_bind_resources,
+        }
+    }
+}
+
+/// What a physical device supports, probed once before the logical device is created.
+/// Required features are always enabled, optional ones only when they are `true` here,
+/// so the renderer can branch on these at runtime.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceCapabilities {
+    pub api_version: u32,
+
+    // Required, the device is rejected without them
+    pub swapchain: bool,
+    pub timeline_semaphore: bool,
+    pub synchronization2: bool,
+    pub dynamic_rendering: bool,
+    pub buffer_device_address: bool,
+    /// Every descriptor indexing feature the bindless heap needs.
+    pub bindless: bool,
+
+    // Optional extensions
+    pub memory_budget: bool,
+    pub maintenance5: bool,
+    pub extended_dynamic_state2_logic_op: bool,
+    pub extended_dynamic_state2_patch_control_points: bool,
+    /// All `false` if `VK_EXT_extended_dynamic_state3` isn't supported.
+    pub extended_dynamic_state3: DynamicState3,
+    pub vertex_input_dynamic_state: bool,
+    pub color_write_enable: bool,
+
+    // Optional features
+    pub sampler_anisotropy: bool,
+    pub multi_draw_indirect: bool,
+    pub draw_indirect_first_instance: bool,
+    pub draw_indirect_count: bool,
+    pub shader_draw_parameters: bool,
+    pub texture_compression_bc: bool,
+    pub depth_clamp: bool,
+    pub depth_bias_clamp: bool,
+    pub fill_mode_non_solid: bool,
+    pub independent_blend: bool,
+    pub sampler_filter_minmax: bool,
+    pub shader_int64: bool,
+    pub shader_float16: bool,
+    pub shader_int8: bool,
+    pub shader_integer_dot_product: bool,
+    pub pipeline_statistics_query: bool,
+
+    /// How many update-after-bind descriptors a bindless set may hold.
+    pub descriptor_limits: DescriptorLimits,
+
+    // Names of the supported optional extensions, these get enabled
+    optional_extensions: Vec<&'static CStr>,
+}
+
+impl DeviceCapabilities {
+    /// Queries the extensions and features of `physical_device` through `get_physical_device_features2`.
+    pub(crate) fn probe(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self {
+        unsafe {
+            let properties = instance.get_physical_device_properties(physical_device);
+            let extensions = instance
+                .enumerate_device_extension_properties(physical_device)
+                .unwrap_or_default();
+            let has_extension = |name: &CStr| {
+                extensions
+                    .iter()
+                    .any(|ext| ext.extension_name_as_c_str() == Ok(name))
+            };
+            let optional_extensions: Vec<&'static CStr> = OPTIONAL_DEVICE_EXTENSIONS
+                .iter()
+                .copied()
+                .filter(|&name| has_extension(name))
+                .collect();
+            let has_optional = |name: &CStr| optional_extensions.contains(&name);
+
+            let mut capabilities = Self {
+                api_version: properties.api_version,
+                swapchain: has_extension(ash::khr::swapchain::NAME),
+                memory_budget: has_optional(ash::ext::memory_budget::NAME),
+                ..Default::default()
+            };
+            // Querying structs of a newer version or an unsupported extension is invalid
+            if properties.api_version < vk::make_api_version(0, 1, 3, 0) {
+                return capabilities;
+            }
+
+            let mut vulkan12_properties = vk::PhysicalDeviceVulkan12Properties::default();
+            let mut properties2 =
+                vk::PhysicalDeviceProperties2::default().push_next(&mut vulkan12_properties);
+            instance.get_physical_device_properties2(physical_device, &mut properties2);
+            capabilities.descriptor_limits =
+                DescriptorLimits::from_properties(&vulkan12_properties);
+
+            let mut vulkan11 = vk::PhysicalDeviceVulkan11Features::default();
+            let mut vulkan12 = vk::PhysicalDeviceVulkan12Features::default();
+            let mut vulkan13 = vk::PhysicalDeviceVulkan13Features::default();
+            let mut dynamic_state2 = vk::PhysicalDeviceExtendedDynamicState2FeaturesEXT::default();
+            let mut dynamic_state3 = vk::PhysicalDeviceExtendedDynamicState3FeaturesEXT::default();
+            let mut vertex_input = vk::PhysicalDeviceVertexInputDynamicStateFeaturesEXT::default();
+            let mut color_write = vk::PhysicalDeviceColorWriteEnableFeaturesEXT::default();
+            let mut maintenance5 = vk::PhysicalDeviceMaintenance5FeaturesKHR::default();
+            let mut features2 = vk::PhysicalDeviceFeatures2::default()
+                .push_next(&mut vulkan11)
+                .push_next(&mut vulkan12)
+                .push_next(&mut vulkan13);
+            if has_optional(ash::ext::extended_dynamic_state2::NAME) {
+                features2 = features2.push_next(&mut dynamic_state2);
+            }
+            if has_optional(ash::ext::extended_dynamic_state3::NAME) {
+                features2 = features2.push_next(&mut dynamic_state3);
+            }
+            if has_optional(ash::ext::vertex_input_dynamic_state::NAME) {
+                features2 = features2.push_next(&mut vertex_input);
+            }
+            if has_optional(ash::ext::color_write_enable::NAME) {
+                features2 = features2.push_next(&mut color_write);
+            }
+            if has_optional(ash::khr::maintenance5::NAME) {
+                features2 = features2.push_next(&mut maintenance5);
+            }
+            instance.get_physical_device_features2(physical_device, &mut features2);
+            let core = features2.features;
+
+            capabilities.timeline_semaphore = supported(vulkan12.timeline_semaphore);
+            capabilities.synchronization2 = supported(vulkan13.synchronization2);
+            capabilities.dynamic_rendering = supported(vulkan13.dynamic_rendering);
+            capabilities.buffer_device_address = supported(vulkan12.buffer_device_address);
+            capabilities.bindless = [
+                vulkan12.runtime_descriptor_array,
+                vulkan12.descriptor_binding_partially_bound,
+                vulkan12.descriptor_binding_variable_descriptor_count,
+                vulkan12.descriptor_binding_update_unused_while_pending,
+                vulkan12.descriptor_binding_sampled_image_update_after_bind,
+                vulkan12.descriptor_binding_storage_image_update_after_bind,
+                vulkan12.descriptor_binding_storage_buffer_update_after_bind,
+                vulkan12.shader_sampled_image_array_non_uniform_indexing,
+                vulkan12.shader_storage_image_array_non_uniform_indexing,
+                vulkan12.shader_storage_buffer_array_non_uniform_indexing,
+            ]
+            .into_iter()
+            .all(supported);
+
+            capabilities.maintenance5 = supported(maintenance5.maintenance5);
+            capabilities.extended_dynamic_state2_logic_op =
+                supported(dynamic_state2.extended_dynamic_state2_logic_op);
+            capabilities.extended_dynamic_state2_patch_control_points =
+                supported(dynamic_state2.extended_dynamic_state2_patch_control_points);
+            capabilities.extended_dynamic_state3 = DynamicState3::from_features(&dynamic_state3);
+            capabilities.vertex_input_dynamic_state =
+                supported(vertex_input.vertex_input_dynamic_state);
+            capabilities.color_write_enable = supported(color_write.color_write_enable);
+
+            capabilities.sampler_anisotropy = supported(core.sampler_anisotropy);
+            capabilities.multi_draw_indirect = supported(core.multi_draw_indirect);
+            capabilities.draw_indirect_first_instance =
+                supported(core.draw_indirect_first_instance);
+            capabilities.draw_indirect_count = supported(vulkan12.draw_indirect_count);
+            capabilities.shader_draw_parameters = supported(vulkan11.shader_draw_parameters);
+            capabilities.texture_compression_bc = supported(core.texture_compression_bc);
+            capabilities.depth_clamp = supported(core.depth_clamp);
+            capabilities.depth_bias_clamp = supported(core.depth_bias_clamp);
+            capabilities.fill_mode_non_solid = supported(core.fill_mode_non_solid);
+            capabilities.independent_blend = supported(core.independent_blend);
+            capabilities.sampler_filter_minmax = supported(vulkan12.sampler_filter_minmax);
+            capabilities.shader_int64 = supported(core.shader_int64);
+            capabilities.shader_float16 = supported(vulkan12.shader_float16);
+            capabilities.shader_int8 = supported(vulkan12.shader_int8);
+            capabilities.shader_integer_dot_product =
+                supported(vulkan13.shader_integer_dot_product);
+            capabilities.pipeline_statistics_query = supported(core.pipeline_statistics_query);
+            capabilities.optional_extensions = optional_extensions;
+            capabilities
+        }
+    }
+
+    /// Why this device can't be used, empty if it has everything required.
+    pub(crate) fn missing_required(&self, presenting: bool) -> Vec<String> {
+        let mut missing = Vec::new();
+        // The patch version doesn't matter, drivers like lavapipe lag behind the headers
+        if self.api_version < vk::make_api_version(0, 1, 3, 0) {
+            missing.push(format!(
+                "Vulkan {}.{} is older than 1.3",
+                vk::api_version_major(self.api_version),
+                vk::api_version_minor(self.api_version),
+            ));
+            return missing;
+        }
+        if presenting && !self.swapchain {
+            missing.push(String::from("missing VK_KHR_swapchain"));
+        }
+        for (name, has) in [
+            ("timelineSemaphore", self.timeline_semaphore),
+            ("synchronization2", self.synchronization2),
+            ("dynamicRendering", self.dynamic_rendering),
+            ("bufferDeviceAddress", self.buffer_device_address),
+            ("descriptor indexing", self.bindless),
+        ] {
+            if !has {
+                missing.push(format!("missing {name}"));
+            }
+        }
+        missing
+    }
+
+    /// How many optional extensions and features are supported, used to score devices.
+    pub(crate) fn optional_feature_count(&self) -> u64 {
+        let features = [
+            self.sampler_anisotropy,
+            self.multi_draw_indirect,
+            self.draw_indirect_first_instance,
+            self.draw_indirect_count,
+            self.shader_draw_parameters,
+            self.texture_compression_bc,
+            self.depth_clamp,
+            self.depth_bias_clamp,
+            self.fill_mode_non_solid,
+            self.independent_blend,
+            self.sampler_filter_minmax,
+            self.shader_int64,
+            self.shader_float16,
+            self.shader_int8,
+            self.shader_integer_dot_product,
+            self.pipeline_statistics_query,
+            self.vertex_input_dynamic_state,
+            self.color_write_enable,
+        ];
+        features.into_iter().filter(|&x| x).count() as u64
+            + self.optional_extensions.len() as u64
+            + self.extended_dynamic_state3.count()
+    }
+
+    /// The supported optional extensions, plus the swapchain when presenting.
+    pub(crate) fn enabled_extensions(&self, presenting: bool) -> Vec<*const c_char> {
+        let required: &[&CStr] = if presenting {
+            REQUIRED_PRESENT_EXTENSIONS
+        } else {
+            &[]
+        };
+        required
+            .iter()
+            .chain(&self.optional_extensions)
+            .map(|name| name.as_ptr())
+            .collect()
+    }
+
+    pub(crate) fn has_extension(&self, name: &CStr) -> bool {
+        self.optional_extensions.contains(&name)
+    }
+
+    /// Names of the enabled optional extensions, for logging.
+    pub(crate) fn optional_extension_names(&self) -> Vec<String> {
+        self.optional_extensions
+            .iter()
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect()
+    }
+}
+
+fn supported(feature: vk::Bool32) -> bool {
+    feature == vk::TRUE
+}
@@ -0,0 +1,365 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::{
+        bindless::{BindlessHeap, DescriptorHandle},
+        frame::MAX_FRAMES_IN_FLIGHT,
+        graph::{BufferState, PassContext},
+        memory::{Allocator, Buffer, MemoryLocation},
+        mesh::{BUCKETS, DRAW_COMMAND_SIZE, MeshRenderer},
+        pipeline::{PipelineCache, StageDesc},
+        queues::Queues,
+        shader::ShaderLibrary,
+        upload::Uploader,
+    },
+    ash::{Device, prelude::VkResult, vk},
+    std::mem::size_of,
+};
+
+const SHADER: &str = "cull.spv";
+const WORKGROUP_SIZE: u32 = 64;
+const HIZ_TILE: u32 = 8;
+/// Format of the depth pyramid levels.
+pub(crate) const HIZ_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+/// Levels the cull shader can test against, enough for 65536 pixels across.
+pub(crate) const MAX_HIZ_LEVELS: usize = 16;
+// Draw counts of both phases, then the commands of the first phase and those of the second
+const PHASES: usize = 2;
+const COUNTS_SIZE: vk::DeviceSize = (PHASES * BUCKETS * size_of::<u32>()) as vk::DeviceSize;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CullPushConstants {
+    scene: u32,
+    instances: u32,
+    primitives: u32,
+    draws: u32,
+    visibility: u32,
+    instance_count: u32,
+    phase: u32,
+    hiz_levels: u32,
+    bucket_starts: [u32; BUCKETS],
+    hiz: [u32; MAX_HIZ_LEVELS],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct HizPushConstants {
+    source: u32,
+    destination: u32,
+    _pad: [u32; 2],
+}
+
+// Plain numbers without padding
+unsafe impl bytemuck::Zeroable for CullPushConstants {}
+unsafe impl bytemuck::Pod for CullPushConstants {}
+unsafe impl bytemuck::Zeroable for HizPushConstants {}
+unsafe impl bytemuck::Pod for HizPushConstants {}
+
+/// The draws one culling phase wrote, for [`MeshRenderer::draw`] to read the count and
+/// commands of each bucket from.
+#[derive(Clone, Copy)]
+pub(crate) struct DrawList {
+    pub(crate) buffer: vk::Buffer,
+    phase: usize,
+    instance_count: u32,
+}
+
+impl DrawList {
+    /// Where the draw count and the first command of `bucket` are, its instances start at `first`.
+    pub(crate) fn offsets(&self, bucket: usize, first: u32) -> (vk::DeviceSize, vk::DeviceSize) {
+        let count = ((self.phase * BUCKETS + bucket) * size_of::<u32>()) as vk::DeviceSize;
+        let command = self.phase as u32 * self.instance_count + first;
+        (
+            count,
+            COUNTS_SIZE + vk::DeviceSize::from(command * DRAW_COMMAND_SIZE),
+        )
+    }
+}
+
+/// Frustum and occlusion culling of every instance on the GPU, writing the draws of each bucket
+/// for `vkCmdDrawIndexedIndirectCount`.
+/// Two phases share a visibility bit per instance carried from frame to frame: the first draws
+/// what was visible last frame, a depth pyramid is built from that, and the second tests everything
+/// against it, drawing what just came into view and the blended instances.
+pub(crate) struct InstanceCulling {
+    // Draw counts and commands, one list per frame in flight
+    draws: Vec<(Buffer, DescriptorHandle)>,
+    // A word per instance, nonzero when it passed the last occlusion test
+    visibility: Option<(Buffer, DescriptorHandle)>,
+    instance_count: u32,
+    bucket_starts: [u32; BUCKETS],
+    // Image slots registered by each frame in flight, and the slot being recorded
+    frame_handles: Vec<Vec<DescriptorHandle>>,
+    slot: usize,
+    // The frame being recorded ran out of image slots for its depth pyramid
+    occlusion_skipped: bool,
+    // Nothing touched the visibility buffer on the GPU yet, so there's nothing to wait on
+    fresh: bool,
+}
+
+impl InstanceCulling {
+    /// Sizes the draw lists for the instances of `meshes`, which must have some.
+    pub(crate) fn new(
+        device: &Device,
+        queues: &Queues,
+        allocator: &mut Allocator,
+        bindless: &mut BindlessHeap,
+        meshes: &MeshRenderer,
+    ) -> VkResult<Self> {
+        let buckets = meshes.buckets();
+        let instance_count = buckets[BUCKETS - 1].end;
+        let size =
+            COUNTS_SIZE + vk::DeviceSize::from(PHASES as u32 * instance_count * DRAW_COMMAND_SIZE);
+        let mut draws = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            let buffer = allocator.create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(size)
+                    .usage(
+                        vk::BufferUsageFlags::STORAGE_BUFFER
+                            | vk::BufferUsageFlags::INDIRECT_BUFFER
+                            | vk::BufferUsageFlags::TRANSFER_DST,
+                    )
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                MemoryLocation::GpuOnly,
+                format_args!("culled draws {frame}"),
+            )?;
+            let handle = bindless
+                .add_storage_buffer(buffer.handle, 0, size)
+                .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)?;
+            draws.push((buffer, handle));
+        }
+
+        // Nothing was visible before the first frame, its second phase draws everything in view
+        let mut uploader = Uploader::new(device, queues, allocator);
+        let visibility = uploader.buffer(
+            &vec![0; instance_count as usize * size_of::<u32>()],
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            "instance visibility",
+        )?;
+        uploader.flush()?;
+        let visibility_handle = bindless
+            .add_storage_buffer(visibility.handle, 0, visibility.size)
+            .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)?;
+
+        Ok(Self {
+            draws,
+            visibility: Some((visibility, visibility_handle)),
+            instance_count,
+            bucket_starts: buckets.clone().map(|x| x.start),
+            frame_handles: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            slot: 0,
+            occlusion_skipped: false,
+            fresh: true,
+        })
+    }
+
+    /// Starts the frame in `slot`, the passes recorded until the next call belong to it.
+    /// The last frame that used `slot` must be done.
+    pub(crate) fn begin(&mut self, bindless: &mut BindlessHeap, slot: usize) {
+        for handle in self.frame_handles[slot].drain(..) {
+            bindless.release(handle, 0);
+        }
+        self.slot = slot;
+        self.occlusion_skipped = false;
+    }
+
+    /// The draw list buffer of `slot`, for the graph to synchronize.
+    pub(crate) fn draw_buffer(&self, slot: usize) -> vk::Buffer {
+        self.draws[slot].0.handle
+    }
+
+    /// The visibility buffer for the graph to import, and the state the last frame left it in.
+    pub(crate) fn visibility_buffer(&mut self) -> Option<(vk::Buffer, BufferState)> {
+        let (buffer, _) = self.visibility.as_ref()?;
+        let initial = if self.fresh {
+            BufferState::NONE
+        } else {
+            BufferState {
+                stage: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                access: vk::AccessFlags2::SHADER_STORAGE_READ
+                    | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            }
+        };
+        self.fresh = false;
+        Some((buffer.handle, initial))
+    }
+
+    /// What `phase` of the frame in `slot` writes.
+    pub(crate) fn draw_list(&self, slot: usize, phase: usize) -> DrawList {
+        DrawList {
+            buffer: self.draws[slot].0.handle,
+            phase,
+            instance_count: self.instance_count,
+        }
+    }
+
+    /// Zeroes the draw counts of both phases.
+    pub(crate) fn clear(&self, context: &PassContext, slot: usize) {
+        unsafe {
+            context.device.cmd_fill_buffer(
+                context.command_buffer,
+                self.draws[slot].0.handle,
+                0,
+                COUNTS_SIZE,
+                0,
+            );
+        }
+    }
+
+    /// Writes the draws of `phase` for the instances of `meshes`, seen through the scene
+    /// constants of the frame. The second phase tests against the depth pyramid `hiz`, or only
+    /// against the frustum when the pyramid couldn't be built.
+    pub(crate) fn cull(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        meshes: &MeshRenderer,
+        (phase, hiz): (usize, &[vk::ImageView]),
+    ) {
+        let (Some((instances, primitives)), Some((_, visibility))) =
+            (meshes.tables(), &self.visibility)
+        else {
+            return;
+        };
+        let mut push = CullPushConstants {
+            scene: meshes.scene_buffer(self.slot).index,
+            instances,
+            primitives,
+            draws: self.draws[self.slot].1.index,
+            visibility: visibility.index,
+            instance_count: self.instance_count,
+            phase: phase as u32,
+            hiz_levels: hiz.len().min(MAX_HIZ_LEVELS) as u32,
+            bucket_starts: self.bucket_starts,
+            hiz: [0; MAX_HIZ_LEVELS],
+        };
+        for (slot, &view) in push.hiz.iter_mut().zip(hiz) {
+            if self.occlusion_skipped {
+                break;
+            }
+            match self.sampled(bindless, view) {
+                Some(index) => *slot = index,
+                None => self.skip_occlusion(),
+            }
+        }
+        if self.occlusion_skipped {
+            push.hiz_levels = 0;
+        }
+        let groups = [self.instance_count.div_ceil(WORKGROUP_SIZE), 1];
+        self.dispatch(
+            context,
+            pipelines,
+            shaders,
+            bindless,
+            ("cull", groups),
+            push,
+        );
+    }
+
+    /// Reduces `source` into the depth pyramid level `destination` of `extent`, each texel
+    /// keeping the farthest of the two by two texels under it.
+    /// Skips the occlusion test of the frame when the heap has no slot left for either.
+    pub(crate) fn build_hiz(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        (source, destination): (vk::ImageView, vk::ImageView),
+        extent: vk::Extent2D,
+    ) {
+        if self.occlusion_skipped {
+            return;
+        }
+        let destination = bindless.add_storage_image(destination);
+        self.frame_handles[self.slot].extend(destination);
+        let (Some(source), Some(destination)) = (self.sampled(bindless, source), destination)
+        else {
+            self.skip_occlusion();
+            return;
+        };
+        let push = HizPushConstants {
+            source,
+            destination: destination.index,
+            _pad: [0; 2],
+        };
+        let groups = [
+            extent.width.div_ceil(HIZ_TILE),
+            extent.height.div_ceil(HIZ_TILE),
+        ];
+        self.dispatch(context, pipelines, shaders, bindless, ("hiz", groups), push);
+    }
+
+    /// Frees every buffer, the GPU must be done with them.
+    pub(crate) fn destroy(&mut self, allocator: &mut Allocator) {
+        if let Some((buffer, _)) = self.visibility.take() {
+            allocator.destroy_buffer(&buffer);
+        }
+        for (buffer, _) in self.draws.drain(..) {
+            allocator.destroy_buffer(&buffer);
+        }
+    }
+
+    /// `None` once every sampled image slot is taken.
+    fn sampled(&mut self, bindless: &mut BindlessHeap, view: vk::ImageView) -> Option<u32> {
+        let handle = bindless.add_sampled_image(view, vk::ImageLayout::READ_ONLY_OPTIMAL)?;
+        self.frame_handles[self.slot].push(handle);
+        Some(handle.index)
+    }
+
+    fn skip_occlusion(&mut self) {
+        if !self.occlusion_skipped {
+            crate::logln!(
+                "vulkan culling warning",
+                "Out of bindless image slots, skipping occlusion culling this frame"
+            );
+        }
+        self.occlusion_skipped = true;
+    }
+
+    fn dispatch<P: bytemuck::Pod>(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &BindlessHeap,
+        (entry, groups): (&str, [u32; 2]),
+        push: P,
+    ) {
+        let cb = context.command_buffer;
+        bindless.bind(cb, vk::PipelineBindPoint::COMPUTE);
+        if !pipelines.bind_compute(cb, shaders, &StageDesc::new(SHADER, entry)) {
+            return;
+        }
+        unsafe {
+            context.device.cmd_push_constants(
+                cb,
+                bindless.pipeline_layout(),
+                vk::ShaderStageFlags::ALL,
+                0,
+                bytemuck::bytes_of(&push),
+            );
+            context.device.cmd_dispatch(cb, groups[0], groups[1], 1);
+        }
+    }
+}
+
+/// How many depth pyramid levels a frame of `extent` gets, halving until one texel is left.
+pub(crate) fn hiz_levels(extent: vk::Extent2D) -> usize {
+    let largest = extent.width.max(extent.height).max(1);
+    // Level zero is already half the size
+    (largest.next_power_of_two().trailing_zeros() as usize).clamp(1, MAX_HIZ_LEVELS)
+}
+
+/// The size of depth pyramid `level` of a frame of `extent`, rounded up so it covers every pixel.
+pub(crate) fn hiz_extent(extent: vk::Extent2D, level: usize) -> vk::Extent2D {
+    let scale = 2 << level;
+    vk::Extent2D {
+        width: extent.width.div_ceil(scale).max(1),
+        height: extent.height.div_ceil(scale).max(1),
+    }
+}
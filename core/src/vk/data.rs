@@ -1,44 +1,104 @@
 #![cfg(feature = "vulkan")]
 
 use {
-    crate::utils::AppState,
-    ash,
-    ash::{ext, khr, vk},
+    crate::{
+        ColorOutput, VSync,
+        utils::AppState,
+        vk::{
+            capabilities::DeviceCapabilities, debug_utils::DebugUtils, memory::Allocator,
+            offscreen::OffscreenTarget, queues::Queues,
+        },
+    },
+    ash::{khr, vk},
+    raw_window_handle::{RawDisplayHandle, RawWindowHandle},
     std::sync::{Arc, mpsc::Receiver},
+    tokio::sync::mpsc::Receiver as TokioReceiver,
+    winit::dpi::PhysicalSize,
 };
 
+#[cfg(feature = "debug")]
+use {crate::ValidationSettings, ash::ext, std::sync::Mutex};
+
+/// Every Vulkan object the renderer runs on, one layer per field.
+/// Each layer destroys its own handles when dropped, fields drop top to bottom, so the display
+/// goes before the device it was made from and the device before the instance.
 pub struct VkCore {
     pub communicator: WindowCommunication,
-    pub core: VkStart,
-    #[cfg(all(debug_assertions, feature = "debug"))]
-    pub debug: Option<VkDebug>,
-    pub devices: VkDevices,
     pub display: VkDisplay,
+    pub devices: VkDevices,
+    #[cfg(feature = "debug")]
+    pub debug: Option<VkDebug>,
+    pub core: VkStart,
 }
 
-pub struct WindowCommunication(Option<Receiver<AppState>>);
+/// Orders from the window, `None` until [`VkCore::init_window_communicator`].
+pub struct WindowCommunication(pub(crate) Option<Receiver<AppState>>);
 
+/// What [`VkCore::new`] should render into.
+pub(crate) enum TargetRequest {
+    Window {
+        handles: (RawDisplayHandle, RawWindowHandle),
+        size: PhysicalSize<u32>,
+        inner_size_reciever: TokioReceiver<PhysicalSize<u32>>,
+        // What's preferred, the surface decides what's possible
+        color_output: ColorOutput,
+        vsync: VSync,
+    },
+    Offscreen(vk::Extent2D, ColorOutput),
+}
+
+/// The loader and the instance, everything else is made from these.
 pub struct VkStart {
     pub entry: Arc<ash::Entry>,
     pub instance: Arc<ash::Instance>,
 }
 
-#[cfg(all(debug_assertions, feature = "debug"))]
+/// Logs validation messages, only created with the debug feature.
+#[cfg(feature = "debug")]
 pub struct VkDebug {
     pub loader: Arc<ext::debug_utils::Instance>,
-    pub messenger: Option<vk::DebugUtilsMessengerEXT>,
+    pub messenger: vk::DebugUtilsMessengerEXT,
+    // Behind the messenger's user data pointer, boxed so it stays put when `VkDebug` moves
+    pub(crate) state: Box<ValidationState>,
+}
+
+/// What the validation callback is configured with and what it has seen.
+#[cfg(feature = "debug")]
+pub(crate) struct ValidationState {
+    pub(crate) settings: ValidationSettings,
+    // The first error, kept around for `ValidationSettings::panic_on_error`
+    pub(crate) first_error: Mutex<Option<String>>,
 }
 
+/// The chosen GPU, its logical device and everything allocated straight from it.
 pub struct VkDevices {
     pub physical: vk::PhysicalDevice,
+    /// What the device supports beyond the required baseline
+    pub capabilities: DeviceCapabilities,
     pub logical: Arc<ash::Device>,
-    pub graphics_queue: vk::Queue,
-    pub present_queue: vk::Queue,
+    /// Names objects and labels passes, does nothing without the debug feature
+    pub debug_utils: DebugUtils,
+    pub queues: Queues,
+    /// Memory for every buffer and image created on `logical`
+    pub allocator: Allocator,
 }
 
-pub struct VkDisplay {
-    pub surface: VkSurface,
+/// Where frames end up, either presented to a window or kept in an offscreen image.
+pub enum VkDisplay {
+    Window(VkWindow),
+    Offscreen(OffscreenTarget),
+}
+
+/// Everything needed to present to a window.
+pub struct VkWindow {
+    // The swapchain goes before the surface it presents to
     pub swapchain: VkSwapchain,
+    pub surface: VkSurface,
+    pub(crate) inner_size_reciever: TokioReceiver<PhysicalSize<u32>>,
+    pub(crate) window_size: PhysicalSize<u32>,
+    // Kept for swapchain recreation, the display may have changed since
+    pub(crate) preferred_output: ColorOutput,
+    pub(crate) vsync: VSync,
 }
 
 pub struct VkSurface {
@@ -49,7 +109,12 @@ pub struct VkSurface {
 pub struct VkSwapchain {
     pub handle: vk::SwapchainKHR,
     pub device: Arc<khr::swapchain::Device>,
-    pub images: Arc<Vec<vk::Image>>,
+    pub images: Vec<vk::Image>,
+    pub image_views: Vec<vk::ImageView>,
     pub format: vk::Format,
     pub extent: vk::Extent2D,
+    /// What the swapchain's color space asks the tonemapper for
+    pub color_output: ColorOutput,
+    // The image views are destroyed through it
+    pub(crate) logical: Arc<ash::Device>,
 }
@@ -0,0 +1,87 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::data::VkStart,
+    ash::{
+        Device,
+        vk::{self, Handle},
+    },
+    std::fmt,
+};
+
+#[cfg(feature = "debug")]
+use {
+    ash::ext,
+    std::{ffi::CString, sync::Arc},
+};
+
+/// Names objects and labels command buffers through VK_EXT_debug_utils, so captures and
+/// validation messages show what the engine calls things.
+/// Every call compiles to nothing without the debug feature.
+#[derive(Clone, Default)]
+pub struct DebugUtils {
+    // `None` when the instance doesn't have the extension
+    #[cfg(feature = "debug")]
+    loader: Option<Arc<ext::debug_utils::Device>>,
+}
+
+#[cfg(feature = "debug")]
+impl DebugUtils {
+    pub(crate) fn new(start: &VkStart, device: &Device) -> Self {
+        let available = unsafe { start.entry.enumerate_instance_extension_properties(None) }
+            .unwrap_or_default()
+            .iter()
+            .any(|extension| extension.extension_name_as_c_str() == Ok(ext::debug_utils::NAME));
+        Self {
+            loader: available
+                .then(|| Arc::new(ext::debug_utils::Device::new(&start.instance, device))),
+        }
+    }
+
+    /// Names `handle`, names with a nul in them are skipped.
+    pub fn name(&self, handle: impl Handle, name: impl fmt::Display) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+        let Ok(name) = CString::new(name.to_string()) else {
+            return;
+        };
+        let info = vk::DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&name);
+        // A missing name isn't worth failing over
+        let _ = unsafe { loader.set_debug_utils_object_name(&info) };
+    }
+
+    /// Opens a label on `command_buffer`, every label needs a matching [`DebugUtils::end_label`].
+    pub fn begin_label(&self, command_buffer: vk::CommandBuffer, name: impl fmt::Display) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+        let name = CString::new(name.to_string()).unwrap_or_default();
+        let label = vk::DebugUtilsLabelEXT::default().label_name(&name);
+        unsafe { loader.cmd_begin_debug_utils_label(command_buffer, &label) };
+    }
+
+    pub fn end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(loader) = &self.loader {
+            unsafe { loader.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+}
+
+#[cfg(not(feature = "debug"))]
+impl DebugUtils {
+    pub(crate) fn new(_start: &VkStart, _device: &Device) -> Self {
+        Self {}
+    }
+
+    #[inline(always)]
+    pub fn name(&self, _handle: impl Handle, _name: impl fmt::Display) {}
+
+    #[inline(always)]
+    pub fn begin_label(&self, _command_buffer: vk::CommandBuffer, _name: impl fmt::Display) {}
+
+    #[inline(always)]
+    pub fn end_label(&self, _command_buffer: vk::CommandBuffer) {}
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "vulkan")]
+
+use {ash::vk, error_stack::Report, std::fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Why the renderer couldn't start or had to stop.
+/// Reports carry what was being created, device names, missing extensions and the Vulkan result.
+pub enum RendererError {
+    /// No Vulkan loader is installed, or it couldn't be opened.
+    LoaderMissing,
+    /// The Vulkan instance couldn't be created.
+    Instance,
+    /// No GPU has everything the renderer needs, every candidate is attached with its reasons.
+    NoSuitableDevice,
+    /// The chosen GPU refused to create a logical device.
+    Device,
+    /// The driver lost the device, usually after a crash or a driver reset.
+    DeviceLost,
+    /// No surface could be created for the window.
+    Surface,
+    /// The window surface went away, the window can't be presented to anymore.
+    SurfaceLost,
+    /// The swapchain couldn't be created or queried.
+    Swapchain,
+    /// The GPU or host ran out of memory.
+    OutOfMemory,
+    /// A buffer, image or descriptor the renderer needs couldn't be created.
+    Resources,
+    /// Recording, submitting or presenting a frame failed.
+    Frame,
+    /// The directory headless frames are written to couldn't be created.
+    OutputDir,
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::LoaderMissing => "Failed to load Vulkan, is a Vulkan driver installed?",
+            Self::Instance => "Failed to create the Vulkan instance",
+            Self::NoSuitableDevice => "No suitable GPU found",
+            Self::Device => "Failed to create the Vulkan device",
+            Self::DeviceLost => "The GPU was lost",
+            Self::Surface => "Failed to create the window surface",
+            Self::SurfaceLost => "The window surface was lost",
+            Self::Swapchain => "Failed to create the swapchain",
+            Self::OutOfMemory => "Out of memory",
+            Self::Resources => "Failed to create the renderer resources",
+            Self::Frame => "Failed to render a frame",
+            Self::OutputDir => "Failed to create the frame output directory",
+        })
+    }
+}
+
+impl std::error::Error for RendererError {}
+
+/// Reports a failed Vulkan call as `context`.
+/// Results that say what went wrong on their own, like a lost surface, end up on top.
+pub(crate) fn vk_report(result: vk::Result, context: RendererError) -> Report<RendererError> {
+    let report = Report::new(result).change_context(context);
+    match result {
+        vk::Result::ERROR_SURFACE_LOST_KHR => report.change_context(RendererError::SurfaceLost),
+        vk::Result::ERROR_DEVICE_LOST => report.change_context(RendererError::DeviceLost),
+        vk::Result::ERROR_OUT_OF_DEVICE_MEMORY | vk::Result::ERROR_OUT_OF_HOST_MEMORY => {
+            report.change_context(RendererError::OutOfMemory)
+        }
+        _ => report,
+    }
+}
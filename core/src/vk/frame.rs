@@ -0,0 +1,503 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::{
+        data::{VkCore, VkDevices, VkDisplay, VkSwapchain},
+        debug_utils::DebugUtils,
+        graph::{ImageDesc, ImageState, PassContext, RenderGraph, TransientImages},
+        memory::Allocator,
+        offscreen::{FrameReadback, OffscreenTarget},
+        queues::Queues,
+        renderer::Renderer,
+    },
+    ash::{Device, prelude::VkResult, vk},
+    std::sync::Arc,
+};
+
+/// How many frames the CPU is allowed to record ahead of the GPU.
+pub(crate) const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Per frame-in-flight resources, reused every `MAX_FRAMES_IN_FLIGHT` frames.
+struct FrameData {
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    // Bins the lights on the async compute queue, None without one
+    compute: Option<(vk::CommandPool, vk::CommandBuffer)>,
+    // Binary, since vkAcquireNextImageKHR can't signal timeline semaphores
+    image_available: vk::Semaphore,
+    // Timeline value the GPU reaches once this slot's last submission is done
+    timeline_value: u64,
+    // Frame number whose pixels sit in this slot's readback buffer (offscreen only)
+    readback_frame: Option<u64>,
+}
+
+/// Owns the per-frame synchronization and command recording state.
+pub(crate) struct Frames {
+    device: Arc<Device>,
+    // Names transient images and labels the graph's passes
+    debug_utils: DebugUtils,
+    frames: Vec<FrameData>,
+    // One per swapchain image, since presentation holds on to it until the image is re-acquired
+    render_finished: Vec<vk::Semaphore>,
+    // Signaled with `frame_number + 1` once a frame finishes on the GPU
+    timeline: vk::Semaphore,
+    // Signaled with `frame_number + 1` once the compute queue binned a frame's lights
+    compute_timeline: Option<vk::Semaphore>,
+    frame_number: u64,
+    readback: Option<FrameReadback>,
+    // Memory behind the render graph's transient images
+    transients: TransientImages,
+}
+
+impl Frames {
+    pub(crate) fn new(vk_core: &VkCore, readback: Option<FrameReadback>) -> VkResult<Self> {
+        let device = vk_core.devices.logical.clone();
+        let families = vk_core.devices.queues.families;
+        let compute = families.has_async_compute().then_some(families.compute);
+        let mut frames = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            frames.push(Self::create_frame_data(
+                &device,
+                families.graphics,
+                compute,
+            )?);
+        }
+        let render_finished =
+            Self::create_binary_semaphores(&device, vk_core.display.swapchain_image_count())?;
+        let timeline = Self::create_timeline_semaphore(&device)?;
+        let compute_timeline = compute
+            .map(|_| Self::create_timeline_semaphore(&device))
+            .transpose()?;
+        Ok(Self {
+            transients: TransientImages::new(device.clone(), vk_core.devices.debug_utils.clone()),
+            debug_utils: vk_core.devices.debug_utils.clone(),
+            device,
+            frames,
+            render_finished,
+            timeline,
+            compute_timeline,
+            frame_number: 0,
+            readback,
+        })
+    }
+
+    fn create_frame_data(
+        device: &Device,
+        graphics_family: u32,
+        compute_family: Option<u32>,
+    ) -> VkResult<FrameData> {
+        let (command_pool, command_buffer) = Self::create_command_buffer(device, graphics_family)?;
+        let compute = compute_family
+            .map(|family| Self::create_command_buffer(device, family))
+            .transpose()?;
+        unsafe {
+            let image_available =
+                device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+            Ok(FrameData {
+                command_pool,
+                command_buffer,
+                compute,
+                image_available,
+                timeline_value: 0,
+                readback_frame: None,
+            })
+        }
+    }
+
+    fn create_command_buffer(
+        device: &Device,
+        queue_family: u32,
+    ) -> VkResult<(vk::CommandPool, vk::CommandBuffer)> {
+        unsafe {
+            // Transient pool, it gets reset as a whole every time the slot is reused
+            let pool_info = vk::CommandPoolCreateInfo::default()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(queue_family);
+            let command_pool = device.create_command_pool(&pool_info, None)?;
+            let allocate_info = vk::CommandBufferAllocateInfo::default()
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1);
+            let command_buffer = device.allocate_command_buffers(&allocate_info)?[0];
+            Ok((command_pool, command_buffer))
+        }
+    }
+
+    fn create_binary_semaphores(device: &Device, count: usize) -> VkResult<Vec<vk::Semaphore>> {
+        (0..count)
+            .map(|_| unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) })
+            .collect()
+    }
+
+    fn create_timeline_semaphore(device: &Device) -> VkResult<vk::Semaphore> {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+        unsafe { device.create_semaphore(&create_info, None) }
+    }
+
+    /// How many frames have been submitted so far.
+    pub(crate) fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    /// The timeline value of the newest frame the GPU finished.
+    /// Frame `n` (counting from 0) signals `n + 1`, so the next frame to be submitted signals `frame_number() + 1`.
+    pub(crate) fn completed_timeline_value(&self) -> VkResult<u64> {
+        unsafe { self.device.get_semaphore_counter_value(self.timeline) }
+    }
+
+    /// Recreates the per-image semaphores after the swapchain was rebuilt.
+    /// The device must be idle, which `VkDisplay::recreate_swapchain` guarantees.
+    pub(crate) fn swapchain_recreated(&mut self, image_count: usize) -> VkResult<()> {
+        for &semaphore in &self.render_finished {
+            unsafe { self.device.destroy_semaphore(semaphore, None) };
+        }
+        self.render_finished = Self::create_binary_semaphores(&self.device, image_count)?;
+        Ok(())
+    }
+
+    /// Records, submits and (for windows) presents a single frame.
+    /// Returns true if the swapchain is out of date or suboptimal and should be recreated.
+    pub(crate) fn draw_frame(
+        &mut self,
+        vk_core: &mut VkCore,
+        renderer: &mut Renderer,
+    ) -> VkResult<bool> {
+        let slot = (self.frame_number % MAX_FRAMES_IN_FLIGHT as u64) as usize;
+        // Wait until the GPU is done with the last frame that used this slot
+        self.wait_for_timeline(self.frames[slot].timeline_value)?;
+        let VkCore {
+            display,
+            devices: VkDevices {
+                queues, allocator, ..
+            },
+            ..
+        } = vk_core;
+        // Transient images replaced by earlier frames can go once those frames are done
+        self.transients
+            .reclaim(allocator, self.completed_timeline_value()?);
+        match display {
+            VkDisplay::Window(window) => {
+                self.draw_window_frame(queues, allocator, renderer, &window.swapchain, slot)
+            }
+            VkDisplay::Offscreen(offscreen) => {
+                self.draw_offscreen_frame(queues, allocator, renderer, offscreen, slot)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Waits for every submitted frame, frees the transient images and hands out the pending readbacks in order.
+    pub(crate) fn finish(&mut self, vk_core: &mut VkCore) -> VkResult<()> {
+        self.wait_for_timeline(self.frame_number)?;
+        self.transients.destroy(&mut vk_core.devices.allocator);
+        let VkDisplay::Offscreen(offscreen) = &vk_core.display else {
+            return Ok(());
+        };
+        let mut pending: Vec<(u64, usize)> = self
+            .frames
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(slot, frame)| frame.readback_frame.take().map(|n| (n, slot)))
+            .collect();
+        pending.sort_unstable();
+        for (frame_number, slot) in pending {
+            self.deliver_readback(offscreen, frame_number, slot)?;
+        }
+        Ok(())
+    }
+
+    fn wait_for_timeline(&self, value: u64) -> VkResult<()> {
+        let semaphores = [self.timeline];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+        unsafe { self.device.wait_semaphores(&wait_info, u64::MAX) }
+    }
+
+    fn deliver_readback(
+        &mut self,
+        offscreen: &OffscreenTarget,
+        frame_number: u64,
+        slot: usize,
+    ) -> VkResult<()> {
+        if let Some(readback) = &mut self.readback {
+            let image = offscreen.read(slot);
+            readback.deliver(frame_number, &image);
+        }
+        Ok(())
+    }
+
+    fn draw_window_frame(
+        &mut self,
+        queues: &Queues,
+        allocator: &mut Allocator,
+        renderer: &mut Renderer,
+        swapchain: &VkSwapchain,
+        slot: usize,
+    ) -> VkResult<bool> {
+        let FrameData {
+            command_pool,
+            command_buffer,
+            image_available,
+            ..
+        } = self.frames[slot];
+        unsafe {
+            // A suboptimal image can still be presented, the swapchain gets rebuilt afterwards
+            let (image_index, suboptimal) = match swapchain.device.acquire_next_image(
+                swapchain.handle,
+                u64::MAX,
+                image_available,
+                vk::Fence::null(),
+            ) {
+                Ok(x) => x,
+                // The semaphore wasn't signaled, so the slot can be reused as is
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(true),
+                Err(x) => return Err(x),
+            };
+
+            self.device
+                .reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())?;
+            // The acquire semaphore is waited on at COLOR_ATTACHMENT_OUTPUT
+            let (graph, _) = renderer.frame_graph(
+                (
+                    swapchain.images[image_index as usize],
+                    swapchain.image_views[image_index as usize],
+                    ImageDesc::new(swapchain.format, swapchain.extent),
+                ),
+                swapchain.color_output,
+                ImageState {
+                    layout: vk::ImageLayout::UNDEFINED,
+                    stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    access: vk::AccessFlags2::NONE,
+                },
+                ImageState {
+                    layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                    stage: vk::PipelineStageFlags2::NONE,
+                    access: vk::AccessFlags2::NONE,
+                },
+                slot,
+            );
+            let clusters_binned = self.submit_light_culling(queues, renderer, slot)?;
+            self.record(command_buffer, allocator, graph, renderer)?;
+            self.device.end_command_buffer(command_buffer)?;
+
+            // Wait for the image before writing to it, signal presentation and the timeline after
+            let signal_value = self.frame_number + 1;
+            let wait_semaphores: Vec<_> = [vk::SemaphoreSubmitInfo::default()
+                .semaphore(image_available)
+                .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)]
+            .into_iter()
+            .chain(clusters_binned)
+            .collect();
+            let command_buffers =
+                [vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)];
+            let signal_semaphores = [
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(self.render_finished[image_index as usize])
+                    .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT),
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(self.timeline)
+                    .value(signal_value)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+            ];
+            let submit_info = vk::SubmitInfo2::default()
+                .wait_semaphore_infos(&wait_semaphores)
+                .command_buffer_infos(&command_buffers)
+                .signal_semaphore_infos(&signal_semaphores);
+            self.device
+                .queue_submit2(queues.graphics, &[submit_info], vk::Fence::null())?;
+            self.frames[slot].timeline_value = signal_value;
+            self.frame_number += 1;
+
+            let present_wait = [self.render_finished[image_index as usize]];
+            let swapchains = [swapchain.handle];
+            let image_indices = [image_index];
+            let present_info = vk::PresentInfoKHR::default()
+                .wait_semaphores(&present_wait)
+                .swapchains(&swapchains)
+                .image_indices(&image_indices);
+            // Waits on the render semaphore, so presenting from another family needs no extra sync
+            let present_queue = queues
+                .present
+                .expect("Window targets always have a present queue");
+            match swapchain.device.queue_present(present_queue, &present_info) {
+                Ok(present_suboptimal) => Ok(suboptimal || present_suboptimal),
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(true),
+                Err(x) => Err(x),
+            }
+        }
+    }
+
+    fn draw_offscreen_frame(
+        &mut self,
+        queues: &Queues,
+        allocator: &mut Allocator,
+        renderer: &mut Renderer,
+        offscreen: &OffscreenTarget,
+        slot: usize,
+    ) -> VkResult<()> {
+        // The slot's previous frame is done, so its pixels can be handed out before reuse
+        if let Some(frame_number) = self.frames[slot].readback_frame.take() {
+            self.deliver_readback(offscreen, frame_number, slot)?;
+        }
+        let FrameData {
+            command_pool,
+            command_buffer,
+            ..
+        } = self.frames[slot];
+        unsafe {
+            self.device
+                .reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())?;
+            // COPY covers the previous offscreen frame's readback still reading the image
+            let (mut graph, target) = renderer.frame_graph(
+                (
+                    offscreen.image.handle,
+                    offscreen.view,
+                    ImageDesc::new(offscreen.format, offscreen.extent),
+                ),
+                offscreen.color_output,
+                ImageState {
+                    layout: vk::ImageLayout::UNDEFINED,
+                    stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags2::COPY,
+                    access: vk::AccessFlags2::NONE,
+                },
+                // Where the readback pass leaves it
+                ImageState {
+                    layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    stage: vk::PipelineStageFlags2::NONE,
+                    access: vk::AccessFlags2::NONE,
+                },
+                slot,
+            );
+            offscreen.add_readback(&mut graph, target, slot);
+            let clusters_binned = self.submit_light_culling(queues, renderer, slot)?;
+            self.record(command_buffer, allocator, graph, renderer)?;
+            self.device.end_command_buffer(command_buffer)?;
+
+            // Only the light culling to wait on, the image is owned by us alone
+            let wait_semaphores: Vec<_> = clusters_binned.into_iter().collect();
+            let signal_value = self.frame_number + 1;
+            let command_buffers =
+                [vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)];
+            let signal_semaphores = [vk::SemaphoreSubmitInfo::default()
+                .semaphore(self.timeline)
+                .value(signal_value)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)];
+            let submit_info = vk::SubmitInfo2::default()
+                .wait_semaphore_infos(&wait_semaphores)
+                .command_buffer_infos(&command_buffers)
+                .signal_semaphore_infos(&signal_semaphores);
+            self.device
+                .queue_submit2(queues.graphics, &[submit_info], vk::Fence::null())?;
+        }
+        self.frames[slot].timeline_value = self.frame_number + 1;
+        self.frames[slot].readback_frame = Some(self.frame_number);
+        self.frame_number += 1;
+        Ok(())
+    }
+
+    /// Records and submits the light culling of `slot` to the async compute queue, if there is one,
+    /// so it overlaps the graphics queue until the forward pass. Returns what the frame's
+    /// graphics submission has to wait on before it acquires the clusters.
+    fn submit_light_culling(
+        &self,
+        queues: &Queues,
+        renderer: &mut Renderer,
+        slot: usize,
+    ) -> VkResult<Option<vk::SemaphoreSubmitInfo<'static>>> {
+        let (Some((command_pool, command_buffer)), Some(timeline)) =
+            (self.frames[slot].compute, self.compute_timeline)
+        else {
+            return Ok(None);
+        };
+        let signal_value = self.frame_number + 1;
+        unsafe {
+            self.device
+                .reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())?;
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.device
+                .begin_command_buffer(command_buffer, &begin_info)?;
+            self.debug_utils
+                .begin_label(command_buffer, "light culling");
+            renderer.cull_lights(
+                &PassContext::outside_graph(&self.device, command_buffer),
+                slot,
+            );
+            self.debug_utils.end_label(command_buffer);
+            self.device.end_command_buffer(command_buffer)?;
+
+            // Waiting on the slot's last frame covered the reads of the clusters it replaces
+            let command_buffers =
+                [vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)];
+            let signal_semaphores = [vk::SemaphoreSubmitInfo::default()
+                .semaphore(timeline)
+                .value(signal_value)
+                .stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)];
+            let submit_info = vk::SubmitInfo2::default()
+                .command_buffer_infos(&command_buffers)
+                .signal_semaphore_infos(&signal_semaphores);
+            self.device
+                .queue_submit2(queues.compute, &[submit_info], vk::Fence::null())?;
+        }
+        // Where the acquire in the frame's graph waits
+        Ok(Some(
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(timeline)
+                .value(signal_value)
+                .stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER),
+        ))
+    }
+
+    /// Begins `command_buffer` and records `graph` into it.
+    fn record(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        allocator: &mut Allocator,
+        graph: RenderGraph<Renderer>,
+        renderer: &mut Renderer,
+    ) -> VkResult<()> {
+        unsafe {
+            let begin_info = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.device
+                .begin_command_buffer(command_buffer, &begin_info)?;
+        }
+        graph.execute(
+            (&self.device, &self.debug_utils),
+            command_buffer,
+            &mut self.transients,
+            allocator,
+            self.frame_number,
+            renderer,
+        )
+    }
+}
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        unsafe {
+            // Nothing may still be executing when the command pools and semaphores go away
+            let _ = self.device.device_wait_idle();
+            for frame in &self.frames {
+                self.device.destroy_semaphore(frame.image_available, None);
+                self.device.destroy_command_pool(frame.command_pool, None);
+                if let Some((command_pool, _)) = frame.compute {
+                    self.device.destroy_command_pool(command_pool, None);
+                }
+            }
+            for &semaphore in &self.render_finished {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+            self.device.destroy_semaphore(self.timeline, None);
+            if let Some(timeline) = self.compute_timeline {
+                self.device.destroy_semaphore(timeline, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,1197 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::{
+        debug_utils::DebugUtils,
+        memory::{Allocation, Allocator, MemoryLocation},
+    },
+    ash::{Device, prelude::VkResult, vk},
+    std::sync::Arc,
+};
+
+/// An image declared in a [`RenderGraph`], only valid for the graph that returned it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageHandle(usize);
+
+/// A buffer imported into a [`RenderGraph`], only valid for the graph that returned it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+/// Size and format of a graph image. Usage is worked out from how the passes access it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+impl ImageDesc {
+    pub fn new(format: vk::Format, extent: vk::Extent2D) -> Self {
+        Self { format, extent }
+    }
+
+    fn aspect(&self) -> vk::ImageAspectFlags {
+        format_aspect(self.format)
+    }
+}
+
+/// The layout an imported image is in before the graph runs, or has to be left in after it,
+/// with the stages and accesses that touch it on the other side.
+#[derive(Clone, Copy)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub stage: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+}
+
+/// The last stages and accesses that touched an imported buffer before the graph runs.
+#[derive(Clone, Copy)]
+pub struct BufferState {
+    pub stage: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+}
+
+impl BufferState {
+    pub const NONE: Self = Self {
+        stage: vk::PipelineStageFlags2::NONE,
+        access: vk::AccessFlags2::NONE,
+    };
+}
+
+/// How a pass uses an image. Attachments are declared with [`PassBuilder::color_attachment`]
+/// and [`PassBuilder::depth_attachment`], which also begin dynamic rendering for the pass.
+#[derive(Clone, Copy)]
+pub enum ImageAccess {
+    ColorAttachment,
+    DepthAttachment,
+    Sampled(vk::PipelineStageFlags2),
+    StorageWrite(vk::PipelineStageFlags2),
+    TransferSrc,
+}
+
+impl ImageAccess {
+    fn is_write(self) -> bool {
+        matches!(
+            self,
+            Self::ColorAttachment | Self::DepthAttachment | Self::StorageWrite(_)
+        )
+    }
+
+    fn stage_access(self) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+        use {vk::AccessFlags2 as A, vk::PipelineStageFlags2 as S};
+        match self {
+            Self::ColorAttachment => (
+                S::COLOR_ATTACHMENT_OUTPUT,
+                A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE,
+            ),
+            Self::DepthAttachment => (
+                S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS,
+                A::DEPTH_STENCIL_ATTACHMENT_READ | A::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            Self::Sampled(stages) => (stages, A::SHADER_SAMPLED_READ),
+            Self::StorageWrite(stages) => {
+                (stages, A::SHADER_STORAGE_READ | A::SHADER_STORAGE_WRITE)
+            }
+            Self::TransferSrc => (S::ALL_TRANSFER, A::TRANSFER_READ),
+        }
+    }
+
+    // The generic synchronization2 layouts work for color and depth alike
+    fn layout(self) -> vk::ImageLayout {
+        match self {
+            Self::ColorAttachment | Self::DepthAttachment => vk::ImageLayout::ATTACHMENT_OPTIMAL,
+            Self::Sampled(_) => vk::ImageLayout::READ_ONLY_OPTIMAL,
+            Self::StorageWrite(_) => vk::ImageLayout::GENERAL,
+            Self::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        }
+    }
+
+    fn usage(self) -> vk::ImageUsageFlags {
+        match self {
+            Self::ColorAttachment => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            Self::DepthAttachment => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            Self::Sampled(_) => vk::ImageUsageFlags::SAMPLED,
+            Self::StorageWrite(_) => vk::ImageUsageFlags::STORAGE,
+            Self::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+        }
+    }
+}
+
+/// How a pass uses a buffer.
+#[derive(Clone, Copy)]
+pub enum BufferAccess {
+    Indirect,
+    StorageRead(vk::PipelineStageFlags2),
+    StorageWrite(vk::PipelineStageFlags2),
+    TransferDst,
+}
+
+impl BufferAccess {
+    fn is_write(self) -> bool {
+        matches!(self, Self::StorageWrite(_) | Self::TransferDst)
+    }
+
+    fn stage_access(self) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+        use {vk::AccessFlags2 as A, vk::PipelineStageFlags2 as S};
+        match self {
+            Self::Indirect => (S::DRAW_INDIRECT, A::INDIRECT_COMMAND_READ),
+            Self::StorageRead(stages) => (stages, A::SHADER_STORAGE_READ),
+            Self::StorageWrite(stages) => {
+                (stages, A::SHADER_STORAGE_READ | A::SHADER_STORAGE_WRITE)
+            }
+            Self::TransferDst => (S::ALL_TRANSFER, A::TRANSFER_WRITE),
+        }
+    }
+}
+
+/// What happens to an attachment's contents when the pass begins.
+#[derive(Clone, Copy)]
+pub enum LoadOp {
+    Load,
+    Clear(vk::ClearValue),
+    DontCare,
+}
+
+impl LoadOp {
+    fn vk(self) -> (vk::AttachmentLoadOp, vk::ClearValue) {
+        match self {
+            Self::Load => (vk::AttachmentLoadOp::LOAD, vk::ClearValue::default()),
+            Self::Clear(value) => (vk::AttachmentLoadOp::CLEAR, value),
+            Self::DontCare => (vk::AttachmentLoadOp::DONT_CARE, vk::ClearValue::default()),
+        }
+    }
+}
+
+enum ImageSource {
+    // Memory comes from `TransientImages` and may be shared with images whose lifetimes don't overlap
+    Transient,
+    Imported {
+        image: vk::Image,
+        view: vk::ImageView,
+        initial: ImageState,
+        final_state: ImageState,
+    },
+}
+
+struct GraphImage {
+    // Only for debug names, kept by the physical image it ends up in
+    name: &'static str,
+    desc: ImageDesc,
+    source: ImageSource,
+}
+
+struct GraphBuffer {
+    buffer: vk::Buffer,
+    initial: BufferState,
+}
+
+type PassFn<'a, U> = Box<dyn FnOnce(&PassContext<'_>, &mut U) + 'a>;
+
+struct Pass<'a, U> {
+    name: String,
+    images: Vec<(ImageHandle, ImageAccess)>,
+    buffers: Vec<(BufferHandle, BufferAccess)>,
+    color_attachments: Vec<(ImageHandle, LoadOp)>,
+    depth_attachment: Option<(ImageHandle, LoadOp)>,
+    side_effects: bool,
+    execute: PassFn<'a, U>,
+}
+
+impl<U> Pass<'_, U> {
+    fn attachment_load(&self, image: ImageHandle) -> Option<LoadOp> {
+        self.color_attachments
+            .iter()
+            .chain(&self.depth_attachment)
+            .find(|(handle, _)| *handle == image)
+            .map(|&(_, load)| load)
+    }
+
+    /// Whether the pass depends on the previous contents of `image`.
+    fn reads_image(&self, image: ImageHandle, access: ImageAccess) -> bool {
+        !access.is_write() || matches!(self.attachment_load(image), Some(LoadOp::Load))
+    }
+}
+
+/// Handed to a pass while it records. Resources are looked up by their graph handles.
+pub struct PassContext<'r> {
+    pub device: &'r Device,
+    pub command_buffer: vk::CommandBuffer,
+    images: &'r [(vk::Image, vk::ImageView)],
+    render_area: vk::Extent2D,
+}
+
+impl<'r> PassContext<'r> {
+    /// For passes recorded outside a graph, like on another queue. Has no images or attachments.
+    pub fn outside_graph(device: &'r Device, command_buffer: vk::CommandBuffer) -> Self {
+        Self {
+            device,
+            command_buffer,
+            images: &[],
+            render_area: vk::Extent2D::default(),
+        }
+    }
+
+    pub fn image(&self, handle: ImageHandle) -> vk::Image {
+        self.images[handle.0].0
+    }
+
+    pub fn view(&self, handle: ImageHandle) -> vk::ImageView {
+        self.images[handle.0].1
+    }
+
+    /// Extent of the attachments, zero for passes without any.
+    pub fn render_area(&self) -> vk::Extent2D {
+        self.render_area
+    }
+}
+
+/// Declares the resources one pass touches, see [`RenderGraph::add_pass`].
+#[must_use = "The pass is only added once `execute` is called"]
+pub struct PassBuilder<'g, 'a, U> {
+    graph: &'g mut RenderGraph<'a, U>,
+    name: String,
+    images: Vec<(ImageHandle, ImageAccess)>,
+    buffers: Vec<(BufferHandle, BufferAccess)>,
+    color_attachments: Vec<(ImageHandle, LoadOp)>,
+    depth_attachment: Option<(ImageHandle, LoadOp)>,
+    side_effects: bool,
+}
+
+impl<'a, U> PassBuilder<'_, 'a, U> {
+    /// Renders into `image` at the next color attachment index, always stored.
+    pub fn color_attachment(mut self, image: ImageHandle, load: LoadOp) -> Self {
+        self.color_attachments.push((image, load));
+        self.image(image, ImageAccess::ColorAttachment)
+    }
+
+    /// Renders depth (and stencil, if the format has it) into `image`, always stored.
+    pub fn depth_attachment(mut self, image: ImageHandle, load: LoadOp) -> Self {
+        self.depth_attachment = Some((image, load));
+        self.image(image, ImageAccess::DepthAttachment)
+    }
+
+    /// Declares an access to `image`, every image is accessed at most once per pass.
+    pub fn image(mut self, image: ImageHandle, access: ImageAccess) -> Self {
+        debug_assert!(
+            self.images.iter().all(|(handle, _)| *handle != image),
+            "Pass {} accesses an image twice",
+            self.name
+        );
+        self.images.push((image, access));
+        self
+    }
+
+    /// Declares an access to `buffer`, every buffer is accessed at most once per pass.
+    pub fn buffer(mut self, buffer: BufferHandle, access: BufferAccess) -> Self {
+        debug_assert!(
+            self.buffers.iter().all(|(handle, _)| *handle != buffer),
+            "Pass {} accesses a buffer twice",
+            self.name
+        );
+        self.buffers.push((buffer, access));
+        self
+    }
+
+    /// Keeps the pass even if nothing reads what it writes, like readbacks or queries.
+    pub fn side_effects(mut self) -> Self {
+        self.side_effects = true;
+        self
+    }
+
+    /// Adds the pass, `execute` records its commands. Passes with attachments are already
+    /// inside `vkCmdBeginRendering` when it runs.
+    pub fn execute(self, execute: impl FnOnce(&PassContext<'_>, &mut U) + 'a) {
+        self.graph.passes.push(Pass {
+            name: self.name,
+            images: self.images,
+            buffers: self.buffers,
+            color_attachments: self.color_attachments,
+            depth_attachment: self.depth_attachment,
+            side_effects: self.side_effects,
+            execute: Box::new(execute),
+        });
+    }
+}
+
+/// A frame's passes and the resources they read and write. Barriers and layout transitions are
+/// derived from the declared accesses, passes whose output nobody uses are culled, and
+/// transient images with non-overlapping lifetimes share memory.
+/// Built every frame, `U` is handed mutably to every pass while recording.
+pub struct RenderGraph<'a, U = ()> {
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass<'a, U>>,
+}
+
+impl<U> Default for RenderGraph<'_, U> {
+    fn default() -> Self {
+        Self {
+            images: Vec::new(),
+            buffers: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+}
+
+impl<'a, U> RenderGraph<'a, U> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An image that only lives for this graph, its contents start out undefined.
+    pub fn create_image(&mut self, name: &'static str, desc: ImageDesc) -> ImageHandle {
+        self.images.push(GraphImage {
+            name,
+            desc,
+            source: ImageSource::Transient,
+        });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    /// An image owned outside the graph. It's moved from `initial` and left in `final_state`
+    /// after the last pass, even if no pass uses it.
+    pub fn import_image(
+        &mut self,
+        image: vk::Image,
+        view: vk::ImageView,
+        desc: ImageDesc,
+        initial: ImageState,
+        final_state: ImageState,
+    ) -> ImageHandle {
+        self.images.push(GraphImage {
+            // Named by whoever owns it
+            name: "imported",
+            desc,
+            source: ImageSource::Imported {
+                image,
+                view,
+                initial,
+                final_state,
+            },
+        });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    pub fn import_buffer(&mut self, buffer: vk::Buffer, initial: BufferState) -> BufferHandle {
+        self.buffers.push(GraphBuffer { buffer, initial });
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    /// Starts declaring a pass, passes run in the order they're added.
+    pub fn add_pass<'g>(&'g mut self, name: impl Into<String>) -> PassBuilder<'g, 'a, U> {
+        PassBuilder {
+            graph: self,
+            name: name.into(),
+            images: Vec::new(),
+            buffers: Vec::new(),
+            color_attachments: Vec::new(),
+            depth_attachment: None,
+            side_effects: false,
+        }
+    }
+
+    /// Which passes contribute to an imported resource or have side effects, walking back from the last one.
+    fn cull(&self) -> Vec<bool> {
+        let mut needed_images: Vec<bool> = self
+            .images
+            .iter()
+            .map(|image| matches!(image.source, ImageSource::Imported { .. }))
+            .collect();
+        let mut needed_buffers = vec![true; self.buffers.len()];
+        let mut alive = vec![false; self.passes.len()];
+        for (index, pass) in self.passes.iter().enumerate().rev() {
+            let writes_needed = pass
+                .images
+                .iter()
+                .any(|&(image, access)| access.is_write() && needed_images[image.0])
+                || pass
+                    .buffers
+                    .iter()
+                    .any(|&(buffer, access)| access.is_write() && needed_buffers[buffer.0]);
+            if !(pass.side_effects || writes_needed) {
+                continue;
+            }
+            alive[index] = true;
+            for &(image, access) in &pass.images {
+                if pass.reads_image(image, access) {
+                    needed_images[image.0] = true;
+                }
+            }
+            for &(buffer, access) in &pass.buffers {
+                if !access.is_write() {
+                    needed_buffers[buffer.0] = true;
+                }
+            }
+        }
+        alive
+    }
+
+    /// Records every pass that isn't culled into `command_buffer`, with the barriers between them,
+    /// each pass inside a debug label with its name.
+    /// Transient images come from `transients`, images it replaces are kept until the timeline reaches `retire_value`.
+    pub(crate) fn execute(
+        self,
+        (device, debug_utils): (&Device, &DebugUtils),
+        command_buffer: vk::CommandBuffer,
+        transients: &mut TransientImages,
+        allocator: &mut Allocator,
+        retire_value: u64,
+        user: &mut U,
+    ) -> VkResult<()> {
+        let alive = self.cull();
+        let passes: Vec<Pass<'a, U>> = self
+            .passes
+            .into_iter()
+            .zip(&alive)
+            .filter_map(|(pass, &alive)| alive.then_some(pass))
+            .collect();
+
+        // Usage and lifetime (first and last pass) of every transient image that is still used
+        let mut usage = vec![vk::ImageUsageFlags::empty(); self.images.len()];
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.images.len()];
+        for (position, pass) in passes.iter().enumerate() {
+            for &(image, access) in &pass.images {
+                usage[image.0] |= access.usage();
+                let lifetime = lifetimes[image.0].get_or_insert((position, position));
+                lifetime.1 = position;
+            }
+        }
+        let transient_indices: Vec<usize> = (0..self.images.len())
+            .filter(|&index| {
+                matches!(self.images[index].source, ImageSource::Transient)
+                    && lifetimes[index].is_some()
+            })
+            .collect();
+        let plan = TransientPlan::new(
+            device,
+            transient_indices
+                .iter()
+                .map(|&index| {
+                    (
+                        self.images[index].name,
+                        self.images[index].desc,
+                        usage[index],
+                        lifetimes[index].unwrap(),
+                    )
+                })
+                .collect(),
+        );
+        let transient_images = transients.prepare(allocator, plan, retire_value)?;
+
+        let mut physical = vec![(vk::Image::null(), vk::ImageView::null()); self.images.len()];
+        let mut image_states: Vec<Tracked> = Vec::with_capacity(self.images.len());
+        for (index, image) in self.images.iter().enumerate() {
+            match image.source {
+                ImageSource::Imported {
+                    image,
+                    view,
+                    initial,
+                    ..
+                } => {
+                    physical[index] = (image, view);
+                    image_states.push(Tracked::initial(
+                        initial.layout,
+                        initial.stage,
+                        initial.access,
+                    ));
+                }
+                // The memory may have been used by an aliased image or by the previous frame,
+                // so the first use waits for every earlier write on the queue
+                ImageSource::Transient => image_states.push(Tracked::initial(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::PipelineStageFlags2::ALL_COMMANDS,
+                    vk::AccessFlags2::MEMORY_WRITE,
+                )),
+            }
+        }
+        for (&index, &image) in transient_indices.iter().zip(transient_images) {
+            physical[index] = image;
+        }
+        let buffers: Vec<vk::Buffer> = self.buffers.iter().map(|buffer| buffer.buffer).collect();
+        let mut buffer_states: Vec<Tracked> = self
+            .buffers
+            .iter()
+            .map(|buffer| {
+                Tracked::initial(
+                    vk::ImageLayout::UNDEFINED,
+                    buffer.initial.stage,
+                    buffer.initial.access,
+                )
+            })
+            .collect();
+
+        for pass in passes {
+            // Barriers go inside the label too, so captures show what each pass waited for
+            debug_utils.begin_label(command_buffer, &pass.name);
+            let mut image_barriers = Vec::new();
+            for &(image, access) in &pass.images {
+                let (stage, access_mask) = access.stage_access();
+                // Attachments that get cleared or don't care can drop their old contents
+                let discard = matches!(
+                    pass.attachment_load(image),
+                    Some(LoadOp::Clear(_) | LoadOp::DontCare)
+                );
+                if let Some((src_stage, src_access, old_layout)) = image_states[image.0].access(
+                    stage,
+                    access_mask,
+                    access.layout(),
+                    access.is_write(),
+                ) {
+                    image_barriers.push(image_barrier(
+                        physical[image.0].0,
+                        &self.images[image.0].desc,
+                        (src_stage, src_access),
+                        (stage, access_mask),
+                        if discard {
+                            vk::ImageLayout::UNDEFINED
+                        } else {
+                            old_layout
+                        },
+                        access.layout(),
+                    ));
+                }
+            }
+            let mut buffer_barriers = Vec::new();
+            for &(buffer, access) in &pass.buffers {
+                let (stage, access_mask) = access.stage_access();
+                if let Some((src_stage, src_access, _)) = buffer_states[buffer.0].access(
+                    stage,
+                    access_mask,
+                    vk::ImageLayout::UNDEFINED,
+                    access.is_write(),
+                ) {
+                    buffer_barriers.push(
+                        vk::BufferMemoryBarrier2::default()
+                            .src_stage_mask(src_stage)
+                            .src_access_mask(src_access)
+                            .dst_stage_mask(stage)
+                            .dst_access_mask(access_mask)
+                            .buffer(buffers[buffer.0])
+                            .size(vk::WHOLE_SIZE),
+                    );
+                }
+            }
+            unsafe {
+                if !image_barriers.is_empty() || !buffer_barriers.is_empty() {
+                    device.cmd_pipeline_barrier2(
+                        command_buffer,
+                        &vk::DependencyInfo::default()
+                            .image_memory_barriers(&image_barriers)
+                            .buffer_memory_barriers(&buffer_barriers),
+                    );
+                }
+            }
+
+            let render_area = pass
+                .color_attachments
+                .iter()
+                .chain(&pass.depth_attachment)
+                .next()
+                .map(|(image, _)| self.images[image.0].desc.extent)
+                .unwrap_or_default();
+            let rendering = pass.color_attachments.len() + pass.depth_attachment.iter().len() > 0;
+            if rendering {
+                let attachment_info = |(image, load): &(ImageHandle, LoadOp)| {
+                    let (load_op, clear_value) = load.vk();
+                    vk::RenderingAttachmentInfo::default()
+                        .image_view(physical[image.0].1)
+                        .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
+                        .load_op(load_op)
+                        .store_op(vk::AttachmentStoreOp::STORE)
+                        .clear_value(clear_value)
+                };
+                let color_attachments: Vec<vk::RenderingAttachmentInfo> =
+                    pass.color_attachments.iter().map(attachment_info).collect();
+                let depth = pass.depth_attachment.as_ref().map(attachment_info);
+                let mut rendering_info = vk::RenderingInfo::default()
+                    .render_area(vk::Rect2D::default().extent(render_area))
+                    .layer_count(1)
+                    .color_attachments(&color_attachments);
+                if let (Some(depth), Some((image, _))) = (&depth, &pass.depth_attachment) {
+                    let aspect = self.images[image.0].desc.aspect();
+                    if aspect.contains(vk::ImageAspectFlags::DEPTH) {
+                        rendering_info = rendering_info.depth_attachment(depth);
+                    }
+                    if aspect.contains(vk::ImageAspectFlags::STENCIL) {
+                        rendering_info = rendering_info.stencil_attachment(depth);
+                    }
+                }
+                unsafe { device.cmd_begin_rendering(command_buffer, &rendering_info) };
+            }
+
+            let context = PassContext {
+                device,
+                command_buffer,
+                images: &physical,
+                render_area,
+            };
+            (pass.execute)(&context, user);
+
+            if rendering {
+                unsafe { device.cmd_end_rendering(command_buffer) };
+            }
+            debug_utils.end_label(command_buffer);
+        }
+
+        // Leave the imported images the way their owners expect them
+        let final_barriers: Vec<vk::ImageMemoryBarrier2> = self
+            .images
+            .iter()
+            .zip(&image_states)
+            .zip(&physical)
+            .filter_map(|((image, state), &(handle, _))| {
+                let ImageSource::Imported { final_state, .. } = image.source else {
+                    return None;
+                };
+                (final_state.layout != state.layout
+                    || final_state.stage != vk::PipelineStageFlags2::NONE)
+                    .then(|| {
+                        image_barrier(
+                            handle,
+                            &image.desc,
+                            (state.write_stages | state.read_stages, state.write_access),
+                            (final_state.stage, final_state.access),
+                            state.layout,
+                            final_state.layout,
+                        )
+                    })
+            })
+            .collect();
+        if !final_barriers.is_empty() {
+            unsafe {
+                device.cmd_pipeline_barrier2(
+                    command_buffer,
+                    &vk::DependencyInfo::default().image_memory_barriers(&final_barriers),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a resource stands between passes: its layout, the last write, and the reads since then.
+#[derive(Clone, Copy)]
+struct Tracked {
+    layout: vk::ImageLayout,
+    write_stages: vk::PipelineStageFlags2,
+    write_access: vk::AccessFlags2,
+    read_stages: vk::PipelineStageFlags2,
+    read_access: vk::AccessFlags2,
+}
+
+impl Tracked {
+    fn initial(
+        layout: vk::ImageLayout,
+        write_stages: vk::PipelineStageFlags2,
+        write_access: vk::AccessFlags2,
+    ) -> Self {
+        Self {
+            layout,
+            write_stages,
+            write_access,
+            read_stages: vk::PipelineStageFlags2::NONE,
+            read_access: vk::AccessFlags2::NONE,
+        }
+    }
+
+    /// Records an access, returning the source scope and old layout of the barrier it needs, if any.
+    fn access(
+        &mut self,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+        layout: vk::ImageLayout,
+        write: bool,
+    ) -> Option<(vk::PipelineStageFlags2, vk::AccessFlags2, vk::ImageLayout)> {
+        let old_layout = self.layout;
+        let transition = layout != old_layout;
+        if write || transition {
+            // Writes and layout transitions wait for the last write and every read since
+            let src_stage = self.write_stages | self.read_stages;
+            let src_access = self.write_access;
+            *self = if write {
+                Self::initial(layout, stage, access)
+            } else {
+                // Later reads in other stages have to wait for the transition, it acts as the write
+                Self {
+                    layout,
+                    write_stages: stage,
+                    write_access: vk::AccessFlags2::NONE,
+                    read_stages: stage,
+                    read_access: access,
+                }
+            };
+            return (transition || src_stage != vk::PipelineStageFlags2::NONE)
+                .then_some((src_stage, src_access, old_layout));
+        }
+        let visible = self.read_stages.contains(stage) && self.read_access.contains(access);
+        let unwritten = self.write_stages == vk::PipelineStageFlags2::NONE;
+        self.read_stages |= stage;
+        self.read_access |= access;
+        (!visible && !unwritten).then_some((self.write_stages, self.write_access, old_layout))
+    }
+}
+
+fn image_barrier(
+    image: vk::Image,
+    desc: &ImageDesc,
+    (src_stage, src_access): (vk::PipelineStageFlags2, vk::AccessFlags2),
+    (dst_stage, dst_access): (vk::PipelineStageFlags2, vk::AccessFlags2),
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) -> vk::ImageMemoryBarrier2<'static> {
+    vk::ImageMemoryBarrier2::default()
+        .src_stage_mask(src_stage)
+        .src_access_mask(src_access)
+        .dst_stage_mask(dst_stage)
+        .dst_access_mask(dst_access)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .image(image)
+        .subresource_range(full_range(desc))
+}
+
+fn full_range(desc: &ImageDesc) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::default()
+        .aspect_mask(desc.aspect())
+        .level_count(vk::REMAINING_MIP_LEVELS)
+        .layer_count(vk::REMAINING_ARRAY_LAYERS)
+}
+
+pub(crate) fn format_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+/// Which memory every transient image goes into. Two frames with the same plan reuse the same images.
+#[derive(Clone, PartialEq, Eq)]
+struct TransientPlan {
+    // Name, description, usage and memory group of each image
+    images: Vec<(&'static str, ImageDesc, vk::ImageUsageFlags, usize)>,
+    // Size, alignment and allowed memory types of each group
+    groups: Vec<(vk::DeviceSize, vk::DeviceSize, u32)>,
+}
+
+impl TransientPlan {
+    fn new(
+        device: &Device,
+        images: Vec<(&'static str, ImageDesc, vk::ImageUsageFlags, (usize, usize))>,
+    ) -> Self {
+        let requirements: Vec<vk::MemoryRequirements> = images
+            .iter()
+            .map(|&(_, desc, usage, _)| {
+                let info = image_create_info(&desc, usage);
+                let mut requirements = vk::MemoryRequirements2::default();
+                unsafe {
+                    device.get_device_image_memory_requirements(
+                        &vk::DeviceImageMemoryRequirements::default().create_info(&info),
+                        &mut requirements,
+                    );
+                }
+                requirements.memory_requirements
+            })
+            .collect();
+        Self::pack(images, &requirements)
+    }
+
+    /// Greedily packs images into memory groups, an image can join a group once every image
+    /// already in it is done, and the memory types they allow overlap.
+    fn pack(
+        images: Vec<(&'static str, ImageDesc, vk::ImageUsageFlags, (usize, usize))>,
+        requirements: &[vk::MemoryRequirements],
+    ) -> Self {
+        let mut order: Vec<usize> = (0..images.len()).collect();
+        order.sort_by_key(|&index| images[index].3.0);
+
+        let mut plan = Self {
+            images: images
+                .iter()
+                .map(|&(name, desc, usage, _)| (name, desc, usage, 0))
+                .collect(),
+            groups: Vec::new(),
+        };
+        // Last pass that uses each group
+        let mut group_ends: Vec<usize> = Vec::new();
+        for index in order {
+            let (_, _, _, (first, last)) = images[index];
+            let requirements = requirements[index];
+            let group = (0..plan.groups.len()).find(|&group| {
+                group_ends[group] < first
+                    && plan.groups[group].2 & requirements.memory_type_bits != 0
+            });
+            let group = match group {
+                Some(group) => {
+                    let (size, alignment, type_bits) = &mut plan.groups[group];
+                    *size = (*size).max(requirements.size);
+                    *alignment = (*alignment).max(requirements.alignment);
+                    *type_bits &= requirements.memory_type_bits;
+                    group_ends[group] = last;
+                    group
+                }
+                None => {
+                    plan.groups.push((
+                        requirements.size,
+                        requirements.alignment,
+                        requirements.memory_type_bits,
+                    ));
+                    group_ends.push(last);
+                    plan.groups.len() - 1
+                }
+            };
+            plan.images[index].3 = group;
+        }
+        plan
+    }
+}
+
+fn image_create_info(desc: &ImageDesc, usage: vk::ImageUsageFlags) -> vk::ImageCreateInfo<'static> {
+    vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(desc.format)
+        .extent(desc.extent.into())
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+}
+
+struct TransientSet {
+    memory: Vec<Allocation>,
+    images: Vec<(vk::Image, vk::ImageView)>,
+}
+
+/// The physical images behind a graph's transient images, kept across frames while the plan stays the same.
+pub(crate) struct TransientImages {
+    device: Arc<Device>,
+    debug_utils: DebugUtils,
+    plan: Option<TransientPlan>,
+    current: TransientSet,
+    // Replaced sets and the timeline value after which no frame uses them anymore
+    retired: Vec<(u64, TransientSet)>,
+}
+
+impl TransientImages {
+    pub(crate) fn new(device: Arc<Device>, debug_utils: DebugUtils) -> Self {
+        Self {
+            device,
+            debug_utils,
+            plan: None,
+            current: TransientSet {
+                memory: Vec::new(),
+                images: Vec::new(),
+            },
+            retired: Vec::new(),
+        }
+    }
+
+    fn prepare(
+        &mut self,
+        allocator: &mut Allocator,
+        plan: TransientPlan,
+        retire_value: u64,
+    ) -> VkResult<&[(vk::Image, vk::ImageView)]> {
+        if self.plan.as_ref() == Some(&plan) {
+            return Ok(&self.current.images);
+        }
+        let mut set = TransientSet {
+            memory: Vec::with_capacity(plan.groups.len()),
+            images: Vec::with_capacity(plan.images.len()),
+        };
+        let result = self.create_set(allocator, &plan, &mut set);
+        if let Err(x) = result {
+            self.destroy_set(allocator, &set);
+            return Err(x);
+        }
+        let old = std::mem::replace(&mut self.current, set);
+        self.retired.push((retire_value, old));
+        self.plan = Some(plan);
+        Ok(&self.current.images)
+    }
+
+    fn create_set(
+        &self,
+        allocator: &mut Allocator,
+        plan: &TransientPlan,
+        set: &mut TransientSet,
+    ) -> VkResult<()> {
+        for &(size, alignment, memory_type_bits) in &plan.groups {
+            set.memory.push(allocator.allocate_memory_for(
+                vk::MemoryRequirements {
+                    size,
+                    alignment,
+                    memory_type_bits,
+                },
+                MemoryLocation::GpuOnly,
+            )?);
+        }
+        for (name, desc, usage, group) in &plan.images {
+            unsafe {
+                let image = self
+                    .device
+                    .create_image(&image_create_info(desc, *usage), None)?;
+                // Sizes tell apart the levels of a chain, like the bloom images
+                self.debug_utils.name(
+                    image,
+                    format_args!("{name} {}x{}", desc.extent.width, desc.extent.height),
+                );
+                let memory = &set.memory[*group];
+                if let Err(x) = self
+                    .device
+                    .bind_image_memory(image, memory.memory, memory.offset)
+                {
+                    self.device.destroy_image(image, None);
+                    return Err(x);
+                }
+                let view = match self.device.create_image_view(
+                    &vk::ImageViewCreateInfo::default()
+                        .image(image)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(desc.format)
+                        .subresource_range(full_range(desc)),
+                    None,
+                ) {
+                    Ok(x) => x,
+                    Err(x) => {
+                        self.device.destroy_image(image, None);
+                        return Err(x);
+                    }
+                };
+                set.images.push((image, view));
+            }
+        }
+        Ok(())
+    }
+
+    fn destroy_set(&self, allocator: &mut Allocator, set: &TransientSet) {
+        unsafe {
+            for &(image, view) in &set.images {
+                self.device.destroy_image_view(view, None);
+                self.device.destroy_image(image, None);
+            }
+        }
+        for memory in &set.memory {
+            allocator.free_memory(memory);
+        }
+    }
+
+    /// Destroys the replaced images no frame up to `completed_value` on the timeline still uses.
+    pub(crate) fn reclaim(&mut self, allocator: &mut Allocator, completed_value: u64) {
+        let (done, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.retired)
+            .into_iter()
+            .partition(|(retire_value, _)| *retire_value <= completed_value);
+        self.retired = pending;
+        for (_, set) in done {
+            self.destroy_set(allocator, &set);
+        }
+    }
+
+    /// Destroys every image, the GPU must be idle.
+    pub(crate) fn destroy(&mut self, allocator: &mut Allocator) {
+        self.reclaim(allocator, u64::MAX);
+        let current = std::mem::replace(
+            &mut self.current,
+            TransientSet {
+                memory: Vec::new(),
+                images: Vec::new(),
+            },
+        );
+        self.destroy_set(allocator, &current);
+        self.plan = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: vk::Extent2D = vk::Extent2D {
+        width: 64,
+        height: 64,
+    };
+
+    fn desc() -> ImageDesc {
+        ImageDesc::new(vk::Format::R8G8B8A8_UNORM, EXTENT)
+    }
+
+    fn sampled() -> ImageAccess {
+        ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+    }
+
+    #[test]
+    fn passes_without_needed_output_or_side_effects_are_culled() {
+        let mut graph: RenderGraph<()> = RenderGraph::new();
+        let target = graph.import_image(
+            vk::Image::null(),
+            vk::ImageView::null(),
+            desc(),
+            ImageState {
+                layout: vk::ImageLayout::UNDEFINED,
+                stage: vk::PipelineStageFlags2::NONE,
+                access: vk::AccessFlags2::NONE,
+            },
+            ImageState {
+                layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                stage: vk::PipelineStageFlags2::NONE,
+                access: vk::AccessFlags2::NONE,
+            },
+        );
+        let [scene, unused, captured] =
+            ["scene", "unused", "captured"].map(|name| graph.create_image(name, desc()));
+        graph
+            .add_pass("scene")
+            .color_attachment(scene, LoadOp::DontCare)
+            .execute(|_, _| ());
+        graph
+            .add_pass("composite")
+            .image(scene, sampled())
+            .color_attachment(target, LoadOp::DontCare)
+            .execute(|_, _| ());
+        graph
+            .add_pass("unused")
+            .image(scene, sampled())
+            .color_attachment(unused, LoadOp::DontCare)
+            .execute(|_, _| ());
+        graph
+            .add_pass("capture")
+            .color_attachment(captured, LoadOp::DontCare)
+            .execute(|_, _| ());
+        graph
+            .add_pass("readback")
+            .image(captured, ImageAccess::TransferSrc)
+            .side_effects()
+            .execute(|_, _| ());
+        assert_eq!(graph.cull(), [true, true, false, true, true]);
+    }
+
+    #[test]
+    fn loaded_attachments_keep_their_previous_writer() {
+        let mut graph: RenderGraph<()> = RenderGraph::new();
+        let [image, overlay] = ["image", "overlay"].map(|name| graph.create_image(name, desc()));
+        graph
+            .add_pass("first")
+            .color_attachment(image, LoadOp::DontCare)
+            .execute(|_, _| ());
+        graph
+            .add_pass("overlay")
+            .color_attachment(overlay, LoadOp::DontCare)
+            .execute(|_, _| ());
+        // Blends onto what "first" drew, but clears "overlay" instead of reading it
+        graph
+            .add_pass("loaded")
+            .color_attachment(image, LoadOp::Load)
+            .color_attachment(overlay, LoadOp::Clear(vk::ClearValue::default()))
+            .execute(|_, _| ());
+        graph
+            .add_pass("readback")
+            .image(image, ImageAccess::TransferSrc)
+            .side_effects()
+            .execute(|_, _| ());
+        assert_eq!(graph.cull(), [true, false, true, true]);
+    }
+
+    // ash only implements `Debug` with the debug feature, hence `assert!` over `assert_eq!`
+    #[test]
+    fn reads_wait_for_the_last_write() {
+        use {vk::AccessFlags2 as A, vk::PipelineStageFlags2 as S};
+        let write = ImageAccess::ColorAttachment;
+        let (write_stage, write_access) = write.stage_access();
+        let (read_stage, read_access) = sampled().stage_access();
+        let mut state = Tracked::initial(vk::ImageLayout::UNDEFINED, S::NONE, A::NONE);
+
+        // The first write only has a layout to transition
+        assert!(
+            state.access(write_stage, write_access, write.layout(), true)
+                == Some((S::NONE, A::NONE, vk::ImageLayout::UNDEFINED))
+        );
+        // Reading waits for the write and moves the image to its read layout
+        assert!(
+            state.access(read_stage, read_access, sampled().layout(), false)
+                == Some((write_stage, write_access, write.layout()))
+        );
+        // Another read of the same kind is covered by the barrier before it
+        assert!(
+            state
+                .access(read_stage, read_access, sampled().layout(), false)
+                .is_none()
+        );
+        // Writing again waits for the transition and every read since
+        assert!(
+            state.access(write_stage, write_access, write.layout(), true)
+                == Some((read_stage, A::NONE, sampled().layout()))
+        );
+    }
+
+    #[test]
+    fn buffer_reads_after_reads_need_no_barrier() {
+        use {vk::AccessFlags2 as A, vk::PipelineStageFlags2 as S};
+        let layout = vk::ImageLayout::UNDEFINED;
+        let mut state = Tracked::initial(layout, S::NONE, A::NONE);
+        let (write_stage, write_access) =
+            BufferAccess::StorageWrite(S::COMPUTE_SHADER).stage_access();
+        let (read_stage, read_access) = BufferAccess::Indirect.stage_access();
+
+        // Nothing wrote the buffer before the graph
+        assert!(
+            state
+                .access(read_stage, read_access, layout, false)
+                .is_none()
+        );
+        assert!(
+            state.access(write_stage, write_access, layout, true)
+                == Some((read_stage, A::NONE, layout))
+        );
+        assert!(
+            state.access(read_stage, read_access, layout, false)
+                == Some((write_stage, write_access, layout))
+        );
+        assert!(
+            state
+                .access(read_stage, read_access, layout, false)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn images_with_disjoint_lifetimes_share_memory() {
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        let images = vec![
+            ("a", desc(), usage, (0, 1)),
+            ("b", desc(), usage, (2, 3)),
+            ("c", desc(), usage, (1, 2)),
+            ("d", desc(), usage, (4, 4)),
+        ];
+        let requirements = |size, alignment, memory_type_bits| vk::MemoryRequirements {
+            size,
+            alignment,
+            memory_type_bits,
+        };
+        let plan = TransientPlan::pack(
+            images,
+            &[
+                requirements(100, 16, 0b11),
+                requirements(200, 64, 0b01),
+                requirements(50, 16, 0b11),
+                // Can't live in the memory of any other image
+                requirements(10, 16, 0b100),
+            ],
+        );
+        let groups: Vec<usize> = plan.images.iter().map(|image| image.3).collect();
+        // "c" overlaps both "a" and "b", which take turns in the first group
+        assert_eq!(groups, [0, 0, 1, 2]);
+        assert_eq!(
+            plan.groups,
+            [(200, 64, 0b01), (50, 16, 0b11), (10, 16, 0b100)]
+        );
+    }
+}
@@ -1,86 +1,228 @@
-#![cfg(all(feature = "debug", feature = "vulkan", debug_assertions))]
+#![cfg(all(feature = "debug", feature = "vulkan"))]
 
 use {
-    crate::{logln, utils::debug_logln, vk::data},
+    crate::{
+        ValidationSettings, ValidationSeverity,
+        vk::{
+            data,
+            error::{RendererError, vk_report},
+        },
+    },
     ash::{ext, vk},
-    error_stack::{IntoReport as _, Report},
-    std::ffi::c_void,
+    error_stack::Report,
+    std::{
+        borrow::Cow,
+        ffi::{CStr, c_void},
+        fmt::Write,
+        sync::{Arc, Mutex},
+    },
 };
 
-const VK_DEBUG_LOCATION: &'static str = "VULKAN VALIDATION";
-
-const VALIDATION: bool = const || {
-    #[cfg(all(debug_assertions, feature = "debug"))]
-    {
-        return true;
+unsafe extern "system" fn vk_debug_callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    // The state is boxed in the `VkDebug` that owns the messenger, so it outlives every call
+    let (callback_data, state) = unsafe {
+        (
+            &*p_callback_data,
+            &*p_user_data.cast::<data::ValidationState>(),
+        )
+    };
+    let message = unsafe { describe(callback_data) };
+    crate::logln!(
+        format!(
+            "vulkan {} {}",
+            type_name(message_type),
+            severity_name(severity)
+        ),
+        "{message}"
+    );
+    // Panicking here would unwind into the driver, the renderer thread panics for it instead
+    if severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR && state.settings.panic_on_error {
+        state
+            .first_error
+            .lock()
+            .unwrap_or_else(|x| x.into_inner())
+            .get_or_insert(message);
     }
-    false
-};
-
-const VALIDATION_LAYER: &'static str = "VK_LAYER_KHRONOS_validation";
+    vk::FALSE
+}
 
-impl data::VkDebug {
-    unsafe extern "system" fn vk_debug_callback(
-        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-        validation_type: vk::DebugUtilsMessageTypeFlagsEXT,
-        p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-        _: *mut c_void,
-    ) -> vk::Bool32 {
-        match severity {
-            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => return vk::FALSE,
-            vk::DebugUtilsMessageSeverityFlagsEXT::INFO => return vk::FALSE,
-            _ => {}
+/// The message with its ID, then every object it names and the labels open on the queue and
+/// command buffer it came from.
+unsafe fn describe(data: &vk::DebugUtilsMessengerCallbackDataEXT) -> String {
+    let lossy = |text: &CStr| text.to_string_lossy().into_owned();
+    let mut text = String::new();
+    unsafe {
+        if let Some(id) = data.message_id_name_as_c_str() {
+            let _ = write!(text, "{}: ", id.to_string_lossy());
+        }
+        text += &data
+            .message_as_c_str()
+            .map_or(Cow::Borrowed("(no message)"), CStr::to_string_lossy);
+        for object in array(data.p_objects, data.object_count) {
+            let _ = write!(
+                text,
+                "\n  {:?} {:#x}",
+                object.object_type, object.object_handle
+            );
+            if let Some(name) = object.object_name_as_c_str() {
+                let _ = write!(text, " \"{}\"", name.to_string_lossy());
+            }
         }
-        match validation_type {
-            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => return vk::FALSE,
-            _ => {}
+        let labels = |labels: &[vk::DebugUtilsLabelEXT]| -> Vec<String> {
+            labels
+                .iter()
+                .filter_map(|label| label.label_name_as_c_str())
+                .map(lossy)
+                .collect()
+        };
+        let queue_labels = labels(array(data.p_queue_labels, data.queue_label_count));
+        if !queue_labels.is_empty() {
+            let _ = write!(text, "\n  queue labels: {}", queue_labels.join(", "));
+        }
+        let command_labels = labels(array(data.p_cmd_buf_labels, data.cmd_buf_label_count));
+        if !command_labels.is_empty() {
+            let _ = write!(text, "\n  command labels: {}", command_labels.join(", "));
         }
+    }
+    text
+}
 
-        debug_logln!(
-            "Type: {:?}, Severity: {:?}, Message: {:?}",
-            validation_type,
-            severity,
-            unsafe { (*p_callback_data).p_message },
-            VK_DEBUG_LOCATION
-        );
+// Vulkan passes arrays as a pointer and a count, the pointer may be null when there are none
+unsafe fn array<'a, T>(pointer: *const T, count: u32) -> &'a [T] {
+    if pointer.is_null() || count == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(pointer, count as usize) }
+    }
+}
 
-        vk::FALSE
+fn severity_name(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> &'static str {
+    match severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "error",
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "warning",
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "info",
+        _ => "verbose",
     }
+}
 
-    fn new(entry: &data::VkStart) -> Result<Self, Report<std::error::Error>> {
-        let debug_utils_loader = ext::debug_utils::Instance::new(*entry.entry, *entry.instance);
-        if !VALIDATION {
-            logln!(
-                "VkDebug::new was called when debugging was disabled, how did you do that??",
-                VK_DEBUG_LOCATION,
-            );
-            return Err(Report::new("Unnecessary VkDebug::new Call")
-                .attach("debug_assertions was disabled")
-                .attach("The \"debug\" feature was disabled"));
+// A message can be of several types at once
+fn type_name(message_type: vk::DebugUtilsMessageTypeFlagsEXT) -> String {
+    [
+        (vk::DebugUtilsMessageTypeFlagsEXT::GENERAL, "general"),
+        (vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION, "validation"),
+        (
+            vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            "performance",
+        ),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| message_type.contains(*flag))
+    .map(|(_, name)| name)
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+impl data::VkDebug {
+    /// `None` if the settings leave no message type to report.
+    pub(crate) fn new(
+        start: &data::VkStart,
+        settings: ValidationSettings,
+    ) -> Result<Option<Self>, Report<RendererError>> {
+        let severity_flags = [
+            (
+                ValidationSeverity::Verbose,
+                vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            ),
+            (
+                ValidationSeverity::Info,
+                vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            ),
+            (
+                ValidationSeverity::Warning,
+                vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            ),
+            (
+                ValidationSeverity::Error,
+                vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            ),
+        ]
+        .into_iter()
+        .filter(|(severity, _)| *severity >= settings.min_severity)
+        .fold(
+            vk::DebugUtilsMessageSeverityFlagsEXT::empty(),
+            |flags, (_, flag)| flags | flag,
+        );
+        let type_flags = [
+            (settings.general, vk::DebugUtilsMessageTypeFlagsEXT::GENERAL),
+            (
+                settings.validation,
+                vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            ),
+            (
+                settings.performance,
+                vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            ),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(
+            vk::DebugUtilsMessageTypeFlagsEXT::empty(),
+            |flags, (_, flag)| flags | flag,
+        );
+        // Messengers have to listen to at least one type
+        if type_flags.is_empty() {
+            return Ok(None);
         }
-        let severity_flags = vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-            | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
-        let type_flags = vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE;
+
+        let loader = Arc::new(ext::debug_utils::Instance::new(
+            &start.entry,
+            &start.instance,
+        ));
+        let mut state = Box::new(data::ValidationState {
+            settings,
+            first_error: Mutex::new(None),
+        });
         let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
             .message_severity(severity_flags)
             .message_type(type_flags)
-            .pfn_user_callback(Some(vk_debug_callback));
-        let messenger = match debug_utils_loader.create_debug_utils_messenger(&debug_info, None) {
-            Ok(x) => x,
-            Err(x) => Report::new(x).attach("Failed to create VkDebug.messenger."),
-        };
-        Self {
-            loader: debug_utils_loader,
+            .pfn_user_callback(Some(vk_debug_callback))
+            .user_data((&mut *state as *mut data::ValidationState).cast());
+        let messenger =
+            unsafe { loader.create_debug_utils_messenger(&debug_info, None) }.map_err(|x| {
+                vk_report(x, RendererError::Instance).attach("creating the debug messenger")
+            })?;
+        Ok(Some(Self {
+            loader,
             messenger,
+            state,
+        }))
+    }
+
+    /// Panics with the first validation error, if errors should panic and there was one.
+    pub(crate) fn check(&self) {
+        let first_error = self
+            .state
+            .first_error
+            .lock()
+            .unwrap_or_else(|x| x.into_inner())
+            .take();
+        if let Some(message) = first_error {
+            panic!("Vulkan validation error: {message}");
         }
     }
 }
 
 impl Drop for data::VkDebug {
+    // The messenger goes before the state its callback reads
     fn drop(&mut self) {
-        if let Some(x) = self.messenger {
-            self.loader.destroy_debug_utils_messenger(x, None);
-        }
+        unsafe {
+            self.loader
+                .destroy_debug_utils_messenger(self.messenger, None)
+        };
     }
 }
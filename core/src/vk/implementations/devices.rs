@@ -1,85 +1,246 @@
-use crate::utils::str_to_p_const_c_char as stpc3;
-use std::sync::Arc;
+#![cfg(feature = "vulkan")]
 
-#[path = "../data.rs"]
-mod data;
+use {
+    crate::{
+        DeviceSelector,
+        vk::{
+            capabilities::DeviceCapabilities,
+            data,
+            debug_utils::DebugUtils,
+            error::{RendererError, vk_report},
+            memory::Allocator,
+            queues::{QueueFamilies, Queues},
+            selection,
+        },
+    },
+    ash::{
+        Device, Instance, ext, khr,
+        vk::{
+            self, DeviceCreateInfo, DeviceQueueCreateInfo, PhysicalDevice,
+            PhysicalDeviceColorWriteEnableFeaturesEXT,
+            PhysicalDeviceExtendedDynamicState2FeaturesEXT, PhysicalDeviceFeatures,
+            PhysicalDeviceMaintenance5FeaturesKHR,
+            PhysicalDeviceVertexInputDynamicStateFeaturesEXT, PhysicalDeviceVulkan11Features,
+            PhysicalDeviceVulkan12Features, PhysicalDeviceVulkan13Features,
+        },
+    },
+    error_stack::Report,
+    std::sync::Arc,
+};
 
 impl data::VkDevices {
-    const REQUIRED_DEVICE_EXTENSIONS: &[*const c_char] = &[
-        // Swapchain Creation
-        stpc3!("VK_KHR_swapchain"),
-        // Added Dynamic Rendering, Flexible Pipelines
-        stpc3!("VK_KHR_dynamic_rendering"),
-        // Graph Frame-friendly Sync
-        stpc3!("VK_KHR_timeline_semaphore"),
-        // Cleaner, less error-prone sync API
-        stpc3!("VK_KHR_synchronization2"),
-        // Change Cull Mode, Topology, Blend State, etc. at draw time.
-        stpc3!("VK_EXT_extended_dynamic_state"),
-        stpc3!("VK_EXT_extended_dynamic_state2"),
-        stpc3!("VK_EXT_extended_dynamic_state3"),
-        // Dynamic Vertex Bindings/Attibutes
-        stpc3!("VK_EXT_vertex_input_dynamic_state"),
-        // Per-attachment color write control
-        stpc3!("VK_EXT_color_write_enable"),
-        // Bindless textures/buffers, sparse descriptor arrays
-        stpc3!("VK_EXT_descriptor_indexing"),
-        // GPU pointers for bindless/resource-descriptor-less acces
-        stpc3!("VK_KHR_buffer_device_address"),
-        // Query VRAM Usage
-        stpc3!("VK_EXT_memory_budget"),
-        // Lower-percision math for bandwidth saving
-        stpc3!("VK_KHR_shader_float16_int8"),
-        // Integer dot products for ML/physics.
-        stpc3!("VK_KHR_shader_integer_dot_product"),
-        // Negative viewport height, minor fixes
-        stpc3!("VK_KHR_maintenance1"),
-        // New image layouts, subpass dependency improvements
-        stpc3!("VK_KHR_maintenance2"),
-        // Descriptor query improvements
-        stpc3!("VK_KHR_maintenance3"),
-        // Device memory null handles, improved queries,
-        stpc3!("VK_KHR_maintenance4"),
-        // Pipeline creation feedback, descriptor indexing improvements
-        stpc3!("VK_KHR_maintenance5"),
-        // SPIR-V 1.4 Support
-        stpc3!("VK_KHR_spirv_1_4"),
-        // Extended Renderpass Creation
-        stpc3!("VK_KHR_create_renderpass2"),
-    ];
-
-    pub(crate) fn new(vk_start: &data::VkStart) -> Self {}
+    /// Picks the GPU, `present_surface` rules out the ones that can't present to the window.
+    pub(crate) fn new(
+        start: &data::VkStart,
+        present_surface: Option<&data::VkSurface>,
+        device_selector: Option<DeviceSelector>,
+    ) -> Result<Self, Report<RendererError>> {
+        let instance = &start.instance;
+        // The environment wins, so a device can be forced without touching the App
+        let device_selector = DeviceSelector::from_env()
+            .map_err(|x| Report::new(RendererError::NoSuitableDevice).attach(x))?
+            .or(device_selector);
+        let (physical, capabilities, queue_families) = Self::pick_physical_device(
+            instance,
+            present_surface.map(|surface| (&*surface.functions, &surface.handle)),
+            device_selector.as_ref(),
+        )?;
+        let logical =
+            Self::create_logical_device(instance, &physical, &capabilities, &queue_families)?;
+        let debug_utils = DebugUtils::new(start, &logical);
+        let queues = Queues::new(&logical, queue_families, &debug_utils);
+        let allocator = Allocator::new(
+            instance.clone(),
+            physical,
+            logical.clone(),
+            &capabilities,
+            debug_utils.clone(),
+        );
+        Ok(Self {
+            physical,
+            capabilities,
+            logical,
+            debug_utils,
+            queues,
+            allocator,
+        })
+    }
 
-    fn pick_physical_device(instance: &ash::Instance) -> Arc<vk::PhysicalDevice> {
-        // Get Physical Devices
-        let devices = unsafe {
-            instance
-                .enumerate_physical_devices()
-                .expect("Failed to find Physical Devices")
+    fn pick_physical_device(
+        instance: &Instance,
+        present_surface: Option<(&khr::surface::Instance, &vk::SurfaceKHR)>,
+        device_selector: Option<&DeviceSelector>,
+    ) -> Result<(PhysicalDevice, DeviceCapabilities, QueueFamilies), Report<RendererError>> {
+        // Scores every device, rejecting the ones missing something we can't run without
+        let (physical_device, candidate) =
+            selection::select_physical_device(instance, present_surface, device_selector)?;
+        crate::logln!(
+            "vulkan device",
+            "Using {} ({}, score {})",
+            candidate.name,
+            selection::device_type_name(candidate.device_type),
+            candidate.score
+        );
+        crate::logln!(
+            "vulkan device",
+            "Optional device extensions: {}",
+            candidate.capabilities.optional_extension_names().join(", ")
+        );
+        let Some(queue_families) = candidate.queue_families else {
+            return Err(Report::new(RendererError::NoSuitableDevice)
+                .attach(format!("{} has no usable queue families", candidate.name)));
         };
-        // Assert that there are devices
-        assert!(!devices.is_empty());
-        // Iterate over all avaliable devices & find a good one.
-        // Panic if none were suitable
-        let physical_device = devices.into_iter().find(|&device| {
-            let mut return_items: (u32, u32, Arc<vk::PhysicalDevice>);
-            // gets physical device properties
-            let props = unsafe { instance.get_physical_device_properties(device) };
-            // skip the device if the vulkan version is less than 1.3.286.0
-            if props.api_version < vk::make_api_version(0, 1, 3, 286) {
-                return false;
-            }
-            // Collect Queue Families
-            let queue_Families =
-                unsafe { instance.get_physical_device_queue_family_properties(device) };
-            // Skip the device if it doesn't support
-        });
+        crate::logln!(
+            "vulkan device",
+            "Queue families: graphics {}, present {:?}, compute {}{}, transfer {}{}",
+            queue_families.graphics,
+            queue_families.present,
+            queue_families.compute,
+            if queue_families.has_async_compute() {
+                " (async)"
+            } else {
+                ""
+            },
+            queue_families.transfer,
+            if queue_families.has_dedicated_transfer() {
+                " (dedicated)"
+            } else {
+                ""
+            },
+        );
+        Ok((physical_device, candidate.capabilities, queue_families))
+    }
+
+    fn create_logical_device(
+        instance: &Instance,
+        physical_device: &PhysicalDevice,
+        capabilities: &DeviceCapabilities,
+        queue_families: &QueueFamilies,
+    ) -> Result<Arc<Device>, Report<RendererError>> {
+        // One queue for every distinct family, roles in the same family share it
+        let priorities: &[f32] = &[1.0];
+        // Create the Logical Device's Queue Info
+        let logical_device_queue_create_info: Vec<DeviceQueueCreateInfo> = queue_families
+            .unique()
+            .into_iter()
+            .map(|family| {
+                DeviceQueueCreateInfo::default()
+                    .queue_family_index(family)
+                    .queue_priorities(priorities)
+            })
+            .collect();
+        // Swapchain only when presenting, plus every supported optional extension
+        let extension_names = capabilities.enabled_extensions(queue_families.present.is_some());
+        // Only enable the core features we use and the device supports
+        let physical_device_features = PhysicalDeviceFeatures::default()
+            .sampler_anisotropy(capabilities.sampler_anisotropy)
+            .multi_draw_indirect(capabilities.multi_draw_indirect)
+            .draw_indirect_first_instance(capabilities.draw_indirect_first_instance)
+            .texture_compression_bc(capabilities.texture_compression_bc)
+            .depth_clamp(capabilities.depth_clamp)
+            .depth_bias_clamp(capabilities.depth_bias_clamp)
+            .fill_mode_non_solid(capabilities.fill_mode_non_solid)
+            .independent_blend(capabilities.independent_blend)
+            .shader_int64(capabilities.shader_int64)
+            .pipeline_statistics_query(capabilities.pipeline_statistics_query);
+        // Collect all good physical device features from Vulkan 1.1
+        let mut physical_device_features_vulkan_11 = PhysicalDeviceVulkan11Features::default()
+            .shader_draw_parameters(capabilities.shader_draw_parameters);
+        // Collect all good physical device features from Vulkan 1.2
+        let mut physical_device_features_vulkan_12 = PhysicalDeviceVulkan12Features::default()
+            // Required
+            .timeline_semaphore(true)
+            .buffer_device_address(true)
+            // Required, Bindless descriptors
+            .runtime_descriptor_array(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_variable_descriptor_count(true)
+            .descriptor_binding_update_unused_while_pending(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .descriptor_binding_storage_image_update_after_bind(true)
+            .descriptor_binding_storage_buffer_update_after_bind(true)
+            .shader_sampled_image_array_non_uniform_indexing(true)
+            .shader_storage_image_array_non_uniform_indexing(true)
+            .shader_storage_buffer_array_non_uniform_indexing(true)
+            // Optional
+            .draw_indirect_count(capabilities.draw_indirect_count)
+            .sampler_filter_minmax(capabilities.sampler_filter_minmax)
+            .shader_float16(capabilities.shader_float16)
+            .shader_int8(capabilities.shader_int8);
+        // Collect all good physical device features from Vulkan 1.3
+        let mut physical_device_features_vulkan_13 = PhysicalDeviceVulkan13Features::default()
+            .dynamic_rendering(true)
+            .synchronization2(true)
+            .shader_integer_dot_product(capabilities.shader_integer_dot_product);
+        // Extension feature structs may only be chained when their extension is enabled
+        let mut physical_device_features_extended_dynamic_state2 =
+            PhysicalDeviceExtendedDynamicState2FeaturesEXT::default()
+                .extended_dynamic_state2_logic_op(capabilities.extended_dynamic_state2_logic_op)
+                .extended_dynamic_state2_patch_control_points(
+                    capabilities.extended_dynamic_state2_patch_control_points,
+                );
+        let mut physical_device_features_extended_dynamic_state3 =
+            capabilities.extended_dynamic_state3.features();
+        let mut physical_device_features_vertex_input_dynamic_state =
+            PhysicalDeviceVertexInputDynamicStateFeaturesEXT::default()
+                .vertex_input_dynamic_state(capabilities.vertex_input_dynamic_state);
+        let mut physical_device_features_color_write_enable =
+            PhysicalDeviceColorWriteEnableFeaturesEXT::default()
+                .color_write_enable(capabilities.color_write_enable);
+        let mut physical_device_features_maintenance5 =
+            PhysicalDeviceMaintenance5FeaturesKHR::default()
+                .maintenance5(capabilities.maintenance5);
+        // Create the Logical Device's info with all of the features inputed
+        let mut logical_device_create_info = DeviceCreateInfo::default()
+            .push_next(&mut physical_device_features_vulkan_11)
+            .push_next(&mut physical_device_features_vulkan_12)
+            .push_next(&mut physical_device_features_vulkan_13)
+            .queue_create_infos(&logical_device_queue_create_info)
+            .enabled_extension_names(&extension_names)
+            .enabled_features(&physical_device_features);
+        if capabilities.has_extension(ext::extended_dynamic_state2::NAME) {
+            logical_device_create_info = logical_device_create_info
+                .push_next(&mut physical_device_features_extended_dynamic_state2);
+        }
+        if capabilities.has_extension(ext::extended_dynamic_state3::NAME) {
+            logical_device_create_info = logical_device_create_info
+                .push_next(&mut physical_device_features_extended_dynamic_state3);
+        }
+        if capabilities.has_extension(ext::vertex_input_dynamic_state::NAME) {
+            logical_device_create_info = logical_device_create_info
+                .push_next(&mut physical_device_features_vertex_input_dynamic_state);
+        }
+        if capabilities.has_extension(ext::color_write_enable::NAME) {
+            logical_device_create_info = logical_device_create_info
+                .push_next(&mut physical_device_features_color_write_enable);
+        }
+        if capabilities.has_extension(khr::maintenance5::NAME) {
+            logical_device_create_info =
+                logical_device_create_info.push_next(&mut physical_device_features_maintenance5);
+        }
+        // Create the Logical Device
+        let device =
+            unsafe { instance.create_device(*physical_device, &logical_device_create_info, None) }
+                .map_err(|x| {
+                    let properties =
+                        unsafe { instance.get_physical_device_properties(*physical_device) };
+                    let name = properties.device_name_as_c_str().unwrap_or_default();
+                    vk_report(x, RendererError::Device)
+                        .attach(format!("device: {}", name.to_string_lossy()))
+                        .attach(format!(
+                            "optional extensions: {}",
+                            capabilities.optional_extension_names().join(", ")
+                        ))
+                })?;
+        Ok(Arc::new(device))
     }
 }
 
 impl Drop for data::VkDevices {
     fn drop(&mut self) {
         unsafe {
+            // Every buffer and image was destroyed by the layers above, only the blocks are left
+            self.allocator.destroy();
             self.logical.destroy_device(None);
         }
     }
@@ -1,18 +1,397 @@
-#[path = "../data.rs"]
-mod data;
+#![cfg(feature = "vulkan")]
 
-impl data::VkDisplay {}
+use {
+    crate::{
+        ColorOutput, VSync,
+        vk::{
+            data,
+            error::{RendererError, vk_report},
+        },
+    },
+    ash::{khr, vk},
+    error_stack::{Report, ResultExt},
+    raw_window_handle::{RawDisplayHandle, RawWindowHandle},
+    std::sync::Arc,
+    tokio::sync::mpsc::Receiver as TokioReceiver,
+    winit::dpi::PhysicalSize,
+};
 
-impl data::VkSwapchain {}
+impl data::VkDisplay {
+    /// Drains every pending resize from the window, returns true if the size changed.
+    /// Offscreen targets never change size.
+    pub(crate) fn poll_window_size(&mut self) -> bool {
+        let data::VkDisplay::Window(window) = self else {
+            return false;
+        };
+        let mut changed = false;
+        while let Ok(size) = window.inner_size_reciever.try_recv() {
+            changed |= size != window.window_size;
+            window.window_size = size;
+        }
+        changed
+    }
 
-impl Drop for data::VkSwapchain {
-    fn drop(&mut self) {}
+    /// A minimized window has no area, there is nothing to render to.
+    pub(crate) fn is_minimized(&self) -> bool {
+        match self {
+            data::VkDisplay::Window(window) => {
+                window.window_size.width == 0 || window.window_size.height == 0
+            }
+            data::VkDisplay::Offscreen(_) => false,
+        }
+    }
+
+    /// Switches the window to `vsync`, returns true if the swapchain has to be rebuilt for it.
+    pub(crate) fn set_vsync(&mut self, vsync: VSync) -> bool {
+        match self {
+            data::VkDisplay::Window(window) if window.vsync != vsync => {
+                window.vsync = vsync;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// How many images frames rotate through, an offscreen target has no swapchain images.
+    pub(crate) fn swapchain_image_count(&self) -> usize {
+        match self {
+            data::VkDisplay::Window(window) => window.swapchain.images.len(),
+            data::VkDisplay::Offscreen(_) => 0,
+        }
+    }
+
+    /// Rebuilds the swapchain from the current surface state, retiring the old one.
+    /// Returns false if the surface has no area right now, in which case nothing was rebuilt.
+    pub(crate) fn recreate_swapchain(
+        &mut self,
+        devices: &data::VkDevices,
+    ) -> Result<bool, Report<RendererError>> {
+        let data::VkDisplay::Window(window) = self else {
+            return Ok(true);
+        };
+        unsafe {
+            // Nothing may still be using the old images or views
+            devices
+                .logical
+                .device_wait_idle()
+                .map_err(|x| vk_report(x, RendererError::Swapchain))?;
+            let surface_capabilities = window
+                .surface
+                .functions
+                .get_physical_device_surface_capabilities(devices.physical, window.surface.handle)
+                .map_err(|x| vk_report(x, RendererError::Swapchain))
+                .attach("querying the surface capabilities")?;
+            // Some platforms report a 0x0 extent while minimized
+            if surface_capabilities.current_extent.width == 0
+                || surface_capabilities.current_extent.height == 0
+            {
+                return Ok(false);
+            }
+        }
+
+        // The old swapchain stays intact until the new one is complete, then drops with the assignment
+        window.swapchain = data::VkSwapchain::new(
+            devices,
+            &window.surface,
+            window.swapchain.device.clone(),
+            (window.window_size, window.preferred_output, window.vsync),
+            window.swapchain.handle,
+        )?;
+        crate::logln!(
+            "vulkan swapchain",
+            "Swapchain recreated at {}x{}",
+            window.swapchain.extent.width,
+            window.swapchain.extent.height
+        );
+        Ok(true)
+    }
+}
+
+impl data::VkWindow {
+    pub(crate) fn new(
+        start: &data::VkStart,
+        devices: &data::VkDevices,
+        surface: data::VkSurface,
+        (window_size, preferred_output, vsync): (PhysicalSize<u32>, ColorOutput, VSync),
+        inner_size_reciever: TokioReceiver<PhysicalSize<u32>>,
+    ) -> Result<Self, Report<RendererError>> {
+        let swapchain_device = Arc::new(khr::swapchain::Device::new(
+            &start.instance,
+            &devices.logical,
+        ));
+        let swapchain = data::VkSwapchain::new(
+            devices,
+            &surface,
+            swapchain_device,
+            (window_size, preferred_output, vsync),
+            vk::SwapchainKHR::null(),
+        )?;
+        if swapchain.color_output != preferred_output {
+            crate::logln!(
+                "vulkan swapchain warning",
+                "{preferred_output:?} output is unavailable, presenting in {:?}",
+                swapchain.color_output
+            );
+        }
+        Ok(Self {
+            swapchain,
+            surface,
+            inner_size_reciever,
+            window_size,
+            preferred_output,
+            vsync,
+        })
+    }
 }
 
 impl data::VkSurface {
-    fn new() -> Self {}
+    pub(crate) fn new(
+        start: &data::VkStart,
+        (display, window): (RawDisplayHandle, RawWindowHandle),
+    ) -> Result<Self, Report<RendererError>> {
+        let functions = Arc::new(khr::surface::Instance::new(&start.entry, &start.instance));
+        let handle = unsafe {
+            ash_window::create_surface(&start.entry, &start.instance, display, window, None)
+        }
+        .map_err(|x| vk_report(x, RendererError::Surface))?;
+        Ok(Self { handle, functions })
+    }
 }
 
 impl Drop for data::VkSurface {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        unsafe { self.functions.destroy_surface(self.handle, None) };
+    }
+}
+
+impl data::VkSwapchain {
+    fn new(
+        devices: &data::VkDevices,
+        surface: &data::VkSurface,
+        device: Arc<khr::swapchain::Device>,
+        (window_size, preferred_output, vsync): (PhysicalSize<u32>, ColorOutput, VSync),
+        old_swapchain: vk::SwapchainKHR,
+    ) -> Result<Self, Report<RendererError>> {
+        unsafe {
+            let (physical_device, surface_functions) = (devices.physical, &surface.functions);
+            // Get the Surface Capabilities
+            let surface_capabilities = surface_functions
+                .get_physical_device_surface_capabilities(physical_device, surface.handle)
+                .map_err(|x| vk_report(x, RendererError::Swapchain))
+                .attach("querying the surface capabilities")?;
+            // Get the Surface Format
+            let surface_formats = surface_functions
+                .get_physical_device_surface_formats(physical_device, surface.handle)
+                .map_err(|x| vk_report(x, RendererError::Swapchain))
+                .attach("querying the surface formats")?;
+            if surface_formats.is_empty() {
+                return Err(
+                    Report::new(RendererError::Swapchain).attach("The surface supports no formats")
+                );
+            }
+            // Get the Surface Present Mode
+            let surface_present_modes = surface_functions
+                .get_physical_device_surface_present_modes(physical_device, surface.handle)
+                .map_err(|x| vk_report(x, RendererError::Swapchain))
+                .attach("querying the surface present modes")?;
+            // Get the BEST surface format for our needs
+            let (surface_format, color_output) =
+                Self::choose_swapchain_surface_format(surface_formats, preferred_output);
+            // Choose the BEST present mode for our needs
+            let present_mode = Self::choose_swapchain_present_mode(surface_present_modes, vsync);
+            let swapchain_extent =
+                Self::choose_swapchain_extent(&surface_capabilities, window_size);
+            // Prefer triple buffering, as long as the surface allows it
+            let mut image_count = std::cmp::max::<u32>(3u32, surface_capabilities.min_image_count);
+            if surface_capabilities.max_image_count > 0
+                && image_count > surface_capabilities.max_image_count
+            {
+                image_count = surface_capabilities.max_image_count;
+            }
+
+            let (sharing_mode, queue_family_indices) = devices.queues.families.swapchain_sharing();
+            let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
+                .flags(vk::SwapchainCreateFlagsKHR::default())
+                .surface(surface.handle)
+                .min_image_count(image_count)
+                .image_format(surface_format.format)
+                .image_color_space(surface_format.color_space)
+                .image_extent(swapchain_extent)
+                .image_array_layers(1)
+                .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+                .image_sharing_mode(sharing_mode)
+                .queue_family_indices(&queue_family_indices)
+                .pre_transform(surface_capabilities.current_transform)
+                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+                .present_mode(present_mode)
+                .clipped(true)
+                .old_swapchain(old_swapchain);
+            let handle = device
+                .create_swapchain(&swapchain_create_info, None)
+                .map_err(|x| vk_report(x, RendererError::Swapchain))
+                .attach(format!(
+                    "extent: {}x{}",
+                    swapchain_extent.width, swapchain_extent.height
+                ))?;
+            // From here on, dropping `swapchain` cleans up whatever was made so far
+            let mut swapchain = Self {
+                handle,
+                device,
+                images: Vec::new(),
+                image_views: Vec::new(),
+                format: surface_format.format,
+                extent: swapchain_extent,
+                color_output,
+                logical: devices.logical.clone(),
+            };
+            swapchain.images = swapchain
+                .device
+                .get_swapchain_images(handle)
+                .map_err(|x| vk_report(x, RendererError::Swapchain))
+                .attach("querying the swapchain images")?;
+            for (index, &image) in swapchain.images.iter().enumerate() {
+                devices
+                    .debug_utils
+                    .name(image, format_args!("swapchain image {index}"));
+            }
+            swapchain.create_image_views()?;
+            Ok(swapchain)
+        }
+    }
+
+    fn create_image_views(&mut self) -> Result<(), Report<RendererError>> {
+        for &image in &self.images {
+            let create_info = vk::ImageViewCreateInfo::default()
+                .image(image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(self.format)
+                .subresource_range(
+                    vk::ImageSubresourceRange::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .level_count(1)
+                        .layer_count(1),
+                );
+            let view = unsafe { self.logical.create_image_view(&create_info, None) }
+                .map_err(|x| vk_report(x, RendererError::Swapchain))
+                .attach("creating the swapchain image views")?;
+            self.image_views.push(view);
+        }
+        Ok(())
+    }
+
+    fn choose_swapchain_surface_format(
+        formats: Vec<vk::SurfaceFormatKHR>,
+        preferred_output: ColorOutput,
+    ) -> (vk::SurfaceFormatKHR, ColorOutput) {
+        /*
+        The scene is rendered into an HDR image and tonemapped on the way into the swapchain,
+        so the tonemapping pass can target whatever the display shows. The HDR color spaces come
+        from VK_EXT_swapchain_colorspace, which the instance enables whenever it's there.
+        In SDR, 10 bit formats band less, sRGB formats encode for free and with UNORM ones the
+        tonemapping pass encodes instead.
+        */
+        const HDR10: &[vk::Format] = &[
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::Format::A2R10G10B10_UNORM_PACK32,
+        ];
+        const SCRGB: &[vk::Format] = &[vk::Format::R16G16B16A16_SFLOAT];
+        const SDR: &[vk::Format] = &[
+            vk::Format::A2B10G10R10_UNORM_PACK32,
+            vk::Format::A2R10G10B10_UNORM_PACK32,
+            vk::Format::B8G8R8A8_SRGB,
+            vk::Format::R8G8B8A8_SRGB,
+            vk::Format::B8G8R8A8_UNORM,
+            vk::Format::R8G8B8A8_UNORM,
+        ];
+        // Either HDR output falls back on the other before giving up on HDR
+        let outputs: &[ColorOutput] = match preferred_output {
+            ColorOutput::Sdr => &[ColorOutput::Sdr],
+            ColorOutput::Hdr10 => &[ColorOutput::Hdr10, ColorOutput::ScRgb, ColorOutput::Sdr],
+            ColorOutput::ScRgb => &[ColorOutput::ScRgb, ColorOutput::Hdr10, ColorOutput::Sdr],
+        };
+        outputs
+            .iter()
+            .find_map(|&output| {
+                let (preferred, color_space) = match output {
+                    ColorOutput::Sdr => (SDR, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+                    ColorOutput::Hdr10 => (HDR10, vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+                    ColorOutput::ScRgb => (SCRGB, vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT),
+                };
+                preferred.iter().find_map(|&preferred| {
+                    formats
+                        .iter()
+                        .find(|format| {
+                            format.format == preferred && format.color_space == color_space
+                        })
+                        .map(|&format| (format, output))
+                })
+            })
+            // If all formats fail the above, the first format is fine
+            .unwrap_or((formats[0], ColorOutput::Sdr))
+    }
+
+    fn choose_swapchain_present_mode(
+        present_modes: Vec<vk::PresentModeKHR>,
+        vsync: VSync,
+    ) -> vk::PresentModeKHR {
+        // FIFO is the one mode every surface has to support
+        let preferred: &[vk::PresentModeKHR] = match vsync {
+            VSync::On => &[vk::PresentModeKHR::FIFO],
+            VSync::Off => &[
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::FIFO,
+            ],
+            VSync::Adaptive => &[vk::PresentModeKHR::FIFO_RELAXED, vk::PresentModeKHR::FIFO],
+        };
+        let present_mode = preferred
+            .iter()
+            .copied()
+            .find(|mode| present_modes.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO);
+        match present_mode {
+            vk::PresentModeKHR::IMMEDIATE => {
+                crate::logln!(
+                    "vulkan swapchain warning",
+                    "Mailbox Present Mode unavaliable, presenting immediately. \
+                     Lowest Latency but most screen tearing, be warned!"
+                );
+            }
+            vk::PresentModeKHR::FIFO if vsync != VSync::On => {
+                crate::logln!(
+                    "vulkan swapchain warning",
+                    "VSync Enabled Automatically, {vsync:?} isn't supported by the surface"
+                );
+            }
+            _ => {}
+        }
+        present_mode
+    }
+
+    fn choose_swapchain_extent(
+        capabilites: &vk::SurfaceCapabilitiesKHR,
+        window_size: PhysicalSize<u32>,
+    ) -> vk::Extent2D {
+        if capabilites.current_extent != vk::Extent2D::default().width(u32::MAX).height(u32::MAX) {
+            return capabilites.current_extent;
+        }
+
+        // The surface lets us pick, so follow the window while staying within the surface limits
+        let (min, max) = (capabilites.min_image_extent, capabilites.max_image_extent);
+        vk::Extent2D::default()
+            .width(window_size.width.clamp(min.width, max.width))
+            .height(window_size.height.clamp(min.height, max.height))
+    }
+}
+
+impl Drop for data::VkSwapchain {
+    // The views go before the swapchain owning their images
+    fn drop(&mut self) {
+        unsafe {
+            for &view in &self.image_views {
+                self.logical.destroy_image_view(view, None);
+            }
+            self.device.destroy_swapchain(self.handle, None);
+        }
+    }
 }
@@ -1,6 +1,132 @@
-#[path = "../data.rs"]
-mod data;
+#![cfg(feature = "vulkan")]
 
-impl data::VkCore {
-    fn new() -> Self {}
+use {
+    crate::{
+        DeviceSelector, ValidationSettings,
+        utils::AppState,
+        vk::{
+            data::{
+                TargetRequest, VkCore, VkDevices, VkDisplay, VkStart, VkSurface, VkWindow,
+                WindowCommunication,
+            },
+            error::{RendererError, vk_report},
+            offscreen::OffscreenTarget,
+        },
+    },
+    error_stack::{Report, ResultExt},
+    std::sync::mpsc::Receiver,
+};
+
+#[path = "start.rs"]
+mod start;
+
+#[cfg(feature = "debug")]
+#[path = "debug.rs"]
+mod debug;
+
+#[path = "devices.rs"]
+mod devices;
+
+#[path = "display.rs"]
+mod display;
+
+impl VkCore {
+    /// Builds every layer in order. A layer that fails drops the ones built before it, in reverse.
+    pub(crate) fn new(
+        target: TargetRequest,
+        application_name: &str,
+        // Variant, Major, Minor, Patch
+        application_version: (u32, u32, u32, u32),
+        device_selector: Option<DeviceSelector>,
+        validation: ValidationSettings,
+    ) -> Result<Self, Report<RendererError>> {
+        crate::logln!("vulkan", "Loading Vulkan");
+        let core = VkStart::new(application_name, application_version)?;
+        // Validation is a development aid, the renderer runs without it
+        #[cfg(feature = "debug")]
+        let debug = crate::vk::data::VkDebug::new(&core, validation)
+            .inspect_err(|report| crate::logln!("vulkan validation error", "{report:?}"))
+            .ok()
+            .flatten();
+        #[cfg(not(feature = "debug"))]
+        let _ = validation;
+        // Headless rendering never touches the windowing system
+        let surface = match &target {
+            TargetRequest::Window { handles, .. } => Some(VkSurface::new(&core, *handles)?),
+            TargetRequest::Offscreen(..) => None,
+        };
+        let mut devices = VkDevices::new(&core, surface.as_ref(), device_selector)?;
+        let display = match (target, surface) {
+            (
+                TargetRequest::Window {
+                    size,
+                    inner_size_reciever,
+                    color_output,
+                    vsync,
+                    ..
+                },
+                Some(surface),
+            ) => VkDisplay::Window(VkWindow::new(
+                &core,
+                &devices,
+                surface,
+                (size, color_output, vsync),
+                inner_size_reciever,
+            )?),
+            (TargetRequest::Offscreen(extent, color_output), _) => VkDisplay::Offscreen(
+                OffscreenTarget::new(
+                    &devices.logical,
+                    &mut devices.allocator,
+                    extent,
+                    color_output,
+                )
+                .map_err(|x| vk_report(x, RendererError::Resources))
+                .attach("the offscreen target")?,
+            ),
+            (TargetRequest::Window { .. }, None) => {
+                unreachable!("Window targets always get a surface")
+            }
+        };
+        devices.allocator.report();
+        crate::logln!("vulkan", "Finished loading Vulkan");
+        Ok(Self {
+            communicator: WindowCommunication(None),
+            display,
+            devices,
+            #[cfg(feature = "debug")]
+            debug,
+            core,
+        })
+    }
+
+    pub(crate) fn init_window_communicator(&mut self, communicator: Receiver<AppState>) {
+        self.communicator = WindowCommunication(Some(communicator));
+    }
+
+    pub(crate) fn window_communicator(&self) -> Option<&Receiver<AppState>> {
+        self.communicator.0.as_ref()
+    }
+
+    /// Panics on the renderer thread for validation errors, see
+    /// [`ValidationSettings::panic_on_error`].
+    pub(crate) fn check_validation(&self) {
+        #[cfg(feature = "debug")]
+        if let Some(debug) = &self.debug {
+            debug.check();
+        }
+    }
+}
+
+impl Drop for VkCore {
+    // Runs before the layers drop themselves
+    fn drop(&mut self) {
+        unsafe {
+            // Cores dropped on an error path never went through `Core`'s shutdown
+            let _ = self.devices.logical.device_wait_idle();
+            // The offscreen image lives in the allocator, which only the devices layer can reach
+            if let VkDisplay::Offscreen(offscreen) = &self.display {
+                offscreen.destroy(&self.devices.logical, &mut self.devices.allocator);
+            }
+        }
+    }
 }
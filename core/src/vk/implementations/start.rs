@@ -1,97 +1,100 @@
-#[path = "../data.rs"]
-mod data;
+#![cfg(feature = "vulkan")]
 
-use {ash, ash::vk, std::ffi, std::sync::Arc};
+use {
+    crate::vk::{
+        data,
+        error::{RendererError, vk_report},
+    },
+    ash::{
+        Entry, Instance,
+        vk::{ApplicationInfo, InstanceCreateFlags, InstanceCreateInfo, make_api_version},
+    },
+    error_stack::{Report, ResultExt},
+    std::{
+        ffi::{CString, c_char},
+        sync::Arc,
+    },
+};
 
 impl data::VkStart {
-    pub(crate) fn new() -> Result<Self, vk::Result> {
-        let entry = Arc::new(ash::Entry::load()?);
-        let instance = Self::create_instance()?;
-        Self { entry, instance }
+    pub(crate) fn new(
+        application_name: &str,
+        // Variant, Major, Minor, Patch
+        application_version: (u32, u32, u32, u32),
+    ) -> Result<Self, Report<RendererError>> {
+        // Create the Entry Point of Vulkan
+        let entry =
+            Arc::new(unsafe { Entry::load() }.change_context(RendererError::LoaderMissing)?);
+        // Create the vulkan instance
+        let instance = Self::create_instance(&entry, application_name, application_version)?;
+        Ok(Self { entry, instance })
     }
 
     fn create_instance(
         entry: &Entry,
         application_name: &str,
-        // Variant Major Minor Patch
+        // Variant, Major, Minor, Patch
         application_version: (u32, u32, u32, u32),
-    ) -> VkResult<Arc<ash::Instance>> {
-        let layer_names: &[*const ffi::c_char] = &Self::instance_layers((entry));
-        let ext_names: &[*const ffi::c_char] = &Self::instance_extensions(entry);
+    ) -> Result<Arc<Instance>, Report<RendererError>> {
+        let layer_names: &[*const c_char] = &Self::instance_layers(entry)?;
+        let ext_names: &[*const c_char] = &Self::instance_extensions(entry)?;
         let (v, ma, mi, p) = application_version;
-        let sanatized_app_name = application_name.replace('\0', "");
-        let app_name = ffi::CString::new(sanatized_app_name)
-            .expect("Sanatized String somehow wasn't sanatized.");
-        let app_info = vk::ApplicationInfo::default()
+        let app_name = CString::new(application_name)
+            .change_context(RendererError::Instance)
+            .attach(format!("application name: {application_name:?}"))?;
+        let app_info = ApplicationInfo::default()
             .application_name(&app_name)
             .application_version(make_api_version(v, ma, mi, p))
             .engine_name(c"Redefyning")
             .engine_version(make_api_version(0, 0, 0, 0))
-            // Vulkan API Version (1.3.286.0) is the max ash Supports
+            // Vulkan API Version (1.3.286.0 is the Max ash supports)
             .api_version(make_api_version(0, 1, 3, 286));
-        let create_info = vk::InstanceCreateInfo::default()
+        let inst_create_info = InstanceCreateInfo::default()
             .flags(InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR)
             .application_info(&app_info)
-            .enabled_extension_names(ext_names)
-            .enabled_layer_names(layer_names);
-        Ok(unsafe { entry.create_instance(&inst_create_info)?.into() })
+            .enabled_layer_names(layer_names)
+            .enabled_extension_names(ext_names);
+        let instance = unsafe { entry.create_instance(&inst_create_info, None) }
+            .map_err(|x| vk_report(x, RendererError::Instance))?;
+        Ok(Arc::new(instance))
     }
 
-    fn instance_extensions(entry: &Entry) -> Vec<*const ffi::c_char> {
-        let extensions = unsafe {
-            entry
-                .enumerate_device_extension_properties(None)
-                .expect("Failed to enumerate instance extensions")
-        };
-        let c_strings = extensions
+    fn instance_extensions(entry: &Entry) -> Result<Vec<*const c_char>, Report<RendererError>> {
+        let extensions = unsafe { entry.enumerate_instance_extension_properties(None) }
+            .map_err(|x| vk_report(x, RendererError::Instance))
+            .attach("enumerating the instance extensions")?;
+        // Names the driver didn't terminate are skipped rather than trusted
+        let cstrings: Vec<CString> = extensions
             .iter()
-            .map(|ext| {
-                let name = ext
-                    .extension_name_as_c_str()
-                    .expect("Failed to convert Extension name to &CStr");
-                ffi::CString::new(
-                    name.to_str()
-                        .expect("Failed to convert CStr to str")
-                        .replace('\0', ""),
-                )
-                .unwrap()
-            })
-            .collect::<Vec<CString>>();
-        c_strings
+            .filter_map(|ext| ext.extension_name_as_c_str().ok())
+            .map(CString::from)
+            .collect();
+        Ok(cstrings
             .into_iter()
-            .map(|cs| cs.into_raw() as *const ffi::c_char)
-            .collect()
+            .map(|cs| cs.into_raw() as *const c_char)
+            .collect())
     }
 
-    fn instance_layers(entry: &Entry) -> Vec<*const ffi::c_char> {
-        let layers = unsafe {
-            entry
-                .enumerate_instance_layer_properties(None)
-                .expect("Failed to enumerate instance layers")
-        };
-        let cstrings = layers.iter().map(|layer| {
-            let name = layer
-                .layer_name_as_c_str()
-                .expect("Failed to convert Layer Name to CStr");
-            CString::new(
-                name.to_str()
-                    .expect("Failed to convert CStr to str")
-                    .replace('\0', ""),
-            )
-            .unwrap()
-        });
+    fn instance_layers(entry: &Entry) -> Result<Vec<*const c_char>, Report<RendererError>> {
+        let layers = unsafe { entry.enumerate_instance_layer_properties() }
+            .map_err(|x| vk_report(x, RendererError::Instance))
+            .attach("enumerating the instance layers")?;
+        let cstrings: Vec<CString> = layers
+            .iter()
+            .filter_map(|layer| layer.layer_name_as_c_str().ok())
+            .map(CString::from)
+            .collect();
 
-        cstrings
+        Ok(cstrings
             .into_iter()
-            .map(|cs| cs.into_raw() as *const ffi::c_char)
-            .collect()
+            .map(|cs| cs.into_raw() as *const c_char)
+            .collect())
     }
 }
 
 impl Drop for data::VkStart {
+    // Last to go, nothing made from the instance may outlive it
     fn drop(&mut self) {
-        unsafe {
-            self.instance.destroy_instance(None);
-        }
+        unsafe { self.instance.destroy_instance(None) };
     }
 }
@@ -0,0 +1,331 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        ShadowSettings,
+        assets::{Light, LightKind, Scene},
+        vk::{
+            bindless::{BindlessHeap, DescriptorHandle},
+            frame::MAX_FRAMES_IN_FLIGHT,
+            graph::PassContext,
+            memory::{Allocator, Buffer, MemoryLocation},
+            pipeline::{PipelineCache, StageDesc},
+            queues::{OwnershipTransfer, QueueFamilies},
+            shader::ShaderLibrary,
+            shadows::ShadowCaster,
+        },
+    },
+    ash::{prelude::VkResult, vk},
+    glam::{Mat4, Vec3, Vec4},
+    std::mem::size_of,
+};
+
+const SHADER: &str = "lights.spv";
+const WORKGROUP_SIZE: u32 = 64;
+// Froxels across, down and in depth, matching `shaders/lights.wgsl` and `shaders/mesh.wgsl`.
+// Depth slices are spaced exponentially between the near and far planes
+const CLUSTERS: [u32; 3] = [16, 9, 24];
+// In u32s, a count followed by up to 127 light indices
+const CLUSTER_STRIDE: u32 = 128;
+// In vec4s
+const LIGHT_SIZE: usize = 4;
+// Illuminance lights without a range are cut off at, so they can still be binned
+const LIGHT_CUTOFF: f32 = 0.01;
+// What scenes without a single light are lit by
+const DEFAULT_LIGHT: Light = Light {
+    name: None,
+    kind: LightKind::Directional,
+    color: Vec3::new(1.0, 0.98, 0.95),
+    intensity: 3.0,
+    range: None,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    scene: u32,
+}
+
+// Plain numbers without padding
+unsafe impl bytemuck::Zeroable for PushConstants {}
+unsafe impl bytemuck::Pod for PushConstants {}
+
+/// The lights of every scene, and the clusters a compute pass bins the local ones into each frame.
+/// Directional lights reach everything and come first, shading loops over all of them and then
+/// over the point and spot lights of its cluster.
+/// With async compute the binning runs on the compute queue and the clusters change hands every frame.
+/// The brightest directional light and the brightest local ones are picked to cast shadows.
+pub(crate) struct ClusteredLights {
+    lights: Option<(Buffer, DescriptorHandle)>,
+    casters: Vec<ShadowCaster>,
+    directional: u32,
+    count: u32,
+    // Light indices per cluster, one list per frame in flight
+    clusters: Vec<(Buffer, DescriptorHandle)>,
+    // From the compute family to the graphics family, None without async compute
+    transfer: Option<OwnershipTransfer>,
+}
+
+impl ClusteredLights {
+    pub(crate) fn new(
+        allocator: &mut Allocator,
+        bindless: &mut BindlessHeap,
+        families: &QueueFamilies,
+        scenes: &[Scene],
+        shadows: &ShadowSettings,
+    ) -> VkResult<Self> {
+        let mut lights: Vec<(&Light, Mat4)> = scenes
+            .iter()
+            .flat_map(|scene| {
+                scene
+                    .light_instances()
+                    .into_iter()
+                    .map(|(light, transform)| (&scene.lights[light], transform))
+            })
+            .collect();
+        if lights.is_empty() {
+            let direction = Vec3::new(-0.4, -1.0, -0.3).normalize();
+            lights.push((
+                &DEFAULT_LIGHT,
+                Mat4::look_to_rh(Vec3::ZERO, direction, Vec3::Y).inverse(),
+            ));
+        }
+        lights.sort_by_key(|(light, _)| light.kind != LightKind::Directional);
+        let directional = lights
+            .iter()
+            .filter(|(light, _)| light.kind == LightKind::Directional)
+            .count();
+        let mut table: Vec<[[f32; 4]; LIGHT_SIZE]> = lights
+            .iter()
+            .map(|&(light, transform)| gpu_light(light, transform))
+            .collect();
+
+        // Brightest first, the sun gets the cascades and the rest share what's left
+        let mut by_brightness: Vec<usize> = (0..lights.len()).collect();
+        by_brightness.sort_by(|&a, &b| {
+            let brightness =
+                |index: usize| lights[index].0.color.max_element() * lights[index].0.intensity;
+            brightness(b).total_cmp(&brightness(a))
+        });
+        let mut casters = Vec::new();
+        let mut views = 0;
+        let mut local = 0;
+        let mut sun = false;
+        for index in by_brightness.into_iter().filter(|_| shadows.enabled) {
+            let [position_range, direction_kind, ..] = table[index];
+            let (position, range) = (Vec3::from_slice(&position_range), position_range[3]);
+            let direction = Vec3::from_slice(&direction_kind);
+            let caster = match lights[index].0.kind {
+                LightKind::Directional if !sun => {
+                    sun = true;
+                    ShadowCaster::Sun { direction }
+                }
+                LightKind::Point if local < shadows.max_local_lights => {
+                    local += 1;
+                    ShadowCaster::Point { position, range }
+                }
+                LightKind::Spot {
+                    outer_cone_angle, ..
+                } if local < shadows.max_local_lights => {
+                    local += 1;
+                    ShadowCaster::Spot {
+                        position,
+                        direction,
+                        range,
+                        outer_cone_angle,
+                    }
+                }
+                _ => continue,
+            };
+            table[index][3][2] = views as f32;
+            views += caster.views(shadows.cascades);
+            casters.push(caster);
+        }
+        let table: Vec<[f32; 4]> = table.into_iter().flatten().collect();
+
+        let size = (table.len() * size_of::<[f32; 4]>()) as vk::DeviceSize;
+        let (sharing, sharing_families) = families.compute_sharing();
+        let mut buffer = allocator.create_buffer(
+            &vk::BufferCreateInfo::default()
+                .size(size)
+                .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                .sharing_mode(sharing)
+                .queue_family_indices(&sharing_families),
+            MemoryLocation::CpuToGpu,
+            "light table",
+        )?;
+        let Some(mapped) = buffer.allocation.mapped_slice_mut() else {
+            allocator.destroy_buffer(&buffer);
+            return Err(vk::Result::ERROR_MEMORY_MAP_FAILED);
+        };
+        mapped[..size as usize].copy_from_slice(bytemuck::cast_slice(&table));
+        let handle = bindless
+            .add_storage_buffer(buffer.handle, 0, size)
+            .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)?;
+
+        let cluster_count = CLUSTERS.iter().product::<u32>();
+        let cluster_size = (cluster_count * CLUSTER_STRIDE) as vk::DeviceSize * 4;
+        let mut clusters = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            let buffer = allocator.create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(cluster_size)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                MemoryLocation::GpuOnly,
+                format_args!("light clusters {frame}"),
+            )?;
+            let handle = bindless
+                .add_storage_buffer(buffer.handle, 0, cluster_size)
+                .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)?;
+            clusters.push((buffer, handle));
+        }
+
+        Ok(Self {
+            lights: Some((buffer, handle)),
+            casters,
+            directional: directional as u32,
+            count: lights.len() as u32,
+            clusters,
+            transfer: OwnershipTransfer::new(families.compute, families.graphics),
+        })
+    }
+
+    /// The light and cluster buffer slots followed by the directional and total light counts,
+    /// one row of the scene constants of `slot`.
+    pub(crate) fn header(&self, slot: usize) -> [u32; 4] {
+        let lights = self.lights.as_ref().map_or(0, |(_, handle)| handle.index);
+        [
+            lights,
+            self.clusters[slot].1.index,
+            self.directional,
+            self.count,
+        ]
+    }
+
+    /// The lights picked to cast shadows, in the order their views are numbered.
+    pub(crate) fn shadow_casters(&self) -> &[ShadowCaster] {
+        &self.casters
+    }
+
+    /// The cluster buffer of `slot`, for the graph to synchronize.
+    pub(crate) fn cluster_buffer(&self, slot: usize) -> vk::Buffer {
+        self.clusters[slot].0.handle
+    }
+
+    /// Whether [`Self::cull`] runs on the async compute queue instead of in the frame's graph.
+    pub(crate) fn on_async_compute(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    /// Releases the clusters of `slot` to the graphics family, after [`Self::cull`] on the compute queue.
+    /// The graphics family gives them back without a release of its own, the next binning
+    /// overwrites every count and doesn't care what the buffer held.
+    pub(crate) fn release(&self, context: &PassContext, slot: usize) {
+        let Some(transfer) = self.transfer else {
+            return;
+        };
+        let barriers = [transfer.release_buffer(
+            self.cluster_buffer(slot),
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_WRITE,
+        )];
+        unsafe {
+            context.device.cmd_pipeline_barrier2(
+                context.command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&barriers),
+            );
+        }
+    }
+
+    /// The acquire half of [`Self::release`], recorded on the graphics queue once the compute
+    /// queue signaled. Leaves the clusters as if the binning had been a compute pass of the frame.
+    pub(crate) fn acquire(&self, context: &PassContext, slot: usize) {
+        let Some(transfer) = self.transfer else {
+            return;
+        };
+        let barriers = [transfer.acquire_buffer(
+            self.cluster_buffer(slot),
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+        )];
+        unsafe {
+            context.device.cmd_pipeline_barrier2(
+                context.command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&barriers),
+            );
+        }
+    }
+
+    /// Bins the local lights into the clusters the scene constants at `scene` point at, seen through their camera.
+    pub(crate) fn cull(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &BindlessHeap,
+        scene: u32,
+    ) {
+        let cb = context.command_buffer;
+        bindless.bind(cb, vk::PipelineBindPoint::COMPUTE);
+        if !pipelines.bind_compute(cb, shaders, &StageDesc::new(SHADER, "cull")) {
+            return;
+        }
+        let cluster_count = CLUSTERS.iter().product::<u32>();
+        unsafe {
+            context.device.cmd_push_constants(
+                cb,
+                bindless.pipeline_layout(),
+                vk::ShaderStageFlags::ALL,
+                0,
+                bytemuck::bytes_of(&PushConstants { scene }),
+            );
+            context
+                .device
+                .cmd_dispatch(cb, cluster_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+
+    /// Frees every buffer, the GPU must be done with them.
+    pub(crate) fn destroy(&mut self, allocator: &mut Allocator) {
+        if let Some((buffer, _)) = self.lights.take() {
+            allocator.destroy_buffer(&buffer);
+        }
+        for (buffer, _) in self.clusters.drain(..) {
+            allocator.destroy_buffer(&buffer);
+        }
+    }
+}
+
+/// `light` placed by `transform` in the layout of `shaders/mesh.wgsl`: position and range,
+/// direction and kind, radiant color, and the spot cone as a scale and offset on the cosine
+/// followed by the first shadow view, -1 until it's picked to cast shadows.
+fn gpu_light(light: &Light, transform: Mat4) -> [[f32; 4]; LIGHT_SIZE] {
+    let position = transform.w_axis.truncate();
+    let direction = transform
+        .transform_vector3(Vec3::NEG_Z)
+        .try_normalize()
+        .unwrap_or(Vec3::NEG_Z);
+    let color = light.color * light.intensity;
+    let range = light
+        .range
+        .unwrap_or_else(|| (color.max_element() / LIGHT_CUTOFF).sqrt());
+    let (kind, cone) = match light.kind {
+        LightKind::Directional => (0.0, [0.0, 0.0]),
+        LightKind::Point => (1.0, [0.0, 1.0]),
+        LightKind::Spot {
+            inner_cone_angle,
+            outer_cone_angle,
+        } => {
+            let scale = 1.0 / (inner_cone_angle.cos() - outer_cone_angle.cos()).max(0.001);
+            (2.0, [scale, -outer_cone_angle.cos() * scale])
+        }
+    };
+    [
+        position.extend(range),
+        direction.extend(kind),
+        color.extend(0.0),
+        Vec4::new(cone[0], cone[1], -1.0, 0.0),
+    ]
+    .map(<[f32; 4]>::from)
+}
@@ -0,0 +1,815 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::{
+        capabilities::DeviceCapabilities, debug_utils::DebugUtils, frame::MAX_FRAMES_IN_FLIGHT,
+    },
+    ash::{Device, Instance, prelude::VkResult, vk},
+    std::{fmt, sync::Arc},
+};
+
+// Size of the blocks small resources get sub-allocated from
+const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+// Resources at least this big get their own `vk::DeviceMemory`
+const DEDICATED_THRESHOLD: vk::DeviceSize = BLOCK_SIZE / 2;
+
+/// Where a resource should live, decides which memory types are tried and in what order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLocation {
+    /// Device local, never touched by the CPU.
+    GpuOnly,
+    /// Host visible and coherent, written by the CPU and read by the GPU (staging, uniforms).
+    CpuToGpu,
+    /// Host visible, written by the GPU and read back by the CPU. Cached when possible.
+    GpuToCpu,
+}
+
+impl MemoryLocation {
+    /// Flags every candidate type must have, and flags that make a type preferred.
+    fn flags(self) -> (vk::MemoryPropertyFlags, vk::MemoryPropertyFlags) {
+        match self {
+            Self::GpuOnly => (
+                vk::MemoryPropertyFlags::empty(),
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ),
+            Self::CpuToGpu => (
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                vk::MemoryPropertyFlags::empty(),
+            ),
+            Self::GpuToCpu => (
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                vk::MemoryPropertyFlags::HOST_CACHED,
+            ),
+        }
+    }
+}
+
+/// A range of device memory, either a piece of a shared block or a dedicated allocation.
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    // Null unless the memory is host visible
+    mapped: *mut u8,
+    memory_type: u32,
+    // Index into `Allocator::blocks`, None for dedicated allocations
+    block: Option<usize>,
+}
+
+impl Allocation {
+    /// The mapped bytes of this allocation, `None` if it isn't host visible.
+    pub fn mapped_slice(&self) -> Option<&[u8]> {
+        (!self.mapped.is_null())
+            .then(|| unsafe { std::slice::from_raw_parts(self.mapped, self.size as usize) })
+    }
+
+    pub fn mapped_slice_mut(&mut self) -> Option<&mut [u8]> {
+        (!self.mapped.is_null())
+            .then(|| unsafe { std::slice::from_raw_parts_mut(self.mapped, self.size as usize) })
+    }
+}
+
+/// A buffer bound to memory from the [`Allocator`].
+pub struct Buffer {
+    pub handle: vk::Buffer,
+    pub allocation: Allocation,
+    pub size: vk::DeviceSize,
+}
+
+/// An image bound to memory from the [`Allocator`].
+pub struct Image {
+    pub handle: vk::Image,
+    pub allocation: Allocation,
+}
+
+// The mapped pointers stay valid until the memory is freed, which only the owner can do
+unsafe impl Send for Allocation {}
+
+/// What one memory heap is using, as reported by `VK_EXT_memory_budget` when it's available.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapBudget {
+    pub heap: u32,
+    pub device_local: bool,
+    pub size: vk::DeviceSize,
+    /// How much the process may use, the heap size without `VK_EXT_memory_budget`.
+    pub budget: vk::DeviceSize,
+    /// How much the whole process uses, our own allocations without `VK_EXT_memory_budget`.
+    pub usage: vk::DeviceSize,
+    /// How much this allocator has allocated from the heap.
+    pub allocated: vk::DeviceSize,
+}
+
+struct FreeRange {
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+/// One `vk::DeviceMemory` that resources get sub-allocated from, first fit with a sorted free list.
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    size: vk::DeviceSize,
+    // Buffers and optimal images never share a block, so `bufferImageGranularity` can't bite
+    linear: bool,
+    mapped: *mut u8,
+    free: Vec<FreeRange>,
+    used: vk::DeviceSize,
+}
+
+impl MemoryBlock {
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<vk::DeviceSize> {
+        let (index, offset) = self.free.iter().enumerate().find_map(|(index, range)| {
+            let offset = range.offset.next_multiple_of(alignment);
+            (offset + size <= range.offset + range.size).then_some((index, offset))
+        })?;
+        let range = self.free.remove(index);
+        // Keep the alignment padding in front and the rest behind as free ranges
+        let end = offset + size;
+        let range_end = range.offset + range.size;
+        if range_end > end {
+            self.free.insert(
+                index,
+                FreeRange {
+                    offset: end,
+                    size: range_end - end,
+                },
+            );
+        }
+        if offset > range.offset {
+            self.free.insert(
+                index,
+                FreeRange {
+                    offset: range.offset,
+                    size: offset - range.offset,
+                },
+            );
+        }
+        self.used += size;
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        self.used -= size;
+        let index = self.free.partition_point(|range| range.offset < offset);
+        self.free.insert(index, FreeRange { offset, size });
+        // Merge with the next range, then with the previous one
+        if index + 1 < self.free.len()
+            && self.free[index].offset + self.free[index].size == self.free[index + 1].offset
+        {
+            self.free[index].size += self.free.remove(index + 1).size;
+        }
+        if index > 0 && self.free[index - 1].offset + self.free[index - 1].size == offset {
+            self.free[index - 1].size += self.free.remove(index).size;
+        }
+    }
+}
+
+/// Sub-allocates device memory for buffers and images created on the logical device.
+/// Has to be destroyed with [`Allocator::destroy`] before the device.
+pub struct Allocator {
+    device: Arc<Device>,
+    instance: Arc<Instance>,
+    physical_device: vk::PhysicalDevice,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    buffer_device_address: bool,
+    memory_budget: bool,
+    // Every buffer and image gets named after what it's for
+    debug_utils: DebugUtils,
+    blocks: Vec<Option<MemoryBlock>>,
+    // Bytes allocated from each heap, blocks and dedicated allocations
+    allocated: [vk::DeviceSize; vk::MAX_MEMORY_HEAPS],
+}
+
+// Only the renderer thread allocates, the block pointers are never shared
+unsafe impl Send for Allocator {}
+
+impl Allocator {
+    pub(crate) fn new(
+        instance: Arc<Instance>,
+        physical_device: vk::PhysicalDevice,
+        device: Arc<Device>,
+        capabilities: &DeviceCapabilities,
+        debug_utils: DebugUtils,
+    ) -> Self {
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        Self {
+            device,
+            instance,
+            physical_device,
+            memory_properties,
+            buffer_device_address: capabilities.buffer_device_address,
+            memory_budget: capabilities.memory_budget,
+            debug_utils,
+            blocks: Vec::new(),
+            allocated: [0; vk::MAX_MEMORY_HEAPS],
+        }
+    }
+
+    /// Creates a buffer named `name` and binds it to freshly allocated memory.
+    pub fn create_buffer(
+        &mut self,
+        info: &vk::BufferCreateInfo,
+        location: MemoryLocation,
+        name: impl fmt::Display,
+    ) -> VkResult<Buffer> {
+        unsafe {
+            let handle = self.device.create_buffer(info, None)?;
+            self.debug_utils.name(handle, name);
+            let mut dedicated = vk::MemoryDedicatedRequirements::default();
+            let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+            self.device.get_buffer_memory_requirements2(
+                &vk::BufferMemoryRequirementsInfo2::default().buffer(handle),
+                &mut requirements,
+            );
+            let requirements = requirements.memory_requirements;
+            let wants_dedicated = dedicated.prefers_dedicated_allocation == vk::TRUE
+                || dedicated.requires_dedicated_allocation == vk::TRUE;
+            let allocation = match self.allocate(
+                requirements,
+                location,
+                true,
+                wants_dedicated.then_some(DedicatedResource::Buffer(handle)),
+            ) {
+                Ok(x) => x,
+                Err(x) => {
+                    self.device.destroy_buffer(handle, None);
+                    return Err(x);
+                }
+            };
+            if let Err(x) =
+                self.device
+                    .bind_buffer_memory(handle, allocation.memory, allocation.offset)
+            {
+                self.device.destroy_buffer(handle, None);
+                self.free(&allocation);
+                return Err(x);
+            }
+            Ok(Buffer {
+                handle,
+                allocation,
+                size: info.size,
+            })
+        }
+    }
+
+    /// Creates an image named `name` and binds it to freshly allocated memory.
+    pub fn create_image(
+        &mut self,
+        info: &vk::ImageCreateInfo,
+        location: MemoryLocation,
+        name: impl fmt::Display,
+    ) -> VkResult<Image> {
+        unsafe {
+            let handle = self.device.create_image(info, None)?;
+            self.debug_utils.name(handle, name);
+            let mut dedicated = vk::MemoryDedicatedRequirements::default();
+            let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+            self.device.get_image_memory_requirements2(
+                &vk::ImageMemoryRequirementsInfo2::default().image(handle),
+                &mut requirements,
+            );
+            let requirements = requirements.memory_requirements;
+            // Render targets like to be dedicated, drivers can compress them better that way
+            let wants_dedicated = dedicated.prefers_dedicated_allocation == vk::TRUE
+                || dedicated.requires_dedicated_allocation == vk::TRUE;
+            let allocation = match self.allocate(
+                requirements,
+                location,
+                info.tiling == vk::ImageTiling::LINEAR,
+                wants_dedicated.then_some(DedicatedResource::Image(handle)),
+            ) {
+                Ok(x) => x,
+                Err(x) => {
+                    self.device.destroy_image(handle, None);
+                    return Err(x);
+                }
+            };
+            if let Err(x) =
+                self.device
+                    .bind_image_memory(handle, allocation.memory, allocation.offset)
+            {
+                self.device.destroy_image(handle, None);
+                self.free(&allocation);
+                return Err(x);
+            }
+            Ok(Image { handle, allocation })
+        }
+    }
+
+    /// Destroys the buffer and frees its memory, the GPU must be done with it.
+    pub fn destroy_buffer(&mut self, buffer: &Buffer) {
+        unsafe { self.device.destroy_buffer(buffer.handle, None) };
+        self.free(&buffer.allocation);
+    }
+
+    /// Destroys the image and frees its memory, the GPU must be done with it.
+    pub fn destroy_image(&mut self, image: &Image) {
+        unsafe { self.device.destroy_image(image.handle, None) };
+        self.free(&image.allocation);
+    }
+
+    /// Allocates memory without creating a resource, for optimal images that alias each other.
+    /// The caller binds the images and frees it with [`Allocator::free_memory`].
+    pub fn allocate_memory_for(
+        &mut self,
+        requirements: vk::MemoryRequirements,
+        location: MemoryLocation,
+    ) -> VkResult<Allocation> {
+        self.allocate(requirements, location, false, None)
+    }
+
+    /// Frees memory from [`Allocator::allocate_memory_for`], every resource bound to it must be destroyed.
+    pub fn free_memory(&mut self, allocation: &Allocation) {
+        self.free(allocation);
+    }
+
+    fn allocate(
+        &mut self,
+        requirements: vk::MemoryRequirements,
+        location: MemoryLocation,
+        linear: bool,
+        dedicated: Option<DedicatedResource>,
+    ) -> VkResult<Allocation> {
+        let dedicated = dedicated.or_else(|| {
+            // Big resources would waste most of a block, give them their own memory
+            (requirements.size >= DEDICATED_THRESHOLD).then_some(DedicatedResource::None)
+        });
+        let mut result = Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        // Try the preferred types first, fall back when a heap is full or over budget
+        for memory_type in self.memory_type_candidates(requirements.memory_type_bits, location) {
+            result = match dedicated {
+                Some(resource) => {
+                    self.allocate_dedicated(requirements, memory_type, linear, resource)
+                }
+                None => self.allocate_from_block(requirements, memory_type, linear),
+            };
+            match result {
+                Err(
+                    vk::Result::ERROR_OUT_OF_DEVICE_MEMORY | vk::Result::ERROR_OUT_OF_HOST_MEMORY,
+                ) => {
+                    continue;
+                }
+                _ => break,
+            }
+        }
+        result
+    }
+
+    fn allocate_from_block(
+        &mut self,
+        requirements: vk::MemoryRequirements,
+        memory_type: u32,
+        linear: bool,
+    ) -> VkResult<Allocation> {
+        let found = self
+            .blocks
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, block)| {
+                let block = block
+                    .as_mut()
+                    .filter(|block| block.memory_type == memory_type && block.linear == linear)?;
+                let offset = block.allocate(requirements.size, requirements.alignment)?;
+                Some((index, offset, block.memory, block.mapped))
+            });
+        let (index, offset, memory, mapped) = match found {
+            Some(x) => x,
+            None => {
+                let heap_size = self.heap_size(memory_type);
+                // Small heaps (like the 256 MiB BAR window) get smaller blocks
+                let size = BLOCK_SIZE.min(heap_size / 8).max(requirements.size);
+                let (memory, mapped) = self.allocate_memory(size, memory_type, linear, None)?;
+                let mut block = MemoryBlock {
+                    memory,
+                    memory_type,
+                    size,
+                    linear,
+                    mapped,
+                    free: vec![FreeRange { offset: 0, size }],
+                    used: 0,
+                };
+                let offset = block
+                    .allocate(requirements.size, requirements.alignment)
+                    .expect("A new block always fits the allocation it was made for");
+                let index = match self.blocks.iter().position(Option::is_none) {
+                    Some(index) => {
+                        self.blocks[index] = Some(block);
+                        index
+                    }
+                    None => {
+                        self.blocks.push(Some(block));
+                        self.blocks.len() - 1
+                    }
+                };
+                (index, offset, memory, mapped)
+            }
+        };
+        Ok(Allocation {
+            memory,
+            offset,
+            size: requirements.size,
+            mapped: if mapped.is_null() {
+                mapped
+            } else {
+                unsafe { mapped.add(offset as usize) }
+            },
+            memory_type,
+            block: Some(index),
+        })
+    }
+
+    fn allocate_dedicated(
+        &mut self,
+        requirements: vk::MemoryRequirements,
+        memory_type: u32,
+        linear: bool,
+        resource: DedicatedResource,
+    ) -> VkResult<Allocation> {
+        let (memory, mapped) =
+            self.allocate_memory(requirements.size, memory_type, linear, Some(resource))?;
+        Ok(Allocation {
+            memory,
+            offset: 0,
+            size: requirements.size,
+            mapped,
+            memory_type,
+            block: None,
+        })
+    }
+
+    /// Allocates and, when host visible, persistently maps a `vk::DeviceMemory`.
+    fn allocate_memory(
+        &mut self,
+        size: vk::DeviceSize,
+        memory_type: u32,
+        linear: bool,
+        dedicated: Option<DedicatedResource>,
+    ) -> VkResult<(vk::DeviceMemory, *mut u8)> {
+        let heap = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        if let Some(budget) = self.heap_budgets().get(heap as usize)
+            && budget.usage + size > budget.budget
+        {
+            crate::logln!(
+                "vulkan memory warning",
+                "Memory heap {heap} is over budget ({} + {} > {} MiB), trying another one",
+                budget.usage / (1024 * 1024),
+                size / (1024 * 1024),
+                budget.budget / (1024 * 1024)
+            );
+            return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        }
+
+        let mut flags_info =
+            vk::MemoryAllocateFlagsInfo::default().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default();
+        match dedicated {
+            Some(DedicatedResource::Buffer(buffer)) => {
+                dedicated_info = dedicated_info.buffer(buffer)
+            }
+            Some(DedicatedResource::Image(image)) => dedicated_info = dedicated_info.image(image),
+            Some(DedicatedResource::None) | None => (),
+        }
+        let mut allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+        // Any buffer in this memory may ask for its device address
+        if linear && self.buffer_device_address {
+            allocate_info = allocate_info.push_next(&mut flags_info);
+        }
+        if matches!(
+            dedicated,
+            Some(DedicatedResource::Buffer(_) | DedicatedResource::Image(_))
+        ) {
+            allocate_info = allocate_info.push_next(&mut dedicated_info);
+        }
+        let memory = unsafe { self.device.allocate_memory(&allocate_info, None)? };
+        let host_visible = self.memory_properties.memory_types[memory_type as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        let mapped = if host_visible {
+            match unsafe {
+                self.device
+                    .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+            } {
+                Ok(x) => x.cast::<u8>(),
+                Err(x) => {
+                    unsafe { self.device.free_memory(memory, None) };
+                    return Err(x);
+                }
+            }
+        } else {
+            std::ptr::null_mut()
+        };
+        self.allocated[heap as usize] += size;
+        Ok((memory, mapped))
+    }
+
+    fn free(&mut self, allocation: &Allocation) {
+        let heap = self.memory_properties.memory_types[allocation.memory_type as usize].heap_index;
+        match allocation.block {
+            None => {
+                self.allocated[heap as usize] -= allocation.size;
+                unsafe { self.device.free_memory(allocation.memory, None) };
+            }
+            Some(index) => {
+                let block = self.blocks[index]
+                    .as_mut()
+                    .expect("Allocation points at a freed block");
+                block.free(allocation.offset, allocation.size);
+                let (empty, memory_type, linear) =
+                    (block.used == 0, block.memory_type, block.linear);
+                // Keep one empty block per type around, so per-frame churn doesn't hit the driver
+                let spare = empty
+                    && self.blocks.iter().enumerate().any(|(other_index, other)| {
+                        other.as_ref().is_some_and(|other| {
+                            other_index != index
+                                && other.memory_type == memory_type
+                                && other.linear == linear
+                                && other.used < other.size
+                        })
+                    });
+                if spare {
+                    let block = self.blocks[index].take().expect("Checked above");
+                    self.allocated[heap as usize] -= block.size;
+                    unsafe { self.device.free_memory(block.memory, None) };
+                }
+            }
+        }
+    }
+
+    /// Memory types allowed by `type_bits` that fit `location`, the preferred ones first.
+    fn memory_type_candidates(&self, type_bits: u32, location: MemoryLocation) -> Vec<u32> {
+        let (required, preferred) = location.flags();
+        let mut candidates: Vec<u32> = (0..self.memory_properties.memory_type_count)
+            .filter(|&index| {
+                type_bits & (1 << index) != 0
+                    && self.memory_properties.memory_types[index as usize]
+                        .property_flags
+                        .contains(required)
+            })
+            .collect();
+        // Stable, so the driver's own ordering decides between equally good types
+        candidates.sort_by_key(|&index| {
+            !self.memory_properties.memory_types[index as usize]
+                .property_flags
+                .contains(preferred)
+        });
+        candidates
+    }
+
+    fn heap_size(&self, memory_type: u32) -> vk::DeviceSize {
+        let heap = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        self.memory_properties.memory_heaps[heap as usize].size
+    }
+
+    /// The budget and usage of every heap, straight from the driver with `VK_EXT_memory_budget`.
+    pub fn heap_budgets(&self) -> Vec<HeapBudget> {
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties2 = vk::PhysicalDeviceMemoryProperties2::default();
+        if self.memory_budget {
+            properties2 = properties2.push_next(&mut budget_properties);
+        }
+        unsafe {
+            self.instance
+                .get_physical_device_memory_properties2(self.physical_device, &mut properties2)
+        };
+        let properties = properties2.memory_properties;
+        (0..properties.memory_heap_count as usize)
+            .map(|heap| {
+                let memory_heap = properties.memory_heaps[heap];
+                let (budget, usage) = if self.memory_budget {
+                    (
+                        budget_properties.heap_budget[heap],
+                        budget_properties.heap_usage[heap],
+                    )
+                } else {
+                    (memory_heap.size, self.allocated[heap])
+                };
+                HeapBudget {
+                    heap: heap as u32,
+                    device_local: memory_heap
+                        .flags
+                        .contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                    size: memory_heap.size,
+                    budget,
+                    usage,
+                    allocated: self.allocated[heap],
+                }
+            })
+            .collect()
+    }
+
+    /// Logs what every heap uses against its budget.
+    pub fn report(&self) {
+        const MIB: vk::DeviceSize = 1024 * 1024;
+        for heap in self.heap_budgets() {
+            crate::logln!(
+                "vulkan memory",
+                "Heap {}{}: {} MiB allocated, {} / {} MiB used (heap {} MiB)",
+                heap.heap,
+                if heap.device_local {
+                    " (device local)"
+                } else {
+                    ""
+                },
+                heap.allocated / MIB,
+                heap.usage / MIB,
+                heap.budget / MIB,
+                heap.size / MIB,
+            );
+        }
+    }
+
+    /// Frees every block, has to happen before the device is destroyed.
+    /// Resources still using the memory must already be destroyed.
+    pub(crate) unsafe fn destroy(&mut self) {
+        for block in self.blocks.drain(..).flatten() {
+            if block.used > 0 {
+                crate::logln!(
+                    "vulkan memory warning",
+                    "Freeing a memory block with {} bytes still in use",
+                    block.used
+                );
+            }
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum DedicatedResource {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+    // Dedicated because of its size, not because the driver asked
+    None,
+}
+
+/// Host visible buffer split into one linear region per frame in flight, for per-frame uploads.
+/// A region is reset when its frame slot comes around again, by then the GPU is done with it.
+pub struct FrameRing {
+    buffer: Buffer,
+    region_size: vk::DeviceSize,
+    slot: usize,
+    head: vk::DeviceSize,
+}
+
+impl FrameRing {
+    pub fn new(
+        allocator: &mut Allocator,
+        region_size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        (sharing, families): (vk::SharingMode, &[u32]),
+        name: impl fmt::Display,
+    ) -> VkResult<Self> {
+        let info = vk::BufferCreateInfo::default()
+            .size(region_size * MAX_FRAMES_IN_FLIGHT as vk::DeviceSize)
+            .usage(usage)
+            .sharing_mode(sharing)
+            .queue_family_indices(families);
+        Ok(Self {
+            buffer: allocator.create_buffer(&info, MemoryLocation::CpuToGpu, name)?,
+            region_size,
+            slot: 0,
+            head: 0,
+        })
+    }
+
+    /// Starts allocating from the region of `slot`, whose previous frame must have finished.
+    pub fn begin_frame(&mut self, slot: usize) {
+        self.slot = slot;
+        self.head = 0;
+    }
+
+    /// Copies `bytes` into the current frame's region and returns where they start in it,
+    /// `None` once the region is full.
+    pub fn push(&mut self, bytes: &[u8], alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let size = bytes.len() as vk::DeviceSize;
+        let start = self.head.next_multiple_of(alignment.max(1));
+        if start + size > self.region_size {
+            return None;
+        }
+        let offset = self.slot as vk::DeviceSize * self.region_size + start;
+        let mapped = self
+            .buffer
+            .allocation
+            .mapped_slice_mut()
+            .expect("Frame rings are host visible");
+        mapped[offset as usize..(offset + size) as usize].copy_from_slice(bytes);
+        self.head = start + size;
+        Some(start)
+    }
+
+    /// The whole region of `slot`, for descriptors that cover every allocation of a frame.
+    pub fn region(&self, slot: usize) -> (vk::Buffer, vk::DeviceSize, vk::DeviceSize) {
+        (
+            self.buffer.handle,
+            slot as vk::DeviceSize * self.region_size,
+            self.region_size,
+        )
+    }
+
+    pub fn destroy(&self, allocator: &mut Allocator) {
+        allocator.destroy_buffer(&self.buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The free lists are pure bookkeeping, a null handle stands in for the device memory
+    fn block(size: vk::DeviceSize) -> MemoryBlock {
+        MemoryBlock {
+            memory: vk::DeviceMemory::null(),
+            memory_type: 0,
+            size,
+            linear: true,
+            mapped: std::ptr::null_mut(),
+            free: vec![FreeRange { offset: 0, size }],
+            used: 0,
+        }
+    }
+
+    fn free_ranges(block: &MemoryBlock) -> Vec<(vk::DeviceSize, vk::DeviceSize)> {
+        block
+            .free
+            .iter()
+            .map(|range| (range.offset, range.size))
+            .collect()
+    }
+
+    #[test]
+    fn alignment_padding_stays_free() {
+        let mut block = block(1024);
+        assert_eq!(block.allocate(10, 1), Some(0));
+        assert_eq!(block.allocate(100, 256), Some(256));
+        // The padding in front of the aligned allocation and the tail behind it
+        assert_eq!(free_ranges(&block), [(10, 246), (356, 668)]);
+        assert_eq!(block.used, 110);
+        // Small allocations fit in the padding, first fit
+        assert_eq!(block.allocate(16, 16), Some(16));
+        assert_eq!(free_ranges(&block), [(10, 6), (32, 224), (356, 668)]);
+    }
+
+    #[test]
+    fn allocations_that_dont_fit_fail() {
+        let mut block = block(256);
+        assert_eq!(block.allocate(200, 1), Some(0));
+        assert_eq!(block.allocate(64, 1), None);
+        // 56 bytes are free, but none at an offset aligned to 256
+        assert_eq!(block.allocate(16, 256), None);
+        assert_eq!(block.used, 200);
+    }
+
+    #[test]
+    fn freed_ranges_merge_with_their_neighbours() {
+        let mut block = block(300);
+        let offsets: Vec<_> = (0..3).map(|_| block.allocate(100, 1).unwrap()).collect();
+        assert_eq!(offsets, [0, 100, 200]);
+        assert!(block.free.is_empty());
+
+        // No neighbours free yet
+        block.free(100, 100);
+        assert_eq!(free_ranges(&block), [(100, 100)]);
+        // Merges with the next range
+        block.free(0, 100);
+        assert_eq!(free_ranges(&block), [(0, 200)]);
+        // Merges with the previous range
+        block.free(200, 100);
+        assert_eq!(free_ranges(&block), [(0, 300)]);
+        assert_eq!(block.used, 0);
+    }
+
+    #[test]
+    fn freed_range_between_two_free_ones_merges_both() {
+        let mut block = block(300);
+        for _ in 0..3 {
+            block.allocate(100, 1).unwrap();
+        }
+        block.free(0, 100);
+        block.free(200, 100);
+        assert_eq!(free_ranges(&block), [(0, 100), (200, 100)]);
+        block.free(100, 100);
+        assert_eq!(free_ranges(&block), [(0, 300)]);
+    }
+
+    #[test]
+    fn freed_memory_is_reused() {
+        let mut block = block(256);
+        let first = block.allocate(128, 64).unwrap();
+        let second = block.allocate(128, 64).unwrap();
+        assert_eq!(block.allocate(1, 1), None);
+        block.free(first, 128);
+        assert_eq!(block.allocate(64, 64), Some(first));
+        assert_eq!(block.allocate(64, 64), Some(64));
+        block.free(second, 128);
+        assert_eq!(block.allocate(128, 128), Some(second));
+        assert_eq!(block.used, 256);
+        assert!(block.free.is_empty());
+    }
+}
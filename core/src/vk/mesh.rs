@@ -0,0 +1,663 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        assets::{AlphaMode, ColorSpace, Material, Scene, Texture, TextureFormat, Vertex},
+        vk::{
+            bindless::{BindlessHeap, DescriptorHandle},
+            capabilities::DeviceCapabilities,
+            culling::DrawList,
+            frame::MAX_FRAMES_IN_FLIGHT,
+            graph::PassContext,
+            memory::{Allocator, Buffer, FrameRing},
+            pipeline::{
+                Blend, GraphicsPipelineDesc, PipelineCache, RenderState, VertexAttribute,
+                VertexBinding, VertexLayout,
+            },
+            queues::Queues,
+            shader::ShaderLibrary,
+            texture::Textures,
+            upload::Uploader,
+        },
+    },
+    ash::{Device, prelude::VkResult, vk},
+    glam::{Mat4, Vec3, Vec4},
+    std::{
+        mem::{offset_of, size_of},
+        ops::Range,
+    },
+};
+
+pub(crate) const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+const SHADER: &str = "mesh.spv";
+// In vec4s, matching the layouts in `shaders/mesh.wgsl`
+const SCENE_SIZE: usize = 13;
+const MATERIAL_SIZE: usize = 4;
+// The model matrix, then the primitive and material
+const INSTANCE_SIZE: usize = 5;
+// Bounding sphere, then where the indices are, the vertex offset and the bucket
+const PRIMITIVE_SIZE: usize = 2;
+/// Instances are drawn in groups sharing a pipeline: opaque single and double sided, then the
+/// blended ones, so everything opaque is behind them already.
+pub(crate) const BUCKETS: usize = 4;
+/// The buckets before this one are opaque.
+pub(crate) const BLENDED: usize = 2;
+/// In bytes, matching `vk::DrawIndexedIndirectCommand`.
+pub(crate) const DRAW_COMMAND_SIZE: u32 = 20;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    scene: u32,
+    materials: u32,
+    instances: u32,
+    material_sampler: u32,
+}
+
+// Plain numbers without padding
+unsafe impl bytemuck::Zeroable for PushConstants {}
+unsafe impl bytemuck::Pod for PushConstants {}
+
+/// The camera of a frame, shared by shading and the shadow cascades fit to it.
+pub(crate) struct View {
+    pub(crate) view: Mat4,
+    pub(crate) projection: Mat4,
+    pub(crate) eye: Vec3,
+    pub(crate) near: f32,
+    pub(crate) far: f32,
+    pub(crate) fov_y: f32,
+    pub(crate) aspect: f32,
+}
+
+/// Where a primitive sits in the shared vertex and index buffers.
+struct GpuPrimitive {
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+    // Index into the material table
+    material: u32,
+    bucket: usize,
+    // Object space bounding sphere
+    center: Vec3,
+    radius: f32,
+}
+
+/// Every primitive's vertices and indices in one buffer each, and the tables shaders find
+/// instances and primitives in.
+struct Geometry {
+    vertices: Buffer,
+    indices: Buffer,
+    instances: (Buffer, DescriptorHandle),
+    primitives: (Buffer, DescriptorHandle),
+    // One draw per instance in bucket order, for passes that draw everything
+    commands: Buffer,
+}
+
+/// The loaded scenes on the GPU, drawn with the built-in metallic-roughness shader.
+/// Instances live in a table on the GPU, each draw's first instance says which one it is.
+pub(crate) struct MeshRenderer {
+    primitives: Vec<GpuPrimitive>,
+    // The primitive of every instance, sorted by bucket
+    instances: Vec<usize>,
+    // The instances of each bucket
+    buckets: [Range<u32>; BUCKETS],
+    geometry: Option<Geometry>,
+    // Multi-draw indirect with instance offsets, otherwise every instance is its own draw call
+    indirect: bool,
+    // Every scene's materials after the default one at index 0
+    materials: Option<(Buffer, DescriptorHandle)>,
+    sampler: DescriptorHandle,
+    // Camera, viewport and where the lights are, one region per frame in flight
+    scene_ring: FrameRing,
+    scene_handles: Vec<DescriptorHandle>,
+    bounds: Option<(Vec3, Vec3)>,
+}
+
+impl MeshRenderer {
+    /// Uploads `scenes` and their textures through the transfer queue, blocking until the graphics queue can draw them.
+    pub(crate) fn new(
+        device: &Device,
+        queues: &Queues,
+        allocator: &mut Allocator,
+        bindless: &mut BindlessHeap,
+        textures: &mut Textures,
+        scenes: &[Scene],
+        capabilities: &DeviceCapabilities,
+    ) -> VkResult<Self> {
+        // Regions start at multiples of their size, 256 is the largest storage buffer offset
+        // alignment a device may ask for
+        let region_size =
+            ((SCENE_SIZE * size_of::<Vec4>()) as vk::DeviceSize).next_multiple_of(256);
+        // Light culling reads it on the async compute queue
+        let (sharing, families) = queues.families.compute_sharing();
+        let scene_ring = FrameRing::new(
+            allocator,
+            region_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            (sharing, &families),
+            "scene",
+        )?;
+        let scene_handles = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|slot| {
+                let (buffer, offset, size) = scene_ring.region(slot);
+                bindless
+                    .add_storage_buffer(buffer, offset, size)
+                    .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+            })
+            .collect::<VkResult<_>>()?;
+
+        let mut uploader = Uploader::new(device, queues, allocator);
+        // What materials without a texture, or with one the device can't sample, read instead
+        let white = textures
+            .upload(&mut uploader, bindless, &solid([255, 255, 255, 255]))?
+            .expect("Every device samples RGBA8");
+        let flat_normal = textures
+            .upload(&mut uploader, bindless, &solid([128, 128, 255, 255]))?
+            .expect("Every device samples RGBA8");
+
+        let mut primitives = Vec::new();
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        let mut draws = Vec::new();
+        let mut materials = vec![Material::default()];
+        // Sampled image slots of the base color, metallic-roughness, normal and emissive textures
+        let mut material_textures =
+            vec![[white.index, white.index, flat_normal.index, white.index]];
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        for scene in scenes {
+            let mut handles = Vec::with_capacity(scene.textures.len());
+            for texture in &scene.textures {
+                handles.push(textures.upload(&mut uploader, bindless, texture)?);
+            }
+            let slot = |texture: Option<usize>, fallback: DescriptorHandle| {
+                texture
+                    .and_then(|x| handles.get(x).copied().flatten())
+                    .unwrap_or(fallback)
+                    .index
+            };
+            material_textures.extend(scene.materials.iter().map(|material| {
+                [
+                    slot(material.base_color_texture, white),
+                    slot(material.metallic_roughness_texture, white),
+                    slot(material.normal_texture, flat_normal),
+                    slot(material.emissive_texture, white),
+                ]
+            }));
+            let material_offset = materials.len();
+            materials.extend_from_slice(&scene.materials);
+            // Primitive indices of every mesh, empty primitives are dropped
+            let mut mesh_primitives = Vec::with_capacity(scene.meshes.len());
+            for mesh in &scene.meshes {
+                let mut mesh_indices = Vec::new();
+                for primitive in mesh.primitives.iter().filter(|x| !x.indices.is_empty()) {
+                    let material = primitive
+                        .material
+                        .map_or(0, |material| material + material_offset);
+                    let blend = materials[material].alpha_mode == AlphaMode::Blend;
+                    mesh_indices.push(primitives.len());
+                    primitives.push(GpuPrimitive {
+                        first_index: indices.len() as u32,
+                        index_count: primitive.indices.len() as u32,
+                        vertex_offset: vertices.len() as i32,
+                        material: material as u32,
+                        bucket: usize::from(blend) * BLENDED
+                            + usize::from(materials[material].double_sided),
+                        center: (primitive.min + primitive.max) * 0.5,
+                        radius: (primitive.max - primitive.min).length() * 0.5,
+                    });
+                    vertices.extend_from_slice(&primitive.vertices);
+                    indices.extend_from_slice(&primitive.indices);
+                }
+                mesh_primitives.push(mesh_indices);
+            }
+            for (mesh, transform) in scene.instances() {
+                draws.extend(mesh_primitives[mesh].iter().map(|&x| (x, transform)));
+            }
+            if let Some((min, max)) = scene.bounds() {
+                bounds = Some(match bounds {
+                    Some(bounds) => (bounds.0.min(min), bounds.1.max(max)),
+                    None => (min, max),
+                });
+            }
+        }
+        draws.sort_by_key(|&(primitive, _)| primitives[primitive].bucket);
+        let buckets = std::array::from_fn(|bucket| {
+            let start = draws.partition_point(|&(x, _)| primitives[x].bucket < bucket);
+            let end = draws.partition_point(|&(x, _)| primitives[x].bucket <= bucket);
+            start as u32..end as u32
+        });
+        let geometry = if draws.is_empty() {
+            None
+        } else {
+            Some(Geometry::new(
+                &mut uploader,
+                bindless,
+                (&vertices, &indices),
+                &primitives,
+                &draws,
+            )?)
+        };
+
+        // Floats stored as their bits, so the texture slots fit in the same table
+        let table: Vec<[u32; 4]> = materials
+            .iter()
+            .zip(&material_textures)
+            .flat_map(|(material, &textures)| {
+                let cutoff = match material.alpha_mode {
+                    AlphaMode::Mask => material.alpha_cutoff,
+                    _ => 0.0,
+                };
+                [
+                    material.base_color,
+                    Vec4::new(material.metallic, material.roughness, cutoff, 0.0),
+                    material.emissive.extend(0.0),
+                ]
+                .map(|x| x.to_array().map(f32::to_bits))
+                .into_iter()
+                .chain([textures])
+            })
+            .collect();
+        debug_assert_eq!(table.len(), materials.len() * MATERIAL_SIZE);
+        let table_buffer = uploader.buffer(
+            bytemuck::cast_slice(&table),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            "material table",
+        )?;
+        uploader.flush()?;
+        let table_handle = bindless
+            .add_storage_buffer(table_buffer.handle, 0, table_buffer.size)
+            .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)?;
+
+        Ok(Self {
+            primitives,
+            instances: draws.into_iter().map(|(primitive, _)| primitive).collect(),
+            buckets,
+            geometry,
+            indirect: capabilities.multi_draw_indirect && capabilities.draw_indirect_first_instance,
+            materials: Some((table_buffer, table_handle)),
+            sampler: textures.sampler(),
+            scene_ring,
+            scene_handles,
+            bounds,
+        })
+    }
+
+    /// A camera framing every scene, rendering to `extent`.
+    pub(crate) fn view(&self, extent: vk::Extent2D) -> View {
+        let (center, radius) = match self.bounds {
+            Some((min, max)) => ((min + max) * 0.5, ((max - min).length() * 0.5).max(0.01)),
+            None => (Vec3::ZERO, 1.0),
+        };
+        let eye = center + Vec3::new(0.0, 0.35, 1.0).normalize() * radius * 2.5;
+        let aspect = extent.width.max(1) as f32 / extent.height.max(1) as f32;
+        let (near, far) = (radius * 0.01, radius * 10.0);
+        let fov_y = 45f32.to_radians();
+        View {
+            view: Mat4::look_at_rh(eye, center, Vec3::Y),
+            projection: Mat4::perspective_rh(fov_y, aspect, near, far),
+            eye,
+            near,
+            far,
+            fov_y,
+            aspect,
+        }
+    }
+
+    /// Writes `view` into the buffer of `slot`, with `lights` from
+    /// [`ClusteredLights::header`](crate::vk::lights::ClusteredLights::header) and the
+    /// [`Shadows::buffer`](crate::vk::shadows::Shadows::buffer) slot in `shadows`.
+    /// The last frame that used `slot` must be done.
+    pub(crate) fn update(
+        &mut self,
+        slot: usize,
+        view: &View,
+        extent: vk::Extent2D,
+        lights: [u32; 4],
+        shadows: u32,
+    ) {
+        let View {
+            view,
+            projection,
+            eye,
+            near,
+            far,
+            ..
+        } = *view;
+        let view_projection = projection * view;
+        let scene: [[f32; 4]; SCENE_SIZE] = [
+            view_projection.x_axis,
+            view_projection.y_axis,
+            view_projection.z_axis,
+            view_projection.w_axis,
+            eye.extend(1.0),
+            view.x_axis,
+            view.y_axis,
+            view.z_axis,
+            view.w_axis,
+            Vec4::new(projection.x_axis.x, projection.y_axis.y, near, far),
+            Vec4::new(0.08, 0.09, 0.11, 0.0),
+            Vec4::from_array(lights.map(f32::from_bits)),
+            Vec4::new(
+                extent.width as f32,
+                extent.height as f32,
+                f32::from_bits(shadows),
+                0.0,
+            ),
+        ]
+        .map(<[f32; 4]>::from);
+        self.scene_ring.begin_frame(slot);
+        // The only upload of the frame, so it starts the region the descriptor covers
+        let offset = self.scene_ring.push(
+            bytemuck::cast_slice(&scene),
+            size_of::<Vec4>() as vk::DeviceSize,
+        );
+        debug_assert_eq!(offset, Some(0));
+    }
+
+    /// The scene constants of `slot`.
+    pub(crate) fn scene_buffer(&self, slot: usize) -> DescriptorHandle {
+        self.scene_handles[slot]
+    }
+
+    /// The storage buffer slots of the instance and primitive tables, laid out as in `shaders/cull.wgsl`.
+    pub(crate) fn tables(&self) -> Option<(u32, u32)> {
+        let geometry = self.geometry.as_ref()?;
+        Some((geometry.instances.1.index, geometry.primitives.1.index))
+    }
+
+    /// The instances of each bucket, numbered the way the instance table is.
+    pub(crate) fn buckets(&self) -> &[Range<u32>; BUCKETS] {
+        &self.buckets
+    }
+
+    /// Whether the device can draw many instances with one indirect call.
+    pub(crate) fn indirect(&self) -> bool {
+        self.indirect
+    }
+
+    /// Draws the pass of `context`, which renders to `color_format` and [`DEPTH_FORMAT`].
+    /// Culled passes draw what `list` holds for each bucket, otherwise every instance is drawn.
+    pub(crate) fn draw(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &BindlessHeap,
+        color_format: vk::Format,
+        (slot, list): (usize, Option<DrawList>),
+    ) {
+        let (Some((_, materials)), Some(geometry)) = (&self.materials, &self.geometry) else {
+            return;
+        };
+        let device = context.device;
+        let cb = context.command_buffer;
+        let desc = GraphicsPipelineDesc::new()
+            .stage(SHADER, "vs_main")
+            .stage(SHADER, "fs_main")
+            .color_format(color_format)
+            .depth_format(DEPTH_FORMAT);
+        let push_constants = PushConstants {
+            scene: self.scene_handles[slot].index,
+            materials: materials.index,
+            instances: geometry.instances.1.index,
+            material_sampler: self.sampler.index,
+        };
+        bindless.bind(cb, vk::PipelineBindPoint::GRAPHICS);
+        for (bucket, instances) in self.buckets.iter().enumerate() {
+            if instances.is_empty() {
+                continue;
+            }
+            let blend = bucket >= BLENDED;
+            let state = RenderState {
+                cull_mode: if bucket % 2 == 1 {
+                    vk::CullModeFlags::NONE
+                } else {
+                    vk::CullModeFlags::BACK
+                },
+                depth_write: !blend,
+                blend: vec![if blend { Blend::Alpha } else { Blend::Opaque }],
+                vertex_layout: vertex_layout(),
+                ..Default::default()
+            };
+            if !pipelines.bind(cb, shaders, &desc, &state, context.render_area()) {
+                return;
+            }
+            unsafe {
+                device.cmd_push_constants(
+                    cb,
+                    bindless.pipeline_layout(),
+                    vk::ShaderStageFlags::ALL,
+                    0,
+                    bytemuck::bytes_of(&push_constants),
+                );
+                device.cmd_bind_vertex_buffers(cb, 0, &[geometry.vertices.handle], &[0]);
+                device.cmd_bind_index_buffer(cb, geometry.indices.handle, 0, vk::IndexType::UINT32);
+            }
+            match list {
+                Some(list) => {
+                    let (count, commands) = list.offsets(bucket, instances.start);
+                    unsafe {
+                        device.cmd_draw_indexed_indirect_count(
+                            cb,
+                            list.buffer,
+                            commands,
+                            list.buffer,
+                            count,
+                            instances.len() as u32,
+                            DRAW_COMMAND_SIZE,
+                        );
+                    }
+                }
+                None => self.draw_instances(context, geometry, instances.clone()),
+            }
+        }
+    }
+
+    /// Draws every instance that isn't blended with the bound depth-only pipeline and push
+    /// constants, which read [`position_layout`] and find the model matrix through the instance index.
+    pub(crate) fn draw_depth(&self, context: &PassContext) {
+        let Some(geometry) = &self.geometry else {
+            return;
+        };
+        let device = context.device;
+        let cb = context.command_buffer;
+        unsafe {
+            device.cmd_bind_vertex_buffers(cb, 0, &[geometry.vertices.handle], &[0]);
+            device.cmd_bind_index_buffer(cb, geometry.indices.handle, 0, vk::IndexType::UINT32);
+        }
+        self.draw_instances(context, geometry, 0..self.buckets[BLENDED].start);
+    }
+
+    /// Draws `instances` from the bound buffers, in one call when the device draws indirectly.
+    fn draw_instances(&self, context: &PassContext, geometry: &Geometry, instances: Range<u32>) {
+        let device = context.device;
+        let cb = context.command_buffer;
+        if self.indirect {
+            unsafe {
+                device.cmd_draw_indexed_indirect(
+                    cb,
+                    geometry.commands.handle,
+                    vk::DeviceSize::from(instances.start * DRAW_COMMAND_SIZE),
+                    instances.len() as u32,
+                    DRAW_COMMAND_SIZE,
+                );
+            }
+            return;
+        }
+        for instance in instances {
+            let primitive = &self.primitives[self.instances[instance as usize]];
+            unsafe {
+                device.cmd_draw_indexed(
+                    cb,
+                    primitive.index_count,
+                    1,
+                    primitive.first_index,
+                    primitive.vertex_offset,
+                    instance,
+                );
+            }
+        }
+    }
+
+    /// Frees every buffer, the GPU must be done with them.
+    pub(crate) fn destroy(&mut self, allocator: &mut Allocator) {
+        if let Some(geometry) = self.geometry.take() {
+            for buffer in [
+                &geometry.vertices,
+                &geometry.indices,
+                &geometry.instances.0,
+                &geometry.primitives.0,
+                &geometry.commands,
+            ] {
+                allocator.destroy_buffer(buffer);
+            }
+        }
+        self.primitives.clear();
+        self.instances.clear();
+        if let Some((buffer, _)) = self.materials.take() {
+            allocator.destroy_buffer(&buffer);
+        }
+        self.scene_ring.destroy(allocator);
+    }
+}
+
+impl Geometry {
+    /// Uploads the merged `vertices` and `indices`, the table of `primitives` and an instance for
+    /// each of `draws`, which are sorted by bucket.
+    fn new(
+        uploader: &mut Uploader,
+        bindless: &mut BindlessHeap,
+        (vertices, indices): (&[Vertex], &[u32]),
+        primitives: &[GpuPrimitive],
+        draws: &[(usize, Mat4)],
+    ) -> VkResult<Self> {
+        // Floats stored as their bits, so the indices fit in the same tables
+        let instances: Vec<[u32; 4]> = draws
+            .iter()
+            .flat_map(|&(primitive, transform)| {
+                transform
+                    .to_cols_array_2d()
+                    .map(|column| column.map(f32::to_bits))
+                    .into_iter()
+                    .chain([[primitive as u32, primitives[primitive].material, 0, 0]])
+            })
+            .collect();
+        debug_assert_eq!(instances.len(), draws.len() * INSTANCE_SIZE);
+        let table: Vec<[u32; 4]> = primitives
+            .iter()
+            .flat_map(|primitive| {
+                [
+                    primitive
+                        .center
+                        .extend(primitive.radius)
+                        .to_array()
+                        .map(f32::to_bits),
+                    [
+                        primitive.first_index,
+                        primitive.index_count,
+                        primitive.vertex_offset as u32,
+                        primitive.bucket as u32,
+                    ],
+                ]
+            })
+            .collect();
+        debug_assert_eq!(table.len(), primitives.len() * PRIMITIVE_SIZE);
+        let commands: Vec<[u32; 5]> = draws
+            .iter()
+            .enumerate()
+            .map(|(instance, &(primitive, _))| {
+                let primitive = &primitives[primitive];
+                [
+                    primitive.index_count,
+                    1,
+                    primitive.first_index,
+                    primitive.vertex_offset as u32,
+                    instance as u32,
+                ]
+            })
+            .collect();
+        let mut storage = |table: &[[u32; 4]], name| -> VkResult<(Buffer, DescriptorHandle)> {
+            let buffer = uploader.buffer(
+                bytemuck::cast_slice(table),
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                name,
+            )?;
+            let handle = bindless
+                .add_storage_buffer(buffer.handle, 0, buffer.size)
+                .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)?;
+            Ok((buffer, handle))
+        };
+        let instances = storage(&instances, "instances")?;
+        let primitives = storage(&table, "primitives")?;
+        Ok(Self {
+            vertices: uploader.buffer(
+                bytemuck::cast_slice(vertices),
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                "vertices",
+            )?,
+            indices: uploader.buffer(
+                bytemuck::cast_slice(indices),
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                "indices",
+            )?,
+            instances,
+            primitives,
+            commands: uploader.buffer(
+                bytemuck::cast_slice(&commands),
+                vk::BufferUsageFlags::INDIRECT_BUFFER,
+                "draw commands",
+            )?,
+        })
+    }
+}
+
+/// A 1x1 linear texture of `rgba`.
+fn solid(rgba: [u8; 4]) -> Texture {
+    Texture {
+        name: None,
+        width: 1,
+        height: 1,
+        format: TextureFormat::Rgba8,
+        color_space: ColorSpace::Linear,
+        levels: vec![rgba.to_vec()],
+    }
+}
+
+/// The [`Vertex`] layout, one interleaved buffer at binding 0.
+fn vertex_layout() -> VertexLayout {
+    let attribute = |location: u32, format: vk::Format, offset: usize| VertexAttribute {
+        location,
+        binding: 0,
+        format,
+        offset: offset as u32,
+    };
+    VertexLayout {
+        bindings: vec![VertexBinding {
+            binding: 0,
+            stride: size_of::<Vertex>() as u32,
+            per_instance: false,
+        }],
+        attributes: vec![
+            attribute(
+                0,
+                vk::Format::R32G32B32_SFLOAT,
+                offset_of!(Vertex, position),
+            ),
+            attribute(1, vk::Format::R32G32B32_SFLOAT, offset_of!(Vertex, normal)),
+            attribute(2, vk::Format::R32G32_SFLOAT, offset_of!(Vertex, uv)),
+            attribute(
+                3,
+                vk::Format::R32G32B32A32_SFLOAT,
+                offset_of!(Vertex, tangent),
+            ),
+        ],
+    }
+}
+
+/// Only the position of [`Vertex`], for depth-only passes.
+pub(crate) fn position_layout() -> VertexLayout {
+    let mut layout = vertex_layout();
+    layout.attributes.truncate(1);
+    layout
+}
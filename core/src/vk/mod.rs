@@ -5,8 +5,68 @@ pub(crate) mod vulkan;
 
 pub(crate) use vulkan::*;
 
-#[path = "setup.rs"]
-pub(crate) mod setup;
+#[path = "error.rs"]
+pub(crate) mod error;
+
+#[path = "debug_utils.rs"]
+pub(crate) mod debug_utils;
+
+#[path = "frame.rs"]
+pub(crate) mod frame;
+
+#[path = "memory.rs"]
+pub(crate) mod memory;
+
+#[path = "offscreen.rs"]
+pub(crate) mod offscreen;
+
+#[path = "bindless.rs"]
+pub(crate) mod bindless;
+
+#[path = "capabilities.rs"]
+pub(crate) mod capabilities;
+
+#[path = "queues.rs"]
+pub(crate) mod queues;
+
+#[path = "selection.rs"]
+pub(crate) mod selection;
+
+#[path = "shader.rs"]
+pub(crate) mod shader;
+
+#[path = "pipeline.rs"]
+pub(crate) mod pipeline;
+
+#[path = "graph.rs"]
+pub(crate) mod graph;
+
+#[path = "upload.rs"]
+pub(crate) mod upload;
+
+#[path = "texture.rs"]
+pub(crate) mod texture;
+
+#[path = "lights.rs"]
+pub(crate) mod lights;
+
+#[path = "mesh.rs"]
+pub(crate) mod mesh;
+
+#[path = "culling.rs"]
+pub(crate) mod culling;
+
+#[path = "shadows.rs"]
+pub(crate) mod shadows;
+
+#[path = "post.rs"]
+pub(crate) mod post;
+
+#[path = "renderer.rs"]
+pub(crate) mod renderer;
 
 #[path = "data.rs"]
 pub(crate) mod data;
+
+#[path = "implementations/mod.rs"]
+pub(crate) mod implementations;
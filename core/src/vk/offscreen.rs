@@ -0,0 +1,264 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        ColorOutput, FrameCallback, FrameImage,
+        vk::{
+            error::RendererError,
+            frame::MAX_FRAMES_IN_FLIGHT,
+            graph::{ImageAccess, ImageHandle, RenderGraph},
+            memory::{Allocator, Buffer, Image, MemoryLocation},
+        },
+    },
+    ash::{Device, prelude::VkResult, vk},
+    error_stack::{Report, ResultExt},
+    image::{ImageBuffer, Rgba, Rgba32FImage, RgbaImage},
+    std::path::PathBuf,
+};
+
+/// Renders into a plain `vk::Image` instead of a swapchain, used when there is no window.
+pub struct OffscreenTarget {
+    pub image: Image,
+    pub view: vk::ImageView,
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    /// What the tonemapper writes, as if a display took this format
+    pub color_output: ColorOutput,
+    // One host visible buffer per frame in flight, so a frame can be read back while the next one renders
+    readback: Vec<Buffer>,
+}
+
+impl OffscreenTarget {
+    pub(crate) fn new(
+        device: &Device,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+        color_output: ColorOutput,
+    ) -> VkResult<Self> {
+        // The formats a swapchain of each output would be most likely to have
+        let format = match color_output {
+            // 8 bits per channel, sRGB encoded, which is exactly what a PNG wants
+            ColorOutput::Sdr => vk::Format::R8G8B8A8_SRGB,
+            ColorOutput::Hdr10 => vk::Format::A2B10G10R10_UNORM_PACK32,
+            ColorOutput::ScRgb => vk::Format::R16G16B16A16_SFLOAT,
+        };
+        let image_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image =
+            allocator.create_image(&image_info, MemoryLocation::GpuOnly, "offscreen target")?;
+
+        let view_info = vk::ImageViewCreateInfo::default()
+            .image(image.handle)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(1)
+                    .layer_count(1),
+            );
+        let view = unsafe { device.create_image_view(&view_info, None)? };
+
+        // Cached memory makes reading it back on the CPU a lot faster
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(u64::from(extent.width) * u64::from(extent.height) * texel_size(format))
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let readback = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|frame| {
+                allocator.create_buffer(
+                    &buffer_info,
+                    MemoryLocation::GpuToCpu,
+                    format_args!("offscreen readback {frame}"),
+                )
+            })
+            .collect::<VkResult<Vec<_>>>()?;
+
+        Ok(Self {
+            image,
+            view,
+            extent,
+            format,
+            color_output,
+            readback,
+        })
+    }
+
+    /// Adds the pass copying `target`, the rendered image, into the readback buffer of `slot`.
+    /// Nothing in the graph reads the copy, so the pass is kept for its side effects.
+    pub(crate) fn add_readback<U>(
+        &self,
+        graph: &mut RenderGraph<'_, U>,
+        target: ImageHandle,
+        slot: usize,
+    ) {
+        let readback = self.readback[slot].handle;
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1),
+            )
+            .image_extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            });
+        // Make the copy visible to the host once the frame's timeline value is reached
+        let to_host = [vk::BufferMemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)
+            .buffer(readback)
+            .size(vk::WHOLE_SIZE)];
+        graph
+            .add_pass("readback")
+            .image(target, ImageAccess::TransferSrc)
+            .side_effects()
+            .execute(move |context, _| unsafe {
+                context.device.cmd_copy_image_to_buffer(
+                    context.command_buffer,
+                    context.image(target),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    readback,
+                    &[region],
+                );
+                context.device.cmd_pipeline_barrier2(
+                    context.command_buffer,
+                    &vk::DependencyInfo::default().buffer_memory_barriers(&to_host),
+                );
+            });
+    }
+
+    /// Copies the pixels of `slot` out of mapped memory: 8 bit RGBA in SDR, the 10 bit HDR10
+    /// signal widened to 16 bits, or scRGB widened to 32 bit floats.
+    /// The frame that last used `slot` must have finished on the GPU.
+    pub(crate) fn read(&self, slot: usize) -> FrameImage {
+        let (width, height) = (self.extent.width, self.extent.height);
+        let size = width as usize * height as usize * texel_size(self.format) as usize;
+        // Host coherent, so the timeline wait is all it takes for the copy to be visible
+        let bytes = &self.readback[slot]
+            .allocation
+            .mapped_slice()
+            .expect("Readback buffers are host visible")[..size];
+        let image = match self.color_output {
+            ColorOutput::Sdr => RgbaImage::from_raw(width, height, bytes.to_vec()).map(Into::into),
+            ColorOutput::Hdr10 => {
+                let pixels = bytes
+                    .as_chunks::<4>()
+                    .0
+                    .iter()
+                    .flat_map(|&texel| {
+                        let packed = u32::from_le_bytes(texel);
+                        // A2B10G10R10 keeps red in the lowest bits
+                        let channel = |shift: u32| {
+                            let value = (packed >> shift & 0x3ff) as u16;
+                            value << 6 | value >> 4
+                        };
+                        [
+                            channel(0),
+                            channel(10),
+                            channel(20),
+                            (packed >> 30) as u16 * 0x5555,
+                        ]
+                    })
+                    .collect();
+                ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, pixels).map(Into::into)
+            }
+            ColorOutput::ScRgb => {
+                let pixels = bytes
+                    .as_chunks::<2>()
+                    .0
+                    .iter()
+                    .map(|&half| f16_to_f32(u16::from_le_bytes(half)))
+                    .collect();
+                Rgba32FImage::from_raw(width, height, pixels).map(Into::into)
+            }
+        };
+        image.expect("Readback buffer is smaller than the image")
+    }
+
+    /// Destroys every handle, has to happen before the allocator and device are destroyed.
+    pub(crate) unsafe fn destroy(&self, device: &Device, allocator: &mut Allocator) {
+        for readback in &self.readback {
+            allocator.destroy_buffer(readback);
+        }
+        unsafe { device.destroy_image_view(self.view, None) };
+        allocator.destroy_image(&self.image);
+    }
+}
+
+/// Where read back frames end up: a user callback, image files, or both.
+pub(crate) struct FrameReadback {
+    callback: Option<FrameCallback>,
+    output_dir: Option<PathBuf>,
+}
+
+impl FrameReadback {
+    pub(crate) fn new(
+        callback: Option<FrameCallback>,
+        output_dir: Option<PathBuf>,
+    ) -> Result<Self, Report<RendererError>> {
+        if let Some(dir) = &output_dir {
+            std::fs::create_dir_all(dir)
+                .map_err(|x| Report::new(RendererError::OutputDir).attach(format!("{x}")))
+                .attach_with(|| format!("path: {}", dir.display()))?;
+        }
+        Ok(Self {
+            callback,
+            output_dir,
+        })
+    }
+
+    pub(crate) fn deliver(&mut self, frame_number: u64, image: &FrameImage) {
+        if let Some(callback) = &mut self.callback {
+            callback(frame_number, image);
+        }
+        if let Some(dir) = &self.output_dir {
+            // PNG holds up to 16 bit integers, floats go to OpenEXR
+            let extension = match image {
+                FrameImage::ImageRgba32F(_) => "exr",
+                _ => "png",
+            };
+            let path = dir.join(format!("frame_{frame_number:05}.{extension}"));
+            if let Err(x) = image.save(&path) {
+                crate::logln!("headless error", "Failed to write {}: {x}", path.display());
+            }
+        }
+    }
+}
+
+/// Bytes per texel of the formats offscreen targets use.
+fn texel_size(format: vk::Format) -> u64 {
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        _ => 4,
+    }
+}
+
+/// Widens an IEEE half float, subnormals and all.
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from(half >> 10 & 0x1f);
+    let mantissa = f32::from(half & 0x3ff);
+    sign * match exponent {
+        0 => mantissa * (-24f32).exp2(),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * ((exponent - 15) as f32).exp2(),
+    }
+}
@@ -0,0 +1,857 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::{
+        capabilities::DeviceCapabilities, debug_utils::DebugUtils, graph::format_aspect,
+        shader::ShaderLibrary,
+    },
+    ash::{Device, Instance, ext, prelude::VkResult, vk},
+    error_stack::{Report, ResultExt},
+    std::{
+        collections::{HashMap, HashSet},
+        ffi::CString,
+        fmt,
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineError {
+    /// A stage's shader couldn't be loaded.
+    Shader,
+    /// A stage names an entry point its shader doesn't have.
+    EntryPoint,
+    /// The driver refused to create the pipeline.
+    Creation,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Shader => "Failed to load a pipeline shader",
+            Self::EntryPoint => "The shader has no such entry point",
+            Self::Creation => "Failed to create the pipeline",
+        })
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+/// One shader stage, `shader` is a name for [`ShaderLibrary::load`].
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct StageDesc {
+    pub shader: String,
+    pub entry_point: String,
+}
+
+impl StageDesc {
+    pub fn new(shader: impl Into<String>, entry_point: impl Into<String>) -> Self {
+        Self {
+            shader: shader.into(),
+            entry_point: entry_point.into(),
+        }
+    }
+}
+
+/// What a graphics pipeline is keyed by: its shaders and the formats it renders to.
+/// Everything else is set at record time through [`RenderState`].
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct GraphicsPipelineDesc {
+    pub stages: Vec<StageDesc>,
+    pub color_formats: Vec<vk::Format>,
+    /// Also the stencil format, when it has a stencil aspect.
+    pub depth_format: vk::Format,
+}
+
+impl GraphicsPipelineDesc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a stage, which one is read from the shader's entry point.
+    pub fn stage(mut self, shader: impl Into<String>, entry_point: impl Into<String>) -> Self {
+        self.stages.push(StageDesc::new(shader, entry_point));
+        self
+    }
+
+    pub fn color_format(mut self, format: vk::Format) -> Self {
+        self.color_formats.push(format);
+        self
+    }
+
+    pub fn depth_format(mut self, format: vk::Format) -> Self {
+        self.depth_format = format;
+        self
+    }
+}
+
+/// How a color attachment is blended, always writing every channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Blend {
+    #[default]
+    Opaque,
+    /// Straight alpha, `src * a + dst * (1 - a)`.
+    Alpha,
+    Additive,
+}
+
+impl Blend {
+    fn enabled(self) -> bool {
+        self != Self::Opaque
+    }
+
+    fn equation(self) -> vk::ColorBlendEquationEXT {
+        use vk::BlendFactor as F;
+        let (src, dst) = match self {
+            Self::Opaque => (F::ONE, F::ZERO),
+            Self::Alpha => (F::SRC_ALPHA, F::ONE_MINUS_SRC_ALPHA),
+            Self::Additive => (F::ONE, F::ONE),
+        };
+        vk::ColorBlendEquationEXT {
+            src_color_blend_factor: src,
+            dst_color_blend_factor: dst,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: if self == Self::Alpha { F::ONE } else { src },
+            dst_alpha_blend_factor: dst,
+            alpha_blend_op: vk::BlendOp::ADD,
+        }
+    }
+
+    fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let equation = self.equation();
+        vk::PipelineColorBlendAttachmentState::default()
+            .blend_enable(self.enabled())
+            .src_color_blend_factor(equation.src_color_blend_factor)
+            .dst_color_blend_factor(equation.dst_color_blend_factor)
+            .color_blend_op(equation.color_blend_op)
+            .src_alpha_blend_factor(equation.src_alpha_blend_factor)
+            .dst_alpha_blend_factor(equation.dst_alpha_blend_factor)
+            .alpha_blend_op(equation.alpha_blend_op)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VertexBinding {
+    pub binding: u32,
+    pub stride: u32,
+    pub per_instance: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub binding: u32,
+    pub format: vk::Format,
+    pub offset: u32,
+}
+
+/// Vertex buffers and what is read from them. Empty when the shaders pull their vertices
+/// out of storage buffers.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    pub bindings: Vec<VertexBinding>,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+/// Depth bias values, only applied when [`RenderState::depth_bias`] is set.
+/// `clamp` is ignored on devices without the `depthBiasClamp` feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DepthBias {
+    pub constant: f32,
+    pub clamp: f32,
+    pub slope: f32,
+}
+
+/// Fixed function state set while recording, right after the pipeline is bound.
+#[derive(Clone)]
+pub struct RenderState {
+    pub topology: vk::PrimitiveTopology,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub polygon_mode: vk::PolygonMode,
+    pub samples: vk::SampleCountFlags,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare: vk::CompareOp,
+    pub depth_bias: Option<DepthBias>,
+    /// Clamps depth to the viewport range instead of clipping, ignored on devices without the `depthClamp` feature.
+    pub depth_clamp: bool,
+    /// One per color attachment, missing ones are [`Blend::Opaque`].
+    pub blend: Vec<Blend>,
+    pub vertex_layout: VertexLayout,
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        Self {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            samples: vk::SampleCountFlags::TYPE_1,
+            depth_test: true,
+            depth_write: true,
+            depth_compare: vk::CompareOp::LESS_OR_EQUAL,
+            depth_bias: None,
+            depth_clamp: false,
+            blend: Vec::new(),
+            vertex_layout: VertexLayout::default(),
+        }
+    }
+}
+
+impl RenderState {
+    fn blend_for(&self, attachment: usize) -> Blend {
+        self.blend.get(attachment).copied().unwrap_or_default()
+    }
+}
+
+/// Which parts of [`RenderState`] the device can set while recording.
+/// The rest gets baked into the pipeline, making it part of the key.
+#[derive(Clone, Copy)]
+struct DynamicSupport {
+    // Whether the features exist at all
+    depth_clamp_feature: bool,
+    depth_bias_clamp: bool,
+    depth_clamp: bool,
+    polygon_mode: bool,
+    samples: bool,
+    blend: bool,
+    vertex_input: bool,
+}
+
+impl DynamicSupport {
+    fn new(capabilities: &DeviceCapabilities) -> Self {
+        let eds3 = &capabilities.extended_dynamic_state3;
+        Self {
+            depth_clamp_feature: capabilities.depth_clamp,
+            depth_bias_clamp: capabilities.depth_bias_clamp,
+            depth_clamp: capabilities.depth_clamp && eds3.depth_clamp_enable,
+            polygon_mode: eds3.polygon_mode,
+            samples: eds3.rasterization_samples,
+            blend: eds3.color_blend_enable && eds3.color_blend_equation && eds3.color_write_mask,
+            vertex_input: capabilities.vertex_input_dynamic_state,
+        }
+    }
+
+    fn states(&self) -> Vec<vk::DynamicState> {
+        let mut states = vec![
+            vk::DynamicState::VIEWPORT_WITH_COUNT,
+            vk::DynamicState::SCISSOR_WITH_COUNT,
+            vk::DynamicState::LINE_WIDTH,
+            vk::DynamicState::DEPTH_BIAS,
+            vk::DynamicState::CULL_MODE,
+            vk::DynamicState::FRONT_FACE,
+            vk::DynamicState::PRIMITIVE_TOPOLOGY,
+            vk::DynamicState::DEPTH_TEST_ENABLE,
+            vk::DynamicState::DEPTH_WRITE_ENABLE,
+            vk::DynamicState::DEPTH_COMPARE_OP,
+            vk::DynamicState::DEPTH_BOUNDS_TEST_ENABLE,
+            vk::DynamicState::STENCIL_TEST_ENABLE,
+            vk::DynamicState::DEPTH_BIAS_ENABLE,
+            vk::DynamicState::RASTERIZER_DISCARD_ENABLE,
+            vk::DynamicState::PRIMITIVE_RESTART_ENABLE,
+        ];
+        if self.depth_clamp {
+            states.push(vk::DynamicState::DEPTH_CLAMP_ENABLE_EXT);
+        }
+        if self.polygon_mode {
+            states.push(vk::DynamicState::POLYGON_MODE_EXT);
+        }
+        if self.samples {
+            states.push(vk::DynamicState::RASTERIZATION_SAMPLES_EXT);
+        }
+        if self.blend {
+            states.extend([
+                vk::DynamicState::COLOR_BLEND_ENABLE_EXT,
+                vk::DynamicState::COLOR_BLEND_EQUATION_EXT,
+                vk::DynamicState::COLOR_WRITE_MASK_EXT,
+            ]);
+        }
+        if self.vertex_input {
+            states.push(vk::DynamicState::VERTEX_INPUT_EXT);
+        }
+        states
+    }
+
+    /// The state that can't be dynamic, anything dynamic is left at its default.
+    fn baked(&self, desc: &GraphicsPipelineDesc, state: &RenderState) -> BakedState {
+        let mut baked = BakedState::default();
+        if !self.depth_clamp {
+            baked.depth_clamp = state.depth_clamp && self.depth_clamp_feature;
+        }
+        if !self.polygon_mode {
+            baked.polygon_mode = state.polygon_mode;
+        }
+        if !self.samples {
+            baked.samples = state.samples;
+        }
+        if !self.blend {
+            baked.blend = (0..desc.color_formats.len())
+                .map(|attachment| state.blend_for(attachment))
+                .collect();
+        }
+        if !self.vertex_input {
+            baked.vertex_layout = state.vertex_layout.clone();
+        }
+        baked
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct BakedState {
+    depth_clamp: bool,
+    polygon_mode: vk::PolygonMode,
+    samples: vk::SampleCountFlags,
+    blend: Vec<Blend>,
+    vertex_layout: VertexLayout,
+}
+
+impl Default for BakedState {
+    fn default() -> Self {
+        Self {
+            depth_clamp: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            samples: vk::SampleCountFlags::TYPE_1,
+            blend: Vec::new(),
+            vertex_layout: VertexLayout::default(),
+        }
+    }
+}
+
+// The driver rejects cache data from another device or driver, but it's cheaper not to hand it over
+const CACHE_HEADER_SIZE: usize = 32;
+
+/// Graphics pipelines built on demand from a [`GraphicsPipelineDesc`], and compute pipelines from
+/// their one [`StageDesc`], kept until one of their shaders is reloaded. Compiled pipelines go
+/// through a `VkPipelineCache` that is saved to disk on drop, so later runs skip most of the compilation.
+pub struct PipelineCache {
+    device: Arc<Device>,
+    eds3: ext::extended_dynamic_state3::Device,
+    vertex_input: ext::vertex_input_dynamic_state::Device,
+    dynamic: DynamicSupport,
+    debug_utils: DebugUtils,
+    layout: vk::PipelineLayout,
+    cache: vk::PipelineCache,
+    cache_path: PathBuf,
+    pipelines: HashMap<(GraphicsPipelineDesc, BakedState), vk::Pipeline>,
+    compute: HashMap<StageDesc, vk::Pipeline>,
+    // Pipelines that failed to build, already reported and skipped until a shader of theirs reloads
+    failed: HashSet<(GraphicsPipelineDesc, BakedState)>,
+    failed_compute: HashSet<StageDesc>,
+    // Stale pipelines and the timeline value after which no frame uses them anymore
+    retired: Vec<(u64, vk::Pipeline)>,
+}
+
+impl PipelineCache {
+    /// `layout` is shared by every pipeline, the bindless one.
+    pub(crate) fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: Arc<Device>,
+        capabilities: &DeviceCapabilities,
+        layout: vk::PipelineLayout,
+        asset_dir: &Path,
+        debug_utils: DebugUtils,
+    ) -> VkResult<Self> {
+        let cache_path = asset_dir.join("cache").join("pipelines.bin");
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let initial_data = std::fs::read(&cache_path)
+            .ok()
+            .filter(|data| Self::header_matches(data, &properties))
+            .unwrap_or_default();
+        if !initial_data.is_empty() {
+            crate::logln!(
+                "vulkan pipeline",
+                "Loaded {} KiB of cached pipelines",
+                initial_data.len() / 1024
+            );
+        }
+        let cache = unsafe {
+            device.create_pipeline_cache(
+                &vk::PipelineCacheCreateInfo::default().initial_data(&initial_data),
+                None,
+            )
+        }
+        .or_else(|_| unsafe {
+            // Corrupt data is allowed to fail creation, start over without it
+            device.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None)
+        })?;
+
+        Ok(Self {
+            eds3: ext::extended_dynamic_state3::Device::new(instance, &device),
+            vertex_input: ext::vertex_input_dynamic_state::Device::new(instance, &device),
+            dynamic: DynamicSupport::new(capabilities),
+            debug_utils,
+            device,
+            layout,
+            cache,
+            cache_path,
+            pipelines: HashMap::new(),
+            compute: HashMap::new(),
+            failed: HashSet::new(),
+            failed_compute: HashSet::new(),
+            retired: Vec::new(),
+        })
+    }
+
+    fn header_matches(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+        let word =
+            |index: usize| u32::from_le_bytes(data[index * 4..index * 4 + 4].try_into().unwrap());
+        data.len() >= CACHE_HEADER_SIZE
+            && word(0) as usize >= CACHE_HEADER_SIZE
+            && word(1) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+            && word(2) == properties.vendor_id
+            && word(3) == properties.device_id
+            && data[16..32] == properties.pipeline_cache_uuid
+    }
+
+    /// Binds the pipeline for `desc` and `state`, creating it first if needed, then sets every
+    /// dynamic state from `state` with a viewport and scissor covering `extent`. The viewport is
+    /// flipped, NDC y points up.
+    /// `false` if the pipeline can't be built, see [`PipelineCache::get`].
+    pub fn bind(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        shaders: &mut ShaderLibrary,
+        desc: &GraphicsPipelineDesc,
+        state: &RenderState,
+        extent: vk::Extent2D,
+    ) -> bool {
+        let Some(pipeline) = self.get(shaders, desc, state) else {
+            return false;
+        };
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline,
+            );
+        }
+        self.apply(command_buffer, desc, state, extent);
+        true
+    }
+
+    /// The pipeline for `desc` and `state`, creating it if needed. `None` if it can't be built,
+    /// which is logged once, it's only tried again after one of its shaders is reloaded.
+    pub fn get(
+        &mut self,
+        shaders: &mut ShaderLibrary,
+        desc: &GraphicsPipelineDesc,
+        state: &RenderState,
+    ) -> Option<vk::Pipeline> {
+        let key = (desc.clone(), self.dynamic.baked(desc, state));
+        if let Some(&pipeline) = self.pipelines.get(&key) {
+            return Some(pipeline);
+        }
+        if self.failed.contains(&key) {
+            return None;
+        }
+        match self.create(shaders, &key.0, &key.1) {
+            Ok(pipeline) => {
+                self.pipelines.insert(key, pipeline);
+                Some(pipeline)
+            }
+            Err(report) => {
+                crate::logln!("vulkan pipeline", "{report:?}");
+                self.failed.insert(key);
+                None
+            }
+        }
+    }
+
+    /// Binds the compute pipeline running `stage`, creating it first if needed.
+    /// `false` if it can't be built, which is handled like in [`PipelineCache::get`].
+    pub fn bind_compute(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        shaders: &mut ShaderLibrary,
+        stage: &StageDesc,
+    ) -> bool {
+        let pipeline = match self.compute.get(stage) {
+            Some(&pipeline) => pipeline,
+            None if self.failed_compute.contains(stage) => return false,
+            None => match self.create_compute(shaders, stage) {
+                Ok(pipeline) => {
+                    self.compute.insert(stage.clone(), pipeline);
+                    pipeline
+                }
+                Err(report) => {
+                    crate::logln!("vulkan pipeline", "{report:?}");
+                    self.failed_compute.insert(stage.clone());
+                    return false;
+                }
+            },
+        };
+        unsafe {
+            self.device
+                .cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+        }
+        true
+    }
+
+    /// The module, stage and entry point name of `stage`.
+    fn load_stage(
+        shaders: &mut ShaderLibrary,
+        stage: &StageDesc,
+    ) -> Result<(vk::ShaderModule, vk::ShaderStageFlags, CString), Report<PipelineError>> {
+        let shader = shaders
+            .load(&stage.shader)
+            .change_context(PipelineError::Shader)?;
+        let stage_flags = shader
+            .reflection
+            .stage_of(&stage.entry_point)
+            .ok_or_else(|| {
+                Report::new(PipelineError::EntryPoint).attach(format!(
+                    "{} has no entry point {}",
+                    stage.shader, stage.entry_point
+                ))
+            })?;
+        let entry_point = CString::new(stage.entry_point.as_str())
+            .map_err(|_| Report::new(PipelineError::EntryPoint))?;
+        Ok((shader.module, stage_flags, entry_point))
+    }
+
+    fn create_compute(
+        &self,
+        shaders: &mut ShaderLibrary,
+        stage: &StageDesc,
+    ) -> Result<vk::Pipeline, Report<PipelineError>> {
+        let (module, stage_flags, entry_point) = Self::load_stage(shaders, stage)?;
+        if stage_flags != vk::ShaderStageFlags::COMPUTE {
+            return Err(Report::new(PipelineError::EntryPoint).attach(format!(
+                "{} in {} is not a compute shader",
+                stage.entry_point, stage.shader
+            )));
+        }
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(stage_flags)
+                    .module(module)
+                    .name(&entry_point),
+            )
+            .layout(self.layout);
+        let pipelines = unsafe {
+            self.device
+                .create_compute_pipelines(self.cache, &[create_info], None)
+        }
+        .map_err(|(_, x)| Report::new(PipelineError::Creation).attach(format!("{x}")))
+        .attach_with(|| format!("shader: {}", stage.shader))?;
+        self.debug_utils.name(
+            pipelines[0],
+            format_args!("compute {} {}", stage.shader, stage.entry_point),
+        );
+        Ok(pipelines[0])
+    }
+
+    fn create(
+        &self,
+        shaders: &mut ShaderLibrary,
+        desc: &GraphicsPipelineDesc,
+        baked: &BakedState,
+    ) -> Result<vk::Pipeline, Report<PipelineError>> {
+        let mut modules = Vec::with_capacity(desc.stages.len());
+        for stage in &desc.stages {
+            modules.push(Self::load_stage(shaders, stage)?);
+        }
+        let stages: Vec<vk::PipelineShaderStageCreateInfo> = modules
+            .iter()
+            .map(|(module, stage, entry_point)| {
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(*stage)
+                    .module(*module)
+                    .name(entry_point)
+            })
+            .collect();
+
+        let bindings: Vec<vk::VertexInputBindingDescription> = baked
+            .vertex_layout
+            .bindings
+            .iter()
+            .map(|binding| vk::VertexInputBindingDescription {
+                binding: binding.binding,
+                stride: binding.stride,
+                input_rate: input_rate(binding.per_instance),
+            })
+            .collect();
+        let attributes: Vec<vk::VertexInputAttributeDescription> = baked
+            .vertex_layout
+            .attributes
+            .iter()
+            .map(|attribute| vk::VertexInputAttributeDescription {
+                location: attribute.location,
+                binding: attribute.binding,
+                format: attribute.format,
+                offset: attribute.offset,
+            })
+            .collect();
+        // Ignored when vertex input is dynamic
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&bindings)
+            .vertex_attribute_descriptions(&attributes);
+        // The values of dynamic state below are placeholders, only the baked ones matter
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let viewport = vk::PipelineViewportStateCreateInfo::default();
+        let rasterization = vk::PipelineRasterizationStateCreateInfo::default()
+            .depth_clamp_enable(baked.depth_clamp)
+            .polygon_mode(baked.polygon_mode)
+            .line_width(1.0);
+        let multisample =
+            vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(baked.samples);
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default();
+        let blend_attachments: Vec<vk::PipelineColorBlendAttachmentState> =
+            (0..desc.color_formats.len())
+                .map(|attachment| {
+                    baked
+                        .blend
+                        .get(attachment)
+                        .copied()
+                        .unwrap_or_default()
+                        .attachment_state()
+                })
+                .collect();
+        let color_blend =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachments);
+        let dynamic_states = self.dynamic.states();
+        let dynamic = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+        // Matches the attachments the render graph begins rendering with
+        let aspect = format_aspect(desc.depth_format);
+        let attachment_format = |wanted| {
+            if aspect.contains(wanted) {
+                desc.depth_format
+            } else {
+                vk::Format::UNDEFINED
+            }
+        };
+        let mut rendering = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&desc.color_formats)
+            .depth_attachment_format(attachment_format(vk::ImageAspectFlags::DEPTH))
+            .stencil_attachment_format(attachment_format(vk::ImageAspectFlags::STENCIL));
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic)
+            .layout(self.layout)
+            .push_next(&mut rendering);
+        let pipelines = unsafe {
+            self.device
+                .create_graphics_pipelines(self.cache, &[create_info], None)
+        }
+        .map_err(|(_, x)| Report::new(PipelineError::Creation).attach(format!("{x}")))
+        .attach_with(|| {
+            let names: Vec<&str> = desc.stages.iter().map(|s| s.shader.as_str()).collect();
+            format!("shaders: {}", names.join(", "))
+        })?;
+        self.debug_utils
+            .name(pipelines[0], PipelineName(&desc.stages));
+        Ok(pipelines[0])
+    }
+
+    /// Sets every dynamic state of the pipelines this cache creates.
+    fn apply(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        desc: &GraphicsPipelineDesc,
+        state: &RenderState,
+        extent: vk::Extent2D,
+    ) {
+        let device = &self.device;
+        let cb = command_buffer;
+        // Flipped, so NDC y points up like the projections and shaders expect, and counter-clockwise
+        // faces stay front-facing
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: extent.height as f32,
+            width: extent.width as f32,
+            height: -(extent.height as f32),
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D::default().extent(extent)];
+        let bias = state.depth_bias.unwrap_or_default();
+        let bias_clamp = if self.dynamic.depth_bias_clamp {
+            bias.clamp
+        } else {
+            0.0
+        };
+        unsafe {
+            device.cmd_set_viewport_with_count(cb, &viewports);
+            device.cmd_set_scissor_with_count(cb, &scissors);
+            device.cmd_set_line_width(cb, 1.0);
+            device.cmd_set_depth_bias(cb, bias.constant, bias_clamp, bias.slope);
+            device.cmd_set_cull_mode(cb, state.cull_mode);
+            device.cmd_set_front_face(cb, state.front_face);
+            device.cmd_set_primitive_topology(cb, state.topology);
+            device.cmd_set_depth_test_enable(cb, state.depth_test);
+            device.cmd_set_depth_write_enable(cb, state.depth_write);
+            device.cmd_set_depth_compare_op(cb, state.depth_compare);
+            device.cmd_set_depth_bounds_test_enable(cb, false);
+            device.cmd_set_stencil_test_enable(cb, false);
+            device.cmd_set_depth_bias_enable(cb, state.depth_bias.is_some());
+            device.cmd_set_rasterizer_discard_enable(cb, false);
+            device.cmd_set_primitive_restart_enable(cb, false);
+
+            if self.dynamic.depth_clamp {
+                self.eds3.cmd_set_depth_clamp_enable(cb, state.depth_clamp);
+            }
+            if self.dynamic.polygon_mode {
+                self.eds3.cmd_set_polygon_mode(cb, state.polygon_mode);
+            }
+            if self.dynamic.samples {
+                self.eds3.cmd_set_rasterization_samples(cb, state.samples);
+            }
+            if self.dynamic.blend && !desc.color_formats.is_empty() {
+                let blends: Vec<Blend> = (0..desc.color_formats.len())
+                    .map(|attachment| state.blend_for(attachment))
+                    .collect();
+                let enables: Vec<vk::Bool32> =
+                    blends.iter().map(|blend| blend.enabled().into()).collect();
+                let equations: Vec<vk::ColorBlendEquationEXT> =
+                    blends.iter().map(|blend| blend.equation()).collect();
+                self.eds3.cmd_set_color_blend_enable(cb, 0, &enables);
+                self.eds3.cmd_set_color_blend_equation(cb, 0, &equations);
+                self.eds3.cmd_set_color_write_mask(
+                    cb,
+                    0,
+                    &vec![vk::ColorComponentFlags::RGBA; blends.len()],
+                );
+            }
+            if self.dynamic.vertex_input {
+                let layout = &state.vertex_layout;
+                let bindings: Vec<vk::VertexInputBindingDescription2EXT> = layout
+                    .bindings
+                    .iter()
+                    .map(|binding| {
+                        vk::VertexInputBindingDescription2EXT::default()
+                            .binding(binding.binding)
+                            .stride(binding.stride)
+                            .input_rate(input_rate(binding.per_instance))
+                            .divisor(1)
+                    })
+                    .collect();
+                let attributes: Vec<vk::VertexInputAttributeDescription2EXT> = layout
+                    .attributes
+                    .iter()
+                    .map(|attribute| {
+                        vk::VertexInputAttributeDescription2EXT::default()
+                            .location(attribute.location)
+                            .binding(attribute.binding)
+                            .format(attribute.format)
+                            .offset(attribute.offset)
+                    })
+                    .collect();
+                self.vertex_input
+                    .cmd_set_vertex_input(cb, &bindings, &attributes);
+            }
+        }
+    }
+
+    /// Drops every pipeline built from one of `reloaded`, they get rebuilt with the new shaders
+    /// the next time they're bound, and so do the ones that failed to build before.
+    /// The old ones are destroyed once the timeline reaches `retire_value`.
+    pub(crate) fn shaders_reloaded(&mut self, reloaded: &[String], retire_value: u64) {
+        self.failed.retain(|(desc, _)| {
+            !desc
+                .stages
+                .iter()
+                .any(|stage| reloaded.contains(&stage.shader))
+        });
+        self.failed_compute
+            .retain(|stage| !reloaded.contains(&stage.shader));
+        let retired = &mut self.retired;
+        self.pipelines.retain(|(desc, _), &mut pipeline| {
+            let stale = desc
+                .stages
+                .iter()
+                .any(|stage| reloaded.contains(&stage.shader));
+            if stale {
+                retired.push((retire_value, pipeline));
+            }
+            !stale
+        });
+        self.compute.retain(|stage, &mut pipeline| {
+            let stale = reloaded.contains(&stage.shader);
+            if stale {
+                retired.push((retire_value, pipeline));
+            }
+            !stale
+        });
+    }
+
+    /// Destroys the retired pipelines no frame up to `completed_value` on the timeline still uses.
+    pub(crate) fn reclaim(&mut self, completed_value: u64) {
+        let device = &self.device;
+        self.retired.retain(|&(retire_value, pipeline)| {
+            let done = retire_value <= completed_value;
+            if done {
+                unsafe { device.destroy_pipeline(pipeline, None) };
+            }
+            !done
+        });
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let data = unsafe { self.device.get_pipeline_cache_data(self.cache) }
+            .map_err(std::io::Error::other)?;
+        if let Some(dir) = self.cache_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Written next to it first, so a crash mid-write can't leave a torn cache behind
+        let temp_path = self.cache_path.with_extension("tmp");
+        std::fs::write(&temp_path, data)?;
+        std::fs::rename(&temp_path, &self.cache_path)
+    }
+}
+
+// Graphics pipelines are named after their stages, only formatted when names are kept
+struct PipelineName<'a>(&'a [StageDesc]);
+
+impl fmt::Display for PipelineName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("graphics")?;
+        for stage in self.0 {
+            write!(f, " {} {}", stage.shader, stage.entry_point)?;
+        }
+        Ok(())
+    }
+}
+
+fn input_rate(per_instance: bool) -> vk::VertexInputRate {
+    if per_instance {
+        vk::VertexInputRate::INSTANCE
+    } else {
+        vk::VertexInputRate::VERTEX
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        if let Err(x) = self.save() {
+            crate::logln!(
+                "vulkan pipeline error",
+                "Failed to save the pipeline cache to {}: {x}",
+                self.cache_path.display()
+            );
+        }
+        unsafe {
+            for &pipeline in self.pipelines.values().chain(self.compute.values()) {
+                self.device.destroy_pipeline(pipeline, None);
+            }
+            for &(_, pipeline) in &self.retired {
+                self.device.destroy_pipeline(pipeline, None);
+            }
+            self.device.destroy_pipeline_cache(self.cache, None);
+        }
+    }
+}
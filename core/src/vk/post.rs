@@ -0,0 +1,649 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        ColorOutput, PostControls, PostSettings, Tonemapper,
+        assets::{ColorSpace, Texture},
+        vk::{
+            bindless::{BindlessHeap, DescriptorHandle},
+            frame::MAX_FRAMES_IN_FLIGHT,
+            graph::{BufferState, PassContext},
+            memory::{Allocator, Buffer, MemoryLocation},
+            pipeline::{Blend, GraphicsPipelineDesc, PipelineCache, RenderState, StageDesc},
+            queues::Queues,
+            shader::ShaderLibrary,
+            texture::Textures,
+            upload::Uploader,
+        },
+    },
+    ash::{Device, prelude::VkResult, vk},
+    std::{path::Path, sync::Arc, time::Instant},
+};
+
+/// What the scene is shaded into before post-processing.
+pub(crate) const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Tonemapped SDR colors anti-aliasing reads, stored encoded so the 8 bits go where the eye can tell them apart.
+pub(crate) const LDR_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+/// SMAA edges, on the left and on the top of each pixel.
+pub(crate) const SMAA_EDGES_FORMAT: vk::Format = vk::Format::R8G8_UNORM;
+/// SMAA blend weights, towards and from the neighbours above and to the left.
+pub(crate) const SMAA_WEIGHTS_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+/// Halvings of the bloom chain, starting at half resolution.
+pub(crate) const BLOOM_LEVELS: usize = 6;
+
+const SHADER: &str = "post.spv";
+const EXPOSURE_SHADER: &str = "exposure.spv";
+// Matching `shaders/exposure.wgsl`, the adapted luminance follows the bins
+const HISTOGRAM_BINS: usize = 256;
+const HISTOGRAM_TILE: u32 = 16;
+
+// Flags of the push constants, matching `shaders/post.wgsl`
+const AUTO_EXPOSURE: u32 = 1;
+const BLOOM: u32 = 2;
+const GRADING: u32 = 4;
+const ENCODE_SRGB: u32 = 8;
+const KARIS_AVERAGE: u32 = 16;
+const HDR_OUTPUT: u32 = 32;
+const ENCODE_PQ: u32 = 64;
+const ENCODE_SCRGB: u32 = 128;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    source: u32,
+    bloom: u32,
+    lut: u32,
+    post_sampler: u32,
+    exposure: u32,
+    flags: u32,
+    tonemapper: u32,
+    lut_size: u32,
+    texel: [f32; 2],
+    exposure_scale: f32,
+    bloom_intensity: f32,
+    paper_white: f32,
+    peak_brightness: f32,
+    smaa: u32,
+    _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ExposurePushConstants {
+    hdr: u32,
+    histogram: u32,
+    width: u32,
+    height: u32,
+    delta_time: f32,
+    adaptation_speed: f32,
+    _pad: [u32; 2],
+}
+
+// Plain numbers without padding
+unsafe impl bytemuck::Zeroable for PushConstants {}
+unsafe impl bytemuck::Pod for PushConstants {}
+unsafe impl bytemuck::Zeroable for ExposurePushConstants {}
+unsafe impl bytemuck::Pod for ExposurePushConstants {}
+
+/// Turns the HDR image the scene is shaded into into what's shown, following the shared [`PostControls`].
+/// The images of the chain are transient in the render graph, so the slots they're sampled through
+/// are registered while recording and handed back once the frame that used them is done.
+pub(crate) struct PostProcess {
+    device: Arc<Device>,
+    controls: PostControls,
+    // What the frame being built uses, read from the controls once per frame
+    settings: PostSettings,
+    output: ColorOutput,
+    sampler: vk::Sampler,
+    sampler_handle: DescriptorHandle,
+    // Histogram bins followed by the adapted luminance, carried from frame to frame
+    exposure: Option<(Buffer, DescriptorHandle)>,
+    // Sampled image slot and the size of one slice
+    lut: Option<(DescriptorHandle, u32)>,
+    // Sampled image slots registered by each frame in flight, and the slot being recorded
+    frame_handles: Vec<Vec<DescriptorHandle>>,
+    slot: usize,
+    // The frame being recorded ran out of sampled image slots, and said so
+    out_of_slots: bool,
+    last_frame: Option<Instant>,
+    delta_time: f32,
+    // Nothing touched the exposure buffer on the GPU yet, so there's nothing to wait on
+    fresh: bool,
+}
+
+impl PostProcess {
+    /// Loads the grading `lut` when there is one, a LUT that can't be used is reported and left out.
+    pub(crate) fn new(
+        device: Arc<Device>,
+        queues: &Queues,
+        allocator: &mut Allocator,
+        bindless: &mut BindlessHeap,
+        textures: &mut Textures,
+        controls: PostControls,
+        lut: Option<&Path>,
+    ) -> VkResult<Self> {
+        let lut = match lut.map(|path| (path, Texture::load(path, ColorSpace::Linear))) {
+            Some((_, Ok(texture))) if texture.width == texture.height * texture.height => {
+                let mut uploader = Uploader::new(&device, queues, allocator);
+                let handle = textures.upload(&mut uploader, bindless, &texture)?;
+                uploader.flush()?;
+                handle.map(|handle| (handle, texture.height))
+            }
+            Some((path, Ok(texture))) => {
+                crate::logln!(
+                    "post lut warning",
+                    "{}: a LUT is N slices of N by N texels side by side, not {}x{}",
+                    path.display(),
+                    texture.width,
+                    texture.height
+                );
+                None
+            }
+            Some((_, Err(report))) => {
+                crate::logln!("post lut error", "{report:?}");
+                None
+            }
+            None => None,
+        };
+
+        let size = ((HISTOGRAM_BINS + 1) * size_of::<u32>()) as vk::DeviceSize;
+        let mut buffer = allocator.create_buffer(
+            &vk::BufferCreateInfo::default()
+                .size(size)
+                .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            MemoryLocation::CpuToGpu,
+            "luminance histogram",
+        )?;
+        // Empty bins, and no adapted luminance so the first frame snaps to its average
+        let Some(mapped) = buffer.allocation.mapped_slice_mut() else {
+            allocator.destroy_buffer(&buffer);
+            return Err(vk::Result::ERROR_MEMORY_MAP_FAILED);
+        };
+        mapped[..size as usize].fill(0);
+        let exposure_handle = bindless
+            .add_storage_buffer(buffer.handle, 0, size)
+            .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)?;
+
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                None,
+            )
+        };
+        let sampler = match sampler {
+            Ok(x) => x,
+            Err(x) => {
+                allocator.destroy_buffer(&buffer);
+                return Err(x);
+            }
+        };
+        let Some(sampler_handle) = bindless.add_sampler(sampler) else {
+            unsafe { device.destroy_sampler(sampler, None) };
+            allocator.destroy_buffer(&buffer);
+            return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY);
+        };
+
+        Ok(Self {
+            device,
+            settings: controls.get(),
+            output: ColorOutput::Sdr,
+            controls,
+            sampler,
+            sampler_handle,
+            exposure: Some((buffer, exposure_handle)),
+            lut,
+            frame_handles: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            slot: 0,
+            out_of_slots: false,
+            last_frame: None,
+            delta_time: 0.0,
+            fresh: true,
+        })
+    }
+
+    /// Starts the frame in `slot` shown as `output`, returning the settings it's built with.
+    /// The passes recorded until the next call belong to it. The last frame that used `slot` must be done.
+    pub(crate) fn begin(
+        &mut self,
+        bindless: &mut BindlessHeap,
+        slot: usize,
+        output: ColorOutput,
+    ) -> PostSettings {
+        // Nothing reads them anymore, they're free as soon as the heap reclaims
+        for handle in self.frame_handles[slot].drain(..) {
+            bindless.release(handle, 0);
+        }
+        self.slot = slot;
+        self.out_of_slots = false;
+        self.output = output;
+        let now = Instant::now();
+        self.delta_time = self
+            .last_frame
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_frame = Some(now);
+        self.settings = self.controls.get();
+        self.settings
+    }
+
+    /// The exposure buffer for the graph to import, and the state the last frame left it in.
+    pub(crate) fn exposure_buffer(&mut self) -> Option<(vk::Buffer, BufferState)> {
+        let (buffer, _) = self.exposure.as_ref()?;
+        let initial = if self.fresh {
+            BufferState::NONE
+        } else {
+            BufferState {
+                stage: vk::PipelineStageFlags2::COMPUTE_SHADER
+                    | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                access: vk::AccessFlags2::SHADER_STORAGE_READ
+                    | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            }
+        };
+        self.fresh = false;
+        Some((buffer.handle, initial))
+    }
+
+    /// Bins the luminance of every pixel of `hdr` into the histogram.
+    pub(crate) fn histogram(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        (hdr, extent): (vk::ImageView, vk::Extent2D),
+    ) {
+        let Some(hdr) = self.sampled(bindless, hdr) else {
+            return;
+        };
+        let push = ExposurePushConstants {
+            hdr,
+            ..self.exposure_push_constants(extent)
+        };
+        let groups = [
+            extent.width.div_ceil(HISTOGRAM_TILE),
+            extent.height.div_ceil(HISTOGRAM_TILE),
+        ];
+        self.dispatch(
+            context,
+            pipelines,
+            shaders,
+            bindless,
+            ("histogram", groups),
+            push,
+        );
+    }
+
+    /// Eases the adapted luminance towards the average of the histogram of an image of `extent`,
+    /// emptying it for the next frame.
+    pub(crate) fn adapt(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &BindlessHeap,
+        extent: vk::Extent2D,
+    ) {
+        let push = self.exposure_push_constants(extent);
+        self.dispatch(
+            context,
+            pipelines,
+            shaders,
+            bindless,
+            ("average", [1, 1]),
+            push,
+        );
+    }
+
+    /// Downsamples `source` into the pass's attachment, taming fireflies on the `first` level.
+    pub(crate) fn bloom_down(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        (source, extent): (vk::ImageView, vk::Extent2D),
+        first: bool,
+    ) {
+        let Some(source) = self.sampled(bindless, source) else {
+            return;
+        };
+        let push = PushConstants {
+            source,
+            flags: if first { KARIS_AVERAGE } else { 0 },
+            texel: texel(extent),
+            ..self.push_constants()
+        };
+        let pass = ("fs_bloom_down", HDR_FORMAT, Blend::Opaque);
+        self.draw(context, pipelines, shaders, bindless, pass, push);
+    }
+
+    /// Upsamples `source` and adds it onto the pass's attachment.
+    pub(crate) fn bloom_up(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        (source, extent): (vk::ImageView, vk::Extent2D),
+    ) {
+        let Some(source) = self.sampled(bindless, source) else {
+            return;
+        };
+        let push = PushConstants {
+            source,
+            texel: texel(extent),
+            ..self.push_constants()
+        };
+        let pass = ("fs_bloom_up", HDR_FORMAT, Blend::Additive);
+        self.draw(context, pipelines, shaders, bindless, pass, push);
+    }
+
+    /// Exposes, blooms, tonemaps and grades `hdr` into the pass's attachment of `format`, encoded
+    /// for the display when it's the `last` pass and left linear for anti-aliasing otherwise.
+    /// HDR output is rolled off towards the peak brightness instead and isn't graded.
+    /// Auto exposure reads the exposure buffer, which the frame must have adapted first.
+    pub(crate) fn tonemap(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        (hdr, bloom): (vk::ImageView, Option<vk::ImageView>),
+        (format, last): (vk::Format, bool),
+    ) {
+        let Some(source) = self.sampled(bindless, hdr) else {
+            return;
+        };
+        let mut push = PushConstants {
+            source,
+            ..self.push_constants()
+        };
+        // The image still gets tonemapped without its bloom
+        if let Some(bloom) = bloom.and_then(|bloom| self.sampled(bindless, bloom)) {
+            push.bloom = bloom;
+            push.flags |= BLOOM;
+        }
+        if self.settings.auto_exposure && self.exposure.is_some() {
+            push.flags |= AUTO_EXPOSURE;
+        }
+        if self.output != ColorOutput::Sdr {
+            push.flags |= HDR_OUTPUT;
+        } else if self.settings.color_grading && self.lut.is_some() {
+            push.flags |= GRADING;
+        }
+        if last {
+            push.flags |= self.encoding(format);
+        }
+        let pass = ("fs_tonemap", format, Blend::Opaque);
+        self.draw(context, pipelines, shaders, bindless, pass, push);
+    }
+
+    /// Smooths the edges of the tonemapped `source` into the pass's attachment of `format`.
+    pub(crate) fn fxaa(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        (source, extent): (vk::ImageView, vk::Extent2D),
+        format: vk::Format,
+    ) {
+        let Some(source) = self.sampled(bindless, source) else {
+            return;
+        };
+        let mut push = PushConstants {
+            source,
+            texel: texel(extent),
+            ..self.push_constants()
+        };
+        push.flags |= self.encoding(format);
+        let pass = ("fs_fxaa", format, Blend::Opaque);
+        self.draw(context, pipelines, shaders, bindless, pass, push);
+    }
+
+    /// Finds the edges of the tonemapped `source` into the pass's attachment, the first of the
+    /// three SMAA passes.
+    pub(crate) fn smaa_edges(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        source: vk::ImageView,
+    ) {
+        let Some(source) = self.sampled(bindless, source) else {
+            return;
+        };
+        let push = PushConstants {
+            source,
+            ..self.push_constants()
+        };
+        let pass = ("fs_smaa_edges", SMAA_EDGES_FORMAT, Blend::Opaque);
+        self.draw(context, pipelines, shaders, bindless, pass, push);
+    }
+
+    /// Measures the shapes the `edges` form into blend weights in the pass's attachment.
+    pub(crate) fn smaa_weights(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        edges: vk::ImageView,
+    ) {
+        let Some(source) = self.sampled(bindless, edges) else {
+            return;
+        };
+        let push = PushConstants {
+            source,
+            ..self.push_constants()
+        };
+        let pass = ("fs_smaa_weights", SMAA_WEIGHTS_FORMAT, Blend::Opaque);
+        self.draw(context, pipelines, shaders, bindless, pass, push);
+    }
+
+    /// Blends every pixel of the tonemapped `source` with its neighbours by the `weights` into the
+    /// pass's attachment of `format`.
+    pub(crate) fn smaa_blend(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &mut BindlessHeap,
+        (source, weights, extent): (vk::ImageView, vk::ImageView, vk::Extent2D),
+        format: vk::Format,
+    ) {
+        let (Some(source), Some(weights)) = (
+            self.sampled(bindless, source),
+            self.sampled(bindless, weights),
+        ) else {
+            return;
+        };
+        let mut push = PushConstants {
+            source,
+            smaa: weights,
+            texel: texel(extent),
+            ..self.push_constants()
+        };
+        push.flags |= self.encoding(format);
+        let pass = ("fs_smaa_blend", format, Blend::Opaque);
+        self.draw(context, pipelines, shaders, bindless, pass, push);
+    }
+
+    /// Frees the exposure buffer, the GPU must be done with it. The LUT goes with the textures.
+    pub(crate) fn destroy(&mut self, allocator: &mut Allocator) {
+        if let Some((buffer, _)) = self.exposure.take() {
+            allocator.destroy_buffer(&buffer);
+        }
+    }
+
+    /// Registers `view` for the frame being recorded, returning its sampled image slot.
+    /// `None` once every slot is taken, the pass that needed it is skipped.
+    fn sampled(&mut self, bindless: &mut BindlessHeap, view: vk::ImageView) -> Option<u32> {
+        let Some(handle) = bindless.add_sampled_image(view, vk::ImageLayout::READ_ONLY_OPTIMAL)
+        else {
+            if !self.out_of_slots {
+                crate::logln!(
+                    "post warning",
+                    "Out of bindless sampled image slots, skipping post-processing passes this frame"
+                );
+            }
+            self.out_of_slots = true;
+            return None;
+        };
+        self.frame_handles[self.slot].push(handle);
+        Some(handle.index)
+    }
+
+    /// The flag that encodes linear colors for the display, into a target of `format`.
+    fn encoding(&self, format: vk::Format) -> u32 {
+        match self.output {
+            ColorOutput::Sdr if is_srgb(format) => 0,
+            ColorOutput::Sdr => ENCODE_SRGB,
+            ColorOutput::Hdr10 => ENCODE_PQ,
+            ColorOutput::ScRgb => ENCODE_SCRGB,
+        }
+    }
+
+    /// What every pass shares, the passes fill in their inputs and flags.
+    fn push_constants(&self) -> PushConstants {
+        let settings = &self.settings;
+        PushConstants {
+            source: 0,
+            bloom: 0,
+            lut: self.lut.map_or(0, |(handle, _)| handle.index),
+            post_sampler: self.sampler_handle.index,
+            exposure: self.exposure.as_ref().map_or(0, |(_, handle)| handle.index),
+            flags: 0,
+            tonemapper: match settings.tonemapper {
+                Tonemapper::None => 0,
+                Tonemapper::Aces => 1,
+                Tonemapper::Agx => 2,
+            },
+            lut_size: self.lut.map_or(0, |(_, size)| size),
+            texel: [0.0; 2],
+            exposure_scale: settings.exposure_compensation.exp2(),
+            bloom_intensity: settings.bloom_intensity,
+            paper_white: settings.paper_white,
+            peak_brightness: settings.peak_brightness,
+            smaa: 0,
+            _pad: 0,
+        }
+    }
+
+    fn exposure_push_constants(&self, extent: vk::Extent2D) -> ExposurePushConstants {
+        ExposurePushConstants {
+            hdr: 0,
+            histogram: self.exposure.as_ref().map_or(0, |(_, handle)| handle.index),
+            width: extent.width,
+            height: extent.height,
+            delta_time: self.delta_time,
+            adaptation_speed: self.settings.adaptation_speed,
+            _pad: [0; 2],
+        }
+    }
+
+    /// Draws one full screen triangle with the `entry` fragment shader into the pass's only
+    /// attachment, of `format` and blended with `blend`.
+    fn draw(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &BindlessHeap,
+        (entry, format, blend): (&str, vk::Format, Blend),
+        push: PushConstants,
+    ) {
+        let cb = context.command_buffer;
+        let desc = GraphicsPipelineDesc::new()
+            .stage(SHADER, "vs_fullscreen")
+            .stage(SHADER, entry)
+            .color_format(format);
+        let state = RenderState {
+            cull_mode: vk::CullModeFlags::NONE,
+            depth_test: false,
+            depth_write: false,
+            blend: vec![blend],
+            ..Default::default()
+        };
+        bindless.bind(cb, vk::PipelineBindPoint::GRAPHICS);
+        if !pipelines.bind(cb, shaders, &desc, &state, context.render_area()) {
+            return;
+        }
+        unsafe {
+            context.device.cmd_push_constants(
+                cb,
+                bindless.pipeline_layout(),
+                vk::ShaderStageFlags::ALL,
+                0,
+                bytemuck::bytes_of(&push),
+            );
+            context.device.cmd_draw(cb, 3, 1, 0, 0);
+        }
+    }
+
+    /// Runs `entry` of the exposure shader over `groups` workgroups.
+    fn dispatch(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &BindlessHeap,
+        (entry, groups): (&str, [u32; 2]),
+        push: ExposurePushConstants,
+    ) {
+        if self.exposure.is_none() {
+            return;
+        }
+        let cb = context.command_buffer;
+        bindless.bind(cb, vk::PipelineBindPoint::COMPUTE);
+        if !pipelines.bind_compute(cb, shaders, &StageDesc::new(EXPOSURE_SHADER, entry)) {
+            return;
+        }
+        unsafe {
+            context.device.cmd_push_constants(
+                cb,
+                bindless.pipeline_layout(),
+                vk::ShaderStageFlags::ALL,
+                0,
+                bytemuck::bytes_of(&push),
+            );
+            context.device.cmd_dispatch(cb, groups[0], groups[1], 1);
+        }
+    }
+}
+
+impl Drop for PostProcess {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_sampler(self.sampler, None) };
+    }
+}
+
+/// Whether `format` encodes to sRGB on write, otherwise the shaders do it.
+fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+fn texel(extent: vk::Extent2D) -> [f32; 2] {
+    [
+        1.0 / extent.width.max(1) as f32,
+        1.0 / extent.height.max(1) as f32,
+    ]
+}
+
+/// The extent of bloom level `level`, half of the one above it.
+pub(crate) fn bloom_extent(extent: vk::Extent2D, level: usize) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width >> (level + 1)).max(1),
+        height: (extent.height >> (level + 1)).max(1),
+    }
+}
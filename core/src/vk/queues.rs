@@ -0,0 +1,270 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::debug_utils::DebugUtils,
+    ash::{Device, Instance, khr::surface, vk},
+    std::collections::BTreeSet,
+};
+
+/// Which queue family each kind of work goes to.
+/// Roles share a family when the hardware doesn't expose a dedicated one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFamilies {
+    pub graphics: u32,
+    /// `None` when rendering headless.
+    pub present: Option<u32>,
+    pub compute: u32,
+    pub transfer: u32,
+}
+
+impl QueueFamilies {
+    /// Picks a family for every role, or explains why the device can't be used.
+    pub(crate) fn find(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        present_surface: Option<(&surface::Instance, &vk::SurfaceKHR)>,
+    ) -> Result<Self, String> {
+        let properties =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        let families = || {
+            properties
+                .iter()
+                .enumerate()
+                .filter(|(_, family)| family.queue_count > 0)
+                .map(|(index, family)| (index as u32, family.queue_flags))
+        };
+
+        let graphics = families()
+            .find(|(_, flags)| flags.contains(vk::QueueFlags::GRAPHICS))
+            .map(|(index, _)| index)
+            .ok_or_else(|| String::from("no graphics queue family"))?;
+
+        let present = match present_surface {
+            None => None,
+            Some((surface_functions, surface)) => {
+                let supports_present = |index: u32| unsafe {
+                    surface_functions
+                        .get_physical_device_surface_support(physical_device, index, *surface)
+                        .unwrap_or(false)
+                };
+                // Presenting from the graphics family avoids a second queue and semaphore hop
+                let present = if supports_present(graphics) {
+                    Some(graphics)
+                } else {
+                    families()
+                        .map(|(index, _)| index)
+                        .find(|&index| supports_present(index))
+                };
+                Some(present.ok_or_else(|| String::from("no queue family can present"))?)
+            }
+        };
+
+        // Async compute wants a family without graphics, so it can overlap the frame
+        let compute = families()
+            .find(|(_, flags)| {
+                flags.contains(vk::QueueFlags::COMPUTE) && !flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map_or(graphics, |(index, _)| index);
+
+        // Prefer the copy engine (transfer only), then anything that isn't the graphics family.
+        // Graphics and compute families always support transfers, even without the flag.
+        let transfer = families()
+            .find(|(_, flags)| {
+                flags.contains(vk::QueueFlags::TRANSFER)
+                    && !flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .or_else(|| {
+                families().find(|(_, flags)| {
+                    flags.intersects(vk::QueueFlags::TRANSFER | vk::QueueFlags::COMPUTE)
+                        && !flags.contains(vk::QueueFlags::GRAPHICS)
+                })
+            })
+            .map_or(graphics, |(index, _)| index);
+
+        Ok(Self {
+            graphics,
+            present,
+            compute,
+            transfer,
+        })
+    }
+
+    /// Every distinct family, one queue gets created for each.
+    pub(crate) fn unique(&self) -> Vec<u32> {
+        [self.graphics, self.compute, self.transfer]
+            .into_iter()
+            .chain(self.present)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub fn has_async_compute(&self) -> bool {
+        self.compute != self.graphics
+    }
+
+    pub fn has_dedicated_transfer(&self) -> bool {
+        self.transfer != self.graphics
+    }
+
+    /// How the swapchain images are shared.
+    /// `CONCURRENT` when presenting from another family, so no ownership transfer is needed per frame.
+    pub(crate) fn swapchain_sharing(&self) -> (vk::SharingMode, Vec<u32>) {
+        match self.present {
+            Some(present) if present != self.graphics => {
+                (vk::SharingMode::CONCURRENT, vec![self.graphics, present])
+            }
+            _ => (vk::SharingMode::EXCLUSIVE, Vec::new()),
+        }
+    }
+
+    /// How buffers the CPU writes for both the graphics and the async compute queue are shared.
+    /// `CONCURRENT` with async compute, so they need no ownership transfer every frame.
+    pub(crate) fn compute_sharing(&self) -> (vk::SharingMode, Vec<u32>) {
+        if self.has_async_compute() {
+            (
+                vk::SharingMode::CONCURRENT,
+                vec![self.graphics, self.compute],
+            )
+        } else {
+            (vk::SharingMode::EXCLUSIVE, Vec::new())
+        }
+    }
+}
+
+/// One queue per role, roles that share a family also share the queue.
+/// Shared queues are only ever submitted to from the renderer thread.
+pub struct Queues {
+    pub families: QueueFamilies,
+    pub graphics: vk::Queue,
+    /// `None` when rendering headless.
+    pub present: Option<vk::Queue>,
+    pub compute: vk::Queue,
+    pub transfer: vk::Queue,
+}
+
+impl Queues {
+    /// Fetches the queues created by `DeviceCreateInfo` with [`QueueFamilies::unique`].
+    pub(crate) fn new(device: &Device, families: QueueFamilies, debug_utils: &DebugUtils) -> Self {
+        let queue = |family: u32| unsafe { device.get_device_queue(family, 0) };
+        let queues = Self {
+            families,
+            graphics: queue(families.graphics),
+            present: families.present.map(queue),
+            compute: queue(families.compute),
+            transfer: queue(families.transfer),
+        };
+        // Roles that share a queue share its name, like "graphics/present queue"
+        let roles: Vec<(vk::Queue, &str)> = [
+            (Some(queues.graphics), "graphics"),
+            (queues.present, "present"),
+            (Some(queues.compute), "compute"),
+            (Some(queues.transfer), "transfer"),
+        ]
+        .into_iter()
+        .filter_map(|(queue, role)| Some((queue?, role)))
+        .collect();
+        for (index, &(queue, _)) in roles.iter().enumerate() {
+            if roles[..index].iter().any(|&(earlier, _)| earlier == queue) {
+                continue;
+            }
+            let shared: Vec<&str> = roles
+                .iter()
+                .filter(|&&(other, _)| other == queue)
+                .map(|&(_, role)| role)
+                .collect();
+            debug_utils.name(queue, format_args!("{} queue", shared.join("/")));
+        }
+        queues
+    }
+}
+
+/// Moves a resource created with `EXCLUSIVE` sharing from one queue family to another.
+/// The release barrier is recorded on the source queue, the acquire barrier on the destination
+/// queue, and the destination submission has to wait on a semaphore signaled after the release.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OwnershipTransfer {
+    pub src_family: u32,
+    pub dst_family: u32,
+}
+
+impl OwnershipTransfer {
+    /// `None` when both families are the same and a normal barrier is enough.
+    pub(crate) fn new(src_family: u32, dst_family: u32) -> Option<Self> {
+        (src_family != dst_family).then_some(Self {
+            src_family,
+            dst_family,
+        })
+    }
+
+    pub(crate) fn release_buffer(
+        &self,
+        buffer: vk::Buffer,
+        src_stage: vk::PipelineStageFlags2,
+        src_access: vk::AccessFlags2,
+    ) -> vk::BufferMemoryBarrier2<'static> {
+        // The destination scope is ignored on release, the acquire provides it
+        vk::BufferMemoryBarrier2::default()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
+            .src_queue_family_index(self.src_family)
+            .dst_queue_family_index(self.dst_family)
+            .buffer(buffer)
+            .size(vk::WHOLE_SIZE)
+    }
+
+    pub(crate) fn acquire_buffer(
+        &self,
+        buffer: vk::Buffer,
+        dst_stage: vk::PipelineStageFlags2,
+        dst_access: vk::AccessFlags2,
+    ) -> vk::BufferMemoryBarrier2<'static> {
+        // The source scope is ignored on acquire, the semaphore wait covers it
+        vk::BufferMemoryBarrier2::default()
+            .dst_stage_mask(dst_stage)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(self.src_family)
+            .dst_queue_family_index(self.dst_family)
+            .buffer(buffer)
+            .size(vk::WHOLE_SIZE)
+    }
+
+    /// The layout transition has to be identical in the release and the acquire.
+    pub(crate) fn release_image(
+        &self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        layouts: (vk::ImageLayout, vk::ImageLayout),
+        src_stage: vk::PipelineStageFlags2,
+        src_access: vk::AccessFlags2,
+    ) -> vk::ImageMemoryBarrier2<'static> {
+        vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
+            .old_layout(layouts.0)
+            .new_layout(layouts.1)
+            .src_queue_family_index(self.src_family)
+            .dst_queue_family_index(self.dst_family)
+            .image(image)
+            .subresource_range(range)
+    }
+
+    pub(crate) fn acquire_image(
+        &self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        layouts: (vk::ImageLayout, vk::ImageLayout),
+        dst_stage: vk::PipelineStageFlags2,
+        dst_access: vk::AccessFlags2,
+    ) -> vk::ImageMemoryBarrier2<'static> {
+        vk::ImageMemoryBarrier2::default()
+            .dst_stage_mask(dst_stage)
+            .dst_access_mask(dst_access)
+            .old_layout(layouts.0)
+            .new_layout(layouts.1)
+            .src_queue_family_index(self.src_family)
+            .dst_queue_family_index(self.dst_family)
+            .image(image)
+            .subresource_range(range)
+    }
+}
@@ -0,0 +1,567 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        AntiAliasing, ColorOutput,
+        vk::{
+            bindless::BindlessHeap,
+            culling::{HIZ_FORMAT, InstanceCulling, hiz_extent, hiz_levels},
+            graph::{
+                BufferAccess, BufferHandle, BufferState, ImageAccess, ImageDesc, ImageHandle,
+                ImageState, LoadOp, PassContext, RenderGraph,
+            },
+            lights::ClusteredLights,
+            mesh::{DEPTH_FORMAT, MeshRenderer},
+            pipeline::PipelineCache,
+            post::{
+                BLOOM_LEVELS, HDR_FORMAT, LDR_FORMAT, PostProcess, SMAA_EDGES_FORMAT,
+                SMAA_WEIGHTS_FORMAT, bloom_extent,
+            },
+            shader::ShaderLibrary,
+            shadows::Shadows,
+            texture::Textures,
+        },
+    },
+    ash::vk,
+};
+
+// The color the HDR image is cleared to every frame.
+const CLEAR_COLOR: [f32; 4] = [0.02, 0.02, 0.03, 1.0];
+
+/// Everything the passes of a frame draw with, handed to them while the render graph executes.
+/// Fields drop in order, so what registers descriptors or builds pipelines goes before the heap.
+pub(crate) struct Renderer {
+    pub(crate) post: PostProcess,
+    // Without indirect count draws every instance is drawn every frame
+    pub(crate) culling: Option<InstanceCulling>,
+    pub(crate) meshes: MeshRenderer,
+    pub(crate) shadows: Shadows,
+    pub(crate) lights: ClusteredLights,
+    pub(crate) textures: Textures,
+    pub(crate) pipelines: PipelineCache,
+    pub(crate) shaders: ShaderLibrary,
+    // Every shader sees this one descriptor set
+    pub(crate) bindless: BindlessHeap,
+}
+
+impl Renderer {
+    /// Bins the lights into the clusters of `slot`, in the frame's graph or on the async compute
+    /// queue, where the clusters are released to the graphics family afterwards.
+    /// The scene constants of `slot` must be written, which [`Renderer::frame_graph`] does.
+    pub(crate) fn cull_lights(&mut self, context: &PassContext, slot: usize) {
+        let Renderer {
+            bindless,
+            shaders,
+            pipelines,
+            lights,
+            meshes,
+            ..
+        } = self;
+        let scene = meshes.scene_buffer(slot).index;
+        lights.cull(context, pipelines, shaders, bindless, scene);
+        lights.release(context, slot);
+    }
+
+    /// The passes of the frame in `slot`, shading into an HDR image and post-processing it into
+    /// `target`, which is left in `final_state` and shown as `output`. Also returns the handle of
+    /// `target`, for passes that come after.
+    /// The last frame that used `slot` must be done.
+    pub(crate) fn frame_graph(
+        &mut self,
+        (image, view, desc): (vk::Image, vk::ImageView, ImageDesc),
+        output: ColorOutput,
+        initial: ImageState,
+        final_state: ImageState,
+        slot: usize,
+    ) -> (RenderGraph<'static, Renderer>, ImageHandle) {
+        let camera = self.meshes.view(desc.extent);
+        self.shadows.update(slot, &camera);
+        self.meshes.update(
+            slot,
+            &camera,
+            desc.extent,
+            self.lights.header(slot),
+            self.shadows.buffer(slot).index,
+        );
+        let settings = self.post.begin(&mut self.bindless, slot, output);
+        let culled = self.culling.as_mut().and_then(|culling| {
+            culling.begin(&mut self.bindless, slot);
+            Some((culling.draw_buffer(slot), culling.visibility_buffer()?))
+        });
+        let mut graph = RenderGraph::new();
+        let target = graph.import_image(image, view, desc, initial, final_state);
+        let hdr = graph.create_image("hdr", ImageDesc::new(HDR_FORMAT, desc.extent));
+        let atlas = self.shadows.atlas().map(|(image, view, desc, initial)| {
+            graph.import_image(image, view, desc, initial, Shadows::sampled_state())
+        });
+        let depth = graph.create_image("depth", ImageDesc::new(DEPTH_FORMAT, desc.extent));
+        // The last frame reading this slot's clusters is done
+        let clusters = graph.import_buffer(self.lights.cluster_buffer(slot), BufferState::NONE);
+        if self.lights.on_async_compute() {
+            // Binned by `cull_lights` on the compute queue, the frame's submission waits for it
+            graph
+                .add_pass("acquire light clusters")
+                .buffer(
+                    clusters,
+                    BufferAccess::StorageWrite(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                )
+                .execute(move |context, renderer: &mut Renderer| {
+                    renderer.lights.acquire(context, slot);
+                });
+        } else {
+            graph
+                .add_pass("light culling")
+                .buffer(
+                    clusters,
+                    BufferAccess::StorageWrite(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                )
+                .execute(move |context, renderer: &mut Renderer| {
+                    renderer.cull_lights(context, slot);
+                });
+        }
+        if let Some(atlas) = atlas {
+            graph
+                .add_pass("shadows")
+                .depth_attachment(
+                    atlas,
+                    LoadOp::Clear(vk::ClearValue {
+                        depth_stencil: vk::ClearDepthStencilValue {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
+                    }),
+                )
+                .execute(move |context, renderer: &mut Renderer| {
+                    let Renderer {
+                        bindless,
+                        shaders,
+                        pipelines,
+                        shadows,
+                        meshes,
+                        ..
+                    } = renderer;
+                    shadows.render(context, pipelines, shaders, bindless, meshes, slot);
+                });
+        }
+        // The last frame reading this slot's draw lists is done
+        let culled = culled.map(|(draws, (visibility, initial))| {
+            (
+                graph.import_buffer(draws, BufferState::NONE),
+                graph.import_buffer(visibility, initial),
+            )
+        });
+        if let Some((draws, visibility)) = culled {
+            graph
+                .add_pass("clear draw counts")
+                .buffer(draws, BufferAccess::TransferDst)
+                .execute(move |context, renderer: &mut Renderer| {
+                    if let Some(culling) = &renderer.culling {
+                        culling.clear(context, slot);
+                    }
+                });
+            // What was visible last frame and is still in view
+            graph
+                .add_pass("early culling")
+                .buffer(
+                    visibility,
+                    BufferAccess::StorageRead(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                )
+                .buffer(
+                    draws,
+                    BufferAccess::StorageWrite(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                )
+                .execute(move |context, renderer: &mut Renderer| {
+                    let Renderer {
+                        bindless,
+                        shaders,
+                        pipelines,
+                        meshes,
+                        culling,
+                        ..
+                    } = renderer;
+                    if let Some(culling) = culling {
+                        culling.cull(context, pipelines, shaders, bindless, meshes, (0, &[]));
+                    }
+                });
+        }
+        let inputs = (clusters, atlas, culled.map(|(draws, _)| draws));
+        forward_pass(&mut graph, "forward", (hdr, depth, true), inputs, (slot, 0));
+
+        // The depth of what was drawn so far, halved down to a texel, then everything else is
+        // tested against it and what turns out visible is drawn on top
+        if let Some((draws, visibility)) = culled {
+            let levels = hiz_levels(desc.extent);
+            let mut pyramid = Vec::with_capacity(levels);
+            let mut source = depth;
+            for level in 0..levels {
+                let extent = hiz_extent(desc.extent, level);
+                let image = graph.create_image("hi-z", ImageDesc::new(HIZ_FORMAT, extent));
+                graph
+                    .add_pass(format!("hi-z {level}"))
+                    .image(
+                        source,
+                        ImageAccess::Sampled(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                    )
+                    .image(
+                        image,
+                        ImageAccess::StorageWrite(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                    )
+                    .execute(move |context, renderer: &mut Renderer| {
+                        let Renderer {
+                            bindless,
+                            shaders,
+                            pipelines,
+                            culling,
+                            ..
+                        } = renderer;
+                        if let Some(culling) = culling {
+                            let views = (context.view(source), context.view(image));
+                            culling.build_hiz(context, pipelines, shaders, bindless, views, extent);
+                        }
+                    });
+                pyramid.push(image);
+                source = image;
+            }
+            let mut late = graph
+                .add_pass("late culling")
+                .buffer(
+                    visibility,
+                    BufferAccess::StorageWrite(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                )
+                .buffer(
+                    draws,
+                    BufferAccess::StorageWrite(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                );
+            for &level in &pyramid {
+                late = late.image(
+                    level,
+                    ImageAccess::Sampled(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                );
+            }
+            late.execute(move |context, renderer: &mut Renderer| {
+                let Renderer {
+                    bindless,
+                    shaders,
+                    pipelines,
+                    meshes,
+                    culling,
+                    ..
+                } = renderer;
+                if let Some(culling) = culling {
+                    let hiz: Vec<vk::ImageView> =
+                        pyramid.iter().map(|&x| context.view(x)).collect();
+                    culling.cull(context, pipelines, shaders, bindless, meshes, (1, &hiz));
+                }
+            });
+            let inputs = (clusters, atlas, Some(draws));
+            forward_pass(
+                &mut graph,
+                "forward late",
+                (hdr, depth, false),
+                inputs,
+                (slot, 1),
+            );
+        }
+
+        // Exposure is measured before bloom spreads the highlights around
+        let exposure = settings
+            .auto_exposure
+            .then(|| self.post.exposure_buffer())
+            .flatten()
+            .map(|(buffer, initial)| graph.import_buffer(buffer, initial));
+        if let Some(exposure) = exposure {
+            graph
+                .add_pass("luminance histogram")
+                .image(
+                    hdr,
+                    ImageAccess::Sampled(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                )
+                .buffer(
+                    exposure,
+                    BufferAccess::StorageWrite(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                )
+                .execute(move |context, renderer: &mut Renderer| {
+                    let Renderer {
+                        bindless,
+                        shaders,
+                        pipelines,
+                        post,
+                        ..
+                    } = renderer;
+                    let hdr = (context.view(hdr), desc.extent);
+                    post.histogram(context, pipelines, shaders, bindless, hdr);
+                });
+            graph
+                .add_pass("exposure adaptation")
+                .buffer(
+                    exposure,
+                    BufferAccess::StorageWrite(vk::PipelineStageFlags2::COMPUTE_SHADER),
+                )
+                .execute(move |context, renderer: &mut Renderer| {
+                    let Renderer {
+                        bindless,
+                        shaders,
+                        pipelines,
+                        post,
+                        ..
+                    } = renderer;
+                    post.adapt(context, pipelines, shaders, bindless, desc.extent);
+                });
+        }
+
+        // Down the chain level by level, then back up adding every level onto the one above
+        let bloom = settings.bloom.then(|| {
+            let mut levels = Vec::with_capacity(BLOOM_LEVELS);
+            let mut source = (hdr, desc.extent);
+            for level in 0..BLOOM_LEVELS {
+                let extent = bloom_extent(desc.extent, level);
+                let image = graph.create_image("bloom", ImageDesc::new(HDR_FORMAT, extent));
+                let (input, input_extent) = source;
+                graph
+                    .add_pass(format!("bloom downsample {level}"))
+                    .color_attachment(image, LoadOp::DontCare)
+                    .image(
+                        input,
+                        ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+                    )
+                    .execute(move |context, renderer: &mut Renderer| {
+                        let Renderer {
+                            bindless,
+                            shaders,
+                            pipelines,
+                            post,
+                            ..
+                        } = renderer;
+                        let input = (context.view(input), input_extent);
+                        post.bloom_down(context, pipelines, shaders, bindless, input, level == 0);
+                    });
+                levels.push((image, extent));
+                source = (image, extent);
+            }
+            for level in (0..BLOOM_LEVELS - 1).rev() {
+                let (image, _) = levels[level];
+                let (input, input_extent) = levels[level + 1];
+                graph
+                    .add_pass(format!("bloom upsample {level}"))
+                    .color_attachment(image, LoadOp::Load)
+                    .image(
+                        input,
+                        ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+                    )
+                    .execute(move |context, renderer: &mut Renderer| {
+                        let Renderer {
+                            bindless,
+                            shaders,
+                            pipelines,
+                            post,
+                            ..
+                        } = renderer;
+                        let input = (context.view(input), input_extent);
+                        post.bloom_up(context, pipelines, shaders, bindless, input);
+                    });
+            }
+            levels[0].0
+        });
+
+        // Anti-aliasing works on tonemapped colors, so tonemapping goes through an LDR image first.
+        // Tonemapped HDR still goes past white, so it stays in floats until it's encoded
+        let ldr_format = match output {
+            ColorOutput::Sdr => LDR_FORMAT,
+            ColorOutput::Hdr10 | ColorOutput::ScRgb => HDR_FORMAT,
+        };
+        let ldr = (settings.anti_aliasing != AntiAliasing::None)
+            .then(|| graph.create_image("ldr", ImageDesc::new(ldr_format, desc.extent)));
+        let (tonemapped, tonemapped_format) = match ldr {
+            Some(ldr) => (ldr, ldr_format),
+            None => (target, desc.format),
+        };
+        let mut tonemap = graph
+            .add_pass("tonemap")
+            .color_attachment(tonemapped, LoadOp::DontCare)
+            .image(
+                hdr,
+                ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+            );
+        if let Some(bloom) = bloom {
+            tonemap = tonemap.image(
+                bloom,
+                ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+            );
+        }
+        if let Some(exposure) = exposure {
+            tonemap = tonemap.buffer(
+                exposure,
+                BufferAccess::StorageRead(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+            );
+        }
+        tonemap.execute(move |context, renderer: &mut Renderer| {
+            let Renderer {
+                bindless,
+                shaders,
+                pipelines,
+                post,
+                ..
+            } = renderer;
+            let inputs = (context.view(hdr), bloom.map(|x| context.view(x)));
+            let output = (tonemapped_format, ldr.is_none());
+            post.tonemap(context, pipelines, shaders, bindless, inputs, output);
+        });
+        match (ldr, settings.anti_aliasing) {
+            (Some(ldr), AntiAliasing::Fxaa) => {
+                graph
+                    .add_pass("fxaa")
+                    .color_attachment(target, LoadOp::DontCare)
+                    .image(
+                        ldr,
+                        ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+                    )
+                    .execute(move |context, renderer: &mut Renderer| {
+                        let Renderer {
+                            bindless,
+                            shaders,
+                            pipelines,
+                            post,
+                            ..
+                        } = renderer;
+                        let input = (context.view(ldr), desc.extent);
+                        post.fxaa(context, pipelines, shaders, bindless, input, desc.format);
+                    });
+            }
+            (Some(ldr), AntiAliasing::Smaa) => {
+                let edges = graph
+                    .create_image("smaa edges", ImageDesc::new(SMAA_EDGES_FORMAT, desc.extent));
+                let weights = graph.create_image(
+                    "smaa weights",
+                    ImageDesc::new(SMAA_WEIGHTS_FORMAT, desc.extent),
+                );
+                graph
+                    .add_pass("smaa edges")
+                    .color_attachment(edges, LoadOp::DontCare)
+                    .image(
+                        ldr,
+                        ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+                    )
+                    .execute(move |context, renderer: &mut Renderer| {
+                        let Renderer {
+                            bindless,
+                            shaders,
+                            pipelines,
+                            post,
+                            ..
+                        } = renderer;
+                        let input = context.view(ldr);
+                        post.smaa_edges(context, pipelines, shaders, bindless, input);
+                    });
+                graph
+                    .add_pass("smaa weights")
+                    .color_attachment(weights, LoadOp::DontCare)
+                    .image(
+                        edges,
+                        ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+                    )
+                    .execute(move |context, renderer: &mut Renderer| {
+                        let Renderer {
+                            bindless,
+                            shaders,
+                            pipelines,
+                            post,
+                            ..
+                        } = renderer;
+                        let input = context.view(edges);
+                        post.smaa_weights(context, pipelines, shaders, bindless, input);
+                    });
+                graph
+                    .add_pass("smaa blend")
+                    .color_attachment(target, LoadOp::DontCare)
+                    .image(
+                        ldr,
+                        ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+                    )
+                    .image(
+                        weights,
+                        ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+                    )
+                    .execute(move |context, renderer: &mut Renderer| {
+                        let Renderer {
+                            bindless,
+                            shaders,
+                            pipelines,
+                            post,
+                            ..
+                        } = renderer;
+                        let input = (context.view(ldr), context.view(weights), desc.extent);
+                        post.smaa_blend(context, pipelines, shaders, bindless, input, desc.format);
+                    });
+            }
+            _ => {}
+        }
+        (graph, target)
+    }
+}
+
+/// Adds a pass shading the meshes into `hdr` and `depth`, cleared first when `clear`. With `draws`
+/// it draws what culling `phase` wrote there, otherwise every instance.
+fn forward_pass(
+    graph: &mut RenderGraph<'static, Renderer>,
+    name: &str,
+    (hdr, depth, clear): (ImageHandle, ImageHandle, bool),
+    (clusters, atlas, draws): (BufferHandle, Option<ImageHandle>, Option<BufferHandle>),
+    (slot, phase): (usize, usize),
+) {
+    let (color_load, depth_load) = if clear {
+        (
+            LoadOp::Clear(vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: CLEAR_COLOR,
+                },
+            }),
+            LoadOp::Clear(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            }),
+        )
+    } else {
+        (LoadOp::Load, LoadOp::Load)
+    };
+    let mut pass = graph
+        .add_pass(name)
+        .color_attachment(hdr, color_load)
+        .buffer(
+            clusters,
+            BufferAccess::StorageRead(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+        )
+        .depth_attachment(depth, depth_load);
+    if let Some(atlas) = atlas {
+        pass = pass.image(
+            atlas,
+            ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+        );
+    }
+    if let Some(draws) = draws {
+        pass = pass.buffer(draws, BufferAccess::Indirect);
+    }
+    pass.execute(move |context, renderer: &mut Renderer| {
+        let Renderer {
+            bindless,
+            shaders,
+            pipelines,
+            meshes,
+            culling,
+            ..
+        } = renderer;
+        let list = culling
+            .as_ref()
+            .filter(|_| draws.is_some())
+            .map(|culling| culling.draw_list(slot, phase));
+        meshes.draw(
+            context,
+            pipelines,
+            shaders,
+            bindless,
+            HDR_FORMAT,
+            (slot, list),
+        );
+    });
+}
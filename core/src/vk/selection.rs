@@ -0,0 +1,369 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        DeviceSelector,
+        vk::{
+            capabilities::DeviceCapabilities,
+            error::{RendererError, vk_report},
+            queues::QueueFamilies,
+        },
+    },
+    ash::{Instance, khr::surface, vk},
+    error_stack::{Report, ResultExt},
+    std::fmt,
+};
+
+// Device types weigh the most, a discrete GPU should win over any integrated one
+const DISCRETE_SCORE: u64 = 100_000;
+const INTEGRATED_SCORE: u64 = 50_000;
+const VIRTUAL_SCORE: u64 = 20_000;
+const CPU_SCORE: u64 = 1_000;
+// Every supported optional extension or feature is worth this much
+const FEATURE_SCORE: u64 = 500;
+// One point for every this many bytes of device local memory
+const VRAM_SCORE_GRANULARITY: u64 = 16 * 1024 * 1024;
+
+/// Everything learned about a device while deciding whether to use it.
+#[derive(Clone)]
+pub struct DeviceCandidate {
+    /// Position in the order Vulkan enumerated the devices.
+    pub index: usize,
+    pub name: String,
+    pub uuid: [u8; 16],
+    pub device_type: vk::PhysicalDeviceType,
+    /// Device local memory in bytes, the `VK_EXT_memory_budget` budget when available.
+    pub vram: u64,
+    pub score: u64,
+    /// Why the device can't be used, empty if it is suitable.
+    pub rejections: Vec<String>,
+    pub capabilities: DeviceCapabilities,
+    /// `None` if the device lacks a graphics or present capable family.
+    pub queue_families: Option<QueueFamilies>,
+    handle: vk::PhysicalDevice,
+}
+
+impl fmt::Display for DeviceCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {} ({}, {} MiB, uuid {})",
+            self.index,
+            self.name,
+            device_type_name(self.device_type),
+            self.vram / (1024 * 1024),
+            format_uuid(&self.uuid),
+        )?;
+        if self.rejections.is_empty() {
+            write!(f, ": suitable, score {}", self.score)
+        } else {
+            write!(f, ": rejected, {}", self.rejections.join("; "))
+        }
+    }
+}
+
+// ash only implements Debug for its types with the debug feature
+impl fmt::Debug for DeviceCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Returned when no device can be used, lists every device and why it was rejected.
+#[derive(Debug, Clone)]
+pub struct DeviceSelectionError {
+    pub candidates: Vec<DeviceCandidate>,
+    /// The selector that narrowed down the choice, if one was set.
+    pub selector: Option<DeviceSelector>,
+}
+
+impl fmt::Display for DeviceSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.candidates.is_empty() {
+            return write!(f, "Failed to find GPUs with Vulkan Support");
+        }
+        write!(f, "No suitable GPU found")?;
+        if let Some(selector) = &self.selector {
+            write!(f, " (requested {selector})")?;
+        }
+        for candidate in &self.candidates {
+            write!(f, "\n  {candidate}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DeviceSelectionError {}
+
+impl DeviceSelectionError {
+    /// Every device becomes its own attachment, with the reasons it was rejected.
+    pub(crate) fn report(self) -> Report<RendererError> {
+        let mut report = Report::new(RendererError::NoSuitableDevice);
+        if self.candidates.is_empty() {
+            report = report.attach("No GPU with Vulkan support was found");
+        }
+        if let Some(selector) = self.selector {
+            report = report.attach(format!("requested {selector}"));
+        }
+        for candidate in self.candidates {
+            report = report.attach(candidate.to_string());
+        }
+        report
+    }
+}
+
+/// Scores every device and picks the best suitable one.
+/// If `selector` is set, only the device it matches is considered.
+pub(crate) fn select_physical_device(
+    instance: &Instance,
+    present_surface: Option<(&surface::Instance, &vk::SurfaceKHR)>,
+    selector: Option<&DeviceSelector>,
+) -> Result<(vk::PhysicalDevice, DeviceCandidate), Report<RendererError>> {
+    // A failing loader or driver isn't the same as having no suitable device
+    let devices = unsafe { instance.enumerate_physical_devices() }
+        .map_err(|x| vk_report(x, RendererError::Instance))
+        .attach("enumerating the physical devices")?;
+    let candidates = devices
+        .into_iter()
+        .enumerate()
+        .map(|(index, device)| evaluate(instance, device, index, present_surface))
+        .collect();
+    let best = rank(candidates, selector).map_err(DeviceSelectionError::report)?;
+    Ok((best.handle, best))
+}
+
+/// The best suitable candidate, after rejecting the ones `selector` doesn't match.
+fn rank(
+    mut candidates: Vec<DeviceCandidate>,
+    selector: Option<&DeviceSelector>,
+) -> Result<DeviceCandidate, DeviceSelectionError> {
+    for candidate in &mut candidates {
+        if selector.is_some_and(|selector| !matches_selector(selector, candidate)) {
+            candidate
+                .rejections
+                .push(String::from("not the requested device"));
+        }
+    }
+
+    // Highest score first, the enumeration order breaks ties
+    candidates.sort_by(|a, b| b.score.cmp(&a.score).then(a.index.cmp(&b.index)));
+    match candidates.iter().position(|c| c.rejections.is_empty()) {
+        Some(best) => Ok(candidates.swap_remove(best)),
+        None => {
+            candidates.sort_by_key(|c| c.index);
+            Err(DeviceSelectionError {
+                candidates,
+                selector: selector.cloned(),
+            })
+        }
+    }
+}
+
+fn evaluate(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    index: usize,
+    present_surface: Option<(&surface::Instance, &vk::SurfaceKHR)>,
+) -> DeviceCandidate {
+    let capabilities = DeviceCapabilities::probe(instance, device);
+    let mut rejections = capabilities.missing_required(present_surface.is_some());
+    unsafe {
+        let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+        let mut properties2 =
+            vk::PhysicalDeviceProperties2::default().push_next(&mut id_properties);
+        instance.get_physical_device_properties2(device, &mut properties2);
+        let properties = properties2.properties;
+        let name = properties
+            .device_name_as_c_str()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let queue_families = QueueFamilies::find(instance, device, present_surface)
+            .inspect_err(|reason| rejections.push(reason.clone()))
+            .ok();
+
+        let vram = device_local_memory(instance, device, capabilities.memory_budget);
+        let score = score(
+            properties.device_type,
+            vram,
+            capabilities.optional_feature_count(),
+        );
+
+        DeviceCandidate {
+            index,
+            name,
+            uuid: id_properties.device_uuid,
+            device_type: properties.device_type,
+            vram,
+            score,
+            rejections,
+            capabilities,
+            queue_families,
+            handle: device,
+        }
+    }
+}
+
+fn score(device_type: vk::PhysicalDeviceType, vram: u64, optional_features: u64) -> u64 {
+    let type_score = match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => DISCRETE_SCORE,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => INTEGRATED_SCORE,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => VIRTUAL_SCORE,
+        vk::PhysicalDeviceType::CPU => CPU_SCORE,
+        _ => 0,
+    };
+    type_score + vram / VRAM_SCORE_GRANULARITY + optional_features * FEATURE_SCORE
+}
+
+/// Sums up the device local heaps, preferring what the driver says we may actually use.
+fn device_local_memory(instance: &Instance, device: vk::PhysicalDevice, has_budget: bool) -> u64 {
+    let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties2 = vk::PhysicalDeviceMemoryProperties2::default();
+    if has_budget {
+        properties2 = properties2.push_next(&mut budget);
+    }
+    unsafe { instance.get_physical_device_memory_properties2(device, &mut properties2) };
+    let properties = properties2.memory_properties;
+    (0..properties.memory_heap_count as usize)
+        .filter(|&heap| {
+            properties.memory_heaps[heap]
+                .flags
+                .contains(vk::MemoryHeapFlags::DEVICE_LOCAL)
+        })
+        .map(|heap| {
+            if has_budget {
+                budget.heap_budget[heap]
+            } else {
+                properties.memory_heaps[heap].size
+            }
+        })
+        .sum()
+}
+
+fn matches_selector(selector: &DeviceSelector, candidate: &DeviceCandidate) -> bool {
+    match selector {
+        DeviceSelector::Name(name) => candidate.name.to_lowercase().contains(&name.to_lowercase()),
+        DeviceSelector::Index(index) => candidate.index == *index,
+        DeviceSelector::Uuid(uuid) => candidate.uuid == *uuid,
+    }
+}
+
+pub(crate) fn format_uuid(uuid: &[u8; 16]) -> String {
+    uuid.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub(crate) fn device_type_name(device_type: vk::PhysicalDeviceType) -> &'static str {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => "discrete",
+        vk::PhysicalDeviceType::INTEGRATED_GPU => "integrated",
+        vk::PhysicalDeviceType::VIRTUAL_GPU => "virtual",
+        vk::PhysicalDeviceType::CPU => "cpu",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn candidate(
+        index: usize,
+        name: &str,
+        device_type: vk::PhysicalDeviceType,
+        vram: u64,
+    ) -> DeviceCandidate {
+        DeviceCandidate {
+            index,
+            name: name.to_string(),
+            uuid: [index as u8; 16],
+            device_type,
+            vram,
+            score: score(device_type, vram, 0),
+            rejections: Vec::new(),
+            capabilities: DeviceCapabilities::default(),
+            queue_families: None,
+            handle: vk::PhysicalDevice::null(),
+        }
+    }
+
+    #[test]
+    fn device_type_outweighs_memory_and_features() {
+        let discrete = score(vk::PhysicalDeviceType::DISCRETE_GPU, 2 * GIB, 0);
+        let integrated = score(vk::PhysicalDeviceType::INTEGRATED_GPU, 32 * GIB, 20);
+        let cpu = score(vk::PhysicalDeviceType::CPU, 64 * GIB, 20);
+        assert!(discrete > integrated);
+        assert!(integrated > cpu);
+    }
+
+    #[test]
+    fn memory_and_features_rank_devices_of_a_type() {
+        let gpu = vk::PhysicalDeviceType::DISCRETE_GPU;
+        assert!(score(gpu, 8 * GIB, 0) > score(gpu, 4 * GIB, 0));
+        assert!(score(gpu, 4 * GIB, 3) > score(gpu, 4 * GIB, 2));
+    }
+
+    #[test]
+    fn highest_score_wins_and_ties_go_to_enumeration_order() {
+        let candidates = vec![
+            candidate(0, "iGPU", vk::PhysicalDeviceType::INTEGRATED_GPU, GIB),
+            candidate(1, "first", vk::PhysicalDeviceType::DISCRETE_GPU, 8 * GIB),
+            candidate(2, "second", vk::PhysicalDeviceType::DISCRETE_GPU, 8 * GIB),
+        ];
+        assert_eq!(rank(candidates, None).unwrap().name, "first");
+    }
+
+    #[test]
+    fn rejected_devices_are_skipped() {
+        let mut broken = candidate(0, "broken", vk::PhysicalDeviceType::DISCRETE_GPU, 8 * GIB);
+        broken
+            .rejections
+            .push(String::from("missing dynamic rendering"));
+        let candidates = vec![
+            broken,
+            candidate(1, "llvmpipe", vk::PhysicalDeviceType::CPU, GIB),
+        ];
+        assert_eq!(rank(candidates, None).unwrap().name, "llvmpipe");
+    }
+
+    #[test]
+    fn selector_overrides_the_score() {
+        let candidates = || {
+            vec![
+                candidate(
+                    0,
+                    "Radeon RX",
+                    vk::PhysicalDeviceType::DISCRETE_GPU,
+                    8 * GIB,
+                ),
+                candidate(1, "llvmpipe (LLVM 17)", vk::PhysicalDeviceType::CPU, GIB),
+            ]
+        };
+        let by_name = DeviceSelector::Name(String::from("LLVMPIPE"));
+        assert_eq!(rank(candidates(), Some(&by_name)).unwrap().index, 1);
+        let by_uuid = DeviceSelector::Uuid([1; 16]);
+        assert_eq!(rank(candidates(), Some(&by_uuid)).unwrap().index, 1);
+        let by_index = DeviceSelector::Index(1);
+        assert_eq!(rank(candidates(), Some(&by_index)).unwrap().index, 1);
+    }
+
+    #[test]
+    fn unmatched_selector_lists_every_device_in_order() {
+        let candidates = vec![
+            candidate(1, "b", vk::PhysicalDeviceType::DISCRETE_GPU, 8 * GIB),
+            candidate(0, "a", vk::PhysicalDeviceType::INTEGRATED_GPU, GIB),
+        ];
+        let selector = DeviceSelector::Index(7);
+        let error = rank(candidates, Some(&selector)).unwrap_err();
+        assert_eq!(error.selector, Some(selector));
+        let indices: Vec<usize> = error.candidates.iter().map(|c| c.index).collect();
+        assert_eq!(indices, [0, 1]);
+        assert!(
+            error
+                .candidates
+                .iter()
+                .all(|c| { c.rejections == ["not the requested device"] })
+        );
+    }
+}
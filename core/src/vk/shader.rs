@@ -0,0 +1,669 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::vk::bindless::{DescriptorKind, PUSH_CONSTANT_SIZE},
+    ash::{Device, vk},
+    error_stack::{Report, ResultExt},
+    std::{
+        collections::HashMap,
+        fmt,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::{Duration, Instant, SystemTime},
+    },
+};
+
+// How often the shader files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Compiled from `core/shaders` by the build script, used when the asset directory has no file of the same name
+const BUILTIN_SHADERS: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/builtin_shaders.rs"));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderError {
+    /// The file couldn't be read.
+    Load,
+    /// The bytes aren't valid SPIR-V.
+    InvalidSpirv,
+    /// The shader declares resources the bindless layout doesn't have.
+    LayoutMismatch,
+    /// The driver refused to create the module.
+    Module,
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Load => "Failed to load the shader",
+            Self::InvalidSpirv => "The shader is not valid SPIR-V",
+            Self::LayoutMismatch => "The shader doesn't match the bindless layout",
+            Self::Module => "Failed to create the shader module",
+        })
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// A shader entry point and the stage it runs in.
+#[derive(Clone)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+}
+
+/// A descriptor a shader declares.
+#[derive(Clone)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// `None` for runtime sized arrays.
+    pub count: Option<u32>,
+}
+
+/// What a SPIR-V module declares, read straight from its instructions.
+#[derive(Clone, Default)]
+pub struct ShaderReflection {
+    pub entry_points: Vec<EntryPoint>,
+    pub bindings: Vec<DescriptorBinding>,
+    /// Bytes of push constants used, 0 if the shader has none.
+    pub push_constant_size: u32,
+}
+
+// The handful of opcodes, decorations and storage classes the reflection needs
+mod op {
+    pub const ENTRY_POINT: u32 = 15;
+    pub const TYPE_INT: u32 = 21;
+    pub const TYPE_FLOAT: u32 = 22;
+    pub const TYPE_VECTOR: u32 = 23;
+    pub const TYPE_MATRIX: u32 = 24;
+    pub const TYPE_IMAGE: u32 = 25;
+    pub const TYPE_SAMPLER: u32 = 26;
+    pub const TYPE_SAMPLED_IMAGE: u32 = 27;
+    pub const TYPE_ARRAY: u32 = 28;
+    pub const TYPE_RUNTIME_ARRAY: u32 = 29;
+    pub const TYPE_STRUCT: u32 = 30;
+    pub const TYPE_POINTER: u32 = 32;
+    pub const CONSTANT: u32 = 43;
+    pub const VARIABLE: u32 = 59;
+    pub const DECORATE: u32 = 71;
+    pub const MEMBER_DECORATE: u32 = 72;
+    pub const TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+    pub const DECORATION_BUFFER_BLOCK: u32 = 3;
+    pub const DECORATION_ARRAY_STRIDE: u32 = 6;
+    pub const DECORATION_BINDING: u32 = 33;
+    pub const DECORATION_DESCRIPTOR_SET: u32 = 34;
+    pub const DECORATION_OFFSET: u32 = 35;
+
+    pub const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+    pub const STORAGE_UNIFORM: u32 = 2;
+    pub const STORAGE_PUSH_CONSTANT: u32 = 9;
+    pub const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+    pub const DIM_BUFFER: u32 = 5;
+    pub const DIM_SUBPASS_DATA: u32 = 6;
+}
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+#[derive(Clone)]
+enum SpirvType {
+    Scalar(u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    // Dim, sampled (1 = with a sampler, 2 = storage)
+    Image(u32, u32),
+    Sampler,
+    SampledImage,
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct(Vec<u32>),
+    Pointer(u32),
+    AccelerationStructure,
+}
+
+impl ShaderReflection {
+    /// Reads entry points, descriptors and push constants out of a SPIR-V module.
+    pub fn parse(words: &[u32]) -> Result<Self, Report<ShaderError>> {
+        if words.len() < 5 || words[0] != SPIRV_MAGIC {
+            return Err(Report::new(ShaderError::InvalidSpirv).attach("Missing the SPIR-V header"));
+        }
+        let mut reflection = Self::default();
+        let mut types: HashMap<u32, SpirvType> = HashMap::new();
+        let mut constants: HashMap<u32, u32> = HashMap::new();
+        let mut sets: HashMap<u32, u32> = HashMap::new();
+        let mut bindings: HashMap<u32, u32> = HashMap::new();
+        let mut buffer_blocks: Vec<u32> = Vec::new();
+        let mut array_strides: HashMap<u32, u32> = HashMap::new();
+        let mut member_offsets: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut variables: Vec<(u32, u32, u32)> = Vec::new();
+
+        let mut cursor = 5;
+        while cursor < words.len() {
+            let word_count = (words[cursor] >> 16) as usize;
+            let opcode = words[cursor] & 0xFFFF;
+            if word_count == 0 || cursor + word_count > words.len() {
+                return Err(Report::new(ShaderError::InvalidSpirv)
+                    .attach(format!("Truncated instruction at word {cursor}")));
+            }
+            let operands = &words[cursor + 1..cursor + word_count];
+            cursor += word_count;
+            match (opcode, operands) {
+                (op::ENTRY_POINT, [model, _function, name @ ..]) => {
+                    if let Some(stage) = execution_model_stage(*model) {
+                        reflection.entry_points.push(EntryPoint {
+                            name: literal_string(name),
+                            stage,
+                        });
+                    }
+                }
+                (op::DECORATE, [target, decoration, rest @ ..]) => match (*decoration, rest) {
+                    (op::DECORATION_DESCRIPTOR_SET, [set, ..]) => {
+                        sets.insert(*target, *set);
+                    }
+                    (op::DECORATION_BINDING, [binding, ..]) => {
+                        bindings.insert(*target, *binding);
+                    }
+                    (op::DECORATION_BUFFER_BLOCK, _) => buffer_blocks.push(*target),
+                    (op::DECORATION_ARRAY_STRIDE, [stride, ..]) => {
+                        array_strides.insert(*target, *stride);
+                    }
+                    _ => (),
+                },
+                (op::MEMBER_DECORATE, [target, member, op::DECORATION_OFFSET, offset, ..]) => {
+                    member_offsets.insert((*target, *member), *offset);
+                }
+                (op::TYPE_INT | op::TYPE_FLOAT, [id, width, ..]) => {
+                    types.insert(*id, SpirvType::Scalar(width / 8));
+                }
+                (op::TYPE_VECTOR, [id, component, count]) => {
+                    types.insert(*id, SpirvType::Vector(*component, *count));
+                }
+                (op::TYPE_MATRIX, [id, column, count]) => {
+                    types.insert(*id, SpirvType::Matrix(*column, *count));
+                }
+                (op::TYPE_IMAGE, [id, _sampled_type, dim, _depth, _arrayed, _ms, sampled, ..]) => {
+                    types.insert(*id, SpirvType::Image(*dim, *sampled));
+                }
+                (op::TYPE_SAMPLER, [id]) => {
+                    types.insert(*id, SpirvType::Sampler);
+                }
+                (op::TYPE_SAMPLED_IMAGE, [id, _]) => {
+                    types.insert(*id, SpirvType::SampledImage);
+                }
+                (op::TYPE_ARRAY, [id, element, length]) => {
+                    types.insert(*id, SpirvType::Array(*element, *length));
+                }
+                (op::TYPE_RUNTIME_ARRAY, [id, element]) => {
+                    types.insert(*id, SpirvType::RuntimeArray(*element));
+                }
+                (op::TYPE_STRUCT, [id, members @ ..]) => {
+                    types.insert(*id, SpirvType::Struct(members.to_vec()));
+                }
+                (op::TYPE_POINTER, [id, _storage_class, pointee]) => {
+                    types.insert(*id, SpirvType::Pointer(*pointee));
+                }
+                (op::TYPE_ACCELERATION_STRUCTURE, [id]) => {
+                    types.insert(*id, SpirvType::AccelerationStructure);
+                }
+                (op::CONSTANT, [_type, id, value, ..]) => {
+                    constants.insert(*id, *value);
+                }
+                (op::VARIABLE, [pointer_type, id, storage_class, ..]) => {
+                    variables.push((*pointer_type, *id, *storage_class));
+                }
+                _ => (),
+            }
+        }
+
+        let module = SpirvModule {
+            types,
+            constants,
+            array_strides,
+            member_offsets,
+        };
+        for (pointer_type, id, storage_class) in variables {
+            let Some(SpirvType::Pointer(pointee)) = module.types.get(&pointer_type) else {
+                continue;
+            };
+            match storage_class {
+                op::STORAGE_PUSH_CONSTANT => {
+                    reflection.push_constant_size =
+                        reflection.push_constant_size.max(module.size_of(*pointee));
+                }
+                op::STORAGE_UNIFORM_CONSTANT | op::STORAGE_UNIFORM | op::STORAGE_STORAGE_BUFFER => {
+                    let (element, count) = module.unwrap_array(*pointee);
+                    let descriptor_type = match module.types.get(&element) {
+                        Some(SpirvType::Sampler) => vk::DescriptorType::SAMPLER,
+                        Some(SpirvType::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        Some(SpirvType::Image(op::DIM_BUFFER, 2)) => {
+                            vk::DescriptorType::STORAGE_TEXEL_BUFFER
+                        }
+                        Some(SpirvType::Image(op::DIM_BUFFER, _)) => {
+                            vk::DescriptorType::UNIFORM_TEXEL_BUFFER
+                        }
+                        Some(SpirvType::Image(op::DIM_SUBPASS_DATA, _)) => {
+                            vk::DescriptorType::INPUT_ATTACHMENT
+                        }
+                        Some(SpirvType::Image(_, 2)) => vk::DescriptorType::STORAGE_IMAGE,
+                        Some(SpirvType::Image(..)) => vk::DescriptorType::SAMPLED_IMAGE,
+                        Some(SpirvType::AccelerationStructure) => {
+                            vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
+                        }
+                        Some(SpirvType::Struct(_))
+                            if storage_class == op::STORAGE_STORAGE_BUFFER
+                                || buffer_blocks.contains(&element) =>
+                        {
+                            vk::DescriptorType::STORAGE_BUFFER
+                        }
+                        Some(SpirvType::Struct(_)) => vk::DescriptorType::UNIFORM_BUFFER,
+                        _ => continue,
+                    };
+                    reflection.bindings.push(DescriptorBinding {
+                        set: sets.get(&id).copied().unwrap_or(0),
+                        binding: bindings.get(&id).copied().unwrap_or(0),
+                        descriptor_type,
+                        count,
+                    });
+                }
+                _ => (),
+            }
+        }
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+        Ok(reflection)
+    }
+
+    /// Checks the declared resources against the bindless set and push constant range.
+    /// Every mismatch gets attached to the report.
+    pub fn validate_bindless(&self) -> Result<(), Report<ShaderError>> {
+        let mut problems = Vec::new();
+        for binding in &self.bindings {
+            let kind = DescriptorKind::from_binding(binding.binding).filter(|_| binding.set == 0);
+            match kind {
+                None => problems.push(format!(
+                    "set {} binding {} is not part of the bindless set",
+                    binding.set, binding.binding
+                )),
+                Some(kind) if kind.descriptor_type() != binding.descriptor_type => {
+                    problems.push(format!(
+                        "set 0 binding {} is a {}, the bindless set has a {} there",
+                        binding.binding,
+                        descriptor_type_name(binding.descriptor_type),
+                        descriptor_type_name(kind.descriptor_type()),
+                    ))
+                }
+                // Runtime sized arrays take whatever the set has
+                Some(kind) if binding.count.is_some_and(|count| count > kind.max_count()) => {
+                    problems.push(format!(
+                        "set 0 binding {} is an array of {} descriptors, the bindless set has at most {}",
+                        binding.binding,
+                        binding.count.unwrap_or_default(),
+                        kind.max_count(),
+                    ))
+                }
+                Some(_) => (),
+            }
+        }
+        if self.push_constant_size > PUSH_CONSTANT_SIZE {
+            problems.push(format!(
+                "{} bytes of push constants, only {PUSH_CONSTANT_SIZE} are available",
+                self.push_constant_size
+            ));
+        }
+        match problems.into_iter().reduce(|a, b| format!("{a}\n{b}")) {
+            None => Ok(()),
+            Some(problems) => Err(Report::new(ShaderError::LayoutMismatch).attach(problems)),
+        }
+    }
+
+    /// The stage of `entry_point`, if the module has it.
+    pub fn stage_of(&self, entry_point: &str) -> Option<vk::ShaderStageFlags> {
+        self.entry_points
+            .iter()
+            .find(|entry| entry.name == entry_point)
+            .map(|entry| entry.stage)
+    }
+}
+
+struct SpirvModule {
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    array_strides: HashMap<u32, u32>,
+    member_offsets: HashMap<(u32, u32), u32>,
+}
+
+impl SpirvModule {
+    /// Strips one level of array off a descriptor type, returning the element and the count.
+    fn unwrap_array(&self, id: u32) -> (u32, Option<u32>) {
+        match self.types.get(&id) {
+            Some(SpirvType::Array(element, length)) => (
+                *element,
+                Some(self.constants.get(length).copied().unwrap_or(1)),
+            ),
+            Some(SpirvType::RuntimeArray(element)) => (*element, None),
+            _ => (id, Some(1)),
+        }
+    }
+
+    /// Size in bytes, good enough to compare push constant blocks against the limit.
+    fn size_of(&self, id: u32) -> u32 {
+        match self.types.get(&id) {
+            Some(SpirvType::Scalar(bytes)) => *bytes,
+            Some(SpirvType::Vector(component, count)) => self.size_of(*component) * count,
+            Some(SpirvType::Matrix(column, count)) => self.size_of(*column) * count,
+            Some(SpirvType::Array(element, length)) => {
+                let stride = self
+                    .array_strides
+                    .get(&id)
+                    .copied()
+                    .unwrap_or_else(|| self.size_of(*element));
+                stride * self.constants.get(length).copied().unwrap_or(1)
+            }
+            Some(SpirvType::Struct(members)) => members
+                .iter()
+                .enumerate()
+                .map(|(index, &member)| {
+                    self.member_offsets
+                        .get(&(id, index as u32))
+                        .copied()
+                        .unwrap_or(0)
+                        + self.size_of(member)
+                })
+                .max()
+                .unwrap_or(0),
+            // Buffer device addresses
+            Some(SpirvType::Pointer(_)) => 8,
+            _ => 0,
+        }
+    }
+}
+
+fn execution_model_stage(model: u32) -> Option<vk::ShaderStageFlags> {
+    Some(match model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        5364 => vk::ShaderStageFlags::TASK_EXT,
+        5365 => vk::ShaderStageFlags::MESH_EXT,
+        _ => return None,
+    })
+}
+
+/// SPIR-V strings are nul terminated UTF-8 packed into little endian words.
+fn literal_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn descriptor_type_name(descriptor_type: vk::DescriptorType) -> &'static str {
+    match descriptor_type {
+        vk::DescriptorType::SAMPLER => "sampler",
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER => "combined image sampler",
+        vk::DescriptorType::SAMPLED_IMAGE => "sampled image",
+        vk::DescriptorType::STORAGE_IMAGE => "storage image",
+        vk::DescriptorType::UNIFORM_TEXEL_BUFFER => "uniform texel buffer",
+        vk::DescriptorType::STORAGE_TEXEL_BUFFER => "storage texel buffer",
+        vk::DescriptorType::UNIFORM_BUFFER => "uniform buffer",
+        vk::DescriptorType::STORAGE_BUFFER => "storage buffer",
+        vk::DescriptorType::INPUT_ATTACHMENT => "input attachment",
+        vk::DescriptorType::ACCELERATION_STRUCTURE_KHR => "acceleration structure",
+        _ => "descriptor",
+    }
+}
+
+/// A loaded shader module, replaced in place when its file changes.
+pub struct Shader {
+    pub module: vk::ShaderModule,
+    pub reflection: ShaderReflection,
+    /// Bumped on every reload, so pipelines can tell they are stale.
+    pub generation: u64,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+/// Loads SPIR-V from `<asset dir>/shaders` and reloads it when the files change.
+/// The built-in shaders are used for names without a file, creating the file overrides them.
+pub struct ShaderLibrary {
+    device: Arc<Device>,
+    dir: PathBuf,
+    shaders: HashMap<String, Shader>,
+    last_poll: Instant,
+}
+
+impl ShaderLibrary {
+    pub(crate) fn new(device: Arc<Device>, asset_dir: &Path) -> Self {
+        Self {
+            device,
+            dir: asset_dir.join("shaders"),
+            shaders: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Loads `name` (relative to the shader directory, like `"mesh.spv"`) the first time it's asked for.
+    pub fn load(&mut self, name: &str) -> Result<&Shader, Report<ShaderError>> {
+        if !self.shaders.contains_key(name) {
+            let shader = self.create(name, 0)?;
+            self.shaders.insert(name.to_string(), shader);
+        }
+        Ok(&self.shaders[name])
+    }
+
+    fn create(&self, name: &str, generation: u64) -> Result<Shader, Report<ShaderError>> {
+        let path = &self.dir.join(name);
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let builtin = BUILTIN_SHADERS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, bytes)| *bytes);
+        let bytes = match (std::fs::read(path), builtin) {
+            (Ok(bytes), _) => bytes,
+            (Err(_), Some(bytes)) => bytes.to_vec(),
+            (Err(x), None) => {
+                return Err(Report::new(x)
+                    .change_context(ShaderError::Load)
+                    .attach(format!("path: {}", path.display())));
+            }
+        };
+        let words = ash::util::read_spv(&mut std::io::Cursor::new(&bytes))
+            .change_context(ShaderError::InvalidSpirv)
+            .attach(format!("path: {}", path.display()))?;
+        let reflection = ShaderReflection::parse(&words)
+            .and_then(|reflection| reflection.validate_bindless().map(|()| reflection))
+            .attach(format!("path: {}", path.display()))?;
+        let module = unsafe {
+            self.device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&words), None)
+        }
+        .map_err(|x| Report::new(ShaderError::Module).attach(format!("{x}")))
+        .attach(format!("path: {}", path.display()))?;
+        Ok(Shader {
+            module,
+            reflection,
+            generation,
+            path: path.to_path_buf(),
+            modified,
+        })
+    }
+
+    /// Reloads every shader whose file changed since it was loaded, at most every [`POLL_INTERVAL`].
+    /// Returns the names of the reloaded shaders. A shader that fails to reload keeps its old
+    /// module, so a typo doesn't take the renderer down.
+    pub(crate) fn poll_changes(&mut self) -> Vec<String> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+        let changed: Vec<String> = self
+            .shaders
+            .iter()
+            .filter(|(_, shader)| {
+                let modified = std::fs::metadata(&shader.path)
+                    .and_then(|m| m.modified())
+                    .ok();
+                modified.is_some() && modified != shader.modified
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let mut reloaded = Vec::new();
+        for name in changed {
+            match self.create(&name, self.shaders[&name].generation + 1) {
+                Ok(shader) => {
+                    // Pipelines built from the old module keep working without it
+                    let old = self.shaders.insert(name.clone(), shader);
+                    if let Some(old) = old {
+                        unsafe { self.device.destroy_shader_module(old.module, None) };
+                    }
+                    crate::logln!("vulkan shader", "Reloaded shader {name}");
+                    reloaded.push(name);
+                }
+                Err(report) => {
+                    crate::logln!(
+                        "vulkan shader error",
+                        "Failed to reload shader {name}: {report:?}"
+                    );
+                    // Don't retry until the file changes again
+                    if let Some(shader) = self.shaders.get_mut(&name) {
+                        shader.modified = std::fs::metadata(&shader.path)
+                            .and_then(|m| m.modified())
+                            .ok();
+                    }
+                }
+            }
+        }
+        reloaded
+    }
+}
+
+impl Drop for ShaderLibrary {
+    fn drop(&mut self) {
+        for shader in self.shaders.values() {
+            unsafe { self.device.destroy_shader_module(shader.module, None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        ash::util::read_spv(&mut std::io::Cursor::new(bytes)).unwrap()
+    }
+
+    fn builtin(name: &str) -> Vec<u32> {
+        let (_, bytes) = BUILTIN_SHADERS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .unwrap();
+        words(bytes)
+    }
+
+    #[test]
+    fn builtin_shaders_match_the_bindless_layout() {
+        assert!(!BUILTIN_SHADERS.is_empty());
+        for (name, bytes) in BUILTIN_SHADERS {
+            let reflection = ShaderReflection::parse(&words(bytes))
+                .unwrap_or_else(|report| panic!("{name}: {report:?}"));
+            assert!(!reflection.entry_points.is_empty(), "{name}");
+            reflection
+                .validate_bindless()
+                .unwrap_or_else(|report| panic!("{name}: {report:?}"));
+        }
+    }
+
+    #[test]
+    fn mesh_shader_reflection() {
+        let reflection = ShaderReflection::parse(&builtin("mesh.spv")).unwrap();
+        let bindings: Vec<_> = reflection
+            .bindings
+            .iter()
+            .map(|b| (b.set, b.binding, b.descriptor_type, b.count))
+            .collect();
+        // ash only implements `Debug` with the debug feature
+        assert!(
+            bindings
+                == [
+                    (0, 0, vk::DescriptorType::SAMPLED_IMAGE, None),
+                    (0, 2, vk::DescriptorType::STORAGE_BUFFER, None),
+                    (0, 3, vk::DescriptorType::SAMPLER, None),
+                ]
+        );
+        assert!(reflection.stage_of("vs_main").is_some());
+        assert!(reflection.push_constant_size > 0);
+        assert!(reflection.push_constant_size <= PUSH_CONSTANT_SIZE);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut words = builtin("mesh.spv");
+        words[0] = !SPIRV_MAGIC;
+        let report = ShaderReflection::parse(&words).err().unwrap();
+        assert_eq!(*report.current_context(), ShaderError::InvalidSpirv);
+    }
+
+    #[test]
+    fn rejects_truncated_streams() {
+        let words = builtin("mesh.spv");
+        // Not even a header
+        let report = ShaderReflection::parse(&words[..3]).err().unwrap();
+        assert_eq!(*report.current_context(), ShaderError::InvalidSpirv);
+        // An instruction claiming more words than are left
+        let mut words = words[..5].to_vec();
+        words.extend([(4 << 16) | op::DECORATE, 1]);
+        let report = ShaderReflection::parse(&words).err().unwrap();
+        assert_eq!(*report.current_context(), ShaderError::InvalidSpirv);
+        // A zero word count would never advance
+        words.truncate(5);
+        words.push(op::DECORATE);
+        let report = ShaderReflection::parse(&words).err().unwrap();
+        assert_eq!(*report.current_context(), ShaderError::InvalidSpirv);
+    }
+
+    #[test]
+    fn validate_bindless_reports_every_mismatch() {
+        let binding = |set, binding, descriptor_type, count| DescriptorBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+        };
+        let reflection = ShaderReflection {
+            entry_points: Vec::new(),
+            bindings: vec![
+                binding(0, 0, vk::DescriptorType::SAMPLED_IMAGE, None),
+                binding(1, 0, vk::DescriptorType::SAMPLED_IMAGE, None),
+                binding(0, 2, vk::DescriptorType::UNIFORM_BUFFER, Some(1)),
+                binding(
+                    0,
+                    3,
+                    vk::DescriptorType::SAMPLER,
+                    Some(DescriptorKind::Sampler.max_count() + 1),
+                ),
+            ],
+            push_constant_size: PUSH_CONSTANT_SIZE * 2,
+        };
+        let report = reflection.validate_bindless().err().unwrap();
+        assert_eq!(*report.current_context(), ShaderError::LayoutMismatch);
+        let problems = format!("{report:?}");
+        assert!(problems.contains("set 1 binding 0 is not part of the bindless set"));
+        assert!(problems.contains("is a uniform buffer, the bindless set has a storage buffer"));
+        assert!(problems.contains("is an array of"));
+        assert!(problems.contains("bytes of push constants"));
+
+        let reflection = ShaderReflection {
+            bindings: vec![binding(0, 3, vk::DescriptorType::SAMPLER, Some(1))],
+            ..ShaderReflection::default()
+        };
+        assert!(reflection.validate_bindless().is_ok());
+    }
+}
@@ -0,0 +1,551 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        ShadowFilter, ShadowSettings,
+        vk::{
+            bindless::{BindlessHeap, DescriptorHandle},
+            frame::MAX_FRAMES_IN_FLIGHT,
+            graph::{ImageDesc, ImageState, PassContext},
+            memory::{Allocator, Buffer, Image, MemoryLocation},
+            mesh::{MeshRenderer, View, position_layout},
+            pipeline::{DepthBias, GraphicsPipelineDesc, PipelineCache, RenderState},
+            shader::ShaderLibrary,
+        },
+    },
+    ash::{Device, prelude::VkResult, vk},
+    glam::{Mat4, Vec3, Vec4},
+    std::{f32::consts::FRAC_PI_2, mem::size_of, sync::Arc},
+};
+
+pub(crate) const SHADOW_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+const SHADER: &str = "shadow.spv";
+pub(crate) const MAX_CASCADES: u32 = 4;
+// In vec4s, matching `shaders/mesh.wgsl`: a header, the filter, the cascade splits, then the views
+const HEADER_SIZE: usize = 3;
+const VIEW_SIZE: usize = 6;
+// Blend between logarithmic and uniform cascade splits, logarithmic keeps near cascades sharp
+const SPLIT_LAMBDA: f32 = 0.75;
+// Biases for a D32 float map, the normal offset in the shader does most of the work
+const DEPTH_BIAS: DepthBias = DepthBias {
+    constant: 2.0,
+    clamp: 0.0,
+    slope: 2.0,
+};
+// Cube faces in the order the shader picks them: +X, -X, +Y, -Y, +Z, -Z
+const CUBE_FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::NEG_Y),
+    (Vec3::NEG_X, Vec3::NEG_Y),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::NEG_Y, Vec3::NEG_Z),
+    (Vec3::Z, Vec3::NEG_Y),
+    (Vec3::NEG_Z, Vec3::NEG_Y),
+];
+
+/// A light picked to cast shadows, in world space.
+#[derive(Clone, Copy)]
+pub(crate) enum ShadowCaster {
+    /// Gets one view per cascade.
+    Sun { direction: Vec3 },
+    /// Gets one view per cube face.
+    Point { position: Vec3, range: f32 },
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        range: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+impl ShadowCaster {
+    /// How many shadow maps the caster takes.
+    pub(crate) fn views(&self, cascades: u32) -> u32 {
+        match self {
+            Self::Sun { .. } => cascades,
+            Self::Point { .. } => 6,
+            Self::Spot { .. } => 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    shadows: u32,
+    view: u32,
+    instances: u32,
+    _pad: u32,
+}
+
+// Plain numbers without padding
+unsafe impl bytemuck::Zeroable for PushConstants {}
+unsafe impl bytemuck::Pod for PushConstants {}
+
+/// One depth atlas holding every shadow map, tiled in a grid of equal squares.
+struct Atlas {
+    image: Image,
+    view: vk::ImageView,
+    handle: DescriptorHandle,
+    extent: vk::Extent2D,
+    columns: u32,
+    tile: u32,
+}
+
+/// Shadow maps of the [`ShadowCaster`]s, rendered with a depth-only pipeline every frame.
+/// Cascades are refit to the camera each frame, the point and spot light views never move.
+pub(crate) struct Shadows {
+    device: Arc<Device>,
+    settings: ShadowSettings,
+    casters: Vec<ShadowCaster>,
+    atlas: Option<Atlas>,
+    // Filter, cascade splits and view matrices, one host visible copy per frame in flight
+    buffers: Vec<(Buffer, DescriptorHandle)>,
+    // Nothing was rendered into the atlas yet, so there's nothing to wait on
+    fresh: bool,
+}
+
+impl Shadows {
+    /// `max_dimension` is the largest image the device can create, the tiles shrink to fit it.
+    pub(crate) fn new(
+        device: Arc<Device>,
+        allocator: &mut Allocator,
+        bindless: &mut BindlessHeap,
+        settings: ShadowSettings,
+        casters: &[ShadowCaster],
+        max_dimension: u32,
+    ) -> VkResult<Self> {
+        let casters = if settings.enabled {
+            casters.to_vec()
+        } else {
+            Vec::new()
+        };
+        let view_count: u32 = casters
+            .iter()
+            .map(|caster| caster.views(settings.cascades))
+            .sum();
+
+        let atlas = if view_count > 0 {
+            let columns = (view_count as f32).sqrt().ceil() as u32;
+            let rows = view_count.div_ceil(columns);
+            let tile = settings
+                .resolution
+                .clamp(1, max_dimension / columns.max(rows));
+            if tile < settings.resolution {
+                crate::logln!(
+                    "vulkan shadows warning",
+                    "Shadow maps are {tile} texels instead of {} to fit {view_count} of them",
+                    settings.resolution
+                );
+            }
+            let extent = vk::Extent2D {
+                width: columns * tile,
+                height: rows * tile,
+            };
+            let image = allocator.create_image(
+                &vk::ImageCreateInfo::default()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(SHADOW_FORMAT)
+                    .extent(extent.into())
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(
+                        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                            | vk::ImageUsageFlags::SAMPLED,
+                    )
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED),
+                MemoryLocation::GpuOnly,
+                "shadow atlas",
+            )?;
+            let view = unsafe {
+                device.create_image_view(
+                    &vk::ImageViewCreateInfo::default()
+                        .image(image.handle)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(SHADOW_FORMAT)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::DEPTH,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        }),
+                    None,
+                )
+            };
+            let view = match view {
+                Ok(x) => x,
+                Err(x) => {
+                    allocator.destroy_image(&image);
+                    return Err(x);
+                }
+            };
+            // The layout the graph leaves it in for the passes sampling it
+            let Some(handle) = bindless.add_sampled_image(view, vk::ImageLayout::READ_ONLY_OPTIMAL)
+            else {
+                unsafe { device.destroy_image_view(view, None) };
+                allocator.destroy_image(&image);
+                return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY);
+            };
+            Some(Atlas {
+                image,
+                view,
+                handle,
+                extent,
+                columns,
+                tile,
+            })
+        } else {
+            None
+        };
+
+        let size = ((HEADER_SIZE + VIEW_SIZE * view_count.max(1) as usize) * size_of::<Vec4>())
+            as vk::DeviceSize;
+        let mut buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            let buffer = allocator.create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(size)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                MemoryLocation::CpuToGpu,
+                format_args!("shadow views {frame}"),
+            )?;
+            let handle = bindless
+                .add_storage_buffer(buffer.handle, 0, size)
+                .ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)?;
+            buffers.push((buffer, handle));
+        }
+
+        Ok(Self {
+            device,
+            settings,
+            casters,
+            atlas,
+            buffers,
+            fresh: true,
+        })
+    }
+
+    /// The shadow constants of `slot`.
+    pub(crate) fn buffer(&self, slot: usize) -> DescriptorHandle {
+        self.buffers[slot].1
+    }
+
+    /// The atlas for the graph to import: image, view, description, and the state it's in.
+    /// None when nothing casts shadows.
+    pub(crate) fn atlas(&mut self) -> Option<(vk::Image, vk::ImageView, ImageDesc, ImageState)> {
+        let atlas = self.atlas.as_ref()?;
+        // Cleared every frame, only the reads of the last frame have to finish first
+        let initial = ImageState {
+            layout: vk::ImageLayout::UNDEFINED,
+            stage: if self.fresh {
+                vk::PipelineStageFlags2::NONE
+            } else {
+                vk::PipelineStageFlags2::FRAGMENT_SHADER
+            },
+            access: vk::AccessFlags2::NONE,
+        };
+        self.fresh = false;
+        Some((
+            atlas.image.handle,
+            atlas.view,
+            ImageDesc::new(SHADOW_FORMAT, atlas.extent),
+            initial,
+        ))
+    }
+
+    /// The state the graph leaves the atlas in, ready for shading.
+    pub(crate) fn sampled_state() -> ImageState {
+        ImageState {
+            layout: vk::ImageLayout::READ_ONLY_OPTIMAL,
+            stage: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+        }
+    }
+
+    /// Fits the cascades to `view` and writes every shadow view into the buffer of `slot`.
+    /// The last frame that used `slot` must be done.
+    pub(crate) fn update(&mut self, slot: usize, view: &View) {
+        let mut rows: Vec<Vec4> = Vec::new();
+        let (filter, parameters) = match (self.settings.filter, &self.atlas) {
+            (_, None) => (0, [0.0; 2]),
+            (ShadowFilter::Pcf { radius }, _) => (1, [radius, 0.0]),
+            (
+                ShadowFilter::Pcss {
+                    sun_angle,
+                    light_radius,
+                },
+                _,
+            ) => (2, [sun_angle.tan(), light_radius]),
+        };
+        let atlas_slot = self.atlas.as_ref().map_or(0, |atlas| atlas.handle.index);
+        let cascades = self.settings.cascades;
+        rows.push(Vec4::from_array(
+            [atlas_slot, filter, cascades, 0].map(f32::from_bits),
+        ));
+        rows.push(Vec4::new(
+            parameters[0],
+            parameters[1],
+            self.atlas
+                .as_ref()
+                .map_or(1.0, |x| 1.0 / x.extent.width as f32),
+            self.atlas
+                .as_ref()
+                .map_or(1.0, |x| 1.0 / x.extent.height as f32),
+        ));
+
+        let splits = self.splits(view, cascades);
+        rows.push(Vec4::from_slice(&splits));
+        let resolution = self.atlas.as_ref().map_or(1, |x| x.tile) as f32;
+        let mut index = 0;
+        for caster in &self.casters {
+            let views: Vec<(Mat4, Vec4)> = match *caster {
+                ShadowCaster::Sun { direction } => (0..cascades as usize)
+                    .map(|cascade| {
+                        let near = if cascade == 0 {
+                            view.near
+                        } else {
+                            splits[cascade - 1]
+                        };
+                        cascade_view(view, direction, near, splits[cascade], resolution)
+                    })
+                    .collect(),
+                ShadowCaster::Point { position, range } => CUBE_FACES
+                    .iter()
+                    .map(|&(forward, up)| {
+                        perspective_view(position, forward, up, FRAC_PI_2, range, resolution)
+                    })
+                    .collect(),
+                ShadowCaster::Spot {
+                    position,
+                    direction,
+                    range,
+                    outer_cone_angle,
+                } => {
+                    let up = if direction.y.abs() > 0.99 {
+                        Vec3::Z
+                    } else {
+                        Vec3::Y
+                    };
+                    let fov = (outer_cone_angle * 2.0 + 0.05).min(3.0);
+                    vec![perspective_view(
+                        position, direction, up, fov, range, resolution,
+                    )]
+                }
+            };
+            for (matrix, parameters) in views {
+                rows.extend([matrix.x_axis, matrix.y_axis, matrix.z_axis, matrix.w_axis]);
+                rows.push(self.tile_rect(index));
+                rows.push(parameters);
+                index += 1;
+            }
+        }
+
+        let rows: Vec<[f32; 4]> = rows.into_iter().map(<[f32; 4]>::from).collect();
+        let bytes: &[u8] = bytemuck::cast_slice(&rows);
+        let (buffer, _) = &mut self.buffers[slot];
+        buffer
+            .allocation
+            .mapped_slice_mut()
+            .expect("Shadow buffers are host visible")[..bytes.len()]
+            .copy_from_slice(bytes);
+    }
+
+    /// View depths where each cascade ends, unused ones repeat the last.
+    fn splits(&self, view: &View, cascades: u32) -> [f32; MAX_CASCADES as usize] {
+        let far = self.settings.distance.map_or(view.far, |x| x.min(view.far));
+        let near = view.near;
+        let mut splits = [far; MAX_CASCADES as usize];
+        for (cascade, split) in splits.iter_mut().enumerate().take(cascades as usize) {
+            let t = (cascade + 1) as f32 / cascades as f32;
+            let logarithmic = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            *split = logarithmic * SPLIT_LAMBDA + uniform * (1.0 - SPLIT_LAMBDA);
+        }
+        splits
+    }
+
+    /// Where view `index` lives in the atlas, as a UV offset and scale.
+    fn tile_rect(&self, index: u32) -> Vec4 {
+        let Some(atlas) = &self.atlas else {
+            return Vec4::ZERO;
+        };
+        let (column, row) = (index % atlas.columns, index / atlas.columns);
+        let (width, height) = (atlas.extent.width as f32, atlas.extent.height as f32);
+        let tile = atlas.tile as f32;
+        Vec4::new(
+            column as f32 * tile / width,
+            row as f32 * tile / height,
+            tile / width,
+            tile / height,
+        )
+    }
+
+    /// Draws every shadow casting mesh into each view's tile of the atlas, inside the pass of `context`.
+    pub(crate) fn render(
+        &mut self,
+        context: &PassContext,
+        pipelines: &mut PipelineCache,
+        shaders: &mut ShaderLibrary,
+        bindless: &BindlessHeap,
+        meshes: &MeshRenderer,
+        slot: usize,
+    ) {
+        let (Some(atlas), Some((instances, _))) = (&self.atlas, meshes.tables()) else {
+            return;
+        };
+        let cb = context.command_buffer;
+        let desc = GraphicsPipelineDesc::new()
+            .stage(SHADER, "vs_main")
+            .depth_format(SHADOW_FORMAT);
+        bindless.bind(cb, vk::PipelineBindPoint::GRAPHICS);
+        let mut index = 0;
+        for caster in &self.casters {
+            // Pancaking: casters between the sun and a cascade are clamped onto its near plane
+            let state = RenderState {
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_bias: Some(DEPTH_BIAS),
+                depth_clamp: matches!(caster, ShadowCaster::Sun { .. }),
+                blend: Vec::new(),
+                vertex_layout: position_layout(),
+                ..Default::default()
+            };
+            if !pipelines.bind(cb, shaders, &desc, &state, context.render_area()) {
+                return;
+            }
+            // Binding set the viewport to the whole atlas, every view draws into its own tile
+            for _ in 0..caster.views(self.settings.cascades) {
+                let (column, row) = (index % atlas.columns, index / atlas.columns);
+                let offset = vk::Offset2D {
+                    x: (column * atlas.tile) as i32,
+                    y: (row * atlas.tile) as i32,
+                };
+                let extent = vk::Extent2D {
+                    width: atlas.tile,
+                    height: atlas.tile,
+                };
+                unsafe {
+                    self.device.cmd_set_viewport_with_count(
+                        cb,
+                        // Flipped like every other viewport, see `PipelineCache::bind`
+                        &[vk::Viewport {
+                            x: offset.x as f32,
+                            y: (offset.y + extent.height as i32) as f32,
+                            width: atlas.tile as f32,
+                            height: -(atlas.tile as f32),
+                            min_depth: 0.0,
+                            max_depth: 1.0,
+                        }],
+                    );
+                    self.device
+                        .cmd_set_scissor_with_count(cb, &[vk::Rect2D { offset, extent }]);
+                }
+                let push_constants = PushConstants {
+                    shadows: self.buffers[slot].1.index,
+                    view: index,
+                    instances,
+                    _pad: 0,
+                };
+                unsafe {
+                    self.device.cmd_push_constants(
+                        cb,
+                        bindless.pipeline_layout(),
+                        vk::ShaderStageFlags::ALL,
+                        0,
+                        bytemuck::bytes_of(&push_constants),
+                    );
+                }
+                meshes.draw_depth(context);
+                index += 1;
+            }
+        }
+    }
+
+    /// Frees the atlas and buffers, the GPU must be done with them.
+    pub(crate) fn destroy(&mut self, allocator: &mut Allocator) {
+        if let Some(atlas) = self.atlas.take() {
+            unsafe { self.device.destroy_image_view(atlas.view, None) };
+            allocator.destroy_image(&atlas.image);
+        }
+        for (buffer, _) in self.buffers.drain(..) {
+            allocator.destroy_buffer(&buffer);
+        }
+    }
+}
+
+/// An orthographic view of the `near` to `far` slice of `view`, seen down `direction`.
+/// The bounding sphere of the slice is snapped to whole texels, so the cascade doesn't shimmer
+/// as the camera moves or turns. Returns the matrix and the parameters the shader filters with.
+fn cascade_view(
+    view: &View,
+    direction: Vec3,
+    near: f32,
+    far: f32,
+    resolution: f32,
+) -> (Mat4, Vec4) {
+    let camera = view.view.inverse();
+    let tan_y = (view.fov_y * 0.5).tan();
+    let tan_x = tan_y * view.aspect;
+    let corners: Vec<Vec3> = [near, far]
+        .iter()
+        .flat_map(|&depth| {
+            [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].map(|(x, y)| {
+                camera.transform_point3(Vec3::new(x * tan_x * depth, y * tan_y * depth, -depth))
+            })
+        })
+        .collect();
+    let center = corners.iter().copied().sum::<Vec3>() / corners.len() as f32;
+    // Only depends on the slice, rounded so it doesn't flicker with float error
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0.0, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let up = if direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+    let light_view = Mat4::look_to_rh(Vec3::ZERO, direction, up);
+    let texel = radius * 2.0 / resolution;
+    let center = light_view.transform_point3(center);
+    let snapped = Vec3::new(
+        (center.x / texel).floor() * texel,
+        (center.y / texel).floor() * texel,
+        center.z,
+    );
+    let projection = Mat4::orthographic_rh(
+        snapped.x - radius,
+        snapped.x + radius,
+        snapped.y - radius,
+        snapped.y + radius,
+        -snapped.z - radius,
+        -snapped.z + radius,
+    );
+    (
+        projection * light_view,
+        Vec4::new(texel, -snapped.z - radius, -snapped.z + radius, 0.0),
+    )
+}
+
+/// A perspective view from `position` down `forward`, reaching `range`.
+/// Returns the matrix and the parameters the shader filters with.
+fn perspective_view(
+    position: Vec3,
+    forward: Vec3,
+    up: Vec3,
+    fov: f32,
+    range: f32,
+    resolution: f32,
+) -> (Mat4, Vec4) {
+    let near = (range * 0.001).clamp(0.001, 0.05);
+    let projection = Mat4::perspective_rh(fov, 1.0, near, range);
+    let view = Mat4::look_to_rh(position, forward, up);
+    // Texel size one unit away, the shader scales it by distance
+    let texel = 2.0 * (fov * 0.5).tan() / resolution;
+    (projection * view, Vec4::new(texel, near, range, 1.0))
+}
@@ -0,0 +1,223 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        assets::{ColorSpace, Texture, TextureFormat},
+        vk::{
+            bindless::{BindlessHeap, DescriptorHandle},
+            capabilities::DeviceCapabilities,
+            memory::{Allocator, Image},
+            upload::Uploader,
+        },
+    },
+    ash::{Device, Instance, prelude::VkResult, vk},
+    std::{collections::HashMap, sync::Arc},
+};
+
+const MAX_ANISOTROPY: f32 = 16.0;
+
+const FORMATS: [TextureFormat; 10] = [
+    TextureFormat::Rgba8,
+    TextureFormat::Rgba16F,
+    TextureFormat::Rgba32F,
+    TextureFormat::Bc1,
+    TextureFormat::Bc2,
+    TextureFormat::Bc3,
+    TextureFormat::Bc4,
+    TextureFormat::Bc5,
+    TextureFormat::Bc6H,
+    TextureFormat::Bc7,
+];
+
+struct GpuTexture {
+    image: Image,
+    view: vk::ImageView,
+}
+
+/// Sampled images in the bindless heap, all read through one linear repeat sampler.
+pub(crate) struct Textures {
+    device: Arc<Device>,
+    // Optimal tiling features of every format a texture can map to
+    features: HashMap<vk::Format, vk::FormatFeatureFlags>,
+    texture_compression_bc: bool,
+    sampler: vk::Sampler,
+    sampler_handle: DescriptorHandle,
+    textures: Vec<GpuTexture>,
+}
+
+impl Textures {
+    pub(crate) fn new(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        device: Arc<Device>,
+        capabilities: &DeviceCapabilities,
+        bindless: &mut BindlessHeap,
+    ) -> VkResult<Self> {
+        let features = FORMATS
+            .iter()
+            .flat_map(|&format| {
+                [ColorSpace::Srgb, ColorSpace::Linear].map(|x| vk_format(format, x))
+            })
+            .map(|format| {
+                let properties = unsafe {
+                    instance.get_physical_device_format_properties(physical_device, format)
+                };
+                (format, properties.optimal_tiling_features)
+            })
+            .collect();
+        let max_anisotropy = unsafe {
+            instance
+                .get_physical_device_properties(physical_device)
+                .limits
+                .max_sampler_anisotropy
+        };
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::REPEAT)
+                    .address_mode_v(vk::SamplerAddressMode::REPEAT)
+                    .address_mode_w(vk::SamplerAddressMode::REPEAT)
+                    .anisotropy_enable(capabilities.sampler_anisotropy)
+                    .max_anisotropy(MAX_ANISOTROPY.min(max_anisotropy))
+                    .max_lod(vk::LOD_CLAMP_NONE),
+                None,
+            )?
+        };
+        let Some(sampler_handle) = bindless.add_sampler(sampler) else {
+            unsafe { device.destroy_sampler(sampler, None) };
+            return Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY);
+        };
+        Ok(Self {
+            device,
+            features,
+            texture_compression_bc: capabilities.texture_compression_bc,
+            sampler,
+            sampler_handle,
+            textures: Vec::new(),
+        })
+    }
+
+    /// The sampler slot every texture is meant to be read with.
+    pub(crate) fn sampler(&self) -> DescriptorHandle {
+        self.sampler_handle
+    }
+
+    /// Queues `texture` on `uploader`, it can be sampled once the uploader is flushed.
+    /// `None` when the device can't sample its format, the reason is logged.
+    pub(crate) fn upload(
+        &mut self,
+        uploader: &mut Uploader,
+        bindless: &mut BindlessHeap,
+        texture: &Texture,
+    ) -> VkResult<Option<DescriptorHandle>> {
+        let name = texture.name.as_deref().unwrap_or("unnamed");
+        let format = vk_format(texture.format, texture.color_space);
+        let features = self.features.get(&format).copied().unwrap_or_default();
+        if texture.format.is_compressed() && !self.texture_compression_bc {
+            crate::logln!(
+                "vulkan texture warning",
+                "Texture {name}: the device doesn't support BC compression"
+            );
+            return Ok(None);
+        }
+        if !features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+            crate::logln!(
+                "vulkan texture warning",
+                "Texture {name}: the device can't sample {:?} textures",
+                texture.format
+            );
+            return Ok(None);
+        }
+
+        // The rest of the chain is downsampled from the last level given, when the format allows
+        let given = texture.levels.len() as u32;
+        let full = texture.full_mip_count();
+        let blit = (!texture.format.is_compressed()
+            && given < full
+            && features
+                .contains(vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST))
+        .then(|| {
+            if features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR) {
+                vk::Filter::LINEAR
+            } else {
+                vk::Filter::NEAREST
+            }
+        });
+        let mip_levels = if blit.is_some() {
+            full
+        } else {
+            given.min(full)
+        };
+
+        let image = uploader.image(texture, format, mip_levels, blit)?;
+        let view = unsafe {
+            self.device.create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .image(image.handle)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(format)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: mip_levels,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    }),
+                None,
+            )
+        };
+        // The uploader still copies into the image, so it's kept either way and destroyed with the rest
+        let view = match view {
+            Ok(x) => x,
+            Err(x) => {
+                self.textures.push(GpuTexture {
+                    image,
+                    view: vk::ImageView::null(),
+                });
+                return Err(x);
+            }
+        };
+        let handle = bindless.add_sampled_image(view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        self.textures.push(GpuTexture { image, view });
+        handle.map(Some).ok_or(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+    }
+
+    /// Frees every image, the GPU must be done with them.
+    pub(crate) fn destroy(&mut self, allocator: &mut Allocator) {
+        for texture in self.textures.drain(..) {
+            unsafe { self.device.destroy_image_view(texture.view, None) };
+            allocator.destroy_image(&texture.image);
+        }
+    }
+}
+
+impl Drop for Textures {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_sampler(self.sampler, None) };
+    }
+}
+
+/// The Vulkan format `format` is uploaded as, the sRGB variant when it has one and `color_space` asks for it.
+fn vk_format(format: TextureFormat, color_space: ColorSpace) -> vk::Format {
+    let srgb = format.has_srgb() && color_space == ColorSpace::Srgb;
+    match (format, srgb) {
+        (TextureFormat::Rgba8, false) => vk::Format::R8G8B8A8_UNORM,
+        (TextureFormat::Rgba8, true) => vk::Format::R8G8B8A8_SRGB,
+        (TextureFormat::Rgba16F, _) => vk::Format::R16G16B16A16_SFLOAT,
+        (TextureFormat::Rgba32F, _) => vk::Format::R32G32B32A32_SFLOAT,
+        (TextureFormat::Bc1, false) => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        (TextureFormat::Bc1, true) => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        (TextureFormat::Bc2, false) => vk::Format::BC2_UNORM_BLOCK,
+        (TextureFormat::Bc2, true) => vk::Format::BC2_SRGB_BLOCK,
+        (TextureFormat::Bc3, false) => vk::Format::BC3_UNORM_BLOCK,
+        (TextureFormat::Bc3, true) => vk::Format::BC3_SRGB_BLOCK,
+        (TextureFormat::Bc4, _) => vk::Format::BC4_UNORM_BLOCK,
+        (TextureFormat::Bc5, _) => vk::Format::BC5_UNORM_BLOCK,
+        (TextureFormat::Bc6H, _) => vk::Format::BC6H_UFLOAT_BLOCK,
+        (TextureFormat::Bc7, false) => vk::Format::BC7_UNORM_BLOCK,
+        (TextureFormat::Bc7, true) => vk::Format::BC7_SRGB_BLOCK,
+    }
+}
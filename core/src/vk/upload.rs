@@ -0,0 +1,551 @@
+#![cfg(feature = "vulkan")]
+
+use {
+    crate::{
+        assets::Texture,
+        vk::{
+            memory::{Allocator, Buffer, Image, MemoryLocation},
+            queues::{OwnershipTransfer, Queues},
+        },
+    },
+    ash::{Device, prelude::VkResult, vk},
+    std::fmt,
+};
+
+// Level offsets in image staging buffers, enough for every texel and block size
+const LEVEL_ALIGNMENT: usize = 16;
+
+/// An image waiting for its levels, and for the rest of its mip chain when `blit` is set.
+struct ImageUpload {
+    staging: Buffer,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    // Where each level given starts in the staging buffer
+    offsets: Vec<vk::DeviceSize>,
+    mip_levels: u32,
+    // The filter the missing levels are downsampled with
+    blit: Option<vk::Filter>,
+}
+
+/// Fills device local buffers and images through host visible staging buffers, copied on the transfer queue.
+/// Nothing may use them before [`Uploader::flush`], which blocks until the graphics queue owns them.
+/// Images end up in `SHADER_READ_ONLY_OPTIMAL`.
+pub(crate) struct Uploader<'s> {
+    device: &'s Device,
+    queues: &'s Queues,
+    allocator: &'s mut Allocator,
+    // (staging, destination)
+    copies: Vec<(Buffer, vk::Buffer)>,
+    images: Vec<ImageUpload>,
+}
+
+impl<'s> Uploader<'s> {
+    pub(crate) fn new(
+        device: &'s Device,
+        queues: &'s Queues,
+        allocator: &'s mut Allocator,
+    ) -> Self {
+        Self {
+            device,
+            queues,
+            allocator,
+            copies: Vec::new(),
+            images: Vec::new(),
+        }
+    }
+
+    fn staging(
+        &mut self,
+        size: usize,
+        fill: impl FnOnce(&mut [u8]),
+        name: impl fmt::Display,
+    ) -> VkResult<Buffer> {
+        let mut staging = self.allocator.create_buffer(
+            &vk::BufferCreateInfo::default()
+                .size(size as vk::DeviceSize)
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            MemoryLocation::CpuToGpu,
+            format_args!("{name} staging"),
+        )?;
+        let Some(mapped) = staging.allocation.mapped_slice_mut() else {
+            self.allocator.destroy_buffer(&staging);
+            return Err(vk::Result::ERROR_MEMORY_MAP_FAILED);
+        };
+        fill(&mut mapped[..size]);
+        Ok(staging)
+    }
+
+    /// A `GpuOnly` buffer named `name` with `usage` that will hold `bytes` once flushed.
+    pub(crate) fn buffer(
+        &mut self,
+        bytes: &[u8],
+        usage: vk::BufferUsageFlags,
+        name: impl fmt::Display,
+    ) -> VkResult<Buffer> {
+        let size = bytes.len() as vk::DeviceSize;
+        let staging = self.staging(bytes.len(), |x| x.copy_from_slice(bytes), &name)?;
+        let buffer = match self.allocator.create_buffer(
+            &vk::BufferCreateInfo::default()
+                .size(size)
+                .usage(usage | vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            MemoryLocation::GpuOnly,
+            name,
+        ) {
+            Ok(x) => x,
+            Err(x) => {
+                self.allocator.destroy_buffer(&staging);
+                return Err(x);
+            }
+        };
+        self.copies.push((staging, buffer.handle));
+        Ok(buffer)
+    }
+
+    /// A sampled `format` image that will hold the levels of `texture` once flushed.
+    /// With `blit`, the levels after those of `texture` up to `mip_levels` are downsampled from them,
+    /// `format` has to support blits and the filter then.
+    pub(crate) fn image(
+        &mut self,
+        texture: &Texture,
+        format: vk::Format,
+        mip_levels: u32,
+        blit: Option<vk::Filter>,
+    ) -> VkResult<Image> {
+        let mut offsets = Vec::with_capacity(texture.levels.len());
+        let mut size: usize = 0;
+        for level in &texture.levels {
+            size = size.next_multiple_of(LEVEL_ALIGNMENT);
+            offsets.push(size as vk::DeviceSize);
+            size += level.len();
+        }
+        let name = texture.name.as_deref().unwrap_or("unnamed");
+        let staging = self.staging(
+            size,
+            |bytes| {
+                for (level, &offset) in texture.levels.iter().zip(&offsets) {
+                    bytes[offset as usize..offset as usize + level.len()].copy_from_slice(level);
+                }
+            },
+            format_args!("texture {name}"),
+        )?;
+        let mut usage = vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST;
+        if blit.is_some() {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+        let extent = vk::Extent2D {
+            width: texture.width,
+            height: texture.height,
+        };
+        let image = match self.allocator.create_image(
+            &vk::ImageCreateInfo::default()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(extent.into())
+                .mip_levels(mip_levels)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED),
+            MemoryLocation::GpuOnly,
+            format_args!("texture {name}"),
+        ) {
+            Ok(x) => x,
+            Err(x) => {
+                self.allocator.destroy_buffer(&staging);
+                return Err(x);
+            }
+        };
+        offsets.truncate(mip_levels as usize);
+        self.images.push(ImageUpload {
+            staging,
+            image: image.handle,
+            extent,
+            offsets,
+            mip_levels,
+            blit,
+        });
+        Ok(image)
+    }
+
+    /// Records and submits every copy, hands the buffers to the graphics family and waits for it.
+    pub(crate) fn flush(mut self) -> VkResult<()> {
+        let copies = std::mem::take(&mut self.copies);
+        let images = std::mem::take(&mut self.images);
+        let result = self.submit(&copies, &images);
+        // Waited on or never submitted, either way the staging buffers are free to go
+        for staging in copies
+            .iter()
+            .map(|x| &x.0)
+            .chain(images.iter().map(|x| &x.staging))
+        {
+            self.allocator.destroy_buffer(staging);
+        }
+        result
+    }
+
+    fn submit(&self, copies: &[(Buffer, vk::Buffer)], images: &[ImageUpload]) -> VkResult<()> {
+        if copies.is_empty() && images.is_empty() {
+            return Ok(());
+        }
+        let families = self.queues.families;
+        let ownership = OwnershipTransfer::new(families.transfer, families.graphics);
+        let transfer_barriers: Vec<_> = copies
+            .iter()
+            .map(|&(_, buffer)| match ownership {
+                Some(ownership) => ownership.release_buffer(
+                    buffer,
+                    vk::PipelineStageFlags2::COPY,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+                None => vk::BufferMemoryBarrier2::default()
+                    .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+                    .buffer(buffer)
+                    .size(vk::WHOLE_SIZE),
+            })
+            .collect();
+        let range = |image: &ImageUpload| vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: image.mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let to_transfer_dst: Vec<_> = images
+            .iter()
+            .map(|image| {
+                vk::ImageMemoryBarrier2::default()
+                    .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .image(image.image)
+                    .subresource_range(range(image))
+            })
+            .collect();
+        // Same family images need no release, the semaphore orders the graphics work after the copies
+        let image_release: Vec<_> = ownership
+            .iter()
+            .flat_map(|ownership| {
+                images.iter().map(|image| {
+                    ownership.release_image(
+                        image.image,
+                        range(image),
+                        (
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        ),
+                        vk::PipelineStageFlags2::COPY,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    )
+                })
+            })
+            .collect();
+
+        unsafe {
+            let timeline = {
+                let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+                    .semaphore_type(vk::SemaphoreType::TIMELINE)
+                    .initial_value(0);
+                self.device.create_semaphore(
+                    &vk::SemaphoreCreateInfo::default().push_next(&mut type_info),
+                    None,
+                )?
+            };
+            let mut pools = Vec::new();
+            let result = (|| {
+                let transfer = self.begin(families.transfer, &mut pools)?;
+                self.device.cmd_pipeline_barrier2(
+                    transfer,
+                    &vk::DependencyInfo::default().image_memory_barriers(&to_transfer_dst),
+                );
+                for (staging, buffer) in copies {
+                    let region = [vk::BufferCopy::default().size(staging.size)];
+                    self.device
+                        .cmd_copy_buffer(transfer, staging.handle, *buffer, &region);
+                }
+                for image in images {
+                    let regions: Vec<_> = image
+                        .offsets
+                        .iter()
+                        .enumerate()
+                        .map(|(level, &offset)| {
+                            vk::BufferImageCopy::default()
+                                .buffer_offset(offset)
+                                .image_subresource(vk::ImageSubresourceLayers {
+                                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                                    mip_level: level as u32,
+                                    base_array_layer: 0,
+                                    layer_count: 1,
+                                })
+                                .image_extent(vk::Extent3D {
+                                    width: (image.extent.width >> level).max(1),
+                                    height: (image.extent.height >> level).max(1),
+                                    depth: 1,
+                                })
+                        })
+                        .collect();
+                    self.device.cmd_copy_buffer_to_image(
+                        transfer,
+                        image.staging.handle,
+                        image.image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &regions,
+                    );
+                }
+                self.device.cmd_pipeline_barrier2(
+                    transfer,
+                    &vk::DependencyInfo::default()
+                        .buffer_memory_barriers(&transfer_barriers)
+                        .image_memory_barriers(&image_release),
+                );
+                self.device.end_command_buffer(transfer)?;
+                self.submit_one(self.queues.transfer, transfer, timeline, None, 1)?;
+                let mut done = 1;
+
+                // The acquire half of the ownership transfer once the copies are done, then the mips,
+                // which need a queue that can blit
+                if ownership.is_some() || !images.is_empty() {
+                    let graphics = self.begin(families.graphics, &mut pools)?;
+                    if let Some(ownership) = ownership {
+                        let buffers: Vec<_> = copies
+                            .iter()
+                            .map(|&(_, buffer)| {
+                                ownership.acquire_buffer(
+                                    buffer,
+                                    vk::PipelineStageFlags2::ALL_COMMANDS,
+                                    vk::AccessFlags2::MEMORY_READ,
+                                )
+                            })
+                            .collect();
+                        let images: Vec<_> = images
+                            .iter()
+                            .map(|image| {
+                                ownership.acquire_image(
+                                    image.image,
+                                    range(image),
+                                    (
+                                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                                    ),
+                                    vk::PipelineStageFlags2::BLIT,
+                                    vk::AccessFlags2::TRANSFER_READ
+                                        | vk::AccessFlags2::TRANSFER_WRITE,
+                                )
+                            })
+                            .collect();
+                        self.device.cmd_pipeline_barrier2(
+                            graphics,
+                            &vk::DependencyInfo::default()
+                                .buffer_memory_barriers(&buffers)
+                                .image_memory_barriers(&images),
+                        );
+                    }
+                    for image in images {
+                        self.finish_image(graphics, image);
+                    }
+                    self.device.end_command_buffer(graphics)?;
+                    self.submit_one(self.queues.graphics, graphics, timeline, Some(1), 2)?;
+                    done = 2;
+                }
+
+                let semaphores = [timeline];
+                let values = [done];
+                self.device.wait_semaphores(
+                    &vk::SemaphoreWaitInfo::default()
+                        .semaphores(&semaphores)
+                        .values(&values),
+                    u64::MAX,
+                )
+            })();
+            // Anything submitted has either finished or failed to submit by now
+            if result.is_err() {
+                let _ = self.device.device_wait_idle();
+            }
+            for pool in pools {
+                self.device.destroy_command_pool(pool, None);
+            }
+            self.device.destroy_semaphore(timeline, None);
+            result
+        }
+    }
+
+    /// Records the blits filling the missing levels of `image`, and the transition to shader reads.
+    fn finish_image(&self, command_buffer: vk::CommandBuffer, image: &ImageUpload) {
+        let level_barrier =
+            |level: u32, count: u32, layouts: (vk::ImageLayout, vk::ImageLayout)| {
+                vk::ImageMemoryBarrier2::default()
+                    .src_stage_mask(vk::PipelineStageFlags2::COPY | vk::PipelineStageFlags2::BLIT)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::BLIT)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+                    .old_layout(layouts.0)
+                    .new_layout(layouts.1)
+                    .image(image.image)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: level,
+                        level_count: count,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+            };
+        let extent = |level: u32| vk::Offset3D {
+            x: (image.extent.width >> level).max(1) as i32,
+            y: (image.extent.height >> level).max(1) as i32,
+            z: 1,
+        };
+        // Every level below the ones given is read once to make the next
+        let given = image.offsets.len() as u32;
+        let mut barriers = Vec::new();
+        if let Some(filter) = image.blit {
+            for level in given..image.mip_levels {
+                let source = [level_barrier(
+                    level - 1,
+                    1,
+                    (
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    ),
+                )];
+                let region = [vk::ImageBlit2::default()
+                    .src_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level - 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .src_offsets([vk::Offset3D::default(), extent(level - 1)])
+                    .dst_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .dst_offsets([vk::Offset3D::default(), extent(level)])];
+                unsafe {
+                    self.device.cmd_pipeline_barrier2(
+                        command_buffer,
+                        &vk::DependencyInfo::default().image_memory_barriers(&source),
+                    );
+                    self.device.cmd_blit_image2(
+                        command_buffer,
+                        &vk::BlitImageInfo2::default()
+                            .src_image(image.image)
+                            .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                            .dst_image(image.image)
+                            .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                            .regions(&region)
+                            .filter(filter),
+                    );
+                }
+            }
+            if image.mip_levels > given {
+                barriers.push(level_barrier(
+                    given - 1,
+                    image.mip_levels - given,
+                    (
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                ));
+            }
+        }
+        // Whatever is left in TRANSFER_DST: the given levels without blits, otherwise the ones
+        // before the last given and the last generated one
+        let written = match image.blit {
+            Some(_) if image.mip_levels > given => {
+                vec![(0, given - 1), (image.mip_levels - 1, 1)]
+            }
+            _ => vec![(0, image.mip_levels)],
+        };
+        for (level, count) in written.into_iter().filter(|&(_, count)| count > 0) {
+            barriers.push(level_barrier(
+                level,
+                count,
+                (
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ),
+            ));
+        }
+        for barrier in &mut barriers {
+            *barrier = barrier
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ);
+        }
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&barriers),
+            );
+        }
+    }
+
+    fn begin(
+        &self,
+        queue_family: u32,
+        pools: &mut Vec<vk::CommandPool>,
+    ) -> VkResult<vk::CommandBuffer> {
+        unsafe {
+            let pool = self.device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                    .queue_family_index(queue_family),
+                None,
+            )?;
+            pools.push(pool);
+            let command_buffer = self.device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            )?[0];
+            self.device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+            Ok(command_buffer)
+        }
+    }
+
+    fn submit_one(
+        &self,
+        queue: vk::Queue,
+        command_buffer: vk::CommandBuffer,
+        timeline: vk::Semaphore,
+        wait: Option<u64>,
+        signal: u64,
+    ) -> VkResult<()> {
+        let wait_semaphores: Vec<_> = wait
+            .map(|value| {
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(timeline)
+                    .value(value)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            })
+            .into_iter()
+            .collect();
+        let command_buffers =
+            [vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)];
+        let signal_semaphores = [vk::SemaphoreSubmitInfo::default()
+            .semaphore(timeline)
+            .value(signal)
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)];
+        let submit_info = vk::SubmitInfo2::default()
+            .wait_semaphore_infos(&wait_semaphores)
+            .command_buffer_infos(&command_buffers)
+            .signal_semaphore_infos(&signal_semaphores);
+        unsafe {
+            self.device
+                .queue_submit2(queue, &[submit_info], vk::Fence::null())
+        }
+    }
+}
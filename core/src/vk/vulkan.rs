@@ -1,30 +1,310 @@
-use crate::utils::AppState;
-use crate::vk::setup;
+use {
+    crate::{
+        DisplayControls, RendererConfig,
+        assets::Scene,
+        utils::{AppState, FramePacer},
+        vk::{
+            bindless::BindlessHeap,
+            culling::InstanceCulling,
+            data::VkCore,
+            error::{RendererError, vk_report},
+            frame,
+            lights::ClusteredLights,
+            mesh::MeshRenderer,
+            offscreen::FrameReadback,
+            pipeline::PipelineCache,
+            post::PostProcess,
+            renderer::Renderer,
+            shader::ShaderLibrary,
+            shadows::{MAX_CASCADES, Shadows},
+            texture::Textures,
+        },
+    },
+    error_stack::{Report, ResultExt},
+    std::{sync::mpsc::TryRecvError, thread, time::Duration},
+};
 
-pub struct Core(setup::VulkanSetup);
+pub struct Core {
+    // Declared before `vk` so the frame resources are dropped while the device still exists
+    frames: frame::Frames,
+    renderer: Renderer,
+    vk: VkCore,
+    // Stop after this many frames, used by headless runs
+    frame_limit: Option<u64>,
+    display: DisplayControls,
+    pacer: FramePacer,
+}
 
 impl Core {
-    pub fn new(input: setup::VulkanSetup) -> Self {
-        Core(input)
+    pub fn new(
+        mut vk: VkCore,
+        readback: Option<FrameReadback>,
+        frame_limit: Option<u64>,
+        config: RendererConfig,
+    ) -> Result<Self, Report<RendererError>> {
+        let RendererConfig {
+            asset_dir,
+            scenes,
+            mut shadows,
+            post,
+            lut,
+            display,
+        } = config;
+        crate::debug_logln!(
+            "vulkan",
+            "Device capabilities: {:#?}",
+            vk.devices.capabilities
+        );
+        let frames = frame::Frames::new(&vk, readback)
+            .map_err(|x| vk_report(x, RendererError::Resources))
+            .attach("frame resources")?;
+        let mut bindless = BindlessHeap::new(
+            vk.devices.logical.clone(),
+            &vk.devices.capabilities.descriptor_limits,
+        )
+        .map_err(|x| vk_report(x, RendererError::Resources))
+        .attach("bindless descriptor heap")?;
+        let shaders = ShaderLibrary::new(vk.devices.logical.clone(), &asset_dir);
+        let pipelines = PipelineCache::new(
+            &vk.core.instance,
+            vk.devices.physical,
+            vk.devices.logical.clone(),
+            &vk.devices.capabilities,
+            bindless.pipeline_layout(),
+            &asset_dir,
+            vk.devices.debug_utils.clone(),
+        )
+        .map_err(|x| vk_report(x, RendererError::Resources))
+        .attach("pipeline cache")?;
+        // A scene that fails to load is reported and left out, the rest still get drawn
+        let scenes: Vec<Scene> = scenes
+            .iter()
+            .filter_map(|path| match Scene::load(asset_dir.join(path)) {
+                Ok(scene) => {
+                    for note in &scene.unsupported {
+                        crate::logln!("scene warning", "{}: {note}", path.display());
+                    }
+                    Some(scene)
+                }
+                Err(report) => {
+                    crate::logln!("scene error", "{report:?}");
+                    None
+                }
+            })
+            .collect();
+        let mut textures = Textures::new(
+            &vk.core.instance,
+            vk.devices.physical,
+            vk.devices.logical.clone(),
+            &vk.devices.capabilities,
+            &mut bindless,
+        )
+        .map_err(|x| vk_report(x, RendererError::Resources))
+        .attach("texture sampler")?;
+        shadows.cascades = shadows.cascades.clamp(1, MAX_CASCADES);
+        let lights = ClusteredLights::new(
+            &mut vk.devices.allocator,
+            &mut bindless,
+            &vk.devices.queues.families,
+            &scenes,
+            &shadows,
+        )
+        .map_err(|x| vk_report(x, RendererError::Resources))
+        .attach("light buffers")?;
+        let max_dimension = unsafe {
+            vk.core
+                .instance
+                .get_physical_device_properties(vk.devices.physical)
+                .limits
+                .max_image_dimension2_d
+        };
+        let shadows = Shadows::new(
+            vk.devices.logical.clone(),
+            &mut vk.devices.allocator,
+            &mut bindless,
+            shadows,
+            lights.shadow_casters(),
+            max_dimension,
+        )
+        .map_err(|x| vk_report(x, RendererError::Resources))
+        .attach("shadow maps")?;
+        let meshes = MeshRenderer::new(
+            &vk.devices.logical,
+            &vk.devices.queues,
+            &mut vk.devices.allocator,
+            &mut bindless,
+            &mut textures,
+            &scenes,
+            &vk.devices.capabilities,
+        )
+        .map_err(|x| vk_report(x, RendererError::Resources))
+        .attach("scene upload")?;
+        let culling = (vk.devices.capabilities.draw_indirect_count
+            && meshes.indirect()
+            && meshes.tables().is_some())
+        .then(|| {
+            InstanceCulling::new(
+                &vk.devices.logical,
+                &vk.devices.queues,
+                &mut vk.devices.allocator,
+                &mut bindless,
+                &meshes,
+            )
+        })
+        .transpose()
+        .map_err(|x| vk_report(x, RendererError::Resources))
+        .attach("culling buffers")?;
+        let lut = lut.map(|path| asset_dir.join(path));
+        let post = PostProcess::new(
+            vk.devices.logical.clone(),
+            &vk.devices.queues,
+            &mut vk.devices.allocator,
+            &mut bindless,
+            &mut textures,
+            post,
+            lut.as_deref(),
+        )
+        .map_err(|x| vk_report(x, RendererError::Resources))
+        .attach("post-processing resources")?;
+        Ok(Core {
+            frames,
+            renderer: Renderer {
+                bindless,
+                shaders,
+                pipelines,
+                textures,
+                lights,
+                shadows,
+                meshes,
+                culling,
+                post,
+            },
+            vk,
+            frame_limit,
+            display,
+            pacer: FramePacer::default(),
+        })
+    }
+
+    /// Renders until the window closes or the frame limit is hit, then tears everything down.
+    /// A frame that fails still gets the teardown, its report is returned afterwards.
+    pub fn main_loop(mut self) -> Result<(), Report<RendererError>> {
+        let rendered = self.render_frames();
+        // Hand out whatever is still in flight before the frame resources go away
+        let finished = self
+            .frames
+            .finish(&mut self.vk)
+            .map_err(|x| vk_report(x, RendererError::Frame))
+            .attach("finishing the last frames");
+        // Presentation and transfers aren't on the timeline, nothing may touch what's destroyed next
+        let idle = unsafe { self.vk.devices.logical.device_wait_idle() }
+            .map_err(|x| vk_report(x, RendererError::Frame))
+            .attach("waiting for the device to go idle");
+        // Memory goes back to the allocator first, then `Core` drops the frames, the renderer
+        // and finally the `VkCore` layers
+        self.renderer.meshes.destroy(&mut self.vk.devices.allocator);
+        if let Some(culling) = &mut self.renderer.culling {
+            culling.destroy(&mut self.vk.devices.allocator);
+        }
+        self.renderer
+            .textures
+            .destroy(&mut self.vk.devices.allocator);
+        self.renderer.lights.destroy(&mut self.vk.devices.allocator);
+        self.renderer
+            .shadows
+            .destroy(&mut self.vk.devices.allocator);
+        self.renderer.post.destroy(&mut self.vk.devices.allocator);
+        self.vk.check_validation();
+        // The first failure is the interesting one, the rest usually follow from it
+        rendered.and(finished).and(idle)
     }
 
-    pub fn main_loop(self) {
+    fn render_frames(&mut self) -> Result<(), Report<RendererError>> {
+        let mut state = AppState::Open;
+        let mut swapchain_dirty = false;
         'main: loop {
-            let receiver = match &self.0.window_communicator {
-                Some(x) => x,
-                None => panic!("Unable to receive orders! Shutting down."),
+            let Some(receiver) = self.vk.window_communicator() else {
+                return Err(Report::new(RendererError::Frame)
+                    .attach("the renderer has no channel to receive the window's orders on"));
             };
 
-            match receiver.recv().unwrap() {
-                AppState::Awaiting => {
-                    println!("Awaiting orders...");
+            // Drain every pending order without blocking, the latest one wins
+            loop {
+                match receiver.try_recv() {
+                    Ok(AppState::Closed) | Err(TryRecvError::Disconnected) => {
+                        crate::logln!("renderer", "Closing...");
+                        break 'main;
+                    }
+                    Ok(AppState::Awaiting) => {
+                        crate::logln!("renderer", "Awaiting orders...");
+                        state = AppState::Awaiting;
+                    }
+                    Ok(x) => state = x,
+                    Err(TryRecvError::Empty) => break,
                 }
-                AppState::Closed => {
-                    println!("Closing...");
-                    break 'main;
+            }
+
+            if self
+                .frame_limit
+                .is_some_and(|limit| self.frames.frame_number() >= limit)
+            {
+                break 'main;
+            }
+
+            if self.vk.display.poll_window_size() {
+                swapchain_dirty = true;
+            }
+            // Settings menus may have switched vsync since the last frame
+            let display = self.display.get();
+            if self.vk.display.set_vsync(display.vsync) {
+                swapchain_dirty = true;
+            }
+
+            // Nothing to draw, don't spin the CPU while waiting
+            if !matches!(state, AppState::Open | AppState::Loading)
+                || self.vk.display.is_minimized()
+            {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+
+            if swapchain_dirty {
+                // Still minimized as far as the surface is concerned, try again later
+                if !self.vk.display.recreate_swapchain(&self.vk.devices)? {
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
                 }
-                _ => {}
+                self.frames
+                    .swapchain_recreated(self.vk.display.swapchain_image_count())
+                    .map_err(|x| vk_report(x, RendererError::Resources))
+                    .attach("frame resources for the new swapchain")?;
             }
+
+            // Slots released by frames that finished can be handed out again
+            let completed = self
+                .frames
+                .completed_timeline_value()
+                .map_err(|x| vk_report(x, RendererError::Frame))
+                .attach("reading the timeline semaphore")?;
+            self.renderer.bindless.reclaim(completed);
+            self.renderer.pipelines.reclaim(completed);
+
+            // Edited shaders are swapped in without restarting, the pipelines using them get rebuilt
+            let reloaded = self.renderer.shaders.poll_changes();
+            if !reloaded.is_empty() {
+                self.renderer
+                    .pipelines
+                    .shaders_reloaded(&reloaded, self.frames.frame_number());
+            }
+
+            self.pacer.wait(display.fps_limit);
+            swapchain_dirty = self
+                .frames
+                .draw_frame(&mut self.vk, &mut self.renderer)
+                .map_err(|x| vk_report(x, RendererError::Frame))?;
+            self.vk.check_validation();
         }
+
+        Ok(())
     }
 }
@@ -1,12 +1,12 @@
 use {
-    crate::utils::{AppState, RawWindowingHandles},
+    crate::utils::{self, AppState, RawWindowingHandles},
     raw_window_handle::{HasDisplayHandle, HasWindowHandle},
-    std::{convert::From, sync::mpsc::Sender},
-    tokio::{
-        runtime::Runtime,
-        sync::{mpsc::Sender as TokioSender, oneshot},
-        task,
+    std::{
+        convert::From,
+        sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError},
+        time::Duration,
     },
+    tokio::sync::{mpsc::Sender as TokioSender, oneshot},
     winit::{
         application::ApplicationHandler,
         dpi::PhysicalSize,
@@ -17,6 +17,9 @@ use {
     },
 };
 
+// How long closing waits for the renderer to let go of the window before destroying it anyway
+const RENDERER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 enum Events {
     Window(WindowEvent),
     Device(DeviceEvent),
@@ -46,27 +49,54 @@ pub struct AppWindow {
     attr: WindowAttributes,
     app_state: AppState,
     render_communicator: Option<Sender<AppState>>,
+    // Disconnects once the renderer destroyed everything made from the window, or died
+    renderer_done: Option<Receiver<()>>,
     surface_handles_sender: Option<oneshot::Sender<RawWindowingHandles>>,
     inner_size_sender: Option<TokioSender<PhysicalSize<u32>>>,
 }
 
 impl AppWindow {
-    fn send_app_state(&self) {
+    fn send_app_state(&mut self, event_loop: &ActiveEventLoop) {
         if let Some(rc) = &self.render_communicator {
-            rc.send(self.app_state.clone());
+            // Only fails once the renderer is gone, there's nothing left to show then
+            if rc.send(self.app_state.clone()).is_err() {
+                crate::logln!("window warning", "The Renderer stopped, closing the Window");
+                self.exit(event_loop);
+            }
         } else {
             eprintln!("Render Communicator not Initalized yet...");
         }
     }
 
+    fn send_inner_size(&self, size: PhysicalSize<u32>) {
+        if let Some(sender) = &self.inner_size_sender {
+            // The event loop isn't async, so block until the renderer has room for it.
+            // This only fails once the renderer is gone, at which point nobody cares anymore.
+            let _ = sender.blocking_send(size);
+        } else {
+            eprintln!("Inner Size Sender not Initalized yet...");
+        }
+    }
+
+    /// Tells the renderer and scripts to stop, and destroys the window once the renderer is done with it.
     fn exit(&mut self, event_loop: &ActiveEventLoop) {
         println!("Close Requested!");
+        utils::request_shutdown();
+        self.app_state = AppState::Closed;
         if let Some(rc) = &self.render_communicator {
-            rc.send(AppState::Closed);
-            self.app_state = AppState::Closed;
-        } else {
-            panic!("Render Communicator failed to be initalized!");
+            // A renderer that's already gone has nothing left to release
+            let _ = rc.send(AppState::Closed);
+        }
+        // The swapchain and surface have to go before the window they were created from
+        if let Some(done) = self.renderer_done.take()
+            && done.recv_timeout(RENDERER_SHUTDOWN_TIMEOUT) == Err(RecvTimeoutError::Timeout)
+        {
+            crate::logln!(
+                "window warning",
+                "The Renderer didn't shut down in time, closing the Window anyway"
+            );
         }
+        self.window = None;
         event_loop.exit();
     }
 
@@ -91,8 +121,14 @@ impl AppWindow {
         self.attr = attrs.clone();
     }
 
-    pub(crate) fn init_render_communicator(&mut self, communicator: Sender<AppState>) {
+    /// `renderer_done` has to disconnect once the renderer released the surface.
+    pub(crate) fn init_render_communicator(
+        &mut self,
+        communicator: Sender<AppState>,
+        renderer_done: Receiver<()>,
+    ) {
         self.render_communicator = Some(communicator);
+        self.renderer_done = Some(renderer_done);
     }
 
     pub(crate) fn start(
@@ -128,12 +164,8 @@ impl ApplicationHandler<Events> for AppWindow {
         // We know at this point surface_handles_sender is Some()
         let sender = self.surface_handles_sender.take().unwrap();
         sender.send(self.get_surface_handles()).expect("Un oh");
-        let inner_size_sender = self.inner_size_sender.take().unwrap();
         let size = self.window.as_ref().unwrap().inner_size();
-        task::block_in_place(move || {
-            let rt = Runtime::new().unwrap();
-            rt.block_on(async { inner_size_sender.send(size).await })
-        });
+        self.send_inner_size(size);
     }
 
     fn window_event(
@@ -144,23 +176,38 @@ impl ApplicationHandler<Events> for AppWindow {
     ) {
         match event {
             WindowEvent::CloseRequested => self.exit(event_loop),
+            WindowEvent::Resized(size) => {
+                self.send_inner_size(size);
+                self.send_app_state(event_loop);
+            }
             WindowEvent::RedrawRequested => {
                 println!("Requested Redraw")
             }
             WindowEvent::KeyboardInput { event, .. } => if let PhysicalKey::Code(KeyCode::Escape) = event.physical_key { self.exit(event_loop) },
-            _ => self.send_app_state(),
+            _ => self.send_app_state(event_loop),
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        // A renderer that failed to start or stopped on an error leaves the window with nothing to show
+        if let Some(done) = &self.renderer_done
+            && done.try_recv() == Err(TryRecvError::Disconnected)
+        {
+            crate::logln!("window warning", "The Renderer stopped, closing the Window");
+            self.exit(event_loop);
         }
     }
 }
 
 impl Default for AppWindow {
     fn default() -> Self {
-        let attr = WindowAttributes::default().with_resizable(false);
+        let attr = WindowAttributes::default();
         Self {
             window: None,
             attr,
             app_state: AppState::default(),
             render_communicator: None,
+            renderer_done: None,
             surface_handles_sender: None,
             inner_size_sender: None,
         }
@@ -0,0 +1,130 @@
+//! Golden image test for headless rendering. Needs a Vulkan device, Mesa's lavapipe is enough
+//! (`mesa-vulkan-drivers` on Debian and Ubuntu):
+//!
+//! ```text
+//! VK_ICD_FILENAMES=/usr/share/vulkan/icd.d/lvp_icd.x86_64.json \
+//!     cargo test -p redefyning --test headless -- --ignored
+//! ```
+//!
+//! With `REDEFYNING_BLESS=1` the rendered frame becomes the new golden image instead.
+
+use {
+    image::RgbaImage,
+    redefyning::{App, AppVersion, HeadlessSettings, PostSettings},
+    std::{
+        env, fs,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    },
+};
+
+const SIZE: u32 = 64;
+const FRAMES: u64 = 3;
+// Per channel, rasterizers are allowed to round differently
+const TOLERANCE: u8 = 2;
+
+/// A single triangle, framed by the camera the renderer fits to the scene. It faces the camera
+/// but leans away from the default sun, so it only gets the flat hemisphere ambient and the
+/// golden image holds two colors with no pixel center close to an edge.
+fn triangle_scene(dir: &Path) -> PathBuf {
+    let positions: [[f32; 3]; 3] = [
+        [-0.525, -0.8, -0.6],
+        [0.525, -0.8, -0.6],
+        [0.0, 0.8, 0.6],
+    ];
+    let bytes: Vec<u8> = positions
+        .iter()
+        .flatten()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    fs::write(dir.join("triangle.bin"), &bytes).unwrap();
+    let gltf = format!(
+        r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scene": 0,
+            "scenes": [{{ "nodes": [0] }}],
+            "nodes": [{{ "mesh": 0 }}],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }} }}] }}],
+            "accessors": [{{
+                "bufferView": 0,
+                "componentType": 5126,
+                "count": 3,
+                "type": "VEC3",
+                "min": [-0.525, -0.8, -0.6],
+                "max": [0.525, 0.8, 0.6]
+            }}],
+            "bufferViews": [{{ "buffer": 0, "byteLength": {0} }}],
+            "buffers": [{{ "uri": "triangle.bin", "byteLength": {0} }}]
+        }}"#,
+        bytes.len()
+    );
+    let path = dir.join("triangle.gltf");
+    fs::write(&path, gltf).unwrap();
+    path
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn headless_triangle_matches_golden() {
+    let dir = env::temp_dir().join(format!("redefyning-golden-{}", std::process::id()));
+    let output_dir = dir.join("frames");
+    fs::create_dir_all(&dir).unwrap();
+    let scene = triangle_scene(&dir);
+
+    let last_frame: Arc<Mutex<Option<(u64, RgbaImage)>>> = Arc::new(Mutex::new(None));
+    let frames = Arc::clone(&last_frame);
+    // The asset directory holds the pipeline cache, keep it out of the source tree
+    App::new("golden", AppVersion::new(0, 0, 0, 0, None), None)
+        .asset_dir(&dir)
+        .add_scene(&scene)
+        .post_processing(PostSettings::minimal())
+        .headless(
+            HeadlessSettings::new(SIZE, SIZE)
+                .frame_limit(FRAMES)
+                .output_dir(&output_dir),
+        )
+        .on_frame(Box::new(move |number, image| {
+            *frames.lock().unwrap() = Some((number, image.to_rgba8()));
+        }))
+        .run()
+        .unwrap_or_else(|report| panic!("Headless rendering failed:\n{report:?}"));
+
+    let (number, rendered) = last_frame
+        .lock()
+        .unwrap()
+        .take()
+        .expect("No frame was read back");
+    // The PNG of the same frame holds the same pixels as the callback got
+    let written = image::open(output_dir.join(format!("frame_{number:05}.png")))
+        .expect("The frame wasn't written to the output directory")
+        .to_rgba8();
+    assert_eq!(written, rendered);
+
+    let golden_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/triangle.png");
+    if env::var_os("REDEFYNING_BLESS").is_some() {
+        fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        rendered.save(&golden_path).unwrap();
+        return;
+    }
+    let golden = image::open(&golden_path)
+        .unwrap_or_else(|x| {
+            panic!(
+                "{}: {x}, run with REDEFYNING_BLESS=1 to create it",
+                golden_path.display()
+            )
+        })
+        .to_rgba8();
+    assert_eq!(golden.dimensions(), rendered.dimensions());
+    let worst = golden
+        .as_raw()
+        .iter()
+        .zip(rendered.as_raw())
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or(0);
+    assert!(
+        worst <= TOLERANCE,
+        "Frame {number} differs from the golden image by up to {worst} per channel, it was written to {}",
+        output_dir.display()
+    );
+}
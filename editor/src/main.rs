@@ -1,4 +1,7 @@
-use redefyning::{App, AppVersion, WindowSettings};
+use {
+    redefyning::{App, AppVersion, WindowSettings},
+    std::process::ExitCode,
+};
 
 fn script1() {
     println!("script 1 running!");
@@ -8,10 +11,17 @@ fn script2() {
     println!("script 2 running!");
 }
 
-fn main() {
+fn main() -> ExitCode {
     let app_version = AppVersion::new(0, 0, 0, 0, None);
-    let app = App::new("test", app_version, None)
+    let result = App::new("test", app_version, None)
         .add_script(Box::new(script1))
         .add_script(Box::new(script2))
         .run();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(report) => {
+            eprintln!("The editor had to close:\n{report:?}");
+            ExitCode::FAILURE
+        }
+    }
 }